@@ -0,0 +1,150 @@
+//! 请求的认证信息
+//!
+//! worker 在每个请求之前从 `AuthProvider` 取得请求头，覆盖配置和 upload 中的同名请求头。
+//! 服务端返回 401 时先调用 `invalidate`，再取一次请求头重试，仍然是 401 才按失败处理。
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::RwLock;
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use crate::core::error::{UploadError, UploadResult};
+
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    /// 附加到请求上的请求头
+    async fn headers(&self) -> UploadResult<HeaderMap>;
+
+    /// 服务端拒绝了当前的凭据，之后的 headers 应该返回新的凭据
+    async fn invalidate(&self) {}
+
+    /// 应用推送的新凭据，例如前端刷新了 token；不支持时返回 Config 错误
+    async fn update(&self, _headers: HeaderMap) -> UploadResult<()> {
+        Err(UploadError::Config("Auth provider does not accept pushed credentials".into()))
+    }
+}
+
+/// 固定的请求头，可以通过 update 整体替换
+#[derive(Debug, Default)]
+pub struct StaticAuth {
+    headers: RwLock<HeaderMap>,
+}
+
+impl StaticAuth {
+    pub fn new(headers: HeaderMap) -> Self {
+        Self { headers: RwLock::new(headers) }
+    }
+
+    /// 只有 `Authorization: Bearer <token>`
+    pub fn bearer(token: &str) -> UploadResult<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(reqwest::header::AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token))?);
+        Ok(Self::new(headers))
+    }
+}
+
+#[async_trait]
+impl AuthProvider for StaticAuth {
+    async fn headers(&self) -> UploadResult<HeaderMap> {
+        Ok(self.headers.read().unwrap().clone())
+    }
+
+    async fn update(&self, headers: HeaderMap) -> UploadResult<()> {
+        *self.headers.write().unwrap() = headers;
+        Ok(())
+    }
+}
+
+type Callback = dyn Fn() -> Pin<Box<dyn Future<Output = UploadResult<HeaderMap>> + Send>> + Send + Sync;
+
+/// 由应用提供的回调取得请求头，结果一直使用到 invalidate 为止
+pub struct CallbackAuth {
+    callback: Box<Callback>,
+    cached: tokio::sync::Mutex<Option<HeaderMap>>,
+}
+
+impl CallbackAuth {
+    pub fn new<F, Fut>(callback: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = UploadResult<HeaderMap>> + Send + 'static,
+    {
+        Self {
+            callback: Box::new(move || Box::pin(callback())),
+            cached: tokio::sync::Mutex::new(None),
+        }
+    }
+}
+
+impl fmt::Debug for CallbackAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallbackAuth").finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl AuthProvider for CallbackAuth {
+    async fn headers(&self) -> UploadResult<HeaderMap> {
+        // 持有锁调用回调，同时开始的多个请求只刷新一次
+        let mut cached = self.cached.lock().await;
+        if let Some(headers) = cached.as_ref() {
+            return Ok(headers.clone());
+        }
+        let headers = (self.callback)().await?;
+        *cached = Some(headers.clone());
+        Ok(headers)
+    }
+
+    async fn invalidate(&self) {
+        *self.cached.lock().await = None;
+    }
+
+    /// 推送的凭据代替回调的结果，直到下一次 invalidate
+    async fn update(&self, headers: HeaderMap) -> UploadResult<()> {
+        *self.cached.lock().await = Some(headers);
+        Ok(())
+    }
+}
+
+/// 把名称和值转换为请求头，名称或值不合法时返回错误
+pub fn header_map(headers: &HashMap<String, String>) -> UploadResult<HeaderMap> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        map.insert(name.parse::<HeaderName>()?, value.parse::<HeaderValue>()?);
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_callback_cached_until_invalidated() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let auth = CallbackAuth::new(move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { header_map(&HashMap::from([("authorization".to_string(), format!("Bearer {}", n))])) }
+        });
+
+        assert_eq!(auth.headers().await.unwrap()["authorization"], "Bearer 1");
+        assert_eq!(auth.headers().await.unwrap()["authorization"], "Bearer 1");
+        auth.invalidate().await;
+        assert_eq!(auth.headers().await.unwrap()["authorization"], "Bearer 2");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // 推送的凭据代替回调的结果
+        let pushed = header_map(&HashMap::from([("authorization".to_string(), "Bearer pushed".to_string())])).unwrap();
+        auth.update(pushed).await.unwrap();
+        assert_eq!(auth.headers().await.unwrap()["authorization"], "Bearer pushed");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let fixed = StaticAuth::bearer("a").unwrap();
+        fixed.invalidate().await;
+        assert_eq!(fixed.headers().await.unwrap()["authorization"], "Bearer a");
+    }
+}
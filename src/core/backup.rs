@@ -0,0 +1,187 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use crate::core::clock::{self, Clock};
+use crate::core::error::{UploadError, UploadResult};
+
+/// 状态文件夹中保存备份的文件夹
+pub const BACKUP_DIR: &str = "backups";
+
+const BACKUP_PREFIX: &str = "state-";
+const BACKUP_EXTENSION: &str = ".json";
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// 状态文件的自动备份
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupPolicy {
+    /// 两次定期备份的最小间隔
+    pub interval: Duration,
+
+    /// 最多保留的备份数量，更早的备份被删除
+    pub keep: usize,
+}
+
+impl Default for BackupPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60 * 60),
+            keep: 10,
+        }
+    }
+}
+
+/// 一个备份文件
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BackupInfo {
+    /// 文件名，恢复时使用
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub size: u64,
+}
+
+#[derive(Debug)]
+pub struct StateBackups {
+    dir: PathBuf,
+    policy: BackupPolicy,
+
+    /// 上一次备份的时间，备份期间持有，同一时间只有一个备份
+    last: tokio::sync::Mutex<Option<Instant>>,
+
+    clock: Arc<dyn Clock>,
+}
+
+impl StateBackups {
+    pub fn new(state_dir: &Path, policy: BackupPolicy) -> Self {
+        Self {
+            dir: state_dir.join(BACKUP_DIR),
+            policy,
+            last: tokio::sync::Mutex::new(None),
+            clock: clock::system(),
+        }
+    }
+
+    /// 使用指定的时钟计算间隔和备份时间
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// 距离上一次备份超过间隔时备份，没有备份时返回 None
+    pub async fn backup_if_due(&self, content: &str) -> UploadResult<Option<BackupInfo>> {
+        let mut last = self.last.lock().await;
+        let now = self.clock.now_instant();
+        if last.is_some_and(|last| now - last < self.policy.interval) {
+            return Ok(None);
+        }
+        self.backup_locked(content, &mut last).await.map(Some)
+    }
+
+    /// 立即备份状态内容
+    pub async fn backup(&self, content: &str) -> UploadResult<BackupInfo> {
+        let mut last = self.last.lock().await;
+        self.backup_locked(content, &mut last).await
+    }
+
+    async fn backup_locked(&self, content: &str, last: &mut Option<Instant>) -> UploadResult<BackupInfo> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        // 同一毫秒内的多次备份内容可能不同，时间顺延到没有使用过的文件名
+        let mut created_at = self.clock.now_utc();
+        let (name, target) = loop {
+            let name = format!("{}{}{}", BACKUP_PREFIX, created_at.format(TIMESTAMP_FORMAT), BACKUP_EXTENSION);
+            let target = self.dir.join(&name);
+            if !target.exists() {
+                break (name, target);
+            }
+            created_at += chrono::TimeDelta::milliseconds(1);
+        };
+        let temp = target.with_extension("tmp");
+        tokio::fs::write(&temp, content).await?;
+        tokio::fs::rename(&temp, &target).await?;
+        *last = Some(self.clock.now_instant());
+
+        self.prune().await?;
+        Ok(BackupInfo { name, created_at, size: content.len() as u64 })
+    }
+
+    /// 所有备份，最新的排在前面
+    pub async fn list(&self) -> UploadResult<Vec<BackupInfo>> {
+        let mut backups = Vec::new();
+        if !self.dir.exists() {
+            return Ok(backups);
+        }
+
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            let Some(created_at) = parse_name(&name) else {
+                continue;
+            };
+            let size = entry.metadata().await?.len();
+            backups.push(BackupInfo { name, created_at, size });
+        }
+
+        backups.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(backups)
+    }
+
+    /// 备份文件的路径，只接受 list 返回的名称
+    pub fn path(&self, name: &str) -> UploadResult<PathBuf> {
+        let path = self.dir.join(name);
+        if parse_name(name).is_none() || !path.exists() {
+            return Err(UploadError::BackupNotFound(name.to_string()));
+        }
+        Ok(path)
+    }
+
+    async fn prune(&self) -> UploadResult<()> {
+        for backup in self.list().await?.into_iter().skip(self.policy.keep) {
+            tokio::fs::remove_file(self.dir.join(&backup.name)).await?;
+        }
+        Ok(())
+    }
+}
+
+/// 从 `state-<时间>.json` 中取出时间，其他文件返回 None
+fn parse_name(name: &str) -> Option<DateTime<Utc>> {
+    let timestamp = name.strip_prefix(BACKUP_PREFIX)?.strip_suffix(BACKUP_EXTENSION)?;
+    NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok().map(|time| time.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestClock;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limited_backups_are_pruned() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = Arc::new(TestClock::new());
+        let backups = StateBackups::new(dir.path(), BackupPolicy { interval: Duration::from_secs(60), keep: 3 })
+            .with_clock(clock.clone());
+        assert!(backups.backup_if_due("{}").await.unwrap().is_some());
+        clock.advance(Duration::from_secs(59)).await;
+        assert!(backups.backup_if_due("{}").await.unwrap().is_none());
+        clock.advance(Duration::from_secs(1)).await;
+        assert!(backups.backup_if_due("{}").await.unwrap().is_some());
+
+        let mut names = Vec::new();
+        for i in 0..4 {
+            clock.advance(Duration::from_millis(2)).await;
+            names.push(backups.backup(&format!("{{\"n\":{}}}", i)).await.unwrap().name);
+        }
+
+        let listed: Vec<String> = backups.list().await.unwrap().into_iter().map(|backup| backup.name).collect();
+        names.reverse();
+        assert_eq!(listed, names[..3]);
+        assert_eq!(tokio::fs::read_to_string(backups.path(&listed[0]).unwrap()).await.unwrap(), "{\"n\":3}");
+
+        assert!(matches!(backups.path("../upload-state.json"), Err(UploadError::BackupNotFound(_))));
+        assert!(matches!(backups.path(&names[3]), Err(UploadError::BackupNotFound(_))));
+    }
+}
@@ -0,0 +1,479 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::select;
+use tokio::sync::Notify;
+
+/// 公平模式下重新计算分配的间隔
+const REBALANCE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 单次等待的最长时间，醒来后按最新的分配重新计算
+const MAX_WAIT: Duration = Duration::from_millis(250);
+
+/// 没有用满份额的 upload 额外保留的余量
+const DEMAND_HEADROOM: f64 = 1.25;
+
+/// 最多累积的额度，避免空闲后突发
+const MAX_BURST: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+struct Member {
+    /// 权重，默认为 1
+    weight: u32,
+
+    /// 可用额度，可以为负数（大块需要先透支再等待）
+    tokens: f64,
+
+    /// 当前分配的速率，字节/秒
+    allocated: f64,
+
+    /// 本周期内消耗的字节
+    used: u64,
+
+    /// 本周期内是否因为额度不足而等待
+    starved: bool,
+
+    /// 上个周期估计的需求速率，用满份额时为无穷大
+    demand: f64,
+
+    /// 这个 upload 自己的上限，字节/秒，与总带宽同时生效
+    cap: Option<u64>,
+
+    /// 自己的上限对应的额度
+    cap_tokens: f64,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// 总带宽，字节/秒，为空时不限制
+    limit: Option<u64>,
+
+    members: HashMap<String, Member>,
+
+    /// 非公平模式下共享的额度
+    tokens: f64,
+
+    last_refill: Instant,
+    last_rebalance: Instant,
+}
+
+/// 全局带宽限制
+///
+/// 非公平模式下所有 upload 共享一个令牌桶，先到先得；
+/// 公平模式下按权重把总带宽分给正在上传的 upload，用不完的份额分给其他 upload。
+/// 每个 upload 还可以有自己的上限。总带宽可以在运行时修改，等待中的 upload 立即按新的限制重新计算
+#[derive(Debug)]
+pub struct BandwidthLimiter {
+    /// 是否按 upload 平分
+    fair: bool,
+
+    inner: Mutex<Inner>,
+
+    /// 限制变化时唤醒等待中的 upload
+    changed: Notify,
+}
+
+impl BandwidthLimiter {
+    /// limit 为空时不限制总带宽，之后可以通过 set_limit 设置
+    pub fn new(limit: Option<u64>, fair: bool) -> Self {
+        let now = Instant::now();
+        Self {
+            fair,
+            inner: Mutex::new(Inner {
+                limit,
+                members: HashMap::new(),
+                tokens: 0.0,
+                last_refill: now,
+                last_rebalance: now,
+            }),
+            changed: Notify::new(),
+        }
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        self.inner.lock().unwrap().limit
+    }
+
+    /// 修改总带宽，为空时取消限制
+    pub fn set_limit(&self, limit: Option<u64>) {
+        {
+            let now = Instant::now();
+            let mut inner = self.inner.lock().unwrap();
+            self.refill(&mut inner, now);
+            inner.limit = limit;
+            inner.tokens = 0.0;
+            inner.last_rebalance = now;
+            for member in inner.members.values_mut() {
+                member.tokens = 0.0;
+                member.demand = f64::INFINITY;
+            }
+            self.allocate(&mut inner);
+        }
+        self.changed.notify_waiters();
+    }
+
+    /// 修改一个 upload 自己的上限，为空时只受总带宽限制
+    fn set_member_limit(&self, id: &str, cap: Option<u64>) {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            self.refill(&mut inner, Instant::now());
+            if let Some(member) = inner.members.get_mut(id) {
+                member.cap = cap;
+                member.cap_tokens = 0.0;
+            }
+            self.allocate(&mut inner);
+        }
+        self.changed.notify_waiters();
+    }
+
+    /// 加入带宽分配，lease 被 drop 时退出
+    pub fn register(self: &Arc<Self>, id: impl Into<String>, weight: u32) -> BandwidthLease {
+        let id = id.into();
+        self.join(&id, weight, Instant::now());
+        BandwidthLease { limiter: self.clone(), id }
+    }
+
+    fn join(&self, id: &str, weight: u32, now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        self.refill(&mut inner, now);
+        inner.members.insert(id.to_string(), Member {
+            weight: weight.max(1),
+            tokens: 0.0,
+            allocated: 0.0,
+            used: 0,
+            starved: false,
+            demand: f64::INFINITY,
+            cap: None,
+            cap_tokens: 0.0,
+        });
+        self.allocate(&mut inner);
+    }
+
+    fn leave(&self, id: &str, now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        self.refill(&mut inner, now);
+        if inner.members.remove(id).is_some() {
+            self.allocate(&mut inner);
+        }
+    }
+
+    /// 当前分配给 upload 的速率，非公平模式、没有限制总带宽或未注册时返回 None
+    pub fn allocated_rate(&self, id: &str) -> Option<u64> {
+        let inner = self.inner.lock().unwrap();
+        if !self.fair || inner.limit.is_none() {
+            return None;
+        }
+        inner.members.get(id).map(|member| member.allocated as u64)
+    }
+
+    /// 所有 upload 的分配速率
+    pub fn allocations(&self) -> HashMap<String, u64> {
+        let inner = self.inner.lock().unwrap();
+        if !self.fair || inner.limit.is_none() {
+            return HashMap::new();
+        }
+        inner.members.iter()
+            .map(|(id, member)| (id.clone(), member.allocated as u64))
+            .collect()
+    }
+
+    /// 尝试消耗额度，额度不足时返回需要等待的时间
+    fn try_take(&self, id: &str, bytes: u64, now: Instant) -> Option<Duration> {
+        let mut inner = self.inner.lock().unwrap();
+        self.refill(&mut inner, now);
+        if self.fair && now.duration_since(inner.last_rebalance) >= REBALANCE_INTERVAL {
+            self.rebalance(&mut inner, now);
+        }
+
+        // 先检查自己的上限，因为自己的上限而等待不算没有分到份额
+        if let Some(member) = inner.members.get(id) {
+            if let (Some(cap), true) = (member.cap, member.cap_tokens < 0.0) {
+                return Some(wait_for(member.cap_tokens, cap as f64));
+            }
+        }
+
+        if let Some(limit) = inner.limit {
+            if self.fair {
+                let member = inner.members.get_mut(id)?;
+                if member.tokens < 0.0 {
+                    member.starved = true;
+                    return Some(wait_for(member.tokens, member.allocated));
+                }
+                member.tokens -= bytes as f64;
+            } else if inner.tokens < 0.0 {
+                return Some(wait_for(inner.tokens, limit as f64));
+            } else {
+                inner.tokens -= bytes as f64;
+            }
+        }
+
+        if let Some(member) = inner.members.get_mut(id) {
+            member.used += bytes;
+            if member.cap.is_some() {
+                member.cap_tokens -= bytes as f64;
+            }
+        }
+        None
+    }
+
+    /// 等待直到可以发送指定的字节数，限制变化时立即重新计算
+    pub async fn acquire(&self, id: &str, bytes: u64) {
+        while let Some(wait) = self.try_take(id, bytes, Instant::now()) {
+            select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = self.changed.notified() => {}
+            }
+        }
+    }
+
+    fn refill(&self, inner: &mut Inner, now: Instant) {
+        let elapsed = now.saturating_duration_since(inner.last_refill).as_secs_f64();
+        inner.last_refill = now;
+
+        for member in inner.members.values_mut() {
+            if let Some(cap) = member.cap {
+                let burst = cap as f64 * MAX_BURST.as_secs_f64();
+                member.cap_tokens = (member.cap_tokens + cap as f64 * elapsed).min(burst);
+            }
+        }
+
+        let Some(limit) = inner.limit else {
+            return;
+        };
+        if self.fair {
+            for member in inner.members.values_mut() {
+                let burst = member.allocated * MAX_BURST.as_secs_f64();
+                member.tokens = (member.tokens + member.allocated * elapsed).min(burst);
+            }
+        } else {
+            let burst = limit as f64 * MAX_BURST.as_secs_f64();
+            inner.tokens = (inner.tokens + limit as f64 * elapsed).min(burst);
+        }
+    }
+
+    /// 根据上个周期的使用情况更新需求，然后重新分配
+    fn rebalance(&self, inner: &mut Inner, now: Instant) {
+        let window = now.saturating_duration_since(inner.last_rebalance).as_secs_f64();
+        inner.last_rebalance = now;
+        let Some(limit) = inner.limit else {
+            return;
+        };
+        if window <= 0.0 || inner.members.is_empty() {
+            return;
+        }
+
+        // 停滞的 upload 保留一点速率，重新开始发送后下个周期就能拿回完整份额
+        let floor = limit as f64 / (20.0 * inner.members.len() as f64);
+        for member in inner.members.values_mut() {
+            let hungry = member.starved || member.tokens < 0.0;
+            member.demand = if hungry {
+                f64::INFINITY
+            } else {
+                (member.used as f64 / window * DEMAND_HEADROOM).max(floor)
+            };
+            member.used = 0;
+            member.starved = false;
+        }
+
+        self.allocate(inner);
+    }
+
+    /// 按权重分配带宽
+    /// 需求低于份额的 upload（包括自己的上限更低的）只分配它需要的速率，剩余部分分给其他 upload
+    fn allocate(&self, inner: &mut Inner) {
+        let Some(limit) = inner.limit else {
+            return;
+        };
+        let mut demands: Vec<(String, f64, f64)> = inner.members.iter()
+            .map(|(id, member)| {
+                let demand = member.cap.map_or(member.demand, |cap| member.demand.min(cap as f64));
+                (id.clone(), member.weight as f64, demand)
+            })
+            .collect();
+        demands.sort_by(|a, b| (a.2 / a.1).total_cmp(&(b.2 / b.1)));
+
+        let mut remaining = limit as f64;
+        let mut remaining_weight: f64 = demands.iter().map(|(_, weight, _)| weight).sum();
+        for (id, weight, demand) in demands {
+            let share = remaining * weight / remaining_weight;
+            let allocated = demand.min(share);
+            remaining -= allocated;
+            remaining_weight -= weight;
+
+            inner.members.get_mut(&id).unwrap().allocated = allocated;
+        }
+    }
+}
+
+/// 参与带宽分配的凭证，drop 时退出分配
+#[derive(Debug)]
+pub struct BandwidthLease {
+    limiter: Arc<BandwidthLimiter>,
+    id: String,
+}
+
+impl BandwidthLease {
+    pub async fn acquire(&self, bytes: u64) {
+        self.limiter.acquire(&self.id, bytes).await
+    }
+
+    pub fn allocated_rate(&self) -> Option<u64> {
+        self.limiter.allocated_rate(&self.id)
+    }
+
+    /// 这个 upload 自己的上限，为空时只受总带宽限制
+    pub fn set_limit(&self, cap: Option<u64>) {
+        self.limiter.set_member_limit(&self.id, cap);
+    }
+}
+
+/// 额度为负数时按速率需要等待的时间
+fn wait_for(tokens: f64, rate: f64) -> Duration {
+    let wait = if rate > 0.0 { Duration::from_secs_f64(-tokens / rate) } else { MAX_WAIT };
+    wait.clamp(Duration::from_millis(1), MAX_WAIT)
+}
+
+impl Drop for BandwidthLease {
+    fn drop(&mut self) {
+        self.limiter.leave(&self.id, Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNK: u64 = 16 * 1024;
+    const TICK: Duration = Duration::from_millis(10);
+
+    /// 模拟时钟推进，每个 tick 每个活动的 upload 尽量发送，返回每个 upload 发送的字节数
+    fn simulate(
+        limiter: &BandwidthLimiter,
+        start: Instant,
+        from: Duration,
+        to: Duration,
+        active: &[&str],
+    ) -> HashMap<String, u64> {
+        let mut sent: HashMap<String, u64> = active.iter().map(|id| (id.to_string(), 0)).collect();
+        let mut elapsed = from;
+        while elapsed < to {
+            for id in active {
+                if limiter.try_take(id, CHUNK, start + elapsed).is_none() {
+                    *sent.get_mut(*id).unwrap() += CHUNK;
+                }
+            }
+            elapsed += TICK;
+        }
+        sent
+    }
+
+    fn assert_rate(sent: u64, seconds: f64, expected: f64) {
+        let rate = sent as f64 / seconds;
+        assert!(
+            (rate - expected).abs() / expected < 0.15,
+            "rate {} is not close to {}", rate, expected
+        );
+    }
+
+    #[test]
+    fn test_equal_shares() {
+        let limit = 300 * 1024;
+        let limiter = BandwidthLimiter::new(Some(limit), true);
+        let start = limiter.inner.lock().unwrap().last_refill;
+        for id in ["a", "b", "c"] {
+            limiter.join(id, 1, start);
+        }
+        assert_eq!(limiter.allocated_rate("a"), Some(limit / 3));
+
+        let sent = simulate(&limiter, start, Duration::ZERO, Duration::from_secs(10), &["a", "b", "c"]);
+        for id in ["a", "b", "c"] {
+            assert_rate(sent[id], 10.0, limit as f64 / 3.0);
+        }
+    }
+
+    #[test]
+    fn test_weighted_shares() {
+        let limit = 300 * 1024;
+        let limiter = BandwidthLimiter::new(Some(limit), true);
+        let start = limiter.inner.lock().unwrap().last_refill;
+        limiter.join("a", 2, start);
+        limiter.join("b", 1, start);
+
+        let sent = simulate(&limiter, start, Duration::ZERO, Duration::from_secs(10), &["a", "b"]);
+        assert_rate(sent["a"], 10.0, limit as f64 * 2.0 / 3.0);
+        assert_rate(sent["b"], 10.0, limit as f64 / 3.0);
+    }
+
+    #[test]
+    fn test_unused_share_spills_over() {
+        let limit = 300 * 1024;
+        let limiter = BandwidthLimiter::new(Some(limit), true);
+        let start = limiter.inner.lock().unwrap().last_refill;
+        for id in ["a", "b", "c"] {
+            limiter.join(id, 1, start);
+        }
+        simulate(&limiter, start, Duration::ZERO, Duration::from_secs(5), &["a", "b", "c"]);
+
+        // c 停滞，1 秒内 a 和 b 分到它的份额
+        simulate(&limiter, start, Duration::from_secs(5), Duration::from_secs(6), &["a", "b"]);
+        assert!(limiter.allocated_rate("a").unwrap() > limit * 2 / 5);
+
+        let sent = simulate(&limiter, start, Duration::from_secs(6), Duration::from_secs(10), &["a", "b"]);
+        let expected = limit as f64 * (1.0 - 1.0 / 60.0) / 2.0;
+        assert_rate(sent["a"], 4.0, expected);
+        assert_rate(sent["b"], 4.0, expected);
+    }
+
+    #[test]
+    fn test_membership_change_rebalances() {
+        let limit = 300 * 1024;
+        let limiter = BandwidthLimiter::new(Some(limit), true);
+        let start = limiter.inner.lock().unwrap().last_refill;
+        limiter.join("a", 1, start);
+        assert_eq!(limiter.allocated_rate("a"), Some(limit));
+
+        limiter.join("b", 1, start + Duration::from_millis(100));
+        assert_eq!(limiter.allocated_rate("a"), Some(limit / 2));
+        assert_eq!(limiter.allocated_rate("b"), Some(limit / 2));
+
+        limiter.leave("b", start + Duration::from_millis(200));
+        assert_eq!(limiter.allocated_rate("a"), Some(limit));
+        assert_eq!(limiter.allocated_rate("b"), None);
+    }
+
+    #[test]
+    fn test_member_cap() {
+        let limiter = BandwidthLimiter::new(None, true);
+        let start = limiter.inner.lock().unwrap().last_refill;
+        limiter.join("a", 1, start);
+        limiter.join("b", 1, start);
+        limiter.set_member_limit("a", Some(100 * 1024));
+
+        // 没有总带宽限制时只有 a 受自己的上限限制
+        let sent = simulate(&limiter, start, Duration::ZERO, Duration::from_secs(10), &["a", "b"]);
+        assert_rate(sent["a"], 10.0, 100.0 * 1024.0);
+        assert_eq!(sent["b"], CHUNK * 1000);
+        assert_eq!(limiter.allocated_rate("a"), None);
+
+        // 自己的上限低于份额时，剩余的带宽分给 b
+        let limit = 300 * 1024;
+        limiter.set_limit(Some(limit));
+        assert_eq!(limiter.allocated_rate("a"), Some(100 * 1024));
+        assert_eq!(limiter.allocated_rate("b"), Some(200 * 1024));
+    }
+
+    #[test]
+    fn test_limit_changes_at_runtime() {
+        let limiter = BandwidthLimiter::new(Some(100 * 1024), false);
+        let start = limiter.inner.lock().unwrap().last_refill;
+        limiter.join("a", 1, start);
+        let sent = simulate(&limiter, start, Duration::ZERO, Duration::from_secs(10), &["a"]);
+        assert_rate(sent["a"], 10.0, 100.0 * 1024.0);
+
+        // 取消限制后每次都不需要等待
+        limiter.set_limit(None);
+        let now = Instant::now();
+        assert_eq!(limiter.limit(), None);
+        assert!((0..100).all(|_| limiter.try_take("a", CHUNK * 64, now).is_none()));
+    }
+}
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use crate::core::error::{UploadError, UploadResult};
+use crate::core::upload::Upload;
+
+/// 暂停后能够保留的进度粒度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum PauseGranularity {
+    /// 最多丢失指定字节数，之前的数据都保留
+    Bytes(u64),
+
+    /// 已经完成的请求保留，进行中的请求丢失
+    RequestBoundary,
+
+    /// 不能暂停，停止后只能重新开始
+    None,
+}
+
+/// 上传方式支持的功能，前端据此决定显示哪些操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    /// 中断后可以从服务端的进度继续
+    pub resumable: bool,
+    pub pause_granularity: PauseGranularity,
+
+    /// 服务端校验发送的数据
+    pub supports_checksum: bool,
+
+    /// 可以删除服务端的资源
+    pub supports_termination: bool,
+}
+
+impl Capabilities {
+    /// tus：按块发送，暂停时最多丢失正在发送的一块
+    pub fn tus(chunk_size: usize) -> Self {
+        Self {
+            resumable: true,
+            pause_granularity: PauseGranularity::Bytes(chunk_size as u64),
+            supports_checksum: false,
+            supports_termination: true,
+        }
+    }
+
+    /// upload 使用的上传方式支持的功能，目前只有 tus 一种实现
+    /// tus 总是可以用 HEAD 取得服务端的进度继续，中断的 upload 不需要特别处理
+    pub fn for_upload(upload: &Upload) -> Self {
+        Self::tus(upload.chunk_size)
+    }
+
+    /// 不能继续的 upload 返回 NotResumable
+    pub fn ensure_resumable(&self, id: &str) -> UploadResult<()> {
+        if !self.resumable {
+            return Err(UploadError::NotResumable(id.to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tus_capabilities() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let upload = Upload::new(file.path().to_path_buf(), 4096).unwrap();
+        assert_eq!(Capabilities::for_upload(&upload), Capabilities {
+            resumable: true,
+            pause_granularity: PauseGranularity::Bytes(4096),
+            supports_checksum: false,
+            supports_termination: true,
+        });
+        assert!(Capabilities::for_upload(&upload).ensure_resumable(&upload.id).is_ok());
+
+        let simple = Capabilities {
+            resumable: false,
+            pause_granularity: PauseGranularity::None,
+            supports_checksum: false,
+            supports_termination: false,
+        };
+        assert!(matches!(simple.ensure_resumable("a"), Err(UploadError::NotResumable(id)) if id == "a"));
+
+        let json = serde_json::to_value(Capabilities::tus(1024)).unwrap();
+        assert_eq!(json["pauseGranularity"], serde_json::json!({ "type": "bytes", "value": 1024 }));
+    }
+}
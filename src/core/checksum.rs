@@ -0,0 +1,63 @@
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha1::Digest;
+
+/// 服务端计算的校验值与 Upload-Checksum 不一致时返回的状态码
+pub const CHECKSUM_MISMATCH_STATUS: u16 = 460;
+
+/// 每块数据的校验算法，名称与 Tus-Checksum-Algorithm 中的一致
+/// 参考 Tus 协议文档：https://tus.io/protocols/resumable-upload#checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    Sha1,
+    Md5,
+    Crc32,
+}
+
+impl ChecksumAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha1 => "sha1",
+            ChecksumAlgorithm::Md5 => "md5",
+            ChecksumAlgorithm::Crc32 => "crc32",
+        }
+    }
+
+    /// crc32 按大端序输出 4 个字节
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            ChecksumAlgorithm::Sha1 => sha1::Sha1::digest(data).to_vec(),
+            ChecksumAlgorithm::Md5 => md5::Md5::digest(data).to_vec(),
+            ChecksumAlgorithm::Crc32 => crc32fast::hash(data).to_be_bytes().to_vec(),
+        }
+    }
+
+    /// Upload-Checksum 的值，例如 "sha1 Kq5sNclPz7QV2+lfQIuc6R7oRu0="
+    pub fn header_value(self, data: &[u8]) -> String {
+        format!("{} {}", self.name(), base64::engine::general_purpose::STANDARD.encode(self.digest(data)))
+    }
+
+    /// 服务端在 Tus-Checksum-Algorithm 中声明了这个算法
+    pub fn is_supported_by(self, algorithms: &[String]) -> bool {
+        algorithms.iter().any(|algorithm| algorithm.eq_ignore_ascii_case(self.name()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_values() {
+        let data = b"hello world";
+        assert_eq!(ChecksumAlgorithm::Sha1.header_value(data), "sha1 Kq5sNclPz7QV2+lfQIuc6R7oRu0=");
+        assert_eq!(ChecksumAlgorithm::Md5.header_value(data), "md5 XrY7u+Ae7tCTyyK7j1rNww==");
+        assert_eq!(ChecksumAlgorithm::Crc32.header_value(data), "crc32 DUoRhQ==");
+
+        let advertised = vec!["md5".to_string(), "SHA1".to_string()];
+        assert!(ChecksumAlgorithm::Sha1.is_supported_by(&advertised));
+        assert!(!ChecksumAlgorithm::Crc32.is_supported_by(&advertised));
+        assert_eq!(serde_json::to_string(&ChecksumAlgorithm::Crc32).unwrap(), "\"crc32\"");
+    }
+}
@@ -0,0 +1,49 @@
+//! 时间来源
+//!
+//! `UploadManager` 持有一个 `Clock`，并传给 worker、状态管理、状态缓存、连接探测等运行时组件，
+//! 退避、重试等待、备份间隔、状态变化时间和保留期限都从这里读取时间和等待。
+//! `Upload`、`Timeline`、`ClockSkew` 等核心类型提供接受时间参数的 `_at` 方法，
+//! 不带 `_at` 的方法使用系统时间，只用于不经过 manager 的调用和测试。生产环境使用 `SystemClock`；
+//! 测试中在 `#[tokio::test(start_paused = true)]` 下使用 `testing::TestClock`，
+//! 用 `advance` 推进时间，不需要真实的等待。
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::time::Instant;
+
+#[async_trait]
+pub trait Clock: Send + Sync + Debug {
+    /// 当前的墙上时间，用于记录和与服务端的时间比较
+    fn now_utc(&self) -> DateTime<Utc>;
+
+    /// 单调时间，用于计算间隔
+    fn now_instant(&self) -> Instant;
+
+    async fn sleep(&self, duration: Duration);
+}
+
+/// 系统时钟
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now_utc(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn now_instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+/// 默认使用的系统时钟
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
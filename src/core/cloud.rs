@@ -0,0 +1,217 @@
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::core::config::TusConfig;
+use crate::core::error::{UploadError, UploadResult};
+use crate::core::state::UploadStateManager;
+
+/// 留在原状态文件夹中、指向实际位置的文件
+pub const POINTER_FILE_NAME: &str = "upload-state.pointer";
+
+/// 常见同步服务的文件夹名称，按小写前缀匹配
+const CLOUD_DIR_NAMES: &[(&str, &str)] = &[
+    ("dropbox", "Dropbox"),
+    ("onedrive", "OneDrive"),
+    ("google drive", "Google Drive"),
+    ("googledrive", "Google Drive"),
+    ("icloud drive", "iCloud Drive"),
+    ("mobile documents", "iCloud Drive"),
+    ("cloudstorage", "CloudStorage"),
+    ("pcloud drive", "pCloud"),
+    ("nextcloud", "Nextcloud"),
+    ("owncloud", "ownCloud"),
+];
+
+/// 状态文件夹位于同步文件夹中时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CloudDirPolicy {
+    /// 继续使用，并发出警告
+    #[default]
+    Warn,
+
+    /// 把状态移到本地缓存文件夹，原位置留下指向它的文件
+    RelocateToLocal,
+
+    /// 拒绝启动
+    Deny,
+}
+
+/// 检测到的同步文件夹
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CloudDirCheck {
+    /// 同步服务名称
+    pub provider: String,
+
+    /// 配置中的状态文件夹
+    pub path: PathBuf,
+
+    /// 状态实际所在的本地文件夹，没有迁移时为空
+    pub relocated_to: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StateDirPointer {
+    state_dir: PathBuf,
+}
+
+/// 按路径判断是否位于同步文件夹中，返回同步服务名称
+pub fn detect_cloud_dir(path: &Path) -> Option<String> {
+    for component in path.components() {
+        let name = component.as_os_str().to_string_lossy().to_lowercase();
+        for (prefix, provider) in CLOUD_DIR_NAMES {
+            if name.starts_with(prefix) {
+                return Some(provider.to_string());
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    if has_cloud_attributes(path) {
+        return Some("Cloud Files".to_string());
+    }
+
+    None
+}
+
+/// OneDrive 等通过 Cloud Files API 同步的文件夹带有重解析点或按需下载属性
+#[cfg(windows)]
+fn has_cloud_attributes(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+    const FILE_ATTRIBUTE_RECALL_ON_OPEN: u32 = 0x40000;
+    const FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS: u32 = 0x400000;
+    const CLOUD_ATTRIBUTES: u32 =
+        FILE_ATTRIBUTE_REPARSE_POINT | FILE_ATTRIBUTE_RECALL_ON_OPEN | FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS;
+
+    path.ancestors()
+        .filter_map(|dir| std::fs::symlink_metadata(dir).ok())
+        .any(|metadata| metadata.file_attributes() & CLOUD_ATTRIBUTES != 0)
+}
+
+/// 没有配置 relocation_dir 时迁移到的本地文件夹
+pub fn default_relocation_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("uploader-rs")
+}
+
+/// 按 cloud_dir_policy 处理状态文件夹，返回实际使用的配置
+pub async fn resolve_state_dir(config: TusConfig) -> UploadResult<(TusConfig, Option<CloudDirCheck>)> {
+    resolve_state_dir_with(config, detect_cloud_dir).await
+}
+
+async fn resolve_state_dir_with(
+    mut config: TusConfig,
+    detect: impl Fn(&Path) -> Option<String>,
+) -> UploadResult<(TusConfig, Option<CloudDirCheck>)> {
+    let configured = config.state_dir.clone();
+
+    // 之前已经迁移过，不论当前策略都使用迁移后的位置，避免丢失状态
+    let pointer = configured.join(POINTER_FILE_NAME);
+    if pointer.exists() {
+        let content = tokio::fs::read_to_string(&pointer).await?;
+        let StateDirPointer { state_dir } = serde_json::from_str(&content)?;
+        config.state_dir = state_dir.clone();
+        let provider = detect(&configured).unwrap_or_default();
+        return Ok((config, Some(CloudDirCheck { provider, path: configured, relocated_to: Some(state_dir) })));
+    }
+
+    let Some(provider) = detect(&configured) else {
+        return Ok((config, None));
+    };
+
+    match config.cloud_dir_policy {
+        CloudDirPolicy::Warn => {
+            log::warn!("State dir {} is inside a {} folder, frequent writes may cause sync conflicts",
+                      configured.display(), provider);
+            Ok((config, Some(CloudDirCheck { provider, path: configured, relocated_to: None })))
+        }
+        CloudDirPolicy::Deny => Err(UploadError::Config(format!(
+            "State dir {} is inside a {} folder", configured.display(), provider
+        ))),
+        CloudDirPolicy::RelocateToLocal => {
+            let local = config.relocation_dir.clone().unwrap_or_else(default_relocation_dir);
+            tokio::fs::create_dir_all(&local).await?;
+            UploadStateManager::migrate_from(&configured, &local).await?;
+
+            tokio::fs::create_dir_all(&configured).await?;
+            let content = serde_json::to_string_pretty(&StateDirPointer { state_dir: local.clone() })?;
+            tokio::fs::write(&pointer, content).await?;
+            log::info!("Relocated state from {} folder {} to {}", provider, configured.display(), local.display());
+
+            config.state_dir = local.clone();
+            Ok((config, Some(CloudDirCheck { provider, path: configured, relocated_to: Some(local) })))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(state_dir: &Path, policy: CloudDirPolicy, relocation_dir: &Path) -> TusConfig {
+        TusConfig {
+            state_dir: state_dir.to_path_buf(),
+            cloud_dir_policy: policy,
+            relocation_dir: Some(relocation_dir.to_path_buf()),
+            ..TusConfig::new("http://127.0.0.1:6440/api/file/tus".to_string())
+        }
+    }
+
+    fn synced(_: &Path) -> Option<String> {
+        Some("Dropbox".to_string())
+    }
+
+    #[test]
+    fn test_detect_cloud_dir() {
+        assert_eq!(detect_cloud_dir(Path::new("/home/a/Dropbox/uploader")).as_deref(), Some("Dropbox"));
+        assert_eq!(detect_cloud_dir(Path::new("/c/Users/a/OneDrive - Contoso/Documents")).as_deref(), Some("OneDrive"));
+        assert_eq!(
+            detect_cloud_dir(Path::new("/Users/a/Library/Mobile Documents/com~apple~CloudDocs")).as_deref(),
+            Some("iCloud Drive")
+        );
+        assert_eq!(detect_cloud_dir(Path::new("/home/a/.cache/uploader-rs")), None);
+    }
+
+    #[tokio::test]
+    async fn test_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_dir = dir.path().join("synced");
+        let local = dir.path().join("local");
+
+        let (resolved, check) = resolve_state_dir_with(config(&state_dir, CloudDirPolicy::Warn, &local), synced)
+            .await.unwrap();
+        assert_eq!(resolved.state_dir, state_dir);
+        assert_eq!(check.unwrap().relocated_to, None);
+
+        let denied = resolve_state_dir_with(config(&state_dir, CloudDirPolicy::Deny, &local), synced).await;
+        assert!(matches!(denied, Err(UploadError::Config(_))));
+
+        let (resolved, check) = resolve_state_dir_with(config(&state_dir, CloudDirPolicy::Deny, &local), |_| None)
+            .await.unwrap();
+        assert_eq!(resolved.state_dir, state_dir);
+        assert!(check.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_relocation_pointer_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_dir = dir.path().join("synced");
+        let local = dir.path().join("local");
+        std::fs::create_dir_all(&state_dir).unwrap();
+        std::fs::write(state_dir.join("upload-state.json"), b"{}").unwrap();
+
+        let (resolved, check) = resolve_state_dir_with(config(&state_dir, CloudDirPolicy::RelocateToLocal, &local), synced)
+            .await.unwrap();
+        assert_eq!(resolved.state_dir, local);
+        assert_eq!(check.unwrap().relocated_to, Some(local.clone()));
+        assert_eq!(std::fs::read(local.join("upload-state.json")).unwrap(), b"{}");
+        assert!(!state_dir.join("upload-state.json").exists());
+        assert!(state_dir.join(POINTER_FILE_NAME).exists());
+
+        // 之后启动时按指向的位置使用，即使策略改为 Deny
+        let (resolved, _) = resolve_state_dir_with(config(&state_dir, CloudDirPolicy::Deny, &local), synced)
+            .await.unwrap();
+        assert_eq!(resolved.state_dir, local);
+    }
+}
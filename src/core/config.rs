@@ -2,7 +2,150 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use std::time::Duration;
 use serde::{Deserialize, Serialize};
+use crate::core::cloud::CloudDirPolicy;
+use crate::core::backup::BackupPolicy;
+use crate::core::location::{CrossOriginPolicy, DEFAULT_MAX_LOCATION_LEN};
+use crate::core::checksum::ChecksumAlgorithm;
+use crate::core::digest::HashAlgorithm;
 use crate::core::error::{UploadError, UploadResult};
+use crate::core::log_file::LogRotation;
+use crate::core::metadata::MetadataLimits;
+use crate::core::proxy::ProxyConfig;
+use crate::core::tls::{self, TlsConfig};
+use crate::core::upload::Upload;
+
+/// 一个命名的服务端配置，例如不同租户使用不同的地址和认证头
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EndpointProfile {
+    /// creation 地址
+    pub url: String,
+
+    /// 覆盖全局同名请求头，名称不区分大小写
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// 这个服务端最多同时上传的任务数，为空时只受全局 max_concurrent 限制
+    #[serde(default)]
+    pub max_concurrent_override: Option<usize>,
+
+    /// 覆盖全局的 tls_pins，空列表表示这个服务端不固定证书
+    #[serde(default)]
+    pub tls_pins: Option<Vec<String>>,
+}
+
+/// 拆分出的部分的文件名
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SplitNaming {
+    /// 在原文件名后加上 `.part01`、`.part02`……
+    #[default]
+    Suffix,
+
+    /// 使用原文件名，只通过元数据区分
+    Original,
+}
+
+/// 超过大小的文件拆分成多个 upload 上传，服务端根据元数据重新组合
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitPolicy {
+    /// 每一部分的最大字节数
+    pub part_size: u64,
+
+    #[serde(default)]
+    pub naming: SplitNaming,
+}
+
+/// 空闲时检查暂停的 upload 在服务端的资源是否还存在
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditPolicy {
+    /// 两次检查的间隔
+    pub interval: Duration,
+
+    /// 每个 HEAD 请求的超时时间
+    pub timeout: Duration,
+
+    /// 同时发送的 HEAD 请求数
+    pub concurrency: usize,
+
+    /// 资源不存在时丢弃进度，下次开始时重新创建
+    #[serde(default)]
+    pub reset_missing: bool,
+}
+
+impl Default for AuditPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(24 * 60 * 60),
+            timeout: Duration::from_secs(5),
+            concurrency: 2,
+            reset_missing: false,
+        }
+    }
+}
+
+/// 失败后自动重新排队，例如电脑睡眠导致一批 upload 失败
+/// 第 n 次自动重试前等待 base_delay * 2^(n-1)，超过 max_attempts 后等用户手动重试；不会重试致命错误和取消的 upload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AutoRetryPolicy {
+    /// 每个 upload 最多自动重试的次数，手动重试后清零
+    pub max_attempts: u32,
+
+    /// 第一次自动重试前的等待时间
+    pub base_delay: Duration,
+}
+
+/// 创建 upload 时是否在 POST 中同时发送第一块数据（creation-with-upload 扩展）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CreationWithUpload {
+    /// 服务端在 OPTIONS 中声明支持时使用
+    #[default]
+    Auto,
+
+    /// 不探测，总是使用
+    Always,
+
+    Never,
+}
+
+/// 读取源文件的方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReadStrategy {
+    /// 读到 buffer 中再发送
+    #[default]
+    Buffered,
+
+    /// 把每块映射到内存直接作为请求体，省去复制到 buffer，适合很大的文件
+    /// 映射失败时（例如网络驱动器）改为 Buffered
+    Mmap,
+}
+
+/// 状态文件中保留多少已完成 upload 的记录
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompletedRetention {
+    /// 最多保留的数量，超过时删除最早完成的
+    pub max_count: usize,
+
+    /// 完成超过这个时间的记录被删除，为空时不按时间删除
+    #[serde(default)]
+    pub max_age: Option<Duration>,
+}
+
+impl Default for CompletedRetention {
+    fn default() -> Self {
+        Self { max_count: 1000, max_age: None }
+    }
+}
+
+/// 自动清理已完成和已取消的 upload，在有 upload 结束时执行，两项都为空时不清理
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FinishedRetention {
+    /// 结束超过这个时间的记录被删除
+    #[serde(default)]
+    pub keep_finished_for: Option<Duration>,
+
+    /// 最多保留的数量，超过时删除最早结束的
+    #[serde(default)]
+    pub max_finished: Option<usize>,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TusConfig {
@@ -12,6 +155,10 @@ pub struct TusConfig {
     /// 额外的请求头参数
     pub headers: HashMap<String, String>,
 
+    /// 命名的服务端配置，upload 通过名称选择，未选择时使用 endpoint
+    #[serde(default)]
+    pub endpoints_by_name: HashMap<String, EndpointProfile>,
+
     /// 最大同时上传任务
     pub max_concurrent: usize,
 
@@ -21,17 +168,343 @@ pub struct TusConfig {
     /// 最大重试次数
     pub max_retries: u8,
 
-    /// 每次重试延迟
+    /// 第一次重试前的延迟，之后每次加倍
     pub retry_delay: Duration,
 
+    /// 重试延迟加倍的上限，实际等待时间在此基础上有 ±20% 的抖动
+    #[serde(default = "default_max_retry_delay")]
+    pub max_retry_delay: Duration,
+
     /// 保存路径文件夹
     pub state_dir: PathBuf,
 
     /// 读取文件的缓冲区大小
     pub buffer_size: usize,
+
+    /// 状态文件夹位于 Dropbox、OneDrive 等同步文件夹中时的处理方式
+    #[serde(default)]
+    pub cloud_dir_policy: CloudDirPolicy,
+
+    /// RelocateToLocal 时迁移到的本地文件夹，为空时使用系统缓存文件夹
+    #[serde(default)]
+    pub relocation_dir: Option<PathBuf>,
+
+    /// 读取任务最多提前读出的块数，发送一块的同时读取下一块
+    /// 为 0 时只使用一个 buffer，读取和发送交替进行，内存受限时使用
+    #[serde(default = "default_read_ahead")]
+    pub read_ahead: usize,
+
+    /// 读取源文件的方式
+    #[serde(default)]
+    pub read_strategy: ReadStrategy,
+
+    /// 上一次使用的状态文件夹，存在时启动会把其中的状态迁移到 state_dir
+    #[serde(default)]
+    pub previous_state_dir: Option<PathBuf>,
+
+    /// state_dir 中没有状态文件时，是否从旧版本的默认位置（文档文件夹）迁移
+    /// 默认关闭，避免新的或测试用的文件夹接管其他安装的状态
+    #[serde(default)]
+    pub migrate_legacy_state: bool,
+
+    /// 重试等待时间较长时，是否让出并发名额给其他任务
+    #[serde(default)]
+    pub yield_slot_during_backoff: bool,
+
+    /// 重试等待超过这个时间才会让出并发名额
+    #[serde(default = "default_yield_backoff_threshold")]
+    pub yield_backoff_threshold: Duration,
+
+    /// 严格模式：写入不一致的 upload 时直接报错，否则尽量修复后写入
+    #[serde(default)]
+    pub strict_invariants: bool,
+
+    /// 所有 upload 共享的带宽上限，字节/秒，为空时不限制
+    #[serde(default)]
+    pub bandwidth_limit: Option<u64>,
+
+    /// 按正在上传的 upload 平分带宽，否则先到先得
+    #[serde(default)]
+    pub fair_bandwidth: bool,
+
+    /// 添加 upload 时先为源文件创建快照，上传期间修改源文件不影响发送的数据
+    #[serde(default)]
+    pub snapshot_sources: bool,
+
+    /// 文件系统不支持克隆时，小于这个大小的文件改为复制，更大的文件直接上传
+    #[serde(default = "default_snapshot_copy_threshold")]
+    pub snapshot_copy_threshold: u64,
+
+    /// Upload-Metadata 的大小限制
+    #[serde(default)]
+    pub metadata_limits: MetadataLimits,
+
+    /// 大于 part_size 的文件拆分上传，为空时不拆分
+    #[serde(default)]
+    pub split_oversize: Option<SplitPolicy>,
+
+    /// 日志和历史文件的大小限制
+    #[serde(default)]
+    pub log_rotation: LogRotation,
+
+    /// 服务端已经创建资源后仍允许修改元数据
+    /// 下一次开始时重新创建资源以发送新的元数据，已上传的数据会被丢弃
+    #[serde(default)]
+    pub recreate_on_metadata_update: bool,
+
+    /// 服务端支持 termination 扩展，放弃的资源发送 DELETE 删除
+    #[serde(default)]
+    pub terminate_abandoned: bool,
+
+    /// 上传时顺便计算文件的摘要，为空时不计算
+    #[serde(default)]
+    pub hash_algorithm: Option<HashAlgorithm>,
+
+    /// 请求被强制门户拦截后探测服务端的间隔
+    #[serde(default = "default_captive_portal_probe_interval")]
+    pub captive_portal_probe_interval: Duration,
+
+    /// 等待状态变化守卫的最长时间，超时后按允许处理并发出 GuardFailed
+    #[serde(default = "default_guard_timeout")]
+    pub guard_timeout: Duration,
+
+    /// 状态文件的自动备份
+    #[serde(default)]
+    pub backup: BackupPolicy,
+
+    /// 已完成 upload 的记录保留多少
+    #[serde(default)]
+    pub completed_retention: CompletedRetention,
+
+    /// 已完成和已取消 upload 的自动清理
+    #[serde(default)]
+    pub finished_retention: FinishedRetention,
+
+    /// 固定的服务端证书公钥（base64 编码的 SPKI SHA-256），不为空时证书链中必须包含其中一个
+    #[serde(default)]
+    pub tls_pins: Vec<String>,
+
+    /// 接受的 Location 最大长度，超过时创建失败
+    #[serde(default = "default_max_location_len")]
+    pub max_location_len: usize,
+
+    /// Location 指向其他服务器时是否接受
+    #[serde(default)]
+    pub location_policy: CrossOriginPolicy,
+
+    /// 继续已经开始的 upload 时，剩余的数据使用当前的 chunk_size 而不是添加时的块大小
+    #[serde(default)]
+    pub rechunk_on_resume: bool,
+
+    /// 小文件可以在创建请求中直接完成，节省一次往返
+    #[serde(default)]
+    pub creation_with_upload: CreationWithUpload,
+
+    /// 每块数据附带的校验值，服务端没有声明支持这个算法时不发送
+    /// 服务端返回 460 时重新发送这一块，不计入网络错误的重试次数
+    #[serde(default)]
+    pub checksum_algorithm: Option<ChecksumAlgorithm>,
+
+    /// 定期检查服务端资源，为空时只在调用 audit_now 时检查
+    #[serde(default)]
+    pub audit: Option<AuditPolicy>,
+
+    /// 失败后自动重新排队，为空时只能手动重试
+    #[serde(default)]
+    pub auto_retry: Option<AutoRetryPolicy>,
+
+    /// 加载状态时发现上次被强制结束而中断的 upload，重新放回队列，否则暂停
+    #[serde(default)]
+    pub resume_on_startup: bool,
+
+    /// 代理拦截 PATCH 和 DELETE 时改用 POST 发送，通过 X-HTTP-Method-Override 指定真正的方法
+    /// 未开启时第一次 PATCH 返回 405 或连接被重置会自动尝试一次，成功后本次运行都使用这种方式
+    #[serde(default)]
+    pub use_method_override: bool,
+
+    /// 服务端返回 423（资源被之前的请求锁定）时最多等待的总时间，从 retry_delay 开始每次加倍
+    /// 超过后暂时让出，稍后重新调度，不标记为失败
+    #[serde(default = "default_lock_wait_budget")]
+    pub lock_wait_budget: Duration,
+
+    /// 每块之前都用 HEAD 确认服务端的偏移，用于已知会丢弃写入的服务端
+    /// 默认只在开始、失败之后和完成前确认，其余时候使用 PATCH 响应中的偏移
+    #[serde(default)]
+    pub head_before_each_chunk: bool,
+
+    /// 建立连接的超时，为 0 时不限制
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: Duration,
+
+    /// 请求的超时，带数据的请求再加上按 min_expected_throughput 发送数据需要的时间
+    /// 超时按网络错误重试，连接断开后不会一直停在没有速度的 Active 状态；为 0 时不限制
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout: Duration,
+
+    /// 预计的最低上传速度，字节/秒，用于计算带数据的请求的超时
+    #[serde(default = "default_min_expected_throughput")]
+    pub min_expected_throughput: u64,
+
+    /// 超过这个时间服务端没有确认新的数据时中断当前请求，重新确认偏移后重试，计入重试次数；为 0 时不检查
+    /// 应大于发送一块需要的时间，否则慢速但正常的连接也会被中断
+    #[serde(default = "default_stall_timeout")]
+    pub stall_timeout: Duration,
+
+    /// 上传请求使用的代理，为空时使用环境变量中的系统代理
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+
+    /// 额外的根证书等证书校验设置
+    #[serde(default)]
+    pub tls: TlsConfig,
+
+    /// 同一个 upload 的 Progress 事件的最小间隔，最后一块的进度总是发出；为 0 时每块都发出
+    #[serde(default = "default_progress_event_interval")]
+    pub progress_event_interval: Duration,
+
+    /// 发出 StatsUpdated 的间隔，为 0 时不发出，只能通过 get_stats 查询
+    #[serde(default = "default_stats_event_interval")]
+    pub stats_event_interval: Duration,
+}
+
+/// 配置中一个字段的问题，field 为字段路径，例如 `endpoints_by_name.eu.url`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldIssue {
+    pub field: String,
+    pub message: String,
 }
 
-fn default_state_dir() -> PathBuf {
+/// 错误中的说明，Config 错误不带前缀
+fn config_message(err: UploadError) -> String {
+    match err {
+        UploadError::Config(message) => message,
+        err => err.to_string(),
+    }
+}
+
+/// 设置界面提交的配置，未填写的字段使用默认值
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitConfig {
+    pub endpoint: String,
+
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    #[serde(default)]
+    pub chunk_size: Option<usize>,
+
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+
+    #[serde(default)]
+    pub max_retries: Option<u8>,
+
+    #[serde(default)]
+    pub state_dir: Option<PathBuf>,
+
+    /// 上一个版本使用的状态文件夹，其中的状态会迁移到 state_dir
+    #[serde(default)]
+    pub previous_state_dir: Option<PathBuf>,
+
+    /// 没有状态时从旧版本的默认位置迁移
+    #[serde(default)]
+    pub migrate_legacy_state: bool,
+
+    /// 同一个 upload 推送进度的最小间隔，毫秒
+    #[serde(default)]
+    pub progress_interval_ms: Option<u64>,
+
+    /// 推送汇总的间隔，毫秒，为 0 时不推送
+    #[serde(default)]
+    pub stats_interval_ms: Option<u64>,
+}
+
+impl InitConfig {
+    pub fn to_config(&self) -> TusConfig {
+        let mut config = TusConfig::new(self.endpoint.clone()).with_headers(self.headers.clone());
+        if let Some(chunk_size) = self.chunk_size {
+            config.chunk_size = chunk_size;
+            config.buffer_size = config.buffer_size.min(chunk_size.max(1));
+        }
+        if let Some(max_concurrent) = self.max_concurrent {
+            config.max_concurrent = max_concurrent;
+        }
+        if let Some(max_retries) = self.max_retries {
+            config.max_retries = max_retries;
+        }
+        if let Some(state_dir) = &self.state_dir {
+            config.state_dir = state_dir.clone();
+        }
+        config.previous_state_dir = self.previous_state_dir.clone();
+        config.migrate_legacy_state = self.migrate_legacy_state;
+        if let Some(interval) = self.progress_interval_ms {
+            config.progress_event_interval = Duration::from_millis(interval);
+        }
+        if let Some(interval) = self.stats_interval_ms {
+            config.stats_event_interval = Duration::from_millis(interval);
+        }
+        config
+    }
+}
+
+fn default_snapshot_copy_threshold() -> u64 {
+    256 * 1024 * 1024
+}
+
+fn default_max_location_len() -> usize {
+    DEFAULT_MAX_LOCATION_LEN
+}
+
+fn default_read_ahead() -> usize {
+    2
+}
+
+fn default_captive_portal_probe_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_guard_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_max_retry_delay() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_lock_wait_budget() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_connect_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_request_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_min_expected_throughput() -> u64 {
+    16 * 1024
+}
+
+fn default_progress_event_interval() -> Duration {
+    Duration::from_millis(250)
+}
+
+fn default_stats_event_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_stall_timeout() -> Duration {
+    Duration::from_secs(120)
+}
+
+fn default_yield_backoff_threshold() -> Duration {
+    Duration::from_secs(5)
+}
+
+pub(crate) fn default_state_dir() -> PathBuf {
     dirs::document_dir().unwrap_or_else(|| PathBuf::from("."))
 }
 
@@ -40,12 +513,58 @@ impl Default for TusConfig {
         Self {
             endpoint: String::new(),
             headers: HashMap::new(),
+            endpoints_by_name: HashMap::new(),
             max_concurrent: 3,
             chunk_size: 1024 * 1024 * 5,
             max_retries: 3,
             retry_delay: Duration::from_secs(1),
+            max_retry_delay: default_max_retry_delay(),
             state_dir: default_state_dir(),
             buffer_size: 1024 * 1024,
+            cloud_dir_policy: CloudDirPolicy::default(),
+            relocation_dir: None,
+            read_ahead: default_read_ahead(),
+            read_strategy: ReadStrategy::default(),
+            previous_state_dir: None,
+            migrate_legacy_state: false,
+            yield_slot_during_backoff: false,
+            yield_backoff_threshold: default_yield_backoff_threshold(),
+            strict_invariants: false,
+            resume_on_startup: false,
+            bandwidth_limit: None,
+            fair_bandwidth: false,
+            snapshot_sources: false,
+            snapshot_copy_threshold: default_snapshot_copy_threshold(),
+            metadata_limits: MetadataLimits::default(),
+            split_oversize: None,
+            log_rotation: LogRotation::default(),
+            recreate_on_metadata_update: false,
+            terminate_abandoned: false,
+            hash_algorithm: None,
+            captive_portal_probe_interval: default_captive_portal_probe_interval(),
+            guard_timeout: default_guard_timeout(),
+            backup: BackupPolicy::default(),
+            completed_retention: CompletedRetention::default(),
+            finished_retention: FinishedRetention::default(),
+            tls_pins: Vec::new(),
+            max_location_len: default_max_location_len(),
+            location_policy: CrossOriginPolicy::default(),
+            rechunk_on_resume: false,
+            creation_with_upload: CreationWithUpload::default(),
+            checksum_algorithm: None,
+            audit: None,
+            auto_retry: None,
+            use_method_override: false,
+            lock_wait_budget: default_lock_wait_budget(),
+            head_before_each_chunk: false,
+            connect_timeout: default_connect_timeout(),
+            request_timeout: default_request_timeout(),
+            min_expected_throughput: default_min_expected_throughput(),
+            stall_timeout: default_stall_timeout(),
+            proxy: None,
+            tls: TlsConfig::default(),
+            progress_event_interval: default_progress_event_interval(),
+            stats_event_interval: default_stats_event_interval(),
         }
     }
 }
@@ -59,40 +578,196 @@ impl TusConfig {
     }
 
     pub fn validate(&self) -> UploadResult<()> {
+        match self.field_errors().into_iter().next() {
+            Some(issue) => Err(UploadError::Config(issue.message)),
+            None => Ok(()),
+        }
+    }
+
+    /// 检查所有字段，返回全部错误，validate 只报告其中第一个
+    pub fn field_errors(&self) -> Vec<FieldIssue> {
+        let mut errors = Vec::new();
+        let mut error = |field: &str, message: String| errors.push(FieldIssue { field: field.to_string(), message });
+
         // Validate endpoint
         if self.endpoint.is_empty() {
-            return Err(UploadError::Config("Endpoint URL cannot be empty".into()));
-        }
-        if !self.endpoint.starts_with("http://") && !self.endpoint.starts_with("https://") {
-            return Err(UploadError::Config("Endpoint URL must start with http:// or https://".into()));
+            error("endpoint", "Endpoint URL cannot be empty".into());
+        } else if !self.endpoint.starts_with("http://") && !self.endpoint.starts_with("https://") {
+            error("endpoint", "Endpoint URL must start with http:// or https://".into());
         }
 
         // Validate concurrent uploads
         if self.max_concurrent == 0 {
-            return Err(UploadError::Config("Max concurrent uploads must be greater than 0".into()));
+            error("max_concurrent", "Max concurrent uploads must be greater than 0".into());
         }
 
         // Validate chunk size
         if self.chunk_size == 0 {
-            return Err(UploadError::Config("Chunk size must be greater than 0".into()));
+            error("chunk_size", "Chunk size must be greater than 0".into());
         }
         if self.chunk_size > 100 * 1024 * 1024 {
-            return Err(UploadError::Config("Chunk size cannot be larger than 100MB".into()));
+            error("chunk_size", "Chunk size cannot be larger than 100MB".into());
         }
 
         // Validate buffer size
         if self.buffer_size == 0 {
-            return Err(UploadError::Config("Buffer size must be greater than 0".into()));
+            error("buffer_size", "Buffer size must be greater than 0".into());
+        } else if self.chunk_size > 0 && self.buffer_size > self.chunk_size {
+            error("buffer_size", "Buffer size cannot be larger than chunk size".into());
+        }
+
+        if let Err(err) = tls::validate_pins(&self.tls_pins) {
+            error("tls_pins", config_message(err));
+        }
+
+        if let Err(err) = self.tls.validate(&self.tls_pins) {
+            error("tls", config_message(err));
+        }
+
+        if let Some(Err(err)) = self.proxy.as_ref().map(ProxyConfig::validate) {
+            error("proxy", config_message(err));
+        }
+
+        for (name, profile) in &self.endpoints_by_name {
+            let field = |key: &str| format!("endpoints_by_name.{}.{}", name, key);
+            if !profile.url.starts_with("http://") && !profile.url.starts_with("https://") {
+                error(&field("url"), format!("Endpoint URL of profile {} must start with http:// or https://", name));
+            }
+            if profile.max_concurrent_override == Some(0) {
+                error(
+                    &field("max_concurrent_override"),
+                    format!("Max concurrent uploads of profile {} must be greater than 0", name),
+                );
+            }
+            if let Some(Err(err)) = profile.tls_pins.as_deref().map(tls::validate_pins) {
+                error(&field("tls_pins"), config_message(err));
+            }
+        }
+
+        if let Some(split) = &self.split_oversize {
+            if split.part_size == 0 {
+                error("split_oversize.part_size", "Split part size must be greater than 0".into());
+            }
+        }
+
+        if self.metadata_limits.max_entry_size == 0 || self.metadata_limits.max_total_size == 0 {
+            error("metadata_limits", "Metadata size limits must be greater than 0".into());
+        }
+
+        if self.log_rotation.max_size == 0 {
+            error("log_rotation.max_size", "Log max size must be greater than 0".into());
+        }
+
+        if self.bandwidth_limit == Some(0) {
+            error("bandwidth_limit", "Bandwidth limit must be greater than 0".into());
+        }
+
+        if self.backup.interval.is_zero() {
+            error("backup.interval", "Backup interval must be greater than 0".into());
+        }
+
+        if let Some(audit) = &self.audit {
+            if audit.interval.is_zero() {
+                error("audit.interval", "Audit interval must be greater than 0".into());
+            }
+            if audit.timeout.is_zero() {
+                error("audit.timeout", "Audit timeout must be greater than 0".into());
+            }
+            if audit.concurrency == 0 {
+                error("audit.concurrency", "Audit concurrency must be greater than 0".into());
+            }
         }
-        if self.buffer_size > self.chunk_size {
-            return Err(UploadError::Config("Buffer size cannot be larger than chunk size".into()));
+
+        if self.auto_retry.is_some_and(|policy| policy.base_delay.is_zero()) {
+            error("auto_retry.base_delay", "Auto retry delay must be greater than 0".into());
+        }
+
+        if self.finished_retention.keep_finished_for.is_some_and(|age| age.is_zero()) {
+            error("finished_retention.keep_finished_for", "Finished upload retention must be greater than 0".into());
+        }
+
+        if self.max_location_len == 0 {
+            error("max_location_len", "Maximum location length must be greater than 0".into());
+        }
+
+        if self.min_expected_throughput == 0 {
+            error("min_expected_throughput", "Minimum expected throughput must be greater than 0".into());
         }
 
-        Ok(())
+        errors
+    }
+
+    /// 发送 len 字节数据的请求的超时，大块在慢速网络上不会被误判为卡住；不限制时返回 None
+    pub fn transfer_timeout(&self, len: usize) -> Option<Duration> {
+        if self.request_timeout.is_zero() {
+            return None;
+        }
+        let sending = Duration::from_secs_f64(len as f64 / self.min_expected_throughput.max(1) as f64);
+        Some(self.request_timeout.saturating_add(sending))
     }
 
     pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
         self.headers.extend(headers);
         self
     }
+
+    /// upload 使用的配置：所属服务端的配置加上 upload 自己的请求头，名称相同（不区分大小写）时 upload 的优先
+    pub fn for_upload(&self, upload: &Upload) -> UploadResult<TusConfig> {
+        let mut config = self.for_profile(upload.endpoint.as_deref())?;
+        for (name, value) in upload.headers.iter().chain(&upload.secret_headers) {
+            config.headers.retain(|existing, _| !existing.eq_ignore_ascii_case(name));
+            config.headers.insert(name.clone(), value.clone());
+        }
+        Ok(config)
+    }
+
+    /// 使用命名的服务端配置，返回替换了地址并合并了请求头的配置
+    pub fn for_profile(&self, name: Option<&str>) -> UploadResult<TusConfig> {
+        let Some(name) = name else {
+            return Ok(self.clone());
+        };
+        let profile = self.endpoints_by_name.get(name)
+            .ok_or_else(|| UploadError::Config(format!("Unknown endpoint profile: {}", name)))?;
+
+        let mut config = self.clone();
+        config.endpoint = profile.url.clone();
+        for (name, value) in &profile.headers {
+            config.headers.retain(|existing, _| !existing.eq_ignore_ascii_case(name));
+            config.headers.insert(name.clone(), value.clone());
+        }
+        if let Some(pins) = &profile.tls_pins {
+            config.tls_pins = pins.clone();
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_headers_override_global() {
+        let mut config = TusConfig::new("https://upload.example.com/files".to_string());
+        config.headers.insert("Authorization".to_string(), "Bearer global".to_string());
+        config.headers.insert("X-Client".to_string(), "app".to_string());
+        let mut headers = HashMap::new();
+        headers.insert("authorization".to_string(), "Bearer tenant".to_string());
+        config.endpoints_by_name.insert("tenant".to_string(), EndpointProfile {
+            url: "https://tenant.example.com/files".to_string(),
+            headers,
+            ..Default::default()
+        });
+
+        // 同名但大小写不同的全局请求头不能发送到租户的服务端
+        let tenant = config.for_profile(Some("tenant")).unwrap();
+        assert_eq!(tenant.endpoint, "https://tenant.example.com/files");
+        let authorization: Vec<_> = tenant.headers.iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+            .map(|(_, value)| value.as_str())
+            .collect();
+        assert_eq!(authorization, ["Bearer tenant"]);
+        assert_eq!(tenant.headers["X-Client"], "app");
+        assert!(matches!(config.for_profile(Some("missing")), Err(UploadError::Config(_))));
+    }
 }
@@ -0,0 +1,216 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use sha2::digest::generic_array::GenericArray;
+use crate::core::error::{UploadError, UploadResult};
+
+/// 上传时计算的摘要算法，只支持能够保存中间状态的算法
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+}
+
+/// SHA-256 的初始状态
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// 可以序列化中间状态的 SHA-256，重启后从保存的位置继续计算
+/// sha2 的 Sha256 不能导出中间状态，这里只保存状态和不足一块的输入，压缩函数使用 sha2 的实现
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sha256 {
+    state: [u32; 8],
+
+    /// 已经输入的字节数
+    len: u64,
+
+    /// 不足一个 64 字节块的剩余输入
+    buffer: Vec<u8>,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self { state: INITIAL_STATE, len: 0, buffer: Vec::with_capacity(64) }
+    }
+}
+
+impl Sha256 {
+    pub fn processed(&self) -> u64 {
+        self.len
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+
+        if !self.buffer.is_empty() {
+            let take = (64 - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() < 64 {
+                return;
+            }
+            let block: [u8; 64] = self.buffer[..].try_into().unwrap();
+            self.compress(&block);
+            self.buffer.clear();
+        }
+
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            self.compress(block.try_into().unwrap());
+        }
+        self.buffer.extend_from_slice(blocks.remainder());
+    }
+
+    /// 结束计算，返回小写十六进制的摘要
+    pub fn finalize(self) -> String {
+        self.finalize_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// 结束计算，返回 32 字节的摘要
+    pub fn finalize_bytes(mut self) -> [u8; 32] {
+        let bit_len = self.len.wrapping_mul(8);
+        let mut tail = std::mem::take(&mut self.buffer);
+        tail.push(0x80);
+        while tail.len() % 64 != 56 {
+            tail.push(0);
+        }
+        tail.extend_from_slice(&bit_len.to_be_bytes());
+        for block in tail.chunks_exact(64) {
+            self.compress(block.try_into().unwrap());
+        }
+
+        let mut digest = [0u8; 32];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        sha2::compress256(&mut self.state, &[GenericArray::clone_from_slice(block)]);
+    }
+}
+
+/// upload 的摘要，上传过程中保存中间状态，完成后保存结果
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadDigest {
+    pub algorithm: HashAlgorithm,
+
+    /// 已经确认上传的数据的中间状态，完成后为空
+    #[serde(default)]
+    pub hasher: Option<Sha256>,
+
+    /// 完成后的摘要
+    #[serde(default)]
+    pub digest: Option<String>,
+}
+
+impl UploadDigest {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        Self { algorithm, hasher: Some(Sha256::default()), digest: None }
+    }
+
+    /// 中间状态覆盖到的字节数
+    pub fn offset(&self) -> Option<u64> {
+        self.hasher.as_ref().map(Sha256::processed)
+    }
+
+    /// 输入从 offset 开始的数据，与中间状态不衔接时忽略
+    pub fn update(&mut self, offset: u64, data: &[u8]) {
+        if let Some(hasher) = self.hasher.as_mut().filter(|hasher| hasher.processed() == offset) {
+            hasher.update(data);
+        }
+    }
+
+    pub fn finalize(&mut self) {
+        if let Some(hasher) = self.hasher.take() {
+            self.digest = Some(hasher.finalize());
+        }
+    }
+}
+
+/// 从文件中读取 [start + hasher.processed(), start + end) 补齐中间状态
+/// 中间状态超过 end（服务端的偏移回退）时从头重新计算
+pub fn catch_up(path: &Path, start: u64, end: u64, hasher: &mut Sha256) -> UploadResult<()> {
+    if hasher.processed() > end {
+        *hasher = Sha256::default();
+    }
+    if hasher.processed() == end {
+        return Ok(());
+    }
+
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(start + hasher.processed()))?;
+    let mut remaining = end - hasher.processed();
+    let mut buffer = vec![0u8; 64 * 1024];
+    while remaining > 0 {
+        let len = (buffer.len() as u64).min(remaining) as usize;
+        let read = file.read(&mut buffer[..len])?;
+        if read == 0 {
+            return Err(UploadError::IncompleteUpload { expected: end, actual: end - remaining });
+        }
+        hasher.update(&buffer[..read]);
+        remaining -= read as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256(data: &[u8]) -> String {
+        let mut hasher = Sha256::default();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    #[test]
+    fn test_known_digests() {
+        assert_eq!(sha256(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            sha256(&vec![b'a'; 1_000_000]),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn test_matches_sha2() {
+        use sha2::Digest;
+
+        let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        for len in [0, 55, 56, 63, 64, 65, 1000] {
+            let expected: String = sha2::Sha256::digest(&data[..len]).iter().map(|byte| format!("{:02x}", byte)).collect();
+            assert_eq!(sha256(&data[..len]), expected, "len {}", len);
+        }
+    }
+
+    #[test]
+    fn test_midstate_survives_serialization() {
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        let mut hasher = Sha256::default();
+        hasher.update(&data[..4099]);
+
+        let json = serde_json::to_string(&hasher).unwrap();
+        let mut restored: Sha256 = serde_json::from_str(&json).unwrap();
+        restored.update(&data[4099..]);
+        assert_eq!(restored.finalize(), sha256(&data));
+
+        // 从文件补齐，以及偏移回退时重新计算
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, &data).unwrap();
+        let mut hasher = Sha256::default();
+        hasher.update(&data[100..300]);
+        catch_up(file.path(), 100, 5000, &mut hasher).unwrap();
+        assert_eq!(hasher.processed(), 5000);
+        catch_up(file.path(), 100, 1000, &mut hasher).unwrap();
+        assert_eq!(hasher.finalize(), sha256(&data[100..1100]));
+    }
+}
@@ -1,4 +1,6 @@
+use std::time::Duration;
 use thiserror::Error;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Error)]
 pub enum UploadError {
@@ -6,11 +8,27 @@ pub enum UploadError {
     IOError(#[from] tokio::io::Error),
 
     #[error("Network error: {0}")]
-    NetworkError(#[from] reqwest::Error),
+    NetworkError(#[source] reqwest::Error),
+
+    /// 无法连接到代理或者代理拒绝建立连接，与服务端不可用区分
+    #[error("Proxy connection failed ({proxy}): {source}")]
+    ProxyFailed {
+        proxy: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    #[error("TLS certificate of {host} does not match any pinned key")]
+    TlsPinMismatch {
+        host: String,
+    },
 
     #[error("Configuration error: {0}")]
     Config(String),
 
+    #[error("Invalid upload options: {0}")]
+    InvalidOptions(String),
+
     #[error("Failed to serialize/deserialize: {0}")]
     SerdeError(#[from] serde_json::Error),
 
@@ -20,11 +38,308 @@ pub enum UploadError {
     #[error("Invalid state transition: {0}")]
     InvalidState(String),
 
+    #[error("Invariant violation: {0}")]
+    InvariantViolation(String),
+
+    #[error("Upload id already exists: {0}")]
+    DuplicateUploadId(String),
+
+    #[error("Metadata of upload {0} was already sent to the server")]
+    MetadataFrozen(String),
+
+    #[error("Metadata {key} is {size} bytes encoded, limit is {limit}")]
+    MetadataTooLarge {
+        key: String,
+        size: usize,
+        limit: usize,
+    },
+
+    #[error("Upload {0} cannot be paused or resumed")]
+    NotResumable(String),
+
+    #[error("Endpoint {url} returned {content_type} instead of a tus response, the network may require sign-in")]
+    EndpointIntercepted {
+        url: String,
+        content_type: String,
+    },
+
+    #[error("Location header is {length} bytes, limit is {limit}")]
+    LocationTooLong {
+        length: usize,
+        limit: usize,
+    },
+
+    #[error("Invalid location: {0}")]
+    InvalidLocation(String),
+
+    #[error("Location points to {origin}, expected {expected}")]
+    LocationOriginMismatch {
+        origin: String,
+        expected: String,
+    },
+
+    #[error("Redirect from {from} to {to} would downgrade to plain http")]
+    RedirectDowngrade {
+        from: String,
+        to: String,
+    },
+
+    #[error("Redirect loop at {url}")]
+    RedirectLoop {
+        url: String,
+    },
+
+    #[error("Stopped after {limit} redirects")]
+    TooManyRedirects {
+        limit: usize,
+    },
+
+    #[error("Scheduler is already running")]
+    SchedulerAlreadyRunning,
+
+    #[error("Backup not found: {0}")]
+    BackupNotFound(String),
+
     #[error("Invalid header name")]
     InvalidHeaderName(#[from] reqwest::header::InvalidHeaderName),
 
+    #[error("HTTP error {status}: {body}")]
+    Http {
+        status: u16,
+        body: String,
+    },
+
+    /// 服务端拒绝了请求（4xx），重试也不会成功
+    #[error("Server rejected the request with status {status}: {body}")]
+    ServerError {
+        status: u16,
+        body: String,
+    },
+
+    #[error("File is {size} bytes, server accepts at most {limit}")]
+    FileTooLarge {
+        size: u64,
+        limit: u64,
+    },
+
+    #[error("Server rejected the checksum of the chunk at offset {offset}")]
+    ChecksumMismatch {
+        offset: u64,
+    },
+
+    #[error("Server offset does not match the chunk sent at offset {offset}")]
+    OffsetConflict {
+        offset: u64,
+    },
+
+    #[error("Server offset {actual} is beyond the {expected} bytes sent, local file and server have diverged")]
+    OffsetDiverged {
+        expected: u64,
+        actual: u64,
+    },
+
+    #[error("Server does not support tus {client}, it supports {}", server.join(", "))]
+    VersionMismatch {
+        client: String,
+        server: Vec<String>,
+    },
+
+    #[error("Upload is still locked by another request after waiting {waited:?}")]
+    UploadLocked {
+        waited: Duration,
+    },
+
+    #[error("Source file {path} changed since it was added, restart the upload to send the new content")]
+    FileChanged {
+        path: String,
+    },
+
+    #[error("No data was confirmed by the server for {timeout:?}")]
+    Stalled {
+        timeout: Duration,
+    },
+
+    #[error("Source file {path} no longer exists or cannot be read")]
+    SourceMissing {
+        path: String,
+    },
+
+    #[error("{path} is not a regular file")]
+    NotAFile {
+        path: String,
+    },
+
+    /// 之前的会话中已经用完了重试次数
+    #[error("Upload {id} failed {attempts} times, retry it manually")]
+    RetriesExhausted {
+        id: String,
+        attempts: u32,
+    },
+
+    #[error("Incomplete upload: expected {expected} bytes, server has {actual}")]
+    IncompleteUpload {
+        expected: u64,
+        actual: u64,
+    },
+
     #[error("Invalid header value")]
     InvalidHeaderValue(#[from] reqwest::header::InvalidHeaderValue),
+
+    /// 程序自身的错误，例如 worker panic
+    #[error("Internal error: {0}")]
+    Internal(String),
 }
 
 pub type UploadResult<T> = Result<T, UploadError>;
+
+impl From<reqwest::Error> for UploadError {
+    fn from(err: reqwest::Error) -> Self {
+        match crate::core::tls::pin_mismatch(&err) {
+            Some(host) => UploadError::TlsPinMismatch { host },
+            None => UploadError::NetworkError(err),
+        }
+    }
+}
+
+impl UploadError {
+    /// 重试也不会成功的错误
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            // 请求头太大，重试只会得到同样的结果
+            UploadError::Http { status: 431, .. } => true,
+            UploadError::MetadataTooLarge { .. } => true,
+            UploadError::FileTooLarge { .. } => true,
+            // 网络被强制门户拦截，登录前重试没有意义
+            UploadError::EndpointIntercepted { .. } => true,
+            // 证书不符时重试可能把文件发给中间人
+            UploadError::TlsPinMismatch { .. } => true,
+            // 服务端返回的地址不可用，重新创建很可能得到同样的地址
+            UploadError::LocationTooLong { .. }
+            | UploadError::InvalidLocation(_)
+            | UploadError::LocationOriginMismatch { .. } => true,
+            // 服务端的重定向配置有误，需要修改配置后重新开始
+            UploadError::RedirectDowngrade { .. }
+            | UploadError::RedirectLoop { .. }
+            | UploadError::TooManyRedirects { .. } => true,
+            UploadError::ServerError { .. } => true,
+            // 协议版本不同，需要更新客户端或服务端
+            UploadError::VersionMismatch { .. } => true,
+            // 服务端保存了没有发送过的数据，继续上传只会得到损坏的文件
+            UploadError::OffsetDiverged { .. } => true,
+            // 新的内容接在旧的偏移后面会得到损坏的文件，需要用户确认后重新开始
+            UploadError::FileChanged { .. } => true,
+            // 文件被删除、移走或者没有权限，用户处理之前重试没有意义
+            UploadError::SourceMissing { .. } => true,
+            UploadError::RetriesExhausted { .. } => true,
+            UploadError::NotAFile { .. } => true,
+            // 重试很可能再次触发同样的问题
+            UploadError::Internal(_) => true,
+            _ => false,
+        }
+    }
+
+    /// 发送数据失败后是否值得重试：网络错误、超时、5xx、409、423 和 429 可以重试，其他 4xx 不行
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            UploadError::Http { status, .. } => is_retryable_status(*status),
+            err => !err.is_fatal(),
+        }
+    }
+
+    /// 不能重试的 HTTP 错误转为 ServerError，保留状态码和响应体
+    pub fn into_permanent(self) -> Self {
+        match self {
+            UploadError::Http { status, body } if !is_retryable_status(status) => {
+                UploadError::ServerError { status, body }
+            }
+            err => err,
+        }
+    }
+
+    /// 由服务端的响应引起的错误的状态码
+    pub fn http_status(&self) -> Option<u16> {
+        match self {
+            UploadError::Http { status, .. } | UploadError::ServerError { status, .. } => Some(*status),
+            UploadError::NetworkError(err) => err.status().map(|status| status.as_u16()),
+            _ => None,
+        }
+    }
+
+    /// 稳定的错误代码，前端按它区分错误类型
+    pub fn code(&self) -> &'static str {
+        match self {
+            UploadError::IOError(_) => "io",
+            UploadError::NetworkError(_) => "network",
+            UploadError::ProxyFailed { .. } => "proxy",
+            UploadError::TlsPinMismatch { .. } => "tls_pin_mismatch",
+            UploadError::Config(_) => "config",
+            UploadError::InvalidOptions(_) => "invalid_options",
+            UploadError::SerdeError(_) => "serde",
+            UploadError::UploadNotFound(_) => "upload_not_found",
+            UploadError::InvalidState(_) => "invalid_state",
+            UploadError::InvariantViolation(_) => "invariant_violation",
+            UploadError::DuplicateUploadId(_) => "duplicate_upload_id",
+            UploadError::MetadataFrozen(_) => "metadata_frozen",
+            UploadError::MetadataTooLarge { .. } => "metadata_too_large",
+            UploadError::NotResumable(_) => "not_resumable",
+            UploadError::LocationTooLong { .. } => "location_too_long",
+            UploadError::InvalidLocation(_) => "invalid_location",
+            UploadError::LocationOriginMismatch { .. } => "location_origin_mismatch",
+            UploadError::RedirectDowngrade { .. } => "redirect_downgrade",
+            UploadError::RedirectLoop { .. } => "redirect_loop",
+            UploadError::TooManyRedirects { .. } => "too_many_redirects",
+            UploadError::SchedulerAlreadyRunning => "scheduler_already_running",
+            UploadError::BackupNotFound(_) => "backup_not_found",
+            UploadError::EndpointIntercepted { .. } => "endpoint_intercepted",
+            UploadError::InvalidHeaderName(_) | UploadError::InvalidHeaderValue(_) => "invalid_header",
+            UploadError::Http { .. } => "http",
+            UploadError::ServerError { .. } => "server_error",
+            UploadError::IncompleteUpload { .. } => "incomplete_upload",
+            UploadError::FileChanged { .. } => "file_changed",
+            UploadError::SourceMissing { .. } => "source_missing",
+            UploadError::RetriesExhausted { .. } => "retries_exhausted",
+            UploadError::NotAFile { .. } => "not_a_file",
+            UploadError::Stalled { .. } => "stalled",
+            UploadError::ChecksumMismatch { .. } => "checksum_mismatch",
+            UploadError::OffsetConflict { .. } => "offset_conflict",
+            UploadError::OffsetDiverged { .. } => "offset_diverged",
+            UploadError::VersionMismatch { .. } => "version_mismatch",
+            UploadError::UploadLocked { .. } => "upload_locked",
+            UploadError::FileTooLarge { .. } => "file_too_large",
+            UploadError::Internal(_) => "internal",
+        }
+    }
+}
+
+fn is_retryable_status(status: u16) -> bool {
+    status >= 500 || matches!(status, 409 | 423 | 429)
+}
+
+/// 返回给调用方的错误
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorDto {
+    pub code: String,
+    pub message: String,
+
+    /// 服务端返回的 HTTP 状态码
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+}
+
+impl ErrorDto {
+    pub fn new(code: &str, message: impl Into<String>) -> Self {
+        Self { code: code.to_string(), message: message.into(), status: None }
+    }
+}
+
+impl From<&UploadError> for ErrorDto {
+    fn from(err: &UploadError) -> Self {
+        Self { status: err.http_status(), ..Self::new(err.code(), err.to_string()) }
+    }
+}
+
+impl From<UploadError> for ErrorDto {
+    fn from(err: UploadError) -> Self {
+        Self::from(&err)
+    }
+}
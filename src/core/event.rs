@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
+use crate::core::error::ErrorDto;
+use crate::core::speed::Speed;
+
+/// 事件通道的容量，订阅方处理太慢时会丢失较早的事件
+const EVENT_CAPACITY: usize = 256;
+
+/// 进度减少的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CorrectionReason {
+    /// 服务端的偏移小于本地记录的进度
+    ServerOffset,
+
+    /// 发送失败，撤回已经计入的进度
+    ChunkRollback,
+
+    /// 服务端资源重新创建，进度从 0 开始
+    Recreated,
+
+    /// 检查发现服务端的资源已经不存在，进度从 0 开始
+    RemoteMissing,
+
+    /// 恢复时服务端的资源已经过期，自动重新创建，进度从 0 开始
+    Expired,
+}
+
+/// 整个队列的活动状态，用于托盘图标等汇总显示
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ActivityState {
+    /// 没有需要处理的 upload
+    #[default]
+    Idle,
+
+    /// 有正在上传、排队或等待重试的 upload
+    Working,
+
+    /// 有还没有确认的失败，优先于 Working
+    Attention,
+}
+
+/// 所有 upload 的汇总，用于底部状态栏等显示
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TransferStats {
+    /// 正在上传的数量
+    pub active: usize,
+
+    /// 排队和等待重试的数量
+    pub pending: usize,
+
+    pub failed: usize,
+    pub completed: usize,
+
+    /// 除已取消之外所有 upload 的大小之和，长度未确定的按 0 计算
+    pub total_bytes: u64,
+
+    /// 除已取消之外所有 upload 已确认的字节数之和
+    pub transferred_bytes: u64,
+
+    /// 最近几秒内服务端确认数据的速度
+    pub aggregate_speed: Speed,
+
+    /// 按平滑后的速度传完正在上传和排队的 upload 需要的秒数，没有在上传或有长度未确定的 upload 时为空
+    pub eta_seconds: Option<u64>,
+}
+
+/// 对外通知的 upload 事件
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum UploadEvent {
+    /// 元数据被修改，携带修改后的完整元数据
+    MetadataUpdated {
+        id: String,
+        metadata: HashMap<String, String>,
+    },
+
+    /// 元数据超过大小限制，值被截断，大小为截断前后值的字节数
+    MetadataTruncated {
+        id: String,
+        key: String,
+        original_size: usize,
+        truncated_size: usize,
+    },
+
+    /// 加载状态时发现重复的 id，较旧的一份被移到了冲突列表
+    IdConflict {
+        id: String,
+    },
+
+    /// 添加到队列
+    Added {
+        id: String,
+        at: DateTime<Utc>,
+    },
+
+    /// 开始上传，还没有服务端资源
+    Started {
+        id: String,
+        at: DateTime<Utc>,
+    },
+
+    /// 从服务端资源的偏移继续上传，例如暂停、重试或重启之后
+    Resumed {
+        id: String,
+        at: DateTime<Utc>,
+    },
+
+    /// 服务端确认了新的数据，按 progress_event_interval 限制频率，total_bytes 在长度未确定时为 0
+    Progress {
+        id: String,
+        bytes_transferred: u64,
+        total_bytes: u64,
+        speed: Speed,
+        at: DateTime<Utc>,
+    },
+
+    /// 用户暂停
+    Paused {
+        id: String,
+        at: DateTime<Utc>,
+    },
+
+    Completed {
+        id: String,
+        location: Option<String>,
+        at: DateTime<Utc>,
+    },
+
+    /// 失败且不再自动重试
+    Failed {
+        id: String,
+        error: ErrorDto,
+        at: DateTime<Utc>,
+    },
+
+    /// 被取消，取消分组时同时从列表中删除
+    Cancelled {
+        id: String,
+        at: DateTime<Utc>,
+    },
+
+    /// 从列表中删除
+    Removed {
+        id: String,
+        at: DateTime<Utc>,
+    },
+
+    /// 进度减少，之后显示的进度从 new 开始
+    ProgressCorrected {
+        id: String,
+        old: u64,
+        new: u64,
+        reason: CorrectionReason,
+    },
+
+    /// 连接停滞，超过 stall_timeout 没有确认新的数据，正在从服务端的偏移 offset 重试
+    Stalled {
+        id: String,
+        offset: u64,
+    },
+
+    /// 源文件被替换，进度从 0 开始
+    SourceReplaced {
+        id: String,
+        old_path: PathBuf,
+        new_path: PathBuf,
+        total_bytes: u64,
+    },
+
+    /// 拆分上传的所有部分都已完成，locations 按部分的顺序排列
+    GroupCompleted {
+        id: String,
+        locations: Vec<String>,
+    },
+
+    /// 请求被强制门户拦截，相关的 upload 已停止，登录后自动恢复
+    CaptivePortalSuspected {
+        endpoint: String,
+    },
+
+    /// 探测到真正的 tus 响应，released 个 upload 重新排队
+    CaptivePortalCleared {
+        released: usize,
+    },
+
+    /// 状态变化守卫 panic 或超时，这次状态变化已按允许处理
+    GuardFailed {
+        id: String,
+        reason: String,
+    },
+
+    /// 服务端的证书与固定的公钥不符，连接可能被拦截，upload 已失败且不会重试
+    TlsPinMismatch {
+        id: String,
+        host: String,
+    },
+
+    /// 服务端资源检查结束，missing 个资源已经不存在，其中 reset 个丢弃了进度
+    /// interrupted 表示因为开始上传或网络断开提前结束
+    AuditCompleted {
+        checked: usize,
+        missing: usize,
+        reset: usize,
+        interrupted: bool,
+    },
+
+    /// 队列的活动状态发生变化，只在变化时发出
+    ActivityChanged {
+        state: ActivityState,
+    },
+
+    /// 按 stats_event_interval 定期发出的汇总，和上一次相同时不发出
+    StatsUpdated {
+        stats: TransferStats,
+    },
+
+    /// 状态文件夹位于同步文件夹中，relocated_to 为空时仍在原位置写入
+    StateDirSynced {
+        provider: String,
+        path: PathBuf,
+        relocated_to: Option<PathBuf>,
+    },
+}
+
+impl UploadEvent {
+    /// 事件所属的 upload，不属于任何 upload 的事件返回空字符串，共用一组序号
+    pub fn upload_id(&self) -> &str {
+        match self {
+            UploadEvent::MetadataUpdated { id, .. } => id,
+            UploadEvent::IdConflict { id } => id,
+            UploadEvent::MetadataTruncated { id, .. } => id,
+            UploadEvent::GroupCompleted { id, .. } => id,
+            UploadEvent::Added { id, .. } | UploadEvent::Started { id, .. } | UploadEvent::Resumed { id, .. } => id,
+            UploadEvent::Progress { id, .. } | UploadEvent::Paused { id, .. } => id,
+            UploadEvent::Completed { id, .. } | UploadEvent::Failed { id, .. } | UploadEvent::Cancelled { id, .. } => id,
+            UploadEvent::Removed { id, .. } => id,
+            UploadEvent::ProgressCorrected { id, .. } => id,
+            UploadEvent::SourceReplaced { id, .. } => id,
+            UploadEvent::Stalled { id, .. } => id,
+            UploadEvent::TlsPinMismatch { id, .. } => id,
+            UploadEvent::GuardFailed { id, .. } => id,
+            UploadEvent::StateDirSynced { .. } | UploadEvent::AuditCompleted { .. } => "",
+            UploadEvent::ActivityChanged { .. } | UploadEvent::StatsUpdated { .. } => "",
+            UploadEvent::CaptivePortalSuspected { .. } | UploadEvent::CaptivePortalCleared { .. } => "",
+        }
+    }
+}
+
+/// 带有序号的事件，同一个 upload 的序号从 1 开始递增
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SequencedEvent {
+    pub seq: u64,
+    pub event: UploadEvent,
+}
+
+/// 为事件分配序号并广播
+#[derive(Debug)]
+pub struct EventBus {
+    sender: broadcast::Sender<SequencedEvent>,
+
+    /// 每个 upload 最后分配的序号
+    watermarks: Mutex<HashMap<String, u64>>,
+
+    /// 还没有订阅方时发出的启动通知，补发给第一个订阅方
+    retained: Mutex<Vec<SequencedEvent>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_CAPACITY).0,
+            watermarks: Mutex::new(HashMap::new()),
+            retained: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl EventBus {
+    pub fn emit(&self, event: UploadEvent) {
+        // 分配序号和发送在同一个锁内，保证订阅方收到的序号是递增的
+        let mut watermarks = self.watermarks.lock().unwrap();
+        let seq = watermarks.entry(event.upload_id().to_string()).or_default();
+        *seq += 1;
+        let _ = self.sender.send(SequencedEvent { seq: *seq, event });
+    }
+
+    /// 和 emit 相同，但没有订阅方时保留事件，第一个订阅方订阅后立即收到
+    /// 用于 new 或后台加载状态时发出的通知，这时调用方通常还来不及订阅
+    pub fn emit_retained(&self, event: UploadEvent) {
+        let mut watermarks = self.watermarks.lock().unwrap();
+        let seq = watermarks.entry(event.upload_id().to_string()).or_default();
+        *seq += 1;
+        let event = SequencedEvent { seq: *seq, event };
+        if self.sender.receiver_count() == 0 {
+            self.retained.lock().unwrap().push(event);
+        } else {
+            let _ = self.sender.send(event);
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SequencedEvent> {
+        self.subscribe_with_watermarks().0
+    }
+
+    /// 每个 upload 已经发出的最大序号
+    pub fn watermarks(&self) -> HashMap<String, u64> {
+        self.watermarks.lock().unwrap().clone()
+    }
+
+    /// 同时订阅并取得当前的序号，之后收到的事件都大于返回的序号
+    /// 保留的启动通知在返回的序号之内，也会补发给这个订阅方
+    pub fn subscribe_with_watermarks(&self) -> (broadcast::Receiver<SequencedEvent>, HashMap<String, u64>) {
+        let watermarks = self.watermarks.lock().unwrap();
+        let receiver = self.sender.subscribe();
+        for event in self.retained.lock().unwrap().drain(..) {
+            let _ = self.sender.send(event);
+        }
+        (receiver, watermarks.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retained_events_replay_to_first_subscriber() {
+        let bus = EventBus::default();
+        bus.emit(UploadEvent::ActivityChanged { state: ActivityState::Idle });
+        bus.emit_retained(UploadEvent::IdConflict { id: "a".to_string() });
+
+        let mut first = bus.subscribe();
+        let mut second = bus.subscribe();
+        let replayed = first.try_recv().unwrap();
+        assert_eq!(replayed, SequencedEvent { seq: 1, event: UploadEvent::IdConflict { id: "a".to_string() } });
+        assert!(first.try_recv().is_err());
+        assert!(second.try_recv().is_err());
+
+        // 已经有订阅方时直接发送
+        bus.emit_retained(UploadEvent::IdConflict { id: "a".to_string() });
+        assert_eq!(first.try_recv().unwrap().seq, 2);
+        assert_eq!(second.try_recv().unwrap().seq, 2);
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use crate::core::digest::Sha256;
+
+/// 参与采样的开头和结尾的长度
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+/// 添加时记录的源文件指纹，继续上传前比较，文件被修改或替换时不再把新的内容接在旧的偏移后面
+///
+/// 只比较大小和修改时间会漏掉保留了修改时间的复制，所以再加上开头和结尾各 64 KiB 的 SHA-256
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceFingerprint {
+    pub size: u64,
+
+    /// 修改时间，UNIX 纪元以来的纳秒，文件系统不支持时为空
+    pub modified: Option<u64>,
+
+    /// 开头和结尾各 SAMPLE_SIZE 字节的 SHA-256，base64 编码，文件较小时是整个文件
+    pub sample: String,
+}
+
+impl SourceFingerprint {
+    pub async fn of(path: &Path) -> std::io::Result<Self> {
+        let mut file = tokio::fs::File::open(path).await?;
+        let metadata = file.metadata().await?;
+        let size = metadata.len();
+        let modified = modified_nanos(&metadata);
+
+        let mut hasher = Sha256::default();
+        let mut buffer = vec![0u8; size.min(SAMPLE_SIZE) as usize];
+        file.read_exact(&mut buffer).await?;
+        hasher.update(&buffer);
+        if size > SAMPLE_SIZE {
+            // 开头和结尾重叠时只读取没有读过的部分
+            let tail = SAMPLE_SIZE.max(size - SAMPLE_SIZE);
+            let mut buffer = vec![0u8; (size - tail) as usize];
+            file.seek(std::io::SeekFrom::Start(tail)).await?;
+            file.read_exact(&mut buffer).await?;
+            hasher.update(&buffer);
+        }
+
+        Ok(Self { size, modified, sample: STANDARD.encode(hasher.finalize_bytes()) })
+    }
+}
+
+/// 修改时间，UNIX 纪元以来的纳秒，文件系统不支持时为空
+pub fn modified_nanos(metadata: &Metadata) -> Option<u64> {
+    metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|elapsed| elapsed.as_nanos() as u64)
+}
+
+/// 用于比较是否是同一个文件的路径：解析符号链接和相对路径，Windows 上不区分大小写
+/// 文件不存在时使用原来的路径
+pub async fn canonical_path(path: &Path) -> PathBuf {
+    let path = tokio::fs::canonicalize(path).await.unwrap_or_else(|_| path.to_path_buf());
+    if cfg!(windows) {
+        PathBuf::from(path.to_string_lossy().to_lowercase())
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Seek, Write};
+
+    #[tokio::test]
+    async fn test_detects_changes() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let content: Vec<u8> = (0..300 * 1024).map(|i| (i % 251) as u8).collect();
+        file.write_all(&content).unwrap();
+        let original = SourceFingerprint::of(file.path()).await.unwrap();
+        assert_eq!(original.size, content.len() as u64);
+        assert_eq!(SourceFingerprint::of(file.path()).await.unwrap(), original);
+
+        // 修改结尾并恢复修改时间，只有采样能发现
+        let modified = file.as_file().metadata().unwrap().modified().unwrap();
+        file.seek(std::io::SeekFrom::End(-1)).unwrap();
+        file.write_all(&[0xff]).unwrap();
+        file.as_file().set_modified(modified).unwrap();
+        let changed = SourceFingerprint::of(file.path()).await.unwrap();
+        assert_eq!((changed.size, changed.modified), (original.size, original.modified));
+        assert_ne!(changed.sample, original.sample);
+
+        // 中间的修改只改变修改时间
+        file.seek(std::io::SeekFrom::Start(150 * 1024)).unwrap();
+        file.write_all(&[0xff]).unwrap();
+        file.as_file().set_modified(modified + std::time::Duration::from_secs(1)).unwrap();
+        assert_ne!(SourceFingerprint::of(file.path()).await.unwrap().modified, original.modified);
+
+        // 小文件整个参与采样
+        let small = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(small.path(), b"hello").unwrap();
+        let fingerprint = SourceFingerprint::of(small.path()).await.unwrap();
+        assert_eq!(fingerprint.size, 5);
+        let mut hasher = Sha256::default();
+        hasher.update(b"hello");
+        assert_eq!(fingerprint.sample, STANDARD.encode(hasher.finalize_bytes()));
+    }
+}
@@ -0,0 +1,22 @@
+use std::time::Duration;
+use async_trait::async_trait;
+use crate::core::upload::{Upload, UploadStatus};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuardDecision {
+    /// 允许状态变化
+    Allow,
+
+    /// 拒绝，upload 进入 Blocked 状态并记录原因
+    Deny(String),
+
+    /// 推迟，等待一段时间后重新调度
+    Defer(Duration),
+}
+
+/// 状态变化守卫
+/// 在修改 upload 之前调用，由使用方实现业务规则（例如每日流量配额）
+#[async_trait]
+pub trait TransitionGuard: Send + Sync {
+    async fn allow(&self, upload: &Upload, from: UploadStatus, to: UploadStatus) -> GuardDecision;
+}
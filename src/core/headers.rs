@@ -1,5 +1,26 @@
 pub const TUS_RESUMABLE: &str = "Tus-Resumable";
 pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_VERSION_HEADER: &str = "Tus-Version";
+pub const TUS_EXTENSION: &str = "Tus-Extension";
+pub const TUS_MAX_SIZE: &str = "Tus-Max-Size";
+pub const TUS_CHECKSUM_ALGORITHM: &str = "Tus-Checksum-Algorithm";
 pub const UPLOAD_OFFSET: &str = "Upload-Offset";
 pub const UPLOAD_LENGTH: &str = "Upload-Length";
+pub const UPLOAD_DEFER_LENGTH: &str = "Upload-Defer-Length";
+pub const UPLOAD_METADATA: &str = "Upload-Metadata";
+pub const UPLOAD_CHECKSUM: &str = "Upload-Checksum";
+pub const UPLOAD_EXPIRES: &str = "Upload-Expires";
+pub const X_HTTP_METHOD_OVERRIDE: &str = "X-HTTP-Method-Override";
 pub const CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+/// 在创建请求中同时发送数据的扩展
+pub const CREATION_WITH_UPLOAD: &str = "creation-with-upload";
+
+/// 创建时不提供长度的扩展
+pub const CREATION_DEFER_LENGTH: &str = "creation-defer-length";
+
+/// 删除服务端资源的扩展
+pub const TERMINATION: &str = "termination";
+
+/// 按块校验数据的扩展
+pub const CHECKSUM: &str = "checksum";
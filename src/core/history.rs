@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::core::error::UploadResult;
+use crate::core::log_file::{LogRotation, RotatingFile};
+use crate::core::speed::Speed;
+use crate::core::upload::{Upload, UploadStatus};
+
+/// 历史文件名称
+pub(crate) const HISTORY_FILE_NAME: &str = "history.jsonl";
+
+/// 已经结束的 upload
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    pub filename: String,
+    pub file_path: PathBuf,
+    pub status: UploadStatus,
+    pub total_bytes: u64,
+    pub location: Option<String>,
+    pub finished_at: DateTime<Utc>,
+
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+
+    /// 实际发送数据的时间
+    #[serde(default)]
+    pub active_duration_ms: u64,
+
+    /// 从创建到结束的时间
+    #[serde(default)]
+    pub wall_duration_ms: u64,
+
+    /// 按发送时间计算的平均速度，字节/秒
+    #[serde(default)]
+    pub average_speed: Speed,
+
+    /// 上传时计算的摘要
+    #[serde(default)]
+    pub digest: Option<String>,
+}
+
+impl From<&Upload> for HistoryEntry {
+    fn from(upload: &Upload) -> Self {
+        let active = upload.active_duration();
+        Self {
+            id: upload.id.clone(),
+            filename: upload.filename.clone(),
+            file_path: upload.file_path.clone(),
+            status: upload.status,
+            total_bytes: upload.total_bytes,
+            location: upload.location.clone(),
+            finished_at: upload.update_at,
+            created_at: Some(upload.created_at),
+            active_duration_ms: active.as_millis() as u64,
+            wall_duration_ms: upload.wall_duration().as_millis() as u64,
+            average_speed: Speed::from_transfer(upload.progress.bytes_transferred, active),
+            digest: upload.digest.as_ref().and_then(|digest| digest.digest.clone()),
+        }
+    }
+}
+
+/// 保存在状态文件夹中的上传历史
+#[derive(Debug)]
+pub struct UploadHistory {
+    file: RotatingFile,
+}
+
+impl UploadHistory {
+    pub fn new(state_dir: &Path, rotation: LogRotation) -> Self {
+        Self { file: RotatingFile::new(state_dir.join(HISTORY_FILE_NAME), rotation) }
+    }
+
+    pub async fn record(&self, upload: &Upload) -> UploadResult<()> {
+        let line = serde_json::to_string(&HistoryEntry::from(upload))?;
+        self.file.append_line(&line).await
+    }
+
+    /// 所有历史，最旧的在前；无法解析的行会被跳过
+    pub async fn entries(&self) -> UploadResult<Vec<HistoryEntry>> {
+        let lines = self.file.read_lines().await?;
+        Ok(lines.iter().filter_map(|line| serde_json::from_str(line).ok()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_history_reads_across_rotated_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = tempfile::NamedTempFile::new().unwrap();
+        let history = UploadHistory::new(dir.path(), LogRotation { max_size: 1024, max_files: 10 });
+
+        let mut ids = Vec::new();
+        for _ in 0..20 {
+            let upload = Upload::new(source.path().to_path_buf(), 1024).unwrap();
+            ids.push(upload.id.clone());
+            history.record(&upload).await.unwrap();
+        }
+
+        assert!(dir.path().join("history.jsonl.1").exists());
+        let entries = history.entries().await.unwrap();
+        let recorded: Vec<String> = entries.into_iter().map(|entry| entry.id).collect();
+        assert_eq!(recorded, ids);
+    }
+}
@@ -0,0 +1,130 @@
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use crate::core::error::{UploadError, UploadResult};
+
+/// Location 的默认最大长度
+pub const DEFAULT_MAX_LOCATION_LEN: usize = 8 * 1024;
+
+/// 一个请求最多跟随的重定向次数
+pub const MAX_REDIRECTS: usize = 5;
+
+/// 服务端返回的 Location 可以指向哪些地址
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrossOriginPolicy {
+    /// 只接受与 endpoint 相同协议、主机和端口的地址
+    #[default]
+    SameOrigin,
+
+    /// 接受任意 http(s) 地址，例如上传数据由 CDN 接收的服务端
+    AllowAny,
+}
+
+/// 校验服务端返回（或导入）的 Location，返回解析后的绝对地址
+/// 相对地址按 endpoint 解析，任何检查失败时都不应该保存这个地址
+pub fn resolve(endpoint: &str, location: &str, max_len: usize, policy: CrossOriginPolicy) -> UploadResult<String> {
+    if location.len() > max_len {
+        return Err(UploadError::LocationTooLong { length: location.len(), limit: max_len });
+    }
+    if location.chars().any(char::is_control) {
+        return Err(UploadError::InvalidLocation("contains control characters".into()));
+    }
+    if location.trim().is_empty() {
+        return Err(UploadError::InvalidLocation("is empty".into()));
+    }
+
+    let base = Url::parse(endpoint).map_err(|_| UploadError::Config("Invalid endpoint".into()))?;
+    let url = base.join(location).map_err(|err| UploadError::InvalidLocation(err.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(UploadError::InvalidLocation(format!("unsupported scheme {}", url.scheme())));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(UploadError::InvalidLocation("has no host".into()));
+    }
+    if policy == CrossOriginPolicy::SameOrigin && url.origin() != base.origin() {
+        return Err(UploadError::LocationOriginMismatch {
+            origin: url.origin().ascii_serialization(),
+            expected: base.origin().ascii_serialization(),
+        });
+    }
+
+    Ok(url.into())
+}
+
+/// 校验重定向的目标，返回解析后的绝对地址
+/// 相对地址按当前请求的地址解析，从 https 降级到 http 总是拒绝
+pub fn redirect(current: &str, location: &str, max_len: usize, policy: CrossOriginPolicy) -> UploadResult<String> {
+    let target = resolve(current, location, max_len, CrossOriginPolicy::AllowAny)?;
+    if current.starts_with("https:") && target.starts_with("http:") {
+        return Err(UploadError::RedirectDowngrade { from: current.to_string(), to: target });
+    }
+    resolve(current, &target, max_len, policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hostile_locations() {
+        let endpoint = "https://upload.example.com/files";
+        let resolve = |location: &str, policy| resolve(endpoint, location, 64, policy);
+        let same = CrossOriginPolicy::SameOrigin;
+
+        assert_eq!(resolve("https://upload.example.com/files/1", same).unwrap(), "https://upload.example.com/files/1");
+        assert_eq!(resolve("/files/2", same).unwrap(), "https://upload.example.com/files/2");
+        assert_eq!(resolve("3", same).unwrap(), "https://upload.example.com/3");
+        assert_eq!(
+            resolve("https://cdn.example.net/u/1", CrossOriginPolicy::AllowAny).unwrap(),
+            "https://cdn.example.net/u/1"
+        );
+
+        let cases: [(&str, fn(&UploadError) -> bool); 10] = [
+            (&"a".repeat(65), |err| matches!(err, UploadError::LocationTooLong { length: 65, limit: 64 })),
+            ("/files/1\nSet-Cookie: x", |err| matches!(err, UploadError::InvalidLocation(_))),
+            ("/files/1\r", |err| matches!(err, UploadError::InvalidLocation(_))),
+            ("/files/\u{0}1", |err| matches!(err, UploadError::InvalidLocation(_))),
+            ("\t", |err| matches!(err, UploadError::InvalidLocation(_))),
+            ("   ", |err| matches!(err, UploadError::InvalidLocation(_))),
+            ("javascript:alert(1)", |err| matches!(err, UploadError::InvalidLocation(_))),
+            ("file:///etc/passwd", |err| matches!(err, UploadError::InvalidLocation(_))),
+            ("https://evil.example.org/files/1", |err| matches!(
+                err,
+                UploadError::LocationOriginMismatch { origin, .. } if origin == "https://evil.example.org"
+            )),
+            ("http://upload.example.com/files/1", |err| matches!(err, UploadError::LocationOriginMismatch { .. })),
+        ];
+        for (location, expected) in cases {
+            let err = resolve(location, same).unwrap_err();
+            assert!(expected(&err), "{:?} -> {:?}", location, err);
+            assert!(err.is_fatal());
+        }
+    }
+
+    #[test]
+    fn test_redirect_targets() {
+        let current = "https://upload.example.com/files";
+        let same = CrossOriginPolicy::SameOrigin;
+
+        assert_eq!(redirect(current, "/v2/files", 64, same).unwrap(), "https://upload.example.com/v2/files");
+        assert!(matches!(
+            redirect(current, "https://cdn.example.net/files", 64, same).unwrap_err(),
+            UploadError::LocationOriginMismatch { .. }
+        ));
+        assert_eq!(
+            redirect(current, "https://cdn.example.net/files", 64, CrossOriginPolicy::AllowAny).unwrap(),
+            "https://cdn.example.net/files"
+        );
+
+        // 即使允许任意地址也不能降级
+        for policy in [same, CrossOriginPolicy::AllowAny] {
+            let err = redirect(current, "http://upload.example.com/files", 64, policy).unwrap_err();
+            assert!(matches!(err, UploadError::RedirectDowngrade { .. }), "{:?}", err);
+            assert!(err.is_fatal());
+        }
+        assert_eq!(
+            redirect("http://upload.example.com/files", "https://upload.example.com/files", 64, CrossOriginPolicy::AllowAny).unwrap(),
+            "https://upload.example.com/files"
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use crate::core::error::UploadResult;
+
+/// 日志文件的大小限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogRotation {
+    /// 单个文件的最大字节数，超过后轮转
+    pub max_size: u64,
+
+    /// 保留的轮转文件数量，更旧的文件会被删除
+    pub max_files: usize,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self {
+            max_size: 10 * 1024 * 1024,
+            max_files: 3,
+        }
+    }
+}
+
+/// 按大小轮转的 NDJSON 文件
+///
+/// 超过大小后依次重命名为 `.1`、`.2`……，`.1` 最新；每一步都是单个 rename，
+/// 中途崩溃最多丢失一个文件
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: LogRotation,
+
+    /// 写入和轮转互斥
+    lock: Mutex<()>,
+}
+
+impl RotatingFile {
+    pub fn new(path: PathBuf, rotation: LogRotation) -> Self {
+        Self { path, rotation, lock: Mutex::new(()) }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    /// 写入一行，写入后会超过大小限制时先轮转
+    pub async fn append_line(&self, line: &str) -> UploadResult<()> {
+        let _guard = self.lock.lock().await;
+
+        let size = match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        if size > 0 && size + line.len() as u64 + 1 > self.rotation.max_size {
+            self.rotate().await?;
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        let mut content = Vec::with_capacity(line.len() + 1);
+        content.extend_from_slice(line.as_bytes());
+        content.push(b'\n');
+        file.write_all(&content).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    /// 从最旧的文件开始移动，rename 会覆盖掉最旧的一个
+    async fn rotate(&self) -> UploadResult<()> {
+        if self.rotation.max_files == 0 {
+            tokio::fs::remove_file(&self.path).await?;
+            return Ok(());
+        }
+
+        for index in (1..self.rotation.max_files).rev() {
+            let source = self.rotated_path(index);
+            if tokio::fs::try_exists(&source).await? {
+                tokio::fs::rename(&source, self.rotated_path(index + 1)).await?;
+            }
+        }
+        tokio::fs::rename(&self.path, self.rotated_path(1)).await?;
+
+        Ok(())
+    }
+
+    /// 按写入顺序读取所有文件中的行，包括轮转的文件
+    pub async fn read_lines(&self) -> UploadResult<Vec<String>> {
+        let _guard = self.lock.lock().await;
+
+        let mut files: Vec<PathBuf> = (1..=self.rotation.max_files).rev()
+            .map(|index| self.rotated_path(index))
+            .collect();
+        files.push(self.path.clone());
+
+        let mut lines = Vec::new();
+        for file in files {
+            match tokio::fs::read_to_string(&file).await {
+                Ok(content) => lines.extend(content.lines().filter(|l| !l.is_empty()).map(str::to_string)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rotation_and_pruning() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wire.ndjson");
+        let file = RotatingFile::new(path.clone(), LogRotation { max_size: 100, max_files: 3 });
+
+        // 每行 10 字节，每个文件 10 行
+        for i in 0..100 {
+            file.append_line(&format!("line-{:04}", i)).await.unwrap();
+        }
+
+        for index in 1..=3 {
+            let rotated = file.rotated_path(index);
+            assert!(rotated.exists());
+            assert!(std::fs::metadata(&rotated).unwrap().len() <= 100);
+        }
+        assert!(!file.rotated_path(4).exists());
+        assert!(std::fs::metadata(&path).unwrap().len() <= 100);
+
+        // 跨文件按顺序读取，最旧的文件已被删除
+        let lines = file.read_lines().await.unwrap();
+        let expected: Vec<String> = (60..100).map(|i| format!("line-{:04}", i)).collect();
+        assert_eq!(lines, expected);
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use crate::core::error::{UploadError, UploadResult};
+use crate::core::options::validate_metadata_key;
+
+/// 整个 Upload-Metadata 头的名称，超过总大小限制时作为 MetadataTooLarge 的 key
+pub const METADATA_HEADER_KEY: &str = "Upload-Metadata";
+
+/// 保存文件名的 key，tus-js-client 等客户端都使用这个名称
+pub const FILENAME_KEY: &str = "filename";
+
+/// Upload-Metadata 编码后的大小限制，避免服务端或代理返回 431
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetadataLimits {
+    /// 单个 `key base64(value)` 的最大字节数
+    pub max_entry_size: usize,
+
+    /// 整个头的最大字节数
+    pub max_total_size: usize,
+
+    /// 超过限制时截断值，否则返回 MetadataTooLarge
+    #[serde(default)]
+    pub truncate: bool,
+}
+
+impl Default for MetadataLimits {
+    fn default() -> Self {
+        Self {
+            max_entry_size: 4 * 1024,
+            max_total_size: 8 * 1024,
+            truncate: false,
+        }
+    }
+}
+
+/// 被截断的值，大小为截断前后值的字节数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataTruncation {
+    pub key: String,
+    pub original_size: usize,
+    pub truncated_size: usize,
+}
+
+fn base64_len(len: usize) -> usize {
+    len.div_ceil(3) * 4
+}
+
+/// `key base64(value)` 的长度
+pub fn encoded_entry_size(key: &str, value: &str) -> usize {
+    key.len() + 1 + base64_len(value.len())
+}
+
+/// 整个 Upload-Metadata 头的长度
+pub fn encoded_size(metadata: &HashMap<String, String>) -> usize {
+    let entries: usize = metadata.iter().map(|(key, value)| encoded_entry_size(key, value)).sum();
+    entries + metadata.len().saturating_sub(1)
+}
+
+/// 编码后不超过 encoded 字节的值的最大长度
+fn value_len_within(key: &str, encoded: usize) -> usize {
+    encoded.saturating_sub(key.len() + 1) / 4 * 3
+}
+
+/// 在字符边界截断
+fn truncate_value(value: &mut String, max_len: usize) {
+    let mut len = max_len.min(value.len());
+    while !value.is_char_boundary(len) {
+        len -= 1;
+    }
+    value.truncate(len);
+}
+
+/// 检查大小限制，允许截断时截断超过限制的值并返回截断的记录
+pub fn enforce_limits(
+    metadata: &mut HashMap<String, String>,
+    limits: &MetadataLimits,
+) -> UploadResult<Vec<MetadataTruncation>> {
+    let mut truncations: HashMap<String, MetadataTruncation> = HashMap::new();
+    let mut record = |key: &str, original_size: usize, truncated_size: usize, truncations: &mut HashMap<String, MetadataTruncation>| {
+        truncations.entry(key.to_string())
+            .or_insert(MetadataTruncation { key: key.to_string(), original_size, truncated_size })
+            .truncated_size = truncated_size;
+    };
+
+    let mut keys: Vec<String> = metadata.keys().cloned().collect();
+    keys.sort();
+    for key in &keys {
+        let value = metadata.get_mut(key).unwrap();
+        let size = encoded_entry_size(key, value);
+        if size <= limits.max_entry_size {
+            continue;
+        }
+        if !limits.truncate || key.len() + 1 > limits.max_entry_size {
+            return Err(UploadError::MetadataTooLarge { key: key.clone(), size, limit: limits.max_entry_size });
+        }
+
+        let original = value.len();
+        truncate_value(value, value_len_within(key, limits.max_entry_size));
+        record(key, original, value.len(), &mut truncations);
+    }
+
+    // 超过总大小时从最长的值开始截断
+    loop {
+        let size = encoded_size(metadata);
+        if size <= limits.max_total_size {
+            break;
+        }
+        let excess = size - limits.max_total_size;
+        let longest = metadata.iter()
+            .filter(|(_, value)| !value.is_empty())
+            .max_by(|a, b| a.1.len().cmp(&b.1.len()).then_with(|| b.0.cmp(a.0)))
+            .map(|(key, _)| key.clone());
+        let Some(key) = longest.filter(|_| limits.truncate) else {
+            return Err(UploadError::MetadataTooLarge {
+                key: METADATA_HEADER_KEY.to_string(),
+                size,
+                limit: limits.max_total_size,
+            });
+        };
+
+        let value = metadata.get_mut(&key).unwrap();
+        let original = value.len();
+        let allowed = encoded_entry_size(&key, value).saturating_sub(excess);
+        truncate_value(value, value_len_within(&key, allowed));
+        record(&key, original, value.len(), &mut truncations);
+    }
+
+    let mut truncations: Vec<MetadataTruncation> = truncations.into_values().collect();
+    truncations.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(truncations)
+}
+
+/// 编码为 Upload-Metadata 头：逗号分隔的 `key base64(value)`，值为空时只有 key
+/// key 按字典序排列，同样的元数据总是得到同样的头
+/// 参考 Tus 协议文档：https://tus.io/protocols/resumable-upload#upload-metadata
+pub fn encode(metadata: &HashMap<String, String>) -> UploadResult<String> {
+    let mut keys: Vec<&String> = metadata.keys().collect();
+    keys.sort();
+
+    let mut pairs = Vec::with_capacity(keys.len());
+    for key in keys {
+        validate_metadata_key(key)?;
+        let value = &metadata[key];
+        if value.is_empty() {
+            pairs.push(key.clone());
+        } else {
+            pairs.push(format!("{} {}", key, STANDARD.encode(value)));
+        }
+    }
+    Ok(pairs.join(","))
+}
+
+/// 解析 Upload-Metadata 头，值必须是 UTF-8
+pub fn decode(header: &str) -> UploadResult<HashMap<String, String>> {
+    let invalid = |pair: &str| UploadError::InvalidOptions(format!("Invalid Upload-Metadata pair: {}", pair));
+    let mut metadata = HashMap::new();
+    for pair in header.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, encoded)) => {
+                let bytes = STANDARD.decode(encoded.trim()).map_err(|_| invalid(pair))?;
+                (key, String::from_utf8(bytes).map_err(|_| invalid(pair))?)
+            }
+            None => (pair, String::new()),
+        };
+        metadata.insert(key.to_string(), value);
+    }
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(truncate: bool) -> MetadataLimits {
+        MetadataLimits { max_entry_size: 100, max_total_size: 150, truncate }
+    }
+
+    #[test]
+    fn test_oversized_metadata_rejected() {
+        let mut metadata = HashMap::from([("description".to_string(), "x".repeat(200))]);
+        match enforce_limits(&mut metadata, &limits(false)) {
+            Err(UploadError::MetadataTooLarge { key, size, limit }) => {
+                assert_eq!(key, "description");
+                assert_eq!(size, 12 + 268);
+                assert_eq!(limit, 100);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // 每个值都没有超过限制，但总大小超过
+        let mut metadata = HashMap::from([
+            ("a".to_string(), "x".repeat(60)),
+            ("b".to_string(), "y".repeat(60)),
+        ]);
+        assert!(matches!(
+            enforce_limits(&mut metadata, &limits(false)),
+            Err(UploadError::MetadataTooLarge { key, .. }) if key == METADATA_HEADER_KEY
+        ));
+    }
+
+    #[test]
+    fn test_oversized_metadata_truncated() {
+        let mut metadata = HashMap::from([
+            ("description".to_string(), "描述".repeat(100)),
+            ("name".to_string(), "y".repeat(70)),
+            ("type".to_string(), "video".to_string()),
+        ]);
+        let truncations = enforce_limits(&mut metadata, &limits(true)).unwrap();
+
+        assert!(encoded_size(&metadata) <= 150);
+        for (key, value) in &metadata {
+            assert!(encoded_entry_size(key, value) <= 100);
+        }
+        assert_eq!(metadata["type"], "video");
+        assert!(metadata["description"].chars().all(|c| c == '描' || c == '述'));
+
+        assert_eq!(truncations.len(), 2);
+        assert_eq!(truncations[0].key, "description");
+        assert_eq!(truncations[0].original_size, 600);
+        assert_eq!(truncations[0].truncated_size, metadata["description"].len());
+        assert_eq!(truncations[1].key, "name");
+        assert_eq!(truncations[1].original_size, 70);
+        // 超过总大小时先截断更长的 name
+        assert_eq!(metadata["name"].len(), 21);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let metadata = HashMap::from([
+            (FILENAME_KEY.to_string(), "周末 旅行 视频.mp4".to_string()),
+            ("type".to_string(), "video/mp4".to_string()),
+            ("empty".to_string(), String::new()),
+            ("note".to_string(), "a,b c".to_string()),
+        ]);
+        let header = encode(&metadata).unwrap();
+        assert!(header.starts_with("empty,filename "));
+        assert!(header.is_ascii() && !header.contains('视'));
+        assert_eq!(decode(&header).unwrap(), metadata);
+
+        assert_eq!(encode(&HashMap::new()).unwrap(), "");
+        for key in ["bad key", "bad,key", ""] {
+            let metadata = HashMap::from([(key.to_string(), "x".to_string())]);
+            assert!(matches!(encode(&metadata), Err(UploadError::InvalidOptions(_))), "{:?}", key);
+        }
+        assert!(decode("filename !!!").is_err());
+    }
+}
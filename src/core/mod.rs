@@ -2,4 +2,27 @@ pub mod error;
 pub mod upload;
 pub mod state;
 pub mod config;
-pub mod headers;
\ No newline at end of file
+pub mod headers;
+pub mod guard;
+pub mod options;
+pub mod skew;
+pub mod bandwidth;
+pub mod event;
+pub mod snapshot;
+pub mod log_file;
+pub mod history;
+pub mod cloud;
+pub mod metadata;
+pub mod capabilities;
+pub mod digest;
+pub mod backup;
+pub mod tls;
+pub mod timeline;
+pub mod location;
+pub mod speed;
+pub mod checksum;
+pub mod clock;
+pub mod rng;
+pub mod fingerprint;
+pub mod auth;
+pub mod proxy;
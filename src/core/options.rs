@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use crate::core::error::{UploadError, UploadResult};
+
+/// 单个块允许的最大值，与 TusConfig::validate 保持一致
+const MAX_CHUNK_SIZE: usize = 100 * 1024 * 1024;
+
+/// 添加 upload 时的可选参数
+///
+/// 通过 `AddUploadOptions::builder()` 构建，或从 JSON 反序列化，两者使用相同的校验
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "RawAddUploadOptions")]
+pub struct AddUploadOptions {
+    /// 元数据，默认为空；key 不能为空，也不能包含空格或逗号
+    pub metadata: HashMap<String, String>,
+
+    /// 每次上传的块大小，默认使用 TusConfig::chunk_size；不能为 0 或超过 100MB
+    pub chunk_size: Option<usize>,
+
+    /// 调用方自己的引用，原样保存在 upload 上，默认为空；设置时不能是空字符串
+    pub client_ref: Option<String>,
+
+    /// TusConfig::endpoints_by_name 中的服务端配置名称，默认使用 TusConfig::endpoint
+    pub endpoint: Option<String>,
+
+    /// 来源的长度未知（仍在写入），创建时使用 Upload-Defer-Length，读到结尾后再告诉服务端
+    pub defer_length: bool,
+
+    /// 这个 upload 自己的带宽上限，字节/秒，默认只受 TusConfig::bandwidth_limit 限制；不能为 0
+    pub bandwidth_limit: Option<u64>,
+
+    /// 这个 upload 的请求都带上的请求头，覆盖 TusConfig::headers 中的同名请求头；不能设置 tus 协议使用的请求头
+    pub headers: HashMap<String, String>,
+
+    /// 与 headers 相同，但不保存到状态中，例如短期有效的签名；重启后不再发送
+    pub secret_headers: HashMap<String, String>,
+
+    /// 这个 upload 最多重试的次数，跨暂停和重启累计，默认使用 TusConfig::max_retries
+    pub max_retries: Option<u32>,
+
+    /// 同一个文件已经在列表中（没有取消或失败）时仍然添加，默认返回已有的 upload
+    pub allow_duplicates: bool,
+
+    /// 加入的分组，可以查看汇总进度并一起暂停、取消；设置时不能是空字符串
+    pub group: Option<String>,
+}
+
+/// 添加 upload 的结果
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", content = "id", rename_all = "snake_case")]
+pub enum AddOutcome {
+    /// 新添加的 upload，拆分上传时是分组的 id
+    Added(String),
+
+    /// 同一个文件已经在列表中，没有添加新的 upload
+    Duplicate(String),
+}
+
+impl AddOutcome {
+    pub fn id(&self) -> &str {
+        match self {
+            AddOutcome::Added(id) | AddOutcome::Duplicate(id) => id,
+        }
+    }
+
+    pub fn into_id(self) -> String {
+        match self {
+            AddOutcome::Added(id) | AddOutcome::Duplicate(id) => id,
+        }
+    }
+
+    pub fn is_duplicate(&self) -> bool {
+        matches!(self, AddOutcome::Duplicate(_))
+    }
+}
+
+/// 批量添加的结果
+#[derive(Debug)]
+pub struct BatchAdded {
+    /// 这一批新添加的 upload 所在的分组
+    pub group: String,
+
+    /// 按传入的顺序，每个文件一个结果
+    pub results: Vec<UploadResult<AddOutcome>>,
+}
+
+/// 分组在队列中的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupPriority {
+    /// 排在队列最前面，先于其他 upload 开始
+    High,
+
+    /// 排在队列最后面
+    Low,
+}
+
+impl AddUploadOptions {
+    pub fn builder() -> AddUploadOptionsBuilder {
+        AddUploadOptionsBuilder::default()
+    }
+
+    pub fn validate(&self) -> UploadResult<()> {
+        for key in self.metadata.keys() {
+            validate_metadata_key(key)?;
+        }
+
+        if let Some(chunk_size) = self.chunk_size {
+            if chunk_size == 0 {
+                return Err(UploadError::InvalidOptions("Chunk size must be greater than 0".into()));
+            }
+            if chunk_size > MAX_CHUNK_SIZE {
+                return Err(UploadError::InvalidOptions("Chunk size cannot be larger than 100MB".into()));
+            }
+        }
+
+        if let Some(client_ref) = &self.client_ref {
+            if client_ref.is_empty() {
+                return Err(UploadError::InvalidOptions("Client reference cannot be empty".into()));
+            }
+        }
+
+        if self.endpoint.as_deref() == Some("") {
+            return Err(UploadError::InvalidOptions("Endpoint name cannot be empty".into()));
+        }
+
+        if self.group.as_deref() == Some("") {
+            return Err(UploadError::InvalidOptions("Group cannot be empty".into()));
+        }
+
+        if self.bandwidth_limit == Some(0) {
+            return Err(UploadError::InvalidOptions("Bandwidth limit must be greater than 0".into()));
+        }
+
+        for (name, value) in self.headers.iter().chain(&self.secret_headers) {
+            validate_header(name, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// 请求头的名称和值必须合法，tus 协议使用的请求头由 worker 设置
+fn validate_header(name: &str, value: &str) -> UploadResult<()> {
+    if HeaderName::from_bytes(name.as_bytes()).is_err() || HeaderValue::from_str(value).is_err() {
+        return Err(UploadError::InvalidOptions(format!("Invalid header: {}", name)));
+    }
+    let lower = name.to_ascii_lowercase();
+    if lower == "tus-resumable" || lower == "content-type" || lower.starts_with("upload-") {
+        return Err(UploadError::InvalidOptions(format!("Header {} is set by the uploader", name)));
+    }
+    Ok(())
+}
+
+/// Upload-Metadata 的 key 不能为空，也不能包含空格或逗号
+pub(crate) fn validate_metadata_key(key: &str) -> UploadResult<()> {
+    if key.is_empty() {
+        return Err(UploadError::InvalidOptions("Metadata key cannot be empty".into()));
+    }
+    if key.contains(' ') || key.contains(',') {
+        return Err(UploadError::InvalidOptions(format!(
+            "Metadata key cannot contain spaces or commas: {}", key
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AddUploadOptionsBuilder {
+    options: AddUploadOptions,
+}
+
+impl AddUploadOptionsBuilder {
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.options.chunk_size = Some(chunk_size);
+        self
+    }
+
+    pub fn client_ref(mut self, client_ref: impl Into<String>) -> Self {
+        self.options.client_ref = Some(client_ref.into());
+        self
+    }
+
+    pub fn endpoint(mut self, name: impl Into<String>) -> Self {
+        self.options.endpoint = Some(name.into());
+        self
+    }
+
+    pub fn defer_length(mut self) -> Self {
+        self.options.defer_length = true;
+        self
+    }
+
+    pub fn bandwidth_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.options.bandwidth_limit = Some(bytes_per_sec);
+        self
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options.headers.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn secret_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options.secret_headers.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.options.max_retries = Some(max_retries);
+        self
+    }
+
+    pub fn allow_duplicates(mut self) -> Self {
+        self.options.allow_duplicates = true;
+        self
+    }
+
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        self.options.group = Some(group.into());
+        self
+    }
+
+    pub fn build(self) -> UploadResult<AddUploadOptions> {
+        self.options.validate()?;
+        Ok(self.options)
+    }
+}
+
+/// 反序列化的中间结构，转换时执行校验
+#[derive(Deserialize)]
+struct RawAddUploadOptions {
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+
+    #[serde(default)]
+    chunk_size: Option<usize>,
+
+    #[serde(default)]
+    client_ref: Option<String>,
+
+    #[serde(default)]
+    endpoint: Option<String>,
+
+    #[serde(default)]
+    defer_length: bool,
+
+    #[serde(default)]
+    bandwidth_limit: Option<u64>,
+
+    #[serde(default)]
+    headers: HashMap<String, String>,
+
+    #[serde(default)]
+    secret_headers: HashMap<String, String>,
+
+    #[serde(default)]
+    max_retries: Option<u32>,
+
+    #[serde(default)]
+    allow_duplicates: bool,
+
+    #[serde(default)]
+    group: Option<String>,
+}
+
+impl TryFrom<RawAddUploadOptions> for AddUploadOptions {
+    type Error = UploadError;
+
+    fn try_from(raw: RawAddUploadOptions) -> Result<Self, Self::Error> {
+        let options = AddUploadOptions {
+            metadata: raw.metadata,
+            chunk_size: raw.chunk_size,
+            client_ref: raw.client_ref,
+            endpoint: raw.endpoint,
+            defer_length: raw.defer_length,
+            bandwidth_limit: raw.bandwidth_limit,
+            headers: raw.headers,
+            secret_headers: raw.secret_headers,
+            max_retries: raw.max_retries,
+            allow_duplicates: raw.allow_duplicates,
+            group: raw.group,
+        };
+        options.validate()?;
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_options() {
+        let cases = [
+            (AddUploadOptions::builder().metadata("", "x"), "Metadata key cannot be empty"),
+            (AddUploadOptions::builder().metadata("file name", "x"), "Metadata key cannot contain spaces or commas: file name"),
+            (AddUploadOptions::builder().metadata("a,b", "x"), "Metadata key cannot contain spaces or commas: a,b"),
+            (AddUploadOptions::builder().chunk_size(0), "Chunk size must be greater than 0"),
+            (AddUploadOptions::builder().chunk_size(MAX_CHUNK_SIZE + 1), "Chunk size cannot be larger than 100MB"),
+            (AddUploadOptions::builder().client_ref(""), "Client reference cannot be empty"),
+            (AddUploadOptions::builder().endpoint(""), "Endpoint name cannot be empty"),
+            (AddUploadOptions::builder().group(""), "Group cannot be empty"),
+            (AddUploadOptions::builder().bandwidth_limit(0), "Bandwidth limit must be greater than 0"),
+            (AddUploadOptions::builder().header("bad name", "x"), "Invalid header: bad name"),
+            (AddUploadOptions::builder().secret_header("X-Token", "a\nb"), "Invalid header: X-Token"),
+            (AddUploadOptions::builder().header("Upload-Offset", "0"), "Header Upload-Offset is set by the uploader"),
+            (AddUploadOptions::builder().header("tus-resumable", "1.0.0"), "Header tus-resumable is set by the uploader"),
+        ];
+
+        for (builder, expected) in cases {
+            match builder.build() {
+                Err(UploadError::InvalidOptions(message)) => assert_eq!(message, expected),
+                other => panic!("expected {:?}, got {:?}", expected, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let options = AddUploadOptions::builder()
+            .metadata("filename", "视频.mp4")
+            .chunk_size(1024)
+            .client_ref("row-1")
+            .endpoint("tenant-a")
+            .defer_length()
+            .bandwidth_limit(64 * 1024)
+            .header("X-Tenant", "a")
+            .secret_header("Authorization", "Bearer signed")
+            .build()
+            .unwrap();
+
+        let json = serde_json::to_string(&options).unwrap();
+        let decoded: AddUploadOptions = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, options);
+
+        let defaults: AddUploadOptions = serde_json::from_str("{}").unwrap();
+        assert_eq!(defaults, AddUploadOptions::default());
+    }
+
+    #[test]
+    fn test_json_uses_same_validation() {
+        let err = serde_json::from_str::<AddUploadOptions>(r#"{"chunk_size": 0}"#).unwrap_err();
+        assert!(err.to_string().contains("Chunk size must be greater than 0"));
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use chrono::{DateTime, TimeDelta, Utc};
+
+/// 保留的样本数量
+const MAX_SAMPLES: usize = 15;
+
+/// 偏差超过这个值时输出一次警告
+const WARN_THRESHOLD: TimeDelta = TimeDelta::minutes(2);
+
+/// 估算服务端与本地的时钟偏差
+/// 使用最近若干个 Date 响应头的中位数，避免单个异常值影响结果
+#[derive(Debug, Default)]
+pub struct ClockSkew {
+    /// 服务端时间减去本地时间
+    samples: Mutex<VecDeque<TimeDelta>>,
+
+    /// 是否已经警告过
+    warned: AtomicBool,
+}
+
+/// 解析 HTTP-date，例如 `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+impl ClockSkew {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录一个响应的 Date 头
+    pub fn observe(&self, date: &str) {
+        if let Some(server_time) = parse_http_date(date) {
+            self.observe_at(server_time, Utc::now());
+        }
+    }
+
+    /// 记录服务端时间与对应的本地时间
+    pub fn observe_at(&self, server_time: DateTime<Utc>, local_time: DateTime<Utc>) {
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(server_time - local_time);
+        drop(samples);
+
+        if let Some(skew) = self.estimate() {
+            if skew.abs() > WARN_THRESHOLD && !self.warned.swap(true, Ordering::SeqCst) {
+                log::warn!("Server clock differs from local clock by {} seconds", skew.num_seconds());
+            }
+        }
+    }
+
+    /// 当前的偏差估计，没有样本时返回 None
+    pub fn estimate(&self) -> Option<TimeDelta> {
+        let samples = self.samples.lock().unwrap();
+        if samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted: Vec<TimeDelta> = samples.iter().copied().collect();
+        sorted.sort();
+        Some(sorted[sorted.len() / 2])
+    }
+
+    /// 以服务端时钟表示的当前时间
+    pub fn server_now(&self) -> DateTime<Utc> {
+        Utc::now() + self.estimate().unwrap_or_default()
+    }
+
+    /// 服务端给出的过期时间是否已经到达
+    pub fn is_expired(&self, expires_at: DateTime<Utc>) -> bool {
+        self.is_expired_at(expires_at, Utc::now())
+    }
+
+    /// 本地时间为 now 时，服务端给出的过期时间是否已经到达
+    pub fn is_expired_at(&self, expires_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        expires_at <= now + self.estimate().unwrap_or_default()
+    }
+
+    /// 解析 Retry-After，支持秒数和 HTTP-date 两种格式
+    pub fn retry_after(&self, value: &str) -> Option<Duration> {
+        if let Ok(seconds) = value.trim().parse::<u64>() {
+            return Some(Duration::from_secs(seconds));
+        }
+
+        let retry_at = parse_http_date(value)?;
+        Some((retry_at - self.server_now()).to_std().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_median_ignores_outliers() {
+        let skew = ClockSkew::new();
+        let local = Utc::now();
+        for _ in 0..4 {
+            skew.observe_at(local + TimeDelta::minutes(10), local);
+        }
+        skew.observe_at(local - TimeDelta::days(3), local);
+
+        assert_eq!(skew.estimate(), Some(TimeDelta::minutes(10)));
+    }
+
+    #[test]
+    fn test_expiry_uses_server_time() {
+        let skew = ClockSkew::new();
+        let expires_at = Utc::now() + TimeDelta::minutes(30);
+        assert!(!skew.is_expired(expires_at));
+
+        // 服务端时钟快一个小时，资源实际上已经过期
+        let local = Utc::now();
+        skew.observe_at(local + TimeDelta::hours(1), local);
+        assert!(skew.is_expired(expires_at));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let skew = ClockSkew::new();
+        assert_eq!(skew.retry_after("120"), Some(Duration::from_secs(120)));
+
+        let local = Utc::now();
+        skew.observe_at(local - TimeDelta::hours(1), local);
+        let retry_at = (local - TimeDelta::hours(1) + TimeDelta::seconds(60)).to_rfc2822();
+        let delay = skew.retry_after(&retry_at).unwrap();
+        assert!(delay > Duration::from_secs(55) && delay <= Duration::from_secs(60));
+    }
+}
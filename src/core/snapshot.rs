@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use crate::core::error::{UploadError, UploadResult};
+
+/// 状态文件夹中保存快照的子文件夹
+pub const SNAPSHOT_DIR: &str = "snapshots";
+
+/// 快照的创建方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotKind {
+    /// 文件系统的写时复制克隆，几乎不占用额外空间
+    Clone,
+
+    /// 普通复制
+    Copy,
+}
+
+pub fn snapshot_dir(state_dir: &Path) -> PathBuf {
+    state_dir.join(SNAPSHOT_DIR)
+}
+
+/// 为 upload 的源文件创建快照，之后上传快照，源文件被修改也不影响发送的数据
+/// 不支持克隆时，小于 copy_threshold 的文件改为复制，更大的文件返回 None，直接上传源文件
+pub async fn create_snapshot(
+    source: &Path,
+    dir: &Path,
+    id: &str,
+    copy_threshold: u64,
+) -> UploadResult<Option<(PathBuf, SnapshotKind)>> {
+    tokio::fs::create_dir_all(dir).await?;
+
+    let mut name = id.to_string();
+    if let Some(extension) = source.extension().and_then(|e| e.to_str()) {
+        name.push('.');
+        name.push_str(extension);
+    }
+    let source = source.to_path_buf();
+    let target = dir.join(name);
+
+    tokio::task::spawn_blocking(move || snapshot_file(&source, &target, copy_threshold, clone_file))
+        .await
+        .map_err(|err| UploadError::Config(format!("Failed to create snapshot: {}", err)))?
+}
+
+fn snapshot_file(
+    source: &Path,
+    target: &Path,
+    copy_threshold: u64,
+    clone: impl FnOnce(&Path, &Path) -> std::io::Result<()>,
+) -> UploadResult<Option<(PathBuf, SnapshotKind)>> {
+    if clone(source, target).is_ok() {
+        return Ok(Some((target.to_path_buf(), SnapshotKind::Clone)));
+    }
+    // 克隆失败时可能留下空文件
+    let _ = std::fs::remove_file(target);
+
+    if std::fs::metadata(source)?.len() > copy_threshold {
+        return Ok(None);
+    }
+
+    std::fs::copy(source, target)?;
+    Ok(Some((target.to_path_buf(), SnapshotKind::Copy)))
+}
+
+/// btrfs、XFS 等文件系统上的 reflink
+#[cfg(target_os = "linux")]
+fn clone_file(source: &Path, target: &Path) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let source = std::fs::File::open(source)?;
+    let target = std::fs::OpenOptions::new().write(true).create_new(true).open(target)?;
+    let result = unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE as _, source.as_raw_fd()) };
+    if result == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// APFS 的 clonefile
+#[cfg(target_os = "macos")]
+fn clone_file(source: &Path, target: &Path) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let source = CString::new(source.as_os_str().as_bytes())?;
+    let target = CString::new(target.as_os_str().as_bytes())?;
+    if unsafe { libc::clonefile(source.as_ptr(), target.as_ptr(), 0) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn clone_file(_source: &Path, _target: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "file cloning is not supported"))
+}
+
+pub async fn remove_snapshot(path: &Path) {
+    if let Err(err) = tokio::fs::remove_file(path).await {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            log::warn!("Failed to remove snapshot {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// 删除不属于任何 upload 的快照，返回删除的数量
+pub async fn sweep_orphans(dir: &Path, keep: &HashSet<PathBuf>) -> UploadResult<usize> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+
+    let mut removed = 0;
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if !keep.contains(&path) {
+            remove_snapshot(&path).await;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unsupported(_: &Path, _: &Path) -> std::io::Result<()> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "no clone"))
+    }
+
+    #[test]
+    fn test_fallback_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("video.mp4");
+        std::fs::write(&source, b"original").unwrap();
+        let target = dir.path().join("snapshot.mp4");
+
+        let (path, kind) = snapshot_file(&source, &target, 1024, unsupported).unwrap().unwrap();
+        assert_eq!(kind, SnapshotKind::Copy);
+        assert_eq!(std::fs::read(path).unwrap(), b"original");
+
+        // 超过阈值时直接上传源文件
+        std::fs::remove_file(&target).unwrap();
+        assert!(snapshot_file(&source, &target, 4, unsupported).unwrap().is_none());
+        assert!(!target.exists());
+    }
+
+    #[tokio::test]
+    async fn test_sweep_orphans() {
+        let dir = tempfile::tempdir().unwrap();
+        let kept = dir.path().join("kept.bin");
+        let orphan = dir.path().join("orphan.bin");
+        std::fs::write(&kept, b"a").unwrap();
+        std::fs::write(&orphan, b"b").unwrap();
+
+        let removed = sweep_orphans(dir.path(), &HashSet::from([kept.clone()])).await.unwrap();
+        assert_eq!(removed, 1);
+        assert!(kept.exists());
+        assert!(!orphan.exists());
+    }
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::time::Duration;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// 传输速度，字节/秒
+///
+/// 序列化为普通数字；构造时把 NaN、无穷大和负数都当作 0，所以总是有限的非负数
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct Speed(f64);
+
+impl Speed {
+    pub const ZERO: Speed = Speed(0.0);
+
+    pub fn new(bytes_per_sec: f64) -> Self {
+        if bytes_per_sec.is_finite() && bytes_per_sec > 0.0 {
+            Self(bytes_per_sec)
+        } else {
+            Self::ZERO
+        }
+    }
+
+    /// 在 elapsed 时间内传输了 bytes 字节，时间为 0 时速度为 0
+    pub fn from_transfer(bytes: u64, elapsed: Duration) -> Self {
+        Self::new(bytes as f64 / elapsed.as_secs_f64())
+    }
+
+    pub fn bytes_per_sec(self) -> f64 {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0.0
+    }
+
+    /// 兆比特/秒
+    pub fn as_mbps(self) -> f64 {
+        self.0 * 8.0 / 1_000_000.0
+    }
+
+    /// 便于显示的文本，例如 "1.5 MiB/s"
+    pub fn humanize(self) -> String {
+        const UNITS: [&str; 5] = ["B/s", "KiB/s", "MiB/s", "GiB/s", "TiB/s"];
+        let mut value = self.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            format!("{:.0} {}", value, UNITS[unit])
+        } else {
+            format!("{:.1} {}", value, UNITS[unit])
+        }
+    }
+}
+
+// 构造时排除了 NaN，比较总是有结果
+impl Eq for Speed {}
+
+impl From<u64> for Speed {
+    fn from(bytes_per_sec: u64) -> Self {
+        Self::new(bytes_per_sec as f64)
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.humanize())
+    }
+}
+
+impl Serialize for Speed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.0)
+    }
+}
+
+/// 旧版本保存的整数和浮点数都可以读取
+impl<'de> Deserialize<'de> for Speed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        f64::deserialize(deserializer).map(Self::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clamps_invalid_values() {
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -1.0, -0.0] {
+            assert_eq!(Speed::new(value), Speed::ZERO, "{}", value);
+        }
+        assert_eq!(Speed::from_transfer(1024, Duration::ZERO), Speed::ZERO);
+        assert_eq!(Speed::from_transfer(1024, Duration::from_millis(500)).bytes_per_sec(), 2048.0);
+        assert_eq!(Speed::from(125_000).as_mbps(), 1.0);
+        assert_eq!(Speed::new(512.0).humanize(), "512 B/s");
+        assert_eq!(Speed::new(1536.0 * 1024.0).humanize(), "1.5 MiB/s");
+    }
+
+    #[test]
+    fn test_serde_accepts_legacy_shapes() {
+        // 整数和浮点数两种旧格式
+        for (json, expected) in [("1024", 1024.0), ("1536.5", 1536.5), ("-3", 0.0), ("0", 0.0)] {
+            let speed: Speed = serde_json::from_str(json).unwrap();
+            assert_eq!(speed.bytes_per_sec(), expected, "{}", json);
+        }
+
+        let speed = Speed::new(2048.25);
+        let json = serde_json::to_string(&speed).unwrap();
+        assert_eq!(json, "2048.25");
+        assert_eq!(serde_json::from_str::<Speed>(&json).unwrap(), speed);
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// 每个 upload 最多保留的记录数，更早的被丢弃
+pub const TIMELINE_LIMIT: usize = 100;
+
+/// upload 过程中值得记录的事情，用于排查问题
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum TimelineEvent {
+    /// 从 offset 发送的一块结果不确定，确认服务端已经保存了其中的 committed 字节，没有重新发送
+    DuplicateSendAvoided {
+        offset: u64,
+        committed: u64,
+    },
+
+    /// 从 offset 发送了 sent 字节，响应中的偏移表明服务端只保存了 committed 字节，剩下的重新发送
+    PartiallyCommitted {
+        offset: u64,
+        sent: u64,
+        committed: u64,
+    },
+
+    /// 服务端校验从 offset 开始的一块失败（460），重新发送
+    ChecksumRejected {
+        offset: u64,
+    },
+
+    /// 服务端的偏移与从 offset 开始的一块不一致（409），重新确认偏移后继续
+    OffsetConflict {
+        offset: u64,
+    },
+
+    /// 服务端的资源已经过期，重新创建，之前上传的 discarded 字节作废
+    Recreated {
+        discarded: u64,
+    },
+
+    /// 超过 stall_timeout 没有确认新的数据，中断请求后从服务端的偏移重试
+    Stalled {
+        offset: u64,
+    },
+
+    /// 暂停或取消时中断了正在发送的请求，服务端最后确认的偏移是 offset，继续时从这里开始
+    Interrupted {
+        offset: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimelineEntry {
+    pub at: DateTime<Utc>,
+
+    #[serde(flatten)]
+    pub event: TimelineEvent,
+}
+
+/// 最近的记录，最旧的在前
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Timeline(VecDeque<TimelineEntry>);
+
+impl Timeline {
+    pub fn record(&mut self, event: TimelineEvent) {
+        self.record_at(event, Utc::now());
+    }
+
+    pub fn record_at(&mut self, event: TimelineEvent, at: DateTime<Utc>) {
+        self.0.push_back(TimelineEntry { at, event });
+        while self.0.len() > TIMELINE_LIMIT {
+            self.0.pop_front();
+        }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &TimelineEntry> {
+        self.0.iter()
+    }
+}
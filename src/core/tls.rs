@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Mutex;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::{Certificate, Client};
+use reqwest::redirect::Policy;
+use serde::{Deserialize, Serialize};
+use crate::core::config::TusConfig;
+use crate::core::digest::Sha256;
+use crate::core::error::{UploadError, UploadResult};
+
+/// 证书链中没有固定的公钥，握手失败
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinMismatch {
+    pub host: String,
+}
+
+impl fmt::Display for PinMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "certificate presented by {} does not match any pinned key", self.host)
+    }
+}
+
+impl Error for PinMismatch {}
+
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+/// 证书校验的设置，例如信任内部 CA 签发的证书
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// 除系统根证书外额外信任的根证书，PEM 格式，一项中可以包含多个证书
+    #[serde(default)]
+    pub extra_root_certs_pem: Vec<String>,
+
+    /// 接受任何证书，包括过期和自签名的证书；连接不再能防止中间人，只用于测试环境
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+
+    /// 接受主机名与证书不符的证书，证书链仍然需要可信
+    #[serde(default)]
+    pub accept_invalid_hostnames: bool,
+}
+
+impl TlsConfig {
+    /// 检查根证书能否解析，与 tls_pins 冲突的设置也在这里报告
+    pub fn validate(&self, pins: &[String]) -> UploadResult<()> {
+        self.root_certs()?;
+        if !pins.is_empty() && (self.accept_invalid_certs || self.accept_invalid_hostnames) {
+            return Err(UploadError::Config("Accepting invalid certificates cannot be combined with tls_pins".into()));
+        }
+        Ok(())
+    }
+
+    /// 额外的根证书，DER 格式
+    fn root_certs(&self) -> UploadResult<Vec<Vec<u8>>> {
+        let mut certs = Vec::new();
+        for (index, pem) in self.extra_root_certs_pem.iter().enumerate() {
+            let invalid = |reason: &str| UploadError::Config(format!("Invalid root certificate #{}: {}", index + 1, reason));
+            let mut rest = pem.as_str();
+            let before = certs.len();
+            while let Some(start) = rest.find(PEM_BEGIN) {
+                let body = &rest[start + PEM_BEGIN.len()..];
+                let end = body.find(PEM_END).ok_or_else(|| invalid("missing END CERTIFICATE"))?;
+                let encoded: String = body[..end].chars().filter(|c| !c.is_ascii_whitespace()).collect();
+                let der = STANDARD.decode(encoded).map_err(|_| invalid("invalid base64"))?;
+                Certificate::from_der(&der).map_err(|err| invalid(&err.to_string()))?;
+                certs.push(der);
+                rest = &body[end + PEM_END.len()..];
+            }
+            if certs.len() == before {
+                return Err(invalid("no PEM certificate found"));
+            }
+        }
+        Ok(certs)
+    }
+}
+
+/// 检查 pin 的格式：base64 编码的 32 字节 SHA-256
+pub fn validate_pins(pins: &[String]) -> UploadResult<()> {
+    for pin in pins {
+        match STANDARD.decode(pin) {
+            Ok(hash) if hash.len() == 32 => {}
+            _ => return Err(UploadError::Config(format!("Invalid TLS pin, expected base64 SHA-256: {}", pin))),
+        }
+    }
+    if !pins.is_empty() && !cfg!(feature = "tls-pinning") {
+        return Err(UploadError::Config("TLS pinning requires the tls-pinning feature".into()));
+    }
+    Ok(())
+}
+
+/// DER 证书中 SubjectPublicKeyInfo 的 SHA-256，base64 编码，与 pin 的格式相同
+pub fn spki_sha256(cert_der: &[u8]) -> Option<String> {
+    let mut hasher = Sha256::default();
+    hasher.update(subject_public_key_info(cert_der)?);
+    Some(STANDARD.encode(hasher.finalize_bytes()))
+}
+
+/// 按配置创建客户端，设置了 tls_pins 时只接受包含固定公钥的证书链
+/// 客户端不自动跟随重定向，由 worker::send_following 校验目标后重新发送；
+/// 所有请求都有 request_timeout，PATCH 按块大小单独设置
+pub fn client_for(config: &TusConfig) -> UploadResult<Client> {
+    let mut builder = Client::builder().redirect(Policy::none());
+    if !config.connect_timeout.is_zero() {
+        builder = builder.connect_timeout(config.connect_timeout);
+    }
+    if !config.request_timeout.is_zero() {
+        builder = builder.timeout(config.request_timeout);
+    }
+    let roots = config.tls.root_certs()?;
+    if let Some(proxy) = &config.proxy {
+        builder = proxy.apply(builder)?;
+    }
+    if config.tls_pins.is_empty() {
+        for der in &roots {
+            builder = builder.add_root_certificate(Certificate::from_der(der)?);
+        }
+        builder = builder
+            .danger_accept_invalid_certs(config.tls.accept_invalid_certs)
+            .danger_accept_invalid_hostnames(config.tls.accept_invalid_hostnames);
+        return Ok(builder.build()?);
+    }
+
+    #[cfg(feature = "tls-pinning")]
+    {
+        let mut store = rustls::RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+        for der in roots {
+            store.add(der.into())
+                .map_err(|err| UploadError::Config(format!("Invalid root certificate: {}", err)))?;
+        }
+        pinned::client(builder, &config.tls_pins, store)
+    }
+    #[cfg(not(feature = "tls-pinning"))]
+    {
+        Err(UploadError::Config("TLS pinning requires the tls-pinning feature".into()))
+    }
+}
+
+/// 按服务端配置共享的客户端，同一个服务端的 upload 复用连接池、TLS 会话和 DNS 缓存
+#[derive(Debug, Default)]
+pub struct ClientCache {
+    clients: Mutex<HashMap<Option<String>, Client>>,
+}
+
+impl ClientCache {
+    /// 命名的服务端配置使用的客户端，第一次使用时创建，profile 为空时使用 endpoint
+    pub fn for_profile(&self, config: &TusConfig, profile: Option<&str>) -> UploadResult<Client> {
+        let key = profile.map(str::to_string);
+        if let Some(client) = self.clients.lock().unwrap().get(&key) {
+            return Ok(client.clone());
+        }
+        let client = client_for(&config.for_profile(profile)?)?;
+        Ok(self.clients.lock().unwrap().entry(key).or_insert(client).clone())
+    }
+}
+
+/// 在错误链中查找 pin 不匹配，返回服务端的主机名
+pub(crate) fn pin_mismatch(err: &(dyn Error + 'static)) -> Option<String> {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(mismatch) = err.downcast_ref::<PinMismatch>() {
+            return Some(mismatch.host.clone());
+        }
+        // rustls::Error 不提供 source，需要手动取出 verifier 返回的错误
+        #[cfg(feature = "tls-pinning")]
+        if let Some(rustls::Error::InvalidCertificate(rustls::CertificateError::Other(other))) = err.downcast_ref() {
+            if let Some(mismatch) = other.0.downcast_ref::<PinMismatch>() {
+                return Some(mismatch.host.clone());
+            }
+        }
+        // io::Error 的 source 跳过了它包装的错误
+        source = match err.downcast_ref::<std::io::Error>().and_then(std::io::Error::get_ref) {
+            Some(inner) => Some(inner as &(dyn Error + 'static)),
+            None => err.source(),
+        };
+    }
+    None
+}
+
+/// 读取一个 DER 元素，返回 (tag, 完整元素, 内容, 剩余数据)
+fn read_der(data: &[u8]) -> Option<(u8, &[u8], &[u8], &[u8])> {
+    let tag = *data.first()?;
+    let first = *data.get(1)? as usize;
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let count = first & 0x7f;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let len = data.get(2..2 + count)?.iter().fold(0usize, |len, byte| (len << 8) | *byte as usize);
+        (len, 2 + count)
+    };
+    let end = header.checked_add(len)?;
+    let element = data.get(..end)?;
+    Some((tag, element, &element[header..], &data[end..]))
+}
+
+/// Certificate -> TBSCertificate -> subjectPublicKeyInfo
+fn subject_public_key_info(cert_der: &[u8]) -> Option<&[u8]> {
+    const SEQUENCE: u8 = 0x30;
+    const VERSION: u8 = 0xa0;
+
+    let (SEQUENCE, _, certificate, _) = read_der(cert_der)? else {
+        return None;
+    };
+    let (SEQUENCE, _, mut tbs, _) = read_der(certificate)? else {
+        return None;
+    };
+    if tbs.first() == Some(&VERSION) {
+        tbs = read_der(tbs)?.3;
+    }
+    // serialNumber、signature、issuer、validity、subject
+    for _ in 0..5 {
+        tbs = read_der(tbs)?.3;
+    }
+    match read_der(tbs)? {
+        (SEQUENCE, spki, _, _) => Some(spki),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "tls-pinning")]
+pub(crate) mod pinned {
+    use std::sync::Arc;
+    use reqwest::{Client, ClientBuilder};
+    use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+    use rustls::client::WebPkiServerVerifier;
+    use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+    use rustls::{CertificateError, DigitallySignedStruct, OtherError, RootCertStore, SignatureScheme};
+    use crate::core::error::{UploadError, UploadResult};
+    use super::{spki_sha256, PinMismatch};
+
+    /// 先按正常流程校验证书链，再要求链中至少有一个固定的公钥
+    #[derive(Debug)]
+    struct PinnedVerifier {
+        inner: Arc<WebPkiServerVerifier>,
+        pins: Vec<String>,
+    }
+
+    impl ServerCertVerifier for PinnedVerifier {
+        fn verify_server_cert(
+            &self,
+            end_entity: &CertificateDer<'_>,
+            intermediates: &[CertificateDer<'_>],
+            server_name: &ServerName<'_>,
+            ocsp_response: &[u8],
+            now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            let verified = self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+            let pinned = std::iter::once(end_entity)
+                .chain(intermediates)
+                .filter_map(|cert| spki_sha256(cert))
+                .any(|hash| self.pins.contains(&hash));
+            if !pinned {
+                let mismatch = PinMismatch { host: server_name.to_str().into_owned() };
+                return Err(rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(Arc::new(mismatch)))));
+            }
+            Ok(verified)
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            self.inner.verify_tls12_signature(message, cert, dss)
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            self.inner.verify_tls13_signature(message, cert, dss)
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.inner.supported_verify_schemes()
+        }
+    }
+
+    /// 使用给定的根证书和 pin 创建客户端，其余设置来自 builder
+    pub fn client(builder: ClientBuilder, pins: &[String], roots: RootCertStore) -> UploadResult<Client> {
+        let tls_error = |err: &dyn std::fmt::Display| UploadError::Config(format!("Failed to configure TLS: {}", err));
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+            .build()
+            .map_err(|err| tls_error(&err))?;
+        let verifier = PinnedVerifier { inner, pins: pins.to_vec() };
+        let tls = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|err| tls_error(&err))?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+
+        Ok(builder.use_preconfigured_tls(tls).build()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::headers;
+    use crate::tus_server::TusServer;
+
+    #[test]
+    fn test_validate_pins() {
+        let pin = STANDARD.encode([7u8; 32]);
+        assert!(validate_pins(&[]).is_ok());
+        assert!(matches!(validate_pins(&["not base64".into()]), Err(UploadError::Config(_))));
+        assert!(matches!(validate_pins(&[STANDARD.encode([7u8; 16])]), Err(UploadError::Config(_))));
+        assert_eq!(validate_pins(&[pin]).is_ok(), cfg!(feature = "tls-pinning"));
+    }
+
+    /// 使用新建的 CA 签发 names 的证书，启动 TLS 服务端，返回服务端、CA 证书和服务端证书
+    async fn start_tls_server(names: &[&str]) -> (TusServer, rcgen::Certificate, rcgen::Certificate) {
+        use std::sync::Arc;
+        use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+        use tokio_rustls::rustls;
+        use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        // 每个 CA 的名称不同，同时信任多个 CA 时按名称找到正确的签发者
+        ca_params.distinguished_name.push(DnType::CommonName, format!("Test CA {}", uuid::Uuid::new_v4()));
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let server_key = KeyPair::generate().unwrap();
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let server_cert = CertificateParams::new(names).unwrap()
+            .signed_by(&server_key, &ca, &ca_key)
+            .unwrap();
+
+        let acceptor_config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![server_cert.der().clone()],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(server_key.serialize_der())),
+            )
+            .unwrap();
+        let server = TusServer::start_tls(tokio_rustls::TlsAcceptor::from(Arc::new(acceptor_config))).await;
+        (server, ca, server_cert)
+    }
+
+    fn create_request(client: Client, server: &TusServer) -> impl std::future::Future<Output = reqwest::Result<reqwest::Response>> {
+        client.post(server.endpoint())
+            .header(headers::TUS_RESUMABLE, headers::TUS_VERSION)
+            .header(headers::UPLOAD_LENGTH, "10")
+            .send()
+    }
+
+    #[tokio::test]
+    async fn test_extra_root_certificates() {
+        let (server, ca, _) = start_tls_server(&["localhost"]).await;
+        let client = |tls: TlsConfig| client_for(&TusConfig { tls, ..TusConfig::new(server.endpoint()) }).unwrap();
+
+        // 内部 CA 签发的证书默认不受信任
+        let err = create_request(client(TlsConfig::default()), &server).await.unwrap_err();
+        assert!(err.is_connect(), "{:?}", err);
+
+        // 和其他证书放在同一个 PEM 中也可以
+        let (_, other, _) = start_tls_server(&["localhost"]).await;
+        let tls = TlsConfig { extra_root_certs_pem: vec![other.pem() + &ca.pem()], ..Default::default() };
+        assert_eq!(create_request(client(tls), &server).await.unwrap().status(), 201);
+
+        let tls = TlsConfig { accept_invalid_certs: true, ..Default::default() };
+        assert_eq!(create_request(client(tls), &server).await.unwrap().status(), 201);
+
+        // 证书链可信但主机名不符
+        let (renamed, ca, _) = start_tls_server(&["uploads.internal"]).await;
+        let client = |tls: TlsConfig| client_for(&TusConfig { tls, ..TusConfig::new(renamed.endpoint()) }).unwrap();
+        let tls = TlsConfig { extra_root_certs_pem: vec![ca.pem()], ..Default::default() };
+        assert!(create_request(client(tls.clone()), &renamed).await.unwrap_err().is_connect());
+        let tls = TlsConfig { accept_invalid_hostnames: true, ..tls };
+        assert_eq!(create_request(client(tls), &renamed).await.unwrap().status(), 201);
+        assert_eq!(server.uploads().len() + renamed.uploads().len(), 3);
+    }
+
+    #[test]
+    fn test_validate_root_certificates() {
+        let config = |pem: &str| TusConfig {
+            tls: TlsConfig { extra_root_certs_pem: vec![pem.to_string()], ..Default::default() },
+            ..TusConfig::new("https://tus.example.com/files".into())
+        };
+        for pem in ["", "not a certificate", "-----BEGIN CERTIFICATE-----\nAAAA", "-----BEGIN CERTIFICATE-----\n!!!\n-----END CERTIFICATE-----"] {
+            let err = config(pem).validate().unwrap_err();
+            assert!(matches!(&err, UploadError::Config(message) if message.starts_with("Invalid root certificate #1")), "{:?}", err);
+        }
+        let garbage = format!("{}\n{}\n{}", PEM_BEGIN, STANDARD.encode([1u8; 64]), PEM_END);
+        assert!(config(&garbage).validate().is_err());
+
+        let mut config = TusConfig::new("https://tus.example.com/files".into());
+        config.tls.accept_invalid_certs = true;
+        config.validate().unwrap();
+        config.tls_pins = vec![STANDARD.encode([7u8; 32])];
+        assert!(config.field_errors().iter().any(|issue| issue.field == "tls"));
+    }
+
+    #[cfg(feature = "tls-pinning")]
+    #[tokio::test]
+    async fn test_pinned_client_against_tls_server() {
+        use rustls::pki_types::CertificateDer;
+
+        let (server, ca, server_cert) = start_tls_server(&["localhost"]).await;
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from(ca.der().to_vec())).unwrap();
+        let request = |client: Client| create_request(client, &server);
+
+        let pin = spki_sha256(server_cert.der()).unwrap();
+        let client = pinned::client(Client::builder(), &[STANDARD.encode([0u8; 32]), pin], roots.clone()).unwrap();
+        assert_eq!(request(client).await.unwrap().status(), 201);
+
+        // 由可信的 CA 签发，但公钥不是固定的那个
+        let client = pinned::client(Client::builder(), &[STANDARD.encode([0u8; 32])], roots).unwrap();
+        let err = UploadError::from(request(client).await.unwrap_err());
+        assert!(matches!(&err, UploadError::TlsPinMismatch { host } if host == "localhost"), "{:?}", err);
+        assert!(err.is_fatal());
+        assert_eq!(server.uploads().len(), 1);
+    }
+}
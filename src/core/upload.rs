@@ -1,9 +1,18 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::time::Duration;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use crate::core::error::{UploadError, UploadResult};
+use crate::core::digest::UploadDigest;
+use crate::core::error::{ErrorDto, UploadError, UploadResult};
+use crate::core::fingerprint::SourceFingerprint;
+use crate::core::metadata;
+use crate::core::speed::Speed;
+use crate::core::timeline::Timeline;
+
+/// 拆分上传的部分在元数据中记录拆分分组 id 的 key
+pub const SPLIT_GROUP_KEY: &str = "group_id";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UploadProgress {
@@ -14,7 +23,7 @@ pub struct UploadProgress {
     pub total_bytes: u64,
 
     /// 当前传输速度
-    pub speed: u64,
+    pub speed: Speed,
 
     /// 最后更新时间
     pub last_update: DateTime<Utc>,
@@ -26,25 +35,103 @@ impl UploadProgress {
         Self {
             total_bytes,
             bytes_transferred: 0,
-            speed: 0,
+            speed: Speed::ZERO,
             last_update: Utc::now(),
         }
     }
 
-    /// 更新
-    pub fn update(&mut self, new_bytes: u64) {
-        let now = Utc::now();
-        let duration = (now - self.last_update).num_milliseconds() as u64 / 1000;
+    /// 服务端确认的偏移前进到 offset，进度直接取服务端的偏移，不单独累加发送的字节数
+    /// 从块中间继续时不会重复计算已经保存的部分；长度已知时不超过总字节数
+    pub fn advance_to(&mut self, offset: u64) {
+        self.advance_at(offset, Utc::now())
+    }
+
+    /// 在 now 时服务端确认的偏移前进到 offset
+    pub fn advance_at(&mut self, offset: u64, now: DateTime<Utc>) {
+        let elapsed = (now - self.last_update).to_std().unwrap_or_default();
+        let offset = if self.total_bytes > 0 { offset.min(self.total_bytes) } else { offset };
 
-        if duration > 0 {
-            self.speed = new_bytes / duration;
+        if elapsed >= Duration::from_secs(1) {
+            self.speed = Speed::from_transfer(offset.saturating_sub(self.bytes_transferred), elapsed);
         }
 
-        self.bytes_transferred += new_bytes;
+        self.bytes_transferred = offset;
         self.last_update = now;
     }
 }
 
+/// 完成前最终 HEAD 的校验结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionVerification {
+    /// 服务端确认的偏移
+    pub offset: u64,
+
+    /// 服务端返回的 Upload-Length
+    pub length: Option<u64>,
+
+    /// 服务端返回的 Upload-Checksum
+    pub checksum: Option<String>,
+
+    /// 校验时间
+    pub verified_at: DateTime<Utc>,
+}
+
+/// 拆分上传时的一部分，只上传源文件中的一段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadPart {
+    /// 从 0 开始的序号
+    pub index: u32,
+
+    /// 总共的部分数量
+    pub count: u32,
+
+    /// 在源文件中的起始偏移
+    pub start: u64,
+
+    /// 在源文件中的结束偏移，不包含
+    pub end: u64,
+}
+
+/// 实际发送数据的累计时间，不包括排队、暂停和重试等待
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActiveTime {
+    /// 已经结束的发送时间段之和
+    pub accumulated: Duration,
+
+    /// 当前时间段的开始时间，没有在发送时为空
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl ActiveTime {
+    /// 开始一个新的时间段，之前的时间段没有结束时直接丢弃它的开始时间
+    pub fn start(&mut self, now: DateTime<Utc>) {
+        self.since = Some(now);
+    }
+
+    /// 结束当前时间段
+    pub fn stop(&mut self, now: DateTime<Utc>) {
+        if let Some(since) = self.since.take() {
+            self.accumulated += (now - since).to_std().unwrap_or_default();
+        }
+    }
+
+    /// 包括当前时间段在内的总时间
+    pub fn total(&self, now: DateTime<Utc>) -> Duration {
+        let current = self.since.map_or(Duration::ZERO, |since| (now - since).to_std().unwrap_or_default());
+        self.accumulated + current
+    }
+}
+
+/// upload 进入 Blocked 状态的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockCause {
+    /// 守卫拒绝启动，由用户或守卫解除
+    Guard,
+
+    /// 请求被强制门户拦截，探测到服务端恢复后自动解除
+    CaptivePortal,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Upload {
     /// 上传文件的唯一 id
@@ -53,6 +140,10 @@ pub struct Upload {
     /// 上传文件的本地路径
     pub file_path: PathBuf,
 
+    /// 开启快照时实际上传的副本，file_path 仍然是用户的文件
+    #[serde(default)]
+    pub snapshot_path: Option<PathBuf>,
+
     /// 上传文件的名称
     pub filename: String,
 
@@ -65,7 +156,8 @@ pub struct Upload {
     /// Tus 创建的资源路径
     pub location: Option<String>,
 
-    /// 每次上传的块大小
+    /// 每次上传的块大小，旧版本的状态文件没有这个字段，加载时用配置补上
+    #[serde(default)]
     pub chunk_size: usize,
 
     /// 进度
@@ -75,6 +167,95 @@ pub struct Upload {
     #[serde(default)]
     pub metadata: HashMap<String, String>,
 
+    /// 进入 Blocked 状态的原因
+    #[serde(default)]
+    pub blocked_reason: Option<String>,
+
+    /// 进入 Blocked 状态的来源，决定由谁恢复这个 upload
+    #[serde(default)]
+    pub blocked_cause: Option<BlockCause>,
+
+    /// 完成校验结果
+    #[serde(default)]
+    pub verification: Option<CompletionVerification>,
+
+    /// 连续失败的次数，跨暂停和重启累计，成功上传一块或用户重新开始后清零
+    #[serde(default)]
+    pub retry_count: u32,
+
+    /// 失败后自动重新排队的次数，用户手动重试后清零
+    #[serde(default)]
+    pub auto_retries: u32,
+
+    /// 这个 upload 最多重试的次数，为空时使用 TusConfig::max_retries
+    #[serde(default)]
+    pub max_retries_override: Option<u32>,
+
+    /// 使用的服务端配置名称，为空时使用默认 endpoint
+    #[serde(default)]
+    pub endpoint: Option<String>,
+
+    /// 调用方自己的引用
+    #[serde(default)]
+    pub client_ref: Option<String>,
+
+    /// 添加时源文件的指纹，开始前比较，文件被修改时失败；旧版本保存的 upload 和长度未知的来源为空
+    #[serde(default)]
+    pub fingerprint: Option<SourceFingerprint>,
+
+    /// 这个 upload 自己的带宽上限，字节/秒，与全局的限制同时生效
+    #[serde(default)]
+    pub bandwidth_limit: Option<u64>,
+
+    /// 这个 upload 的所有请求都带上的请求头，与配置中的同名请求头冲突时使用这里的值
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// 不保存到状态中的请求头，例如短期有效的签名，重启后不再发送
+    #[serde(skip)]
+    pub secret_headers: HashMap<String, String>,
+
+    /// 所属的分组：一起添加的文件（文件夹、批量添加）使用同一个分组；
+    /// 拆分出的部分不在其他分组中时使用拆分分组
+    #[serde(default)]
+    pub group: Option<String>,
+
+    /// 只上传源文件的一段，为空时上传整个文件
+    #[serde(default)]
+    pub part: Option<UploadPart>,
+
+    /// 上传时计算的摘要，未开启时为空
+    #[serde(default)]
+    pub digest: Option<UploadDigest>,
+
+    /// 处于 Active 且没有在重试等待的累计时间
+    #[serde(default)]
+    pub active_time: ActiveTime,
+
+    /// 上传过程中的重要记录
+    #[serde(default)]
+    pub timeline: Timeline,
+
+    /// 检查时发现服务端的资源已经不存在（过期或被删除）
+    #[serde(default)]
+    pub remote_missing: bool,
+
+    /// 服务端通过 Upload-Expires 给出的资源过期时间，之后恢复时直接重新创建
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+
+    /// 添加时长度未知，读到来源的结尾后才确定；确定之前 total_bytes 为 0
+    #[serde(default)]
+    pub length_deferred: bool,
+
+    /// 失败的原因，离开 Failed 状态时清除
+    #[serde(default)]
+    pub last_error: Option<ErrorDto>,
+
+    /// 被守卫推迟或等待重试时重新排队的时间，状态变化或重新排队时清除
+    #[serde(default)]
+    pub retry_at: Option<DateTime<Utc>>,
+
     /// 创建时间
     pub created_at: DateTime<Utc>,
 
@@ -85,6 +266,10 @@ pub struct Upload {
 impl Upload {
     pub fn new(file_path: PathBuf, chunk_size: usize) -> UploadResult<Self> {
         let metadata = std::fs::metadata(file_path.clone())?;
+        // 目录、设备和管道等没有固定的长度，不能按文件上传
+        if !metadata.is_file() {
+            return Err(UploadError::NotAFile { path: file_path.display().to_string() });
+        }
         let filename = file_path
             .file_name()
             .and_then(|s| s.to_str())
@@ -94,6 +279,7 @@ impl Upload {
         Ok(Self {
             id: Uuid::new_v4().to_string(),
             file_path,
+            snapshot_path: None,
             filename,
             chunk_size,
             location: None,
@@ -102,42 +288,290 @@ impl Upload {
             progress: UploadProgress::new(metadata.len()),
             created_at: Utc::now(),
             update_at: Utc::now(),
-            metadata: HashMap::new()
+            metadata: HashMap::new(),
+            blocked_reason: None,
+            blocked_cause: None,
+            verification: None,
+            retry_count: 0,
+            auto_retries: 0,
+            max_retries_override: None,
+            endpoint: None,
+            client_ref: None,
+            bandwidth_limit: None,
+            headers: HashMap::new(),
+            secret_headers: HashMap::new(),
+            fingerprint: None,
+            active_time: ActiveTime::default(),
+            group: None,
+            part: None,
+            digest: None,
+            timeline: Timeline::default(),
+            remote_missing: false,
+            expires_at: None,
+            length_deferred: false,
+            last_error: None,
+            retry_at: None,
         })
     }
 
+    /// 长度未知的来源，例如仍在写入的录制文件，读到结尾时才确定长度
+    pub fn new_deferred(file_path: PathBuf, chunk_size: usize) -> UploadResult<Self> {
+        let mut upload = Self::new(file_path, chunk_size)?;
+        upload.total_bytes = 0;
+        upload.progress = UploadProgress::new(0);
+        upload.length_deferred = true;
+        Ok(upload)
+    }
+
+    /// 已知的总长度，延迟长度时为 None
+    pub fn known_length(&self) -> Option<u64> {
+        (!self.length_deferred).then_some(self.total_bytes)
+    }
+
+    /// 读到来源的结尾，确定总长度
+    pub fn resolve_length(&mut self, length: u64) {
+        self.total_bytes = length;
+        self.progress.total_bytes = length;
+        self.length_deferred = false;
+    }
+
+    /// 上传源文件中的一段，total_bytes 为这一段的长度
+    pub fn new_part(file_path: PathBuf, chunk_size: usize, group: String, part: UploadPart) -> UploadResult<Self> {
+        let mut upload = Self::new(file_path, chunk_size)?;
+        if part.start > part.end || part.end > upload.total_bytes {
+            return Err(UploadError::InvalidOptions(format!(
+                "Part {}..{} is outside of the {} byte file", part.start, part.end, upload.total_bytes
+            )));
+        }
+
+        upload.total_bytes = part.end - part.start;
+        upload.progress = UploadProgress::new(upload.total_bytes);
+        upload.group = Some(group);
+        upload.part = Some(part);
+        Ok(upload)
+    }
+
     pub fn transition_to(&mut self, status: UploadStatus) -> UploadResult<()> {
+        self.transition_at(status, Utc::now())
+    }
+
+    /// 在 now 时进入 status，发送时间按 now 开始或结束
+    pub fn transition_at(&mut self, status: UploadStatus, now: DateTime<Utc>) -> UploadResult<()> {
         if !self.status.can_transition_to(status) {
             return Err(UploadError::InvalidState(
                 format!("Cannot transition from {:?} to {:?}", self.status, status)
             ));
         }
 
+        if status != UploadStatus::Blocked {
+            self.blocked_reason = None;
+            self.blocked_cause = None;
+        }
+        if status != UploadStatus::Failed {
+            self.last_error = None;
+        }
+        self.retry_at = None;
+        if matches!(status, UploadStatus::Completed | UploadStatus::Failed | UploadStatus::Cancelled) {
+            self.progress.speed = Speed::ZERO;
+        }
+
+        if status == UploadStatus::Active {
+            self.active_time.start(now);
+        } else {
+            self.active_time.stop(now);
+        }
         self.status = status;
-        self.update_at = Utc::now();
+        self.update_at = now;
+
+        Ok(())
+    }
+
+    /// 进入 Blocked 状态并记录来源和原因
+    pub fn block(&mut self, cause: BlockCause, reason: impl Into<String>) -> UploadResult<()> {
+        self.block_at(cause, reason, Utc::now())
+    }
 
+    pub fn block_at(&mut self, cause: BlockCause, reason: impl Into<String>, now: DateTime<Utc>) -> UploadResult<()> {
+        self.transition_at(UploadStatus::Blocked, now)?;
+        self.blocked_reason = Some(reason.into());
+        self.blocked_cause = Some(cause);
+        Ok(())
+    }
+
+    /// 最多重试的次数，default 为 TusConfig::max_retries
+    pub fn retry_budget(&self, default: u32) -> u32 {
+        self.max_retries_override.unwrap_or(default)
+    }
+
+    /// 累计的失败次数已经超过重试次数，用户重新开始之前不再自动上传
+    pub fn retries_exhausted(&self, default: u32) -> bool {
+        self.retry_count > self.retry_budget(default)
+    }
+
+    /// 进入 Failed 状态并记录原因
+    pub fn fail(&mut self, err: &UploadError) -> UploadResult<()> {
+        self.fail_at(err, Utc::now())
+    }
+
+    pub fn fail_at(&mut self, err: &UploadError, now: DateTime<Utc>) -> UploadResult<()> {
+        self.transition_at(UploadStatus::Failed, now)?;
+        self.last_error = Some(ErrorDto::from(err));
         Ok(())
     }
 
     pub fn set_location(&mut self, location: impl Into<String>) {
         self.location = Some(location.into());
+        self.remote_missing = false;
+        self.expires_at = None;
         self.update_at = Utc::now();
     }
 
+    /// 创建时发送给服务端的元数据：文件名加上自定义的元数据，自定义的 filename 优先
+    pub fn server_metadata(&self) -> HashMap<String, String> {
+        let mut metadata = self.metadata.clone();
+        metadata.entry(metadata::FILENAME_KEY.to_string()).or_insert_with(|| self.filename.clone());
+        metadata
+    }
+
+    /// 按 server_metadata 检查大小限制，补充的 filename 也计入
+    /// 截断的值写回 metadata，补充的 filename 被截断时写回 filename，替换源文件后重新检查
+    pub fn enforce_metadata_limits(&mut self, limits: &metadata::MetadataLimits) -> UploadResult<Vec<metadata::MetadataTruncation>> {
+        let mut sent = self.server_metadata();
+        let truncations = metadata::enforce_limits(&mut sent, limits)?;
+        if !self.metadata.contains_key(metadata::FILENAME_KEY) {
+            self.filename = sent.remove(metadata::FILENAME_KEY).unwrap_or_default();
+        }
+        self.metadata = sent;
+        Ok(truncations)
+    }
+
+    /// 实际发送数据的时间
+    pub fn active_duration(&self) -> Duration {
+        self.active_duration_at(Utc::now())
+    }
+
+    /// 到 now 为止实际发送数据的时间
+    pub fn active_duration_at(&self, now: DateTime<Utc>) -> Duration {
+        self.active_time.total(now)
+    }
+
+    /// 从创建到结束（未结束时到现在）的时间
+    pub fn wall_duration(&self) -> Duration {
+        self.wall_duration_at(Utc::now())
+    }
+
+    /// 从创建到结束（未结束时到 now）的时间
+    pub fn wall_duration_at(&self, now: DateTime<Utc>) -> Duration {
+        let end = if self.is_finished() { self.update_at } else { now };
+        (end - self.created_at).to_std().unwrap_or_default()
+    }
+
+    /// 上传时读取的文件，有快照时读取快照
+    pub fn read_path(&self) -> &PathBuf {
+        self.snapshot_path.as_ref().unwrap_or(&self.file_path)
+    }
+
     pub fn is_active(&self) -> bool {
         matches!(self.status, UploadStatus::Active)
     }
 
     pub fn can_start(&self) -> bool {
-        matches!(self.status, UploadStatus::Pending | UploadStatus::Paused)
+        matches!(self.status, UploadStatus::Pending | UploadStatus::Paused | UploadStatus::WaitingRetry)
+    }
+
+    /// 拆分上传的部分所属的拆分分组，加入其他分组后从元数据中取得
+    pub fn split_group(&self) -> Option<&str> {
+        self.part?;
+        self.metadata.get(SPLIT_GROUP_KEY).or(self.group.as_ref()).map(String::as_str)
     }
 
     pub fn is_finished(&self) -> bool {
-        matches!(self.status, UploadStatus::Completed | UploadStatus::Failed)
+        matches!(self.status, UploadStatus::Completed | UploadStatus::Failed | UploadStatus::Cancelled)
+    }
+
+    /// 检查字段之间的一致性，返回第一个不满足的约束
+    pub fn validate_invariants(&self) -> UploadResult<()> {
+        match self.invariant_violations().into_iter().next() {
+            Some(violation) => Err(UploadError::InvariantViolation(violation)),
+            None => Ok(()),
+        }
+    }
+
+    /// 所有不满足的约束
+    pub fn invariant_violations(&self) -> Vec<String> {
+        let mut violations = Vec::new();
+        let progress = &self.progress;
+
+        if progress.total_bytes != self.total_bytes {
+            violations.push(format!(
+                "progress total {} does not match upload total {}", progress.total_bytes, self.total_bytes
+            ));
+        }
+        if !self.length_deferred && progress.bytes_transferred > self.total_bytes {
+            violations.push(format!(
+                "transferred {} bytes exceeds total {}", progress.bytes_transferred, self.total_bytes
+            ));
+        }
+        if self.status == UploadStatus::Completed && progress.bytes_transferred != self.total_bytes {
+            violations.push(format!(
+                "completed upload transferred {} of {} bytes", progress.bytes_transferred, self.total_bytes
+            ));
+        }
+        if self.status == UploadStatus::Completed && self.location.is_none() {
+            violations.push("completed upload has no location".to_string());
+        }
+        if self.is_finished() && !progress.speed.is_zero() {
+            violations.push(format!("finished upload still reports speed {}", progress.speed));
+        }
+        if self.location.is_none() && progress.bytes_transferred > 0 {
+            violations.push(format!(
+                "{:?} upload without location reports {} transferred bytes", self.status, progress.bytes_transferred
+            ));
+        }
+        if self.update_at < self.created_at {
+            violations.push("updated before it was created".to_string());
+        }
+
+        violations
+    }
+
+    /// 修复可以安全修复的约束，返回修复的内容
+    pub fn repair_invariants(&mut self) -> Vec<String> {
+        let mut repairs = Vec::new();
+
+        if self.progress.total_bytes != self.total_bytes {
+            self.progress.total_bytes = self.total_bytes;
+            repairs.push("reset progress total".to_string());
+        }
+        if !self.length_deferred && self.progress.bytes_transferred > self.total_bytes {
+            self.progress.bytes_transferred = self.total_bytes;
+            repairs.push("clamped transferred bytes".to_string());
+        }
+        if self.status == UploadStatus::Completed && self.progress.bytes_transferred != self.total_bytes {
+            // 不能确认服务端收到了全部数据，改为暂停；继续时 worker 先用 HEAD 确认服务端的偏移
+            self.status = UploadStatus::Paused;
+            self.verification = None;
+            repairs.push("paused completed upload with incomplete progress".to_string());
+        }
+        if self.is_finished() && !self.progress.speed.is_zero() {
+            self.progress.speed = Speed::ZERO;
+            repairs.push("cleared speed".to_string());
+        }
+        if self.location.is_none() && self.progress.bytes_transferred > 0 {
+            // 服务端还没有资源，不可能传输过数据
+            self.progress.bytes_transferred = 0;
+            repairs.push("reset progress of upload without location".to_string());
+        }
+        if self.update_at < self.created_at {
+            self.update_at = self.created_at;
+            repairs.push("reset update time".to_string());
+        }
+
+        repairs
     }
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum UploadStatus {
     /// 已创建，但尚未开始
     Pending,
@@ -153,6 +587,15 @@ pub enum UploadStatus {
 
     /// 上传遇到错误
     Failed,
+
+    /// 被 TransitionGuard 拒绝，需要等待外部条件满足
+    Blocked,
+
+    /// 重试等待中，已让出并发名额，到时间后重新调度
+    WaitingRetry,
+
+    /// 被用户取消，不会再上传
+    Cancelled,
 }
 
 impl UploadStatus {
@@ -160,10 +603,14 @@ impl UploadStatus {
         use UploadStatus::*;
         match (*self, target) {
             (Pending, Active) => true,
+            // 排队中也可以暂停，例如暂停整个分组
+            (Pending, Paused) => true,
 
             (Active, Paused) => true,
             (Active, Completed) => true,
             (Active, Failed) => true,
+            // 开始前就无法继续，例如服务端配置无效
+            (Pending, Failed) => true,
 
             (Paused, Pending) => true,
             (Paused, Active) => true,
@@ -171,6 +618,18 @@ impl UploadStatus {
             (Failed, Pending) => true,
             (Failed, Active) => true,
 
+            (Pending, Blocked) => true,
+            (Paused, Blocked) => true,
+            (Failed, Blocked) => true,
+            (Blocked, Pending) => true,
+
+            (Active, WaitingRetry) => true,
+            (WaitingRetry, Active) => true,
+            (WaitingRetry, Paused) => true,
+
+            // 没有结束的 upload 都可以取消，取消后不能再改变
+            (Pending | Active | Paused | Failed | Blocked | WaitingRetry, Cancelled) => true,
+
             _ => false,
         }
     }
@@ -185,10 +644,20 @@ mod tests {
         let transitions = [
             (UploadStatus::Pending, UploadStatus::Active, true),
             (UploadStatus::Active, UploadStatus::Paused, true),
+            (UploadStatus::Pending, UploadStatus::Paused, true),
             (UploadStatus::Paused, UploadStatus::Active, true),
             (UploadStatus::Active, UploadStatus::Completed, true),
             (UploadStatus::Completed, UploadStatus::Active, false),
             (UploadStatus::Failed, UploadStatus::Completed, false),
+            (UploadStatus::Pending, UploadStatus::Failed, true),
+            (UploadStatus::Paused, UploadStatus::Failed, false),
+            (UploadStatus::Pending, UploadStatus::Blocked, true),
+            (UploadStatus::Blocked, UploadStatus::Pending, true),
+            (UploadStatus::Blocked, UploadStatus::Active, false),
+            (UploadStatus::Paused, UploadStatus::Cancelled, true),
+            (UploadStatus::WaitingRetry, UploadStatus::Cancelled, true),
+            (UploadStatus::Completed, UploadStatus::Cancelled, false),
+            (UploadStatus::Cancelled, UploadStatus::Pending, false),
         ];
 
         for (from, to, expected) in transitions {
@@ -197,19 +666,154 @@ mod tests {
         }
     }
 
+    fn corrupt_uploads() -> Vec<Upload> {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), vec![0u8; 100]).unwrap();
+        let upload = Upload::new(file.path().to_path_buf(), 10).unwrap();
+
+        let mut overflow = upload.clone();
+        overflow.set_location("http://localhost/files/1");
+        overflow.progress.bytes_transferred = 150;
+
+        let mut short_completed = upload.clone();
+        short_completed.set_location("http://localhost/files/1");
+        short_completed.status = UploadStatus::Completed;
+        short_completed.progress.bytes_transferred = 40;
+
+        let mut paused_without_location = upload.clone();
+        paused_without_location.status = UploadStatus::Paused;
+        paused_without_location.progress.bytes_transferred = 40;
+
+        let mut failed_with_speed = upload.clone();
+        failed_with_speed.status = UploadStatus::Failed;
+        failed_with_speed.progress.speed = Speed::from(1024);
+
+        let mut time_travel = upload.clone();
+        time_travel.update_at = time_travel.created_at - chrono::TimeDelta::hours(1);
+
+        vec![overflow, short_completed, paused_without_location, failed_with_speed, time_travel]
+    }
+
+    #[test]
+    fn test_invariants_detect_and_repair() {
+        for mut upload in corrupt_uploads() {
+            assert!(matches!(upload.validate_invariants(), Err(UploadError::InvariantViolation(_))));
+
+            let repairs = upload.repair_invariants();
+            assert!(!repairs.is_empty());
+            assert!(upload.validate_invariants().is_ok(), "not repaired: {:?}", upload.invariant_violations());
+        }
+
+        // 进度不完整的已完成 upload 不会被当作已经传完
+        let mut short_completed = corrupt_uploads().swap_remove(1);
+        short_completed.repair_invariants();
+        assert_eq!(short_completed.status, UploadStatus::Paused);
+        assert_eq!(short_completed.progress.bytes_transferred, 40);
+    }
+
+    #[test]
+    fn test_active_duration_excludes_waiting() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut upload = Upload::new(file.path().to_path_buf(), 10).unwrap();
+        let segment = chrono::TimeDelta::milliseconds(60);
+        let mut now = upload.created_at;
+        let mut after = |segments: i32| {
+            now += segment * segments;
+            now
+        };
+
+        // 排队
+        upload.transition_at(UploadStatus::Active, after(1)).unwrap();
+
+        // 暂停
+        upload.transition_at(UploadStatus::Paused, after(1)).unwrap();
+        upload.transition_at(UploadStatus::Active, after(1)).unwrap();
+
+        // 让出名额的重试等待
+        upload.transition_at(UploadStatus::WaitingRetry, after(1)).unwrap();
+        upload.transition_at(UploadStatus::Active, after(1)).unwrap();
+
+        // 原地的重试等待
+        upload.active_time.stop(after(1));
+        upload.active_time.start(after(1));
+
+        upload.set_location("http://localhost/files/1");
+        upload.progress.bytes_transferred = upload.total_bytes;
+        let completed_at = after(1);
+        upload.transition_at(UploadStatus::Completed, completed_at).unwrap();
+
+        let segment = segment.to_std().unwrap();
+        assert_eq!(upload.active_duration_at(completed_at), segment * 4);
+        assert_eq!(upload.wall_duration_at(completed_at), segment * 8);
+
+        // 结束后不再增加
+        let later = completed_at + chrono::TimeDelta::minutes(1);
+        assert_eq!(upload.active_duration_at(later), segment * 4);
+        assert_eq!(upload.wall_duration_at(later), segment * 8);
+    }
+
+    #[test]
+    fn test_metadata_limits_include_filename() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut upload = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+        upload.filename = "f".repeat(100);
+        upload.metadata.insert("title".to_string(), "t".repeat(60));
+        let limits = metadata::MetadataLimits { max_entry_size: 200, max_total_size: 150, truncate: false };
+
+        // 只看 metadata 没有超过限制，加上 filename 后超过
+        assert!(metadata::enforce_limits(&mut upload.metadata.clone(), &limits).is_ok());
+        assert!(matches!(upload.clone().enforce_metadata_limits(&limits), Err(UploadError::MetadataTooLarge { .. })));
+
+        let truncations = upload.enforce_metadata_limits(&metadata::MetadataLimits { truncate: true, ..limits }).unwrap();
+        assert_eq!(truncations.len(), 1);
+        assert_eq!(truncations[0].key, metadata::FILENAME_KEY);
+        assert!(metadata::encoded_size(&upload.server_metadata()) <= 150);
+        assert!(upload.filename.len() < 100);
+        // filename 仍然由 upload.filename 补充，替换源文件后按新的文件名重新检查
+        assert!(!upload.metadata.contains_key(metadata::FILENAME_KEY));
+    }
+
+    #[test]
+    fn test_secret_headers_not_persisted() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut upload = Upload::new(file.path().to_path_buf(), 10).unwrap();
+        upload.headers.insert("X-Tenant".to_string(), "a".to_string());
+        upload.secret_headers.insert("Authorization".to_string(), "Bearer signed".to_string());
+
+        let json = serde_json::to_string(&upload).unwrap();
+        assert!(!json.contains("Bearer signed"));
+        let decoded: Upload = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.headers, upload.headers);
+        assert!(decoded.secret_headers.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_non_file_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = Upload::new(dir.path().to_path_buf(), 1024);
+        assert!(matches!(result, Err(UploadError::NotAFile { .. })));
+        assert!(result.unwrap_err().is_fatal());
+
+        // 空文件可以上传，长度为 0
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let upload = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+        assert_eq!(upload.total_bytes, 0);
+    }
+
     #[test]
     fn test_progress_update() {
         let total_bytes = 1024 * 1024 * 10; // 10MB
         let mut progress = UploadProgress::new(total_bytes);
         assert_eq!(progress.bytes_transferred, 0);
 
-        progress.update(1024 * 1024 * 2);
+        progress.advance_to(1024 * 1024 * 2);
         assert_eq!(progress.bytes_transferred, 1024 * 1024 * 2);
 
-        progress.update(1024 * 1024 * 2);
-        assert_eq!(progress.bytes_transferred, 1024 * 1024 * 4);
+        // 从块中间的偏移继续
+        progress.advance_to(1024 * 1024 * 3 + 200);
+        assert_eq!(progress.bytes_transferred, 1024 * 1024 * 3 + 200);
 
-        progress.update(1024 * 1024 * 4);
-        assert_eq!(progress.bytes_transferred, 1024 * 1024 * 8);
+        progress.advance_to(total_bytes + 1024);
+        assert_eq!(progress.bytes_transferred, total_bytes);
     }
 }
@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+use crate::core::error::{ErrorDto, UploadError, UploadResult};
+use crate::ipc::protocol::token_path;
+
+/// 简单的客户端，主要用于测试和命令行工具
+pub struct IpcClient {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+    next_id: u64,
+
+    /// 等待响应时收到的事件
+    events: VecDeque<Value>,
+}
+
+impl IpcClient {
+    /// 连接并使用状态文件夹中的 token 认证
+    pub async fn connect(socket: &Path, state_dir: &Path) -> UploadResult<Self> {
+        let token = tokio::fs::read_to_string(token_path(state_dir)).await?;
+        let (reader, writer) = UnixStream::connect(socket).await?.into_split();
+        let mut client = Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            next_id: 0,
+            events: VecDeque::new(),
+        };
+
+        client.call("auth", json!({ "token": token.trim() })).await?
+            .map_err(|error| UploadError::Config(error.message))?;
+        Ok(client)
+    }
+
+    /// 发送请求并等待对应的响应
+    pub async fn call(&mut self, method: &str, params: Value) -> UploadResult<Result<Value, ErrorDto>> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let mut line = json!({ "id": id, "method": method, "params": params }).to_string();
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+
+        loop {
+            let message = self.read_message().await?;
+            if message.get("event").is_some() {
+                self.events.push_back(message["event"].clone());
+                continue;
+            }
+            if message["id"] != json!(id) {
+                continue;
+            }
+
+            return match message.get("error") {
+                Some(error) => Ok(Err(serde_json::from_value(error.clone())?)),
+                None => Ok(Ok(message["result"].clone())),
+            };
+        }
+    }
+
+    /// 下一个推送的事件
+    pub async fn next_event(&mut self) -> UploadResult<Value> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        loop {
+            let message = self.read_message().await?;
+            if let Some(event) = message.get("event") {
+                return Ok(event.clone());
+            }
+        }
+    }
+
+    async fn read_message(&mut self) -> UploadResult<Value> {
+        let line = self.lines.next_line().await?.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "ipc connection closed")
+        })?;
+        Ok(serde_json::from_str(&line)?)
+    }
+}
@@ -0,0 +1,12 @@
+//! 通过本地 socket 控制 UploadManager
+//!
+//! 每行一个 JSON 消息。请求为 `{"id": 1, "method": "status", "params": {...}}`，
+//! 响应为 `{"id": 1, "result": ...}` 或 `{"id": 1, "error": {"code": ..., "message": ...}}`，
+//! subscribe 之后事件以 `{"event": ...}` 推送。连接后第一个请求必须是 auth，
+//! token 保存在状态文件夹的 ipc.token 中，只有当前用户可读。
+
+pub mod protocol;
+pub mod server;
+
+#[cfg(unix)]
+pub mod client;
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::core::config::InitConfig;
+use crate::core::error::ErrorDto;
+use crate::core::options::{AddUploadOptions, GroupPriority};
+use crate::core::upload::UploadStatus;
+use crate::uploader::directory::AddDirectoryOptions;
+
+/// 状态文件夹中保存 token 的文件名
+pub const TOKEN_FILE_NAME: &str = "ipc.token";
+
+pub fn token_path(state_dir: &Path) -> PathBuf {
+    state_dir.join(TOKEN_FILE_NAME)
+}
+
+/// 一行请求
+#[derive(Debug, Deserialize)]
+pub struct IpcRequest {
+    /// 调用方选择的请求 id，原样放在响应中
+    pub id: u64,
+
+    #[serde(flatten)]
+    pub command: IpcCommand,
+}
+
+/// 与 Tauri 命令对应的操作
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum IpcCommand {
+    /// 连接后的第一个请求
+    Auth { token: String },
+
+    Add {
+        path: PathBuf,
+        #[serde(default)]
+        options: AddUploadOptions,
+    },
+
+    /// 一次添加多个文件，返回分组 id 和每个文件的 `{"result": ...}` 或 `{"error": ...}`
+    AddUploads { paths: Vec<PathBuf> },
+
+    /// 添加文件夹中的所有文件，返回分组 id、新添加的 id 和跳过的数量
+    AddDirectoryUpload {
+        path: PathBuf,
+        #[serde(default)]
+        options: AddDirectoryOptions,
+    },
+
+    Pause { id: String },
+
+    /// 分组的汇总进度
+    GroupStatus { group: String },
+
+    /// 暂停分组中没有结束的 upload，返回数量
+    PauseGroup { group: String },
+
+    /// 取消并删除分组中的所有 upload，返回数量
+    CancelGroup { group: String },
+
+    /// 把分组中排队的 upload 移到队列最前面或最后面，返回移动的数量
+    SetGroupPriority { group: String, priority: GroupPriority },
+
+    /// 立即开始指定的 upload，返回是否有空闲名额
+    Start { id: String },
+
+    /// 继续暂停或失败的 upload
+    Resume { id: String },
+
+    /// 等待开始的 upload 的顺序
+    Queue,
+
+    /// 已完成的 upload
+    Completed,
+
+    /// 按完整的 id 列表重新排列队列
+    ReorderQueue { ids: Vec<String> },
+
+    /// 重新开始一个失败的 upload
+    Retry { id: String },
+
+    /// 重新开始所有失败的 upload，返回数量
+    RetryFailed,
+
+    /// 取消 upload，开启 terminate_abandoned 时同时删除服务端资源
+    Cancel { id: String },
+
+    /// 从列表中删除 upload，delete_remote 时同时删除服务端资源
+    Remove {
+        id: String,
+
+        #[serde(default)]
+        delete_remote: bool,
+    },
+
+    /// 删除已经结束的 upload，status 为空时删除已完成和已取消的，返回数量
+    ClearCompletedUploads {
+        #[serde(default)]
+        status: Option<UploadStatus>,
+    },
+
+    /// 用新的文件重新开始 upload，pause_active 时先暂停正在上传的 upload
+    ReplaceSource {
+        id: String,
+        path: PathBuf,
+        #[serde(default)]
+        pause_active: bool,
+    },
+
+    /// 源文件被修改后放弃已有的进度，按文件当前的内容重新开始
+    ForceRestart { id: String },
+
+    ListBackups,
+
+    /// 检查设置界面的配置，不影响当前的 manager，skip_probe 时不连接服务端
+    ValidateConfig {
+        config: InitConfig,
+        #[serde(default)]
+        skip_probe: bool,
+    },
+
+    /// 用备份替换所有 upload，force 时先停止正在上传的 upload
+    RestoreBackup {
+        name: String,
+        #[serde(default)]
+        force: bool,
+    },
+
+    /// 立即检查暂停的 upload 在服务端的资源是否还存在
+    AuditNow,
+
+    /// 服务端声明的功能，profile 为空时使用默认的 endpoint
+    ServerCapabilities {
+        #[serde(default)]
+        profile: Option<String>,
+    },
+
+    /// 队列的活动状态
+    ActivityState,
+
+    /// 等待状态文件加载完成，返回数量统计，recovered 为上次被强制结束时中断的 upload 数量
+    StateLoaded,
+
+    /// 所有 upload 的数量、字节数、速度和预计剩余时间
+    GetTransferStats,
+
+    /// 确认当前所有的失败，清除 attention 状态
+    AcknowledgeFailures,
+
+    /// 修改总带宽限制，字节/秒，为空时取消限制
+    SetBandwidthLimit {
+        #[serde(default)]
+        limit: Option<u64>,
+    },
+
+    /// 推送刷新后的认证请求头，需要 manager 注册了认证信息
+    UpdateAuth { headers: HashMap<String, String> },
+
+    Status { id: String },
+
+    List,
+
+    /// 从 version 之后的变化，返回 tooOld 时改用 List
+    ChangesSince { version: u64 },
+
+    History,
+
+    /// 之后的事件推送到这个连接
+    Subscribe,
+
+    /// 应用退出前停止所有 upload 并写入状态，timeout_ms 为空时使用默认的等待时间
+    Shutdown {
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+}
+
+pub(crate) fn unauthorized() -> ErrorDto {
+    ErrorDto::new("unauthorized", "Connection is not authenticated")
+}
+
+pub(crate) fn bad_request(message: impl Into<String>) -> ErrorDto {
+    ErrorDto::new("bad_request", message)
+}
@@ -0,0 +1,271 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use crate::core::error::{ErrorDto, UploadResult};
+use crate::ipc::protocol::{bad_request, token_path, unauthorized, IpcCommand, IpcRequest};
+use crate::uploader::manager::UploadManager;
+use crate::uploader::discovery;
+
+/// 把 UploadManager 暴露在本地 socket 上
+pub struct IpcServer {
+    manager: Arc<UploadManager>,
+    token: String,
+    connections: TaskTracker,
+}
+
+impl IpcServer {
+    /// 生成新的 token 并写入状态文件夹，之前的 token 失效
+    pub async fn new(manager: Arc<UploadManager>) -> UploadResult<Self> {
+        let token = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+        write_token(&token_path(&manager.config().state_dir), &token).await?;
+
+        Ok(Self { manager, token, connections: TaskTracker::new() })
+    }
+
+    /// 在 Unix domain socket 上接受连接，直到 token 被取消
+    #[cfg(unix)]
+    pub async fn serve_unix(self: Arc<Self>, path: &Path, shutdown: CancellationToken) -> UploadResult<()> {
+        // 上一次运行留下的 socket 文件
+        if tokio::fs::try_exists(path).await? {
+            tokio::fs::remove_file(path).await?;
+        }
+        let listener = tokio::net::UnixListener::bind(path)?;
+
+        loop {
+            let stream = select! {
+                _ = shutdown.cancelled() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        log::warn!("Failed to accept ipc connection: {}", err);
+                        continue;
+                    }
+                },
+            };
+            self.connections.spawn(self.clone().handle_connection(stream, shutdown.clone()));
+        }
+
+        self.close().await;
+        let _ = tokio::fs::remove_file(path).await;
+        Ok(())
+    }
+
+    /// 在 Windows named pipe 上接受连接，直到 token 被取消
+    #[cfg(windows)]
+    pub async fn serve_named_pipe(self: Arc<Self>, name: &str, shutdown: CancellationToken) -> UploadResult<()> {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        let mut server = ServerOptions::new().first_pipe_instance(true).create(name)?;
+        loop {
+            select! {
+                _ = shutdown.cancelled() => break,
+                connected = server.connect() => connected?,
+            }
+            // 先创建下一个实例再处理当前连接，避免客户端连接时没有可用实例
+            let stream = std::mem::replace(&mut server, ServerOptions::new().create(name)?);
+            self.connections.spawn(self.clone().handle_connection(stream, shutdown.clone()));
+        }
+
+        self.close().await;
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.connections.close();
+        self.connections.wait().await;
+    }
+
+    /// 处理一个连接，响应和事件通过同一个写入任务按顺序发出
+    pub async fn handle_connection<S>(self: Arc<Self>, stream: S, shutdown: CancellationToken)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let (sender, mut outgoing) = mpsc::unbounded_channel::<Value>();
+        let write_task = tokio::spawn(async move {
+            while let Some(message) = outgoing.recv().await {
+                let mut line = message.to_string();
+                line.push('\n');
+                if writer.write_all(line.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        let connection_token = shutdown.child_token();
+        let mut authenticated = false;
+        let mut lines = BufReader::new(reader).lines();
+        loop {
+            let line = select! {
+                _ = connection_token.cancelled() => break,
+                line = lines.next_line() => match line {
+                    Ok(Some(line)) => line,
+                    _ => break,
+                },
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let request: IpcRequest = match serde_json::from_str(&line) {
+                Ok(request) => request,
+                Err(err) => {
+                    // 尽量带上请求 id，方便调用方对应
+                    let id = serde_json::from_str::<Value>(&line).ok().and_then(|value| value.get("id").cloned());
+                    let _ = sender.send(json!({ "id": id, "error": bad_request(err.to_string()) }));
+                    continue;
+                }
+            };
+
+            let result = match request.command {
+                IpcCommand::Auth { token } => {
+                    authenticated = token == self.token;
+                    if authenticated { Ok(Value::Null) } else { Err(unauthorized()) }
+                }
+                _ if !authenticated => Err(unauthorized()),
+                IpcCommand::Subscribe => {
+                    self.subscribe(sender.clone(), connection_token.clone());
+                    Ok(Value::Null)
+                }
+                command => self.dispatch(command).await,
+            };
+
+            let response = match result {
+                Ok(result) => json!({ "id": request.id, "result": result }),
+                Err(error) => json!({ "id": request.id, "error": error }),
+            };
+            if sender.send(response).is_err() {
+                break;
+            }
+        }
+
+        // 停止事件推送，写完剩余的消息
+        connection_token.cancel();
+        drop(sender);
+        let _ = write_task.await;
+    }
+
+    async fn dispatch(&self, command: IpcCommand) -> Result<Value, ErrorDto> {
+        let manager = &self.manager;
+        let result = match command {
+            IpcCommand::Add { path, options } => {
+                manager.add_upload_with_options(path, options).await.map(|outcome| json!(outcome))
+            }
+            IpcCommand::AddUploads { paths } => {
+                let added = manager.add_uploads(paths).await;
+                let results: Vec<Value> = added.results.into_iter()
+                    .map(|result| match result {
+                        Ok(outcome) => json!({ "result": outcome }),
+                        Err(err) => json!({ "error": ErrorDto::from(&err) }),
+                    })
+                    .collect();
+                Ok(json!({ "group": added.group, "results": results }))
+            }
+            IpcCommand::AddDirectoryUpload { path, options } => {
+                manager.add_directory(path, options).await.map(|added| json!(added))
+            }
+            IpcCommand::Pause { id } => manager.pause_upload(id).await.map(|_| Value::Null),
+            IpcCommand::GroupStatus { group } => manager.get_group_status(&group).await.map(|status| json!(status)),
+            IpcCommand::PauseGroup { group } => manager.pause_group(&group).await.map(|count| json!(count)),
+            IpcCommand::CancelGroup { group } => manager.cancel_group(&group).await.map(|count| json!(count)),
+            IpcCommand::SetGroupPriority { group, priority } => {
+                manager.set_group_priority(&group, priority).await.map(|count| json!(count))
+            }
+            IpcCommand::Start { id } => manager.start_upload(&id).await.map(|started| json!(started)),
+            IpcCommand::Resume { id } => manager.resume_upload(&id).await.map(|_| Value::Null),
+            IpcCommand::Completed => Ok(json!(manager.list_completed().await)),
+            IpcCommand::Queue => Ok(json!(manager.get_queue().await)),
+            IpcCommand::ReorderQueue { ids } => manager.reorder_queue(&ids).await.map(|_| Value::Null),
+            IpcCommand::Retry { id } => manager.retry_upload(&id).await.map(|_| Value::Null),
+            IpcCommand::RetryFailed => manager.retry_failed().await.map(|count| json!(count)),
+            IpcCommand::Remove { id, delete_remote } => manager.remove_upload(&id, delete_remote).await.map(|_| Value::Null),
+            IpcCommand::ClearCompletedUploads { status } => manager.clear_finished(status).await.map(|count| json!(count)),
+            IpcCommand::Cancel { id } => manager.cancel_upload(&id).await.map(|_| Value::Null),
+            IpcCommand::ReplaceSource { id, path, pause_active } => {
+                manager.replace_source(&id, path, pause_active).await.map(|_| Value::Null)
+            }
+            IpcCommand::ForceRestart { id } => manager.force_restart(&id).await.map(|_| Value::Null),
+            IpcCommand::ValidateConfig { config, skip_probe } => {
+                Ok(json!(discovery::validate_config(&config, !skip_probe).await))
+            }
+            IpcCommand::ListBackups => manager.list_backups().await.map(|backups| json!(backups)),
+            IpcCommand::RestoreBackup { name, force } => {
+                manager.restore_backup(&name, force).await.map(|loaded| json!(loaded))
+            }
+            IpcCommand::AuditNow => manager.audit_now().await.map(|summary| json!(summary)),
+            IpcCommand::ServerCapabilities { profile } => {
+                manager.get_server_capabilities(profile.as_deref()).await.map(|server| json!(server))
+            }
+            IpcCommand::ActivityState => Ok(json!(manager.get_activity_state().await)),
+            IpcCommand::StateLoaded => Ok(json!(manager.wait_state_loaded().await)),
+            IpcCommand::GetTransferStats => Ok(json!(manager.get_stats().await)),
+            IpcCommand::AcknowledgeFailures => manager.acknowledge_failures().await.map(|count| json!(count)),
+            IpcCommand::SetBandwidthLimit { limit } => manager.set_bandwidth_limit(limit).map(|_| Value::Null),
+            IpcCommand::UpdateAuth { headers } => manager.update_auth(headers).await.map(|_| Value::Null),
+            IpcCommand::Status { id } => manager.get_upload_status(&id).await.map(|status| json!(status)),
+            IpcCommand::List => Ok(json!(manager.list_upload_statuses().await)),
+            IpcCommand::ChangesSince { version } => Ok(json!(manager.get_changes_since(version).await)),
+            IpcCommand::History => manager.get_history().await.map(|history| json!(history)),
+            IpcCommand::Shutdown { timeout_ms: Some(timeout) } => {
+                manager.shutdown_with_timeout(Duration::from_millis(timeout)).await.map(|_| Value::Null)
+            }
+            IpcCommand::Shutdown { timeout_ms: None } => manager.shutdown().await.map(|_| Value::Null),
+            IpcCommand::Auth { .. } | IpcCommand::Subscribe => unreachable!("handled by the connection"),
+        };
+        result.map_err(ErrorDto::from)
+    }
+
+    /// 把事件转发到连接，直到连接关闭
+    fn subscribe(&self, sender: mpsc::UnboundedSender<Value>, token: CancellationToken) {
+        let mut receiver = self.manager.subscribe();
+        self.connections.spawn(async move {
+            loop {
+                let event = select! {
+                    _ = token.cancelled() => return,
+                    event = receiver.recv() => match event {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(skipped)) => {
+                            log::warn!("Ipc subscriber skipped {} events", skipped);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => return,
+                    },
+                };
+                if sender.send(json!({ "event": event })).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+/// 写入 token，只有当前用户可读写
+async fn write_token(path: &Path, token: &str) -> UploadResult<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+
+    // 文件已经存在时 mode 不会生效
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).await?;
+    }
+
+    file.write_all(token.as_bytes()).await?;
+    file.flush().await?;
+    Ok(())
+}
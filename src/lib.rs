@@ -1,4 +1,14 @@
 #![allow(warnings, warnings)]
 
-mod core;
-mod uploader;
\ No newline at end of file
+pub mod core;
+pub mod uploader;
+
+#[cfg(feature = "ipc")]
+pub mod ipc;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+#[cfg(test)]
+#[path = "../tests/support/tus_server.rs"]
+mod tus_server;
\ No newline at end of file
@@ -0,0 +1,99 @@
+//! 测试工具
+//!
+//! 需要控制时间的测试使用 `#[tokio::test(start_paused = true)]` 和 `TestClock`：
+//! 把 clock 传给 `UploadManager::new_with`、`UploadWorker::with_clock` 等，然后用 `advance`
+//! 推进时间。所有任务都在等待时 tokio 会自动把时间推进到下一个定时器，所以等待中的代码不会真的睡眠。
+//! 需要确定的随机数时用 `Rng::seeded` 创建。
+//! 连接真实服务端的测试需要把 `connect_timeout`、`request_timeout` 和 `stall_timeout` 设为 0：等待响应时没有其他任务，
+//! tokio 会直接把时间推进到超时。
+use std::sync::Mutex;
+use std::time::Duration;
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use tokio::time::Instant;
+use crate::core::clock::Clock;
+
+/// 跟随 tokio 时间的时钟
+///
+/// 单调时间就是 tokio 的时间，暂停后只在 advance 或自动推进时前进；墙上时间从创建时开始，随单调时间一起前进，
+/// 也可以用 shift 单独调整，例如模拟系统时间被修改
+#[derive(Debug)]
+pub struct TestClock {
+    origin: Instant,
+    origin_utc: DateTime<Utc>,
+    shift: Mutex<TimeDelta>,
+}
+
+impl TestClock {
+    pub fn new() -> Self {
+        Self::starting_at(Utc::now())
+    }
+
+    /// 墙上时间从 start 开始
+    pub fn starting_at(start: DateTime<Utc>) -> Self {
+        Self {
+            origin: Instant::now(),
+            origin_utc: start,
+            shift: Mutex::new(TimeDelta::zero()),
+        }
+    }
+
+    /// 推进时间，到期的定时器在返回前触发，需要先暂停 tokio 的时间
+    pub async fn advance(&self, duration: Duration) {
+        tokio::time::advance(duration).await;
+    }
+
+    /// 只调整墙上时间，单调时间不变
+    pub fn shift(&self, delta: TimeDelta) {
+        *self.shift.lock().unwrap() += delta;
+    }
+
+    /// 创建以来经过的单调时间
+    pub fn elapsed(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+impl Default for TestClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Clock for TestClock {
+    fn now_utc(&self) -> DateTime<Utc> {
+        let elapsed = TimeDelta::from_std(self.origin.elapsed()).unwrap_or(TimeDelta::MAX);
+        self.origin_utc + elapsed + *self.shift.lock().unwrap()
+    }
+
+    fn now_instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_clock_follows_paused_time() {
+        let start = Utc::now();
+        let clock = TestClock::starting_at(start);
+        clock.advance(Duration::from_secs(90)).await;
+        assert_eq!(clock.now_utc(), start + TimeDelta::seconds(90));
+
+        // 自动推进：没有其他任务时直接跳到定时器到期
+        let real = std::time::Instant::now();
+        clock.sleep(Duration::from_secs(3600)).await;
+        assert!(real.elapsed() < Duration::from_secs(1));
+        assert_eq!(clock.elapsed(), Duration::from_secs(3690));
+
+        clock.shift(TimeDelta::days(-1));
+        assert_eq!(clock.now_utc(), start + TimeDelta::seconds(3690) - TimeDelta::days(1));
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::select;
+use tokio::sync::{Mutex, Notify};
+use tokio_util::sync::CancellationToken;
+use crate::core::error::UploadResult;
+use crate::core::event::{ActivityState, EventBus, UploadEvent};
+use crate::core::state::UploadStateManager;
+use crate::core::upload::UploadStatus;
+
+/// 已确认的失败保存的文件名称
+pub(crate) const ACKNOWLEDGED_FILE_NAME: &str = "acknowledged_failures.json";
+
+#[derive(Debug, Default)]
+struct Current {
+    state: ActivityState,
+
+    /// 已经确认过的失败 upload
+    acknowledged: HashSet<String>,
+}
+
+/// 根据队列、正在上传和失败的 upload 计算活动状态，变化时发出 ActivityChanged
+///
+/// 状态写入后重新计算，不在状态中的正在上传的 upload 单独计数
+pub(crate) struct ActivityMonitor {
+    upload_state: Arc<UploadStateManager>,
+    events: Arc<EventBus>,
+    path: PathBuf,
+
+    /// 已经出队的 upload，放回状态之后不再计入
+    running: std::sync::Mutex<HashSet<String>>,
+
+    /// 状态之外的变化
+    touched: Notify,
+
+    current: Mutex<Current>,
+}
+
+impl ActivityMonitor {
+    pub async fn new(
+        state_dir: &Path,
+        upload_state: Arc<UploadStateManager>,
+        events: Arc<EventBus>,
+    ) -> Self {
+        let path = state_dir.join(ACKNOWLEDGED_FILE_NAME);
+        let acknowledged = match tokio::fs::read(&path).await {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|err| {
+                log::warn!("Ignoring unreadable acknowledged failures: {}", err);
+                HashSet::new()
+            }),
+            Err(_) => HashSet::new(),
+        };
+
+        Self {
+            upload_state,
+            events,
+            path,
+            running: std::sync::Mutex::new(HashSet::new()),
+            touched: Notify::new(),
+            current: Mutex::new(Current { state: ActivityState::Idle, acknowledged }),
+        }
+    }
+
+    /// upload 出队，在持有状态锁时调用，避免计算时看不到它
+    pub fn started(&self, id: &str) {
+        self.running.lock().unwrap().insert(id.to_string());
+    }
+
+    /// 出队的 upload 已经放回状态或者被丢弃
+    pub fn finished(&self, id: &str) {
+        self.running.lock().unwrap().remove(id);
+        self.touch();
+    }
+
+    /// 状态之外的变化，例如停止了正在上传的 upload
+    pub fn touch(&self) {
+        self.touched.notify_one();
+    }
+
+    /// 每次变化后重新计算，直到取消
+    pub async fn run(&self, token: CancellationToken) {
+        loop {
+            self.refresh().await;
+            select! {
+                _ = token.cancelled() => return,
+                _ = self.upload_state.wait_changed() => {}
+                _ = self.touched.notified() => {}
+            }
+        }
+    }
+
+    /// 重新计算活动状态，变化时发出事件
+    pub async fn refresh(&self) -> ActivityState {
+        let mut current = self.current.lock().await;
+
+        // 先读取状态再读取出队的 upload，upload 在它们之间移动时至少被计入一次；
+        // 已经放回状态的 upload 按状态中的记录计算，等待重试的也在状态中
+        let uploads = self.upload_state.list().await;
+        let known: HashSet<&str> = uploads.iter().map(|upload| upload.id.as_str()).collect();
+        let running = self.running.lock().unwrap().iter().any(|id| !known.contains(id.as_str()));
+        let busy = running || uploads.iter()
+            .any(|upload| matches!(upload.status, UploadStatus::Pending | UploadStatus::WaitingRetry));
+
+        // 不再失败（重试或删除）的 upload 之后再失败需要重新提示
+        let failed: HashSet<&str> = uploads.iter()
+            .filter(|upload| upload.status == UploadStatus::Failed)
+            .map(|upload| upload.id.as_str())
+            .collect();
+        let before = current.acknowledged.len();
+        current.acknowledged.retain(|id| failed.contains(id.as_str()));
+        if current.acknowledged.len() != before {
+            self.persist(&current.acknowledged).await;
+        }
+
+        let state = if failed.iter().any(|id| !current.acknowledged.contains(*id)) {
+            ActivityState::Attention
+        } else if busy {
+            ActivityState::Working
+        } else {
+            ActivityState::Idle
+        };
+        if state != current.state {
+            current.state = state;
+            self.events.emit(UploadEvent::ActivityChanged { state });
+        }
+        state
+    }
+
+    /// 确认当前所有的失败，返回新确认的数量
+    pub async fn acknowledge(&self) -> UploadResult<usize> {
+        let failed: Vec<String> = self.upload_state.list().await.into_iter()
+            .filter(|upload| upload.status == UploadStatus::Failed)
+            .map(|upload| upload.id)
+            .collect();
+
+        let acknowledged = {
+            let mut current = self.current.lock().await;
+            let before = current.acknowledged.len();
+            current.acknowledged.extend(failed);
+            let acknowledged = current.acknowledged.len() - before;
+            if acknowledged > 0 {
+                write_acknowledged(&self.path, &current.acknowledged).await?;
+            }
+            acknowledged
+        };
+        self.refresh().await;
+        Ok(acknowledged)
+    }
+
+    async fn persist(&self, acknowledged: &HashSet<String>) {
+        if let Err(err) = write_acknowledged(&self.path, acknowledged).await {
+            log::error!("Failed to persist acknowledged failures: {}", err);
+        }
+    }
+}
+
+async fn write_acknowledged(path: &Path, acknowledged: &HashSet<String>) -> UploadResult<()> {
+    let mut ids: Vec<&String> = acknowledged.iter().collect();
+    ids.sort();
+    let temp = path.with_extension("json.tmp");
+    tokio::fs::write(&temp, serde_json::to_vec(&ids)?).await?;
+    tokio::fs::rename(&temp, path).await?;
+    Ok(())
+}
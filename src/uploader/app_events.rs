@@ -0,0 +1,207 @@
+//! 推送给前端的应用事件
+//!
+//! 前端不需要轮询 `get_upload_status`，初始化 manager 后用 `AppEventBridge::spawn` 订阅，
+//! Tauri 中 `AppEmitter` 由 `AppHandle::emit_all` 实现。事件名称是对外接口的一部分：
+//!
+//! - `upload://progress`：服务端确认了新的数据，同一个 upload 按 `progress_event_interval` 限制频率，
+//!   最后一块的进度总是发出
+//! - `upload://state-changed`：添加、开始、继续、暂停、完成、失败、取消和删除，不限制频率
+//!
+//! 内容是 upload 的状态（与 `get_upload_status` 相同的字段）加上 `kind`，例如
+//! `{"kind": "completed", "id": "...", "status": "Completed", "bytes_transferred": 3000, ...}`。
+use std::collections::HashMap;
+use std::sync::Arc;
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use crate::core::event::UploadEvent;
+use crate::uploader::manager::UploadManager;
+use crate::uploader::status::UploadStatusInfo;
+
+/// 进度事件的名称
+pub const PROGRESS_EVENT: &str = "upload://progress";
+
+/// 状态变化事件的名称
+pub const STATE_CHANGED_EVENT: &str = "upload://state-changed";
+
+/// 向所有窗口发送事件，例如 Tauri 的 AppHandle
+pub trait AppEmitter: Send + Sync + 'static {
+    fn emit_all(&self, event: &str, payload: &AppEvent);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AppEventKind {
+    Added,
+    Started,
+    Resumed,
+    Progress,
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+    Removed,
+}
+
+impl AppEventKind {
+    /// 对应的 upload 事件，其他事件不推送
+    fn of(event: &UploadEvent) -> Option<Self> {
+        Some(match event {
+            UploadEvent::Added { .. } => AppEventKind::Added,
+            UploadEvent::Started { .. } => AppEventKind::Started,
+            UploadEvent::Resumed { .. } => AppEventKind::Resumed,
+            UploadEvent::Progress { .. } => AppEventKind::Progress,
+            UploadEvent::Paused { .. } => AppEventKind::Paused,
+            UploadEvent::Completed { .. } => AppEventKind::Completed,
+            UploadEvent::Failed { .. } => AppEventKind::Failed,
+            UploadEvent::Cancelled { .. } => AppEventKind::Cancelled,
+            UploadEvent::Removed { .. } => AppEventKind::Removed,
+            _ => return None,
+        })
+    }
+
+    /// 发送时使用的事件名称
+    pub fn event_name(self) -> &'static str {
+        match self {
+            AppEventKind::Progress => PROGRESS_EVENT,
+            _ => STATE_CHANGED_EVENT,
+        }
+    }
+}
+
+/// 推送的内容
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AppEvent {
+    pub kind: AppEventKind,
+
+    #[serde(flatten)]
+    pub status: UploadStatusInfo,
+}
+
+/// 把 manager 的事件转换为应用事件，直到被丢弃
+pub struct AppEventBridge {
+    handle: JoinHandle<()>,
+}
+
+impl AppEventBridge {
+    pub fn spawn(manager: Arc<UploadManager>, emitter: impl AppEmitter) -> Self {
+        let mut receiver = manager.subscribe();
+        let handle = tokio::spawn(async move {
+            // 删除后查询不到 upload，使用最后一次推送的状态
+            let mut last: HashMap<String, UploadStatusInfo> = HashMap::new();
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(event) => event.event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("App event bridge skipped {} events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                let Some(kind) = AppEventKind::of(&event) else {
+                    continue;
+                };
+
+                let id = event.upload_id();
+                let status = match kind {
+                    AppEventKind::Removed => last.remove(id),
+                    _ => match manager.get_upload_status(id).await {
+                        Ok(status) => {
+                            last.insert(id.to_string(), status.clone());
+                            Some(status)
+                        }
+                        // 取消分组时同时删除了 upload
+                        Err(_) => last.remove(id),
+                    },
+                };
+                let Some(status) = status else {
+                    continue;
+                };
+                emitter.emit_all(kind.event_name(), &AppEvent { kind, status });
+            }
+        });
+
+        Self { handle }
+    }
+}
+
+impl Drop for AppEventBridge {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use crate::core::config::InitConfig;
+    use crate::core::upload::UploadStatus;
+    use crate::tus_server::TusServer;
+
+    /// 记录发送的事件，代替 Tauri 的 AppHandle
+    #[derive(Default, Clone)]
+    struct MockApp {
+        emitted: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+    }
+
+    impl AppEmitter for MockApp {
+        fn emit_all(&self, event: &str, payload: &AppEvent) {
+            self.emitted.lock().unwrap().push((event.to_string(), serde_json::to_value(payload).unwrap()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_events_reach_app() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let init = InitConfig {
+            endpoint: server.endpoint(),
+            chunk_size: Some(1024),
+            state_dir: Some(state_dir.path().to_path_buf()),
+            progress_interval_ms: Some(60_000),
+            ..Default::default()
+        };
+        let manager = Arc::new(UploadManager::new(init.to_config()).await.unwrap());
+        let app = MockApp::default();
+        let _bridge = AppEventBridge::spawn(manager.clone(), app.clone());
+        let run = manager.run().unwrap();
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, &[7u8; 3000]).unwrap();
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+
+        // 最后一块的进度可能在完成之后才推送
+        let done = |emitted: &[(String, serde_json::Value)]| {
+            emitted.iter().any(|(_, payload)| payload["kind"] == "completed")
+                && emitted.iter().any(|(_, payload)| payload["kind"] == "progress" && payload["bytes_transferred"] == 3000)
+        };
+        for _ in 0..100 {
+            if done(&app.emitted.lock().unwrap()) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let emitted = app.emitted.lock().unwrap().clone();
+        assert!(done(&emitted), "{:?}", emitted);
+        let kinds = |name: &str| -> Vec<String> {
+            emitted.iter()
+                .filter(|(event, _)| event == name)
+                .map(|(_, payload)| payload["kind"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(kinds(STATE_CHANGED_EVENT), ["added", "started", "completed"]);
+        // 间隔内的进度被合并，第一块和最后一块都会推送
+        assert_eq!(kinds(PROGRESS_EVENT), ["progress", "progress"]);
+
+        let (_, completed) = emitted.iter().find(|(_, payload)| payload["kind"] == "completed").unwrap();
+        assert_eq!(completed["id"], serde_json::json!(id));
+        assert_eq!(completed["status"], serde_json::json!(UploadStatus::Completed));
+        assert_eq!(completed["percent"], 100);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use crate::core::config::TusConfig;
+use crate::core::error::{UploadError, UploadResult};
+use crate::core::event::{CorrectionReason, EventBus, UploadEvent};
+use crate::core::headers;
+use crate::core::state::UploadStateManager;
+use crate::core::tls;
+use crate::core::upload::{Upload, UploadStatus};
+use crate::uploader::status::{ProgressReporter, StatusCache};
+use crate::uploader::worker::send_following;
+
+/// 一次检查的结果
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditSummary {
+    /// 得到明确结果的 upload 数
+    pub checked: usize,
+
+    /// 资源已经不存在的 upload
+    pub missing: Vec<String>,
+
+    /// 其中丢弃了进度的 upload 数
+    pub reset: usize,
+
+    /// 因为开始上传或网络断开提前结束
+    pub interrupted: bool,
+}
+
+/// 一个 HEAD 请求的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Probe {
+    Present,
+    Missing,
+
+    /// 服务端返回了其他错误，这次不做判断
+    Unknown,
+
+    /// 无法连接或超时，可能已经离线
+    Unreachable,
+}
+
+/// 保存检查结果后 upload 的变化
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recorded {
+    Unchanged,
+    Missing,
+
+    /// 资源不存在并且丢弃了这么多字节的进度
+    Reset(u64),
+}
+
+/// 检查暂停和阻塞的 upload 在服务端的资源是否还存在
+///
+/// 使用独立的 client 和很低的并发，不占用 upload 的并发名额；
+/// 检查期间开始上传或者网络断开时立即停止
+pub(crate) struct LocationAudit {
+    config: TusConfig,
+    upload_state: Arc<UploadStateManager>,
+    events: Arc<EventBus>,
+    progress: Arc<ProgressReporter>,
+    status_cache: Arc<StatusCache>,
+
+    /// 同一时间只有一次检查
+    running: Mutex<()>,
+}
+
+impl LocationAudit {
+    pub fn new(
+        config: TusConfig,
+        upload_state: Arc<UploadStateManager>,
+        events: Arc<EventBus>,
+        progress: Arc<ProgressReporter>,
+        status_cache: Arc<StatusCache>,
+    ) -> Self {
+        Self { config, upload_state, events, progress, status_cache, running: Mutex::new(()) }
+    }
+
+    /// 执行一次检查，每一批请求发送前调用 idle，返回 false 时停止
+    pub async fn run<F: Future<Output = bool>>(&self, idle: impl Fn() -> F) -> UploadResult<AuditSummary> {
+        let _running = self.running.lock().await;
+        let policy = self.config.audit.unwrap_or_default();
+        let candidates: Vec<Upload> = self.upload_state.list().await.into_iter()
+            .filter(|upload| matches!(upload.status, UploadStatus::Paused | UploadStatus::Blocked))
+            .filter(|upload| upload.location.is_some())
+            .collect();
+
+        let mut clients: HashMap<Option<String>, (Client, TusConfig)> = HashMap::new();
+        let mut summary = AuditSummary::default();
+        for batch in candidates.chunks(policy.concurrency.max(1)) {
+            if !idle().await {
+                summary.interrupted = true;
+                break;
+            }
+
+            let mut probes = JoinSet::new();
+            for upload in batch {
+                if !clients.contains_key(&upload.endpoint) {
+                    let config = self.config.for_profile(upload.endpoint.as_deref())?;
+                    clients.insert(upload.endpoint.clone(), (tls::client_for(&config)?, config));
+                }
+                let (client, config) = clients[&upload.endpoint].clone();
+                let (id, location) = (upload.id.clone(), upload.location.clone().unwrap());
+                probes.spawn(async move {
+                    let probe = probe(&client, &config, &location, policy.timeout).await;
+                    (id, location, probe)
+                });
+            }
+
+            let results = probes.join_all().await;
+            if results.iter().any(|(_, _, probe)| *probe == Probe::Unreachable) {
+                summary.interrupted = true;
+                break;
+            }
+            for (id, location, probe) in results {
+                if probe == Probe::Unknown {
+                    continue;
+                }
+                summary.checked += 1;
+                match self.record(&id, &location, probe == Probe::Missing, policy.reset_missing).await {
+                    Ok(Recorded::Unchanged) => {}
+                    Ok(Recorded::Missing) => summary.missing.push(id),
+                    Ok(Recorded::Reset(discarded)) => {
+                        if discarded > 0 {
+                            self.progress.correct(&id, discarded, 0, CorrectionReason::RemoteMissing);
+                        }
+                        summary.missing.push(id);
+                        summary.reset += 1;
+                    }
+                    Err(err) => log::warn!("Failed to record audit result of upload {}: {}", id, err),
+                }
+            }
+        }
+
+        self.events.emit(UploadEvent::AuditCompleted {
+            checked: summary.checked,
+            missing: summary.missing.len(),
+            reset: summary.reset,
+            interrupted: summary.interrupted,
+        });
+        Ok(summary)
+    }
+
+    /// 保存检查结果，资源不存在并且允许时丢弃进度
+    async fn record(&self, id: &str, location: &str, missing: bool, reset: bool) -> UploadResult<Recorded> {
+        let (_, recorded) = self.upload_state.update(id, |upload| {
+            // 检查期间开始上传或重新创建的 upload 不处理
+            if !matches!(upload.status, UploadStatus::Paused | UploadStatus::Blocked)
+                || upload.location.as_deref() != Some(location)
+            {
+                return Ok(Recorded::Unchanged);
+            }
+
+            upload.remote_missing = missing;
+            if !missing {
+                return Ok(Recorded::Unchanged);
+            }
+            if !reset {
+                return Ok(Recorded::Missing);
+            }
+            upload.location = None;
+            upload.verification = None;
+            Ok(Recorded::Reset(std::mem::take(&mut upload.progress.bytes_transferred)))
+        }).await?;
+        self.status_cache.invalidate(id);
+        Ok(recorded)
+    }
+}
+
+async fn probe(client: &Client, config: &TusConfig, location: &str, timeout: std::time::Duration) -> Probe {
+    let mut request = client.head(location)
+        .header(headers::TUS_RESUMABLE, headers::TUS_VERSION)
+        .timeout(timeout);
+    for (name, value) in &config.headers {
+        request = request.header(name, value);
+    }
+
+    let response = match request.build() {
+        Ok(request) => send_following(client, config, request).await.map(|redirected| redirected.response),
+        Err(err) => Err(err.into()),
+    };
+    match response {
+        Ok(response) if response.status().is_success() => Probe::Present,
+        // 过期的资源返回 404 或 410
+        Ok(response) if matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::GONE) => Probe::Missing,
+        Ok(_) => Probe::Unknown,
+        Err(UploadError::NetworkError(err)) if err.is_connect() || err.is_timeout() => Probe::Unreachable,
+        Err(UploadError::ProxyFailed { .. }) => Probe::Unreachable,
+        Err(_) => Probe::Unknown,
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::{HashMap, VecDeque};
+use serde::Serialize;
+use tokio::sync::{Mutex, MutexGuard};
+use crate::core::capabilities::Capabilities;
+use crate::core::error::ErrorDto;
+use crate::core::upload::UploadStatus;
+use crate::uploader::status::UploadStatusInfo;
+
+/// 保留最近多少个版本的变化，更早的基准版本需要重新获取完整列表
+pub const CHANGE_HISTORY_LEN: usize = 256;
+
+/// 版本之间的变化
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum UploadChanges {
+    Diff {
+        added: Vec<UploadStatusInfo>,
+        removed: Vec<String>,
+        changed: Vec<UploadStatusInfo>,
+        version: u64,
+    },
+
+    /// 基准版本太旧（或来自之前的进程），需要重新获取完整列表，之后从 version 开始查询
+    TooOld {
+        version: u64,
+    },
+}
+
+/// 会改变列表显示的字段，进度、速度和耗时的变化不算
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Structure {
+    status: UploadStatus,
+    total_bytes: u64,
+    blocked_reason: Option<String>,
+    last_error: Option<ErrorDto>,
+    endpoint: Option<String>,
+    group: Option<String>,
+    part_index: Option<u32>,
+    capabilities: Capabilities,
+    digest: Option<String>,
+    remote_missing: bool,
+}
+
+impl From<&UploadStatusInfo> for Structure {
+    fn from(info: &UploadStatusInfo) -> Self {
+        Self {
+            status: info.status,
+            total_bytes: info.total_bytes,
+            blocked_reason: info.blocked_reason.clone(),
+            last_error: info.last_error.clone(),
+            endpoint: info.endpoint.clone(),
+            group: info.group.clone(),
+            part_index: info.part_index,
+            capabilities: info.capabilities,
+            digest: info.digest.clone(),
+            remote_missing: info.remote_missing,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Default)]
+struct ChangeLogState {
+    version: u64,
+
+    /// 上一个版本中每个 upload 的结构
+    known: HashMap<String, Structure>,
+
+    /// 每个版本相对上一个版本的变化，最旧的在前
+    history: VecDeque<(u64, Vec<(String, ChangeKind)>)>,
+}
+
+/// 列表的版本号，每次发现结构变化（添加、删除、状态变化等）时递增
+///
+/// 变化在读取版本时与上一次看到的列表比较得出，两次读取之间的多次变化合并为一个版本
+#[derive(Debug)]
+pub struct ChangeLog {
+    state: Mutex<ChangeLogState>,
+    capacity: usize,
+}
+
+impl Default for ChangeLog {
+    fn default() -> Self {
+        Self::new(CHANGE_HISTORY_LEN)
+    }
+}
+
+impl ChangeLog {
+    pub fn new(capacity: usize) -> Self {
+        Self { state: Mutex::new(ChangeLogState::default()), capacity }
+    }
+
+    /// 获取锁后再读取列表，避免并发的读取按错误的顺序记录
+    pub async fn lock(&self) -> ChangeLogGuard<'_> {
+        ChangeLogGuard { state: self.state.lock().await, capacity: self.capacity }
+    }
+}
+
+pub struct ChangeLogGuard<'a> {
+    state: MutexGuard<'a, ChangeLogState>,
+    capacity: usize,
+}
+
+impl ChangeLogGuard<'_> {
+    /// 记录当前的列表，有结构变化时递增版本，返回当前版本
+    pub fn observe(&mut self, statuses: &[UploadStatusInfo]) -> u64 {
+        let state = &mut *self.state;
+        let mut changes = Vec::new();
+        let mut current = HashMap::with_capacity(statuses.len());
+        for info in statuses {
+            let structure = Structure::from(info);
+            match state.known.get(&info.id) {
+                None => changes.push((info.id.clone(), ChangeKind::Added)),
+                Some(known) if *known != structure => changes.push((info.id.clone(), ChangeKind::Changed)),
+                Some(_) => {}
+            }
+            current.insert(info.id.clone(), structure);
+        }
+        for id in state.known.keys() {
+            if !current.contains_key(id) {
+                changes.push((id.clone(), ChangeKind::Removed));
+            }
+        }
+
+        state.known = current;
+        if !changes.is_empty() {
+            state.version += 1;
+            state.history.push_back((state.version, changes));
+            while state.history.len() > self.capacity {
+                state.history.pop_front();
+            }
+        }
+        state.version
+    }
+
+    /// 从 version 到当前版本的变化，调用前先 observe 同一个列表
+    pub fn since(&self, version: u64, statuses: &[UploadStatusInfo]) -> UploadChanges {
+        let state = &*self.state;
+        let oldest = state.history.front().map_or(state.version, |(oldest, _)| oldest - 1);
+        if version > state.version || version < oldest {
+            return UploadChanges::TooOld { version: state.version };
+        }
+
+        // 第一次出现的变化决定它在基准版本中是否存在
+        let mut existed: HashMap<&str, bool> = HashMap::new();
+        for (_, changes) in state.history.iter().filter(|(changed_at, _)| *changed_at > version) {
+            for (id, kind) in changes {
+                existed.entry(id.as_str()).or_insert(*kind != ChangeKind::Added);
+            }
+        }
+
+        let mut added = Vec::new();
+        let mut changed = Vec::new();
+        for info in statuses {
+            match existed.remove(info.id.as_str()) {
+                Some(true) => changed.push(info.clone()),
+                Some(false) => added.push(info.clone()),
+                None => {}
+            }
+        }
+        // 剩下的在当前列表中已经不存在
+        let mut removed: Vec<String> = existed.into_iter()
+            .filter(|(_, existed)| *existed)
+            .map(|(id, _)| id.to_string())
+            .collect();
+        removed.sort();
+
+        UploadChanges::Diff { added, removed, changed, version: state.version }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::upload::Upload;
+
+    fn status(id: &str, status: UploadStatus) -> UploadStatusInfo {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut upload = Upload::new(file.path().to_path_buf(), 10).unwrap();
+        upload.id = id.to_string();
+        let mut info = UploadStatusInfo::from(&upload);
+        info.status = status;
+        info
+    }
+
+    #[tokio::test]
+    async fn test_too_old_baseline() {
+        let log = ChangeLog::new(2);
+        let mut guard = log.lock().await;
+        let mut list = vec![status("a", UploadStatus::Pending)];
+        assert_eq!(guard.observe(&list), 1);
+
+        // 只有进度变化不产生新版本
+        list[0].bytes_transferred = 5;
+        assert_eq!(guard.observe(&list), 1);
+
+        list.push(status("b", UploadStatus::Pending));
+        assert_eq!(guard.observe(&list), 2);
+        list.remove(0);
+        assert_eq!(guard.observe(&list), 3);
+
+        assert_eq!(guard.since(0, &list), UploadChanges::TooOld { version: 3 });
+        assert_eq!(guard.since(4, &list), UploadChanges::TooOld { version: 3 });
+        assert_eq!(guard.since(1, &list), UploadChanges::Diff {
+            added: vec![list[0].clone()],
+            removed: vec!["a".to_string()],
+            changed: Vec::new(),
+            version: 3,
+        });
+        assert_eq!(guard.since(3, &list), UploadChanges::Diff {
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+            version: 3,
+        });
+    }
+}
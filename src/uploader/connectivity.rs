@@ -0,0 +1,139 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use crate::core::clock::Clock;
+use crate::core::config::TusConfig;
+use crate::core::event::{EventBus, UploadEvent};
+use crate::core::headers;
+use crate::core::tls;
+use crate::core::state::UploadStateManager;
+use crate::core::upload::{BlockCause, Upload, UploadStatus};
+use crate::uploader::worker::{is_tus_response, send_following};
+
+/// 被强制门户拦截的 upload 进入 Blocked 时记录的原因
+pub const CAPTIVE_PORTAL_REASON: &str = "Network requires sign-in (captive portal suspected)";
+
+/// 请求被强制门户拦截后，定期探测服务端，收到真正的 tus 响应后恢复被拦截的 upload
+pub(crate) struct ConnectivityWatcher {
+    config: TusConfig,
+    upload_state: Arc<UploadStateManager>,
+    events: Arc<EventBus>,
+    token: CancellationToken,
+    tasks: TaskTracker,
+    clock: Arc<dyn Clock>,
+
+    /// 正在探测，同一时间只有一个探测任务
+    probing: AtomicBool,
+}
+
+impl ConnectivityWatcher {
+    pub fn new(
+        config: TusConfig,
+        upload_state: Arc<UploadStateManager>,
+        events: Arc<EventBus>,
+        token: CancellationToken,
+        tasks: TaskTracker,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            config,
+            upload_state,
+            events,
+            token,
+            tasks,
+            clock,
+            probing: AtomicBool::new(false),
+        }
+    }
+
+    /// 请求被拦截，正在等待网络恢复
+    pub fn is_probing(&self) -> bool {
+        self.probing.load(Ordering::SeqCst)
+    }
+
+    /// 停止被拦截的 upload，等待用户登录后自动恢复
+    /// 探测这个 upload 使用的服务端地址
+    pub async fn intercepted(self: &Arc<Self>, mut upload: Upload) {
+        let upload_id = upload.id.clone();
+        let config = self.config.for_profile(upload.endpoint.as_deref()).unwrap_or_else(|_| self.config.clone());
+        let now = self.clock.now_utc();
+        let blocked = upload.transition_at(UploadStatus::Paused, now)
+            .and_then(|_| upload.block_at(BlockCause::CaptivePortal, CAPTIVE_PORTAL_REASON, now));
+        if blocked.is_ok() {
+            if let Err(err) = self.upload_state.shelve(upload).await {
+                log::error!("Failed to persist intercepted upload {}: {}", upload_id, err);
+            }
+        }
+
+        self.start_probe(config);
+    }
+
+    /// 加载状态后调用，上次运行中被拦截的 upload 还在等待恢复时继续探测
+    pub async fn resume(self: &Arc<Self>) {
+        let blocked = self.upload_state.list().await.into_iter().find(is_intercepted);
+        if let Some(upload) = blocked {
+            let config = self.config.for_profile(upload.endpoint.as_deref()).unwrap_or_else(|_| self.config.clone());
+            self.start_probe(config);
+        }
+    }
+
+    fn start_probe(self: &Arc<Self>, config: TusConfig) {
+        if self.probing.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.events.emit(UploadEvent::CaptivePortalSuspected { endpoint: config.endpoint.clone() });
+
+        let watcher = self.clone();
+        self.tasks.spawn(async move { watcher.probe(config).await });
+    }
+
+    async fn probe(&self, config: TusConfig) {
+        // 探测也要校验固定的证书，否则中间人可以伪造 tus 响应
+        let client = tls::client_for(&config).unwrap_or_default();
+        loop {
+            select! {
+                _ = self.token.cancelled() => return,
+                _ = self.clock.sleep(self.config.captive_portal_probe_interval) => {}
+            }
+
+            let mut request = client.request(reqwest::Method::OPTIONS, &config.endpoint)
+                .header(headers::TUS_RESUMABLE, headers::TUS_VERSION);
+            for (name, value) in &config.headers {
+                request = request.header(name, value);
+            }
+            let Ok(request) = request.build() else { continue };
+            match send_following(&client, &config, request).await {
+                Ok(redirected) if is_tus_response(&redirected.response) => break,
+                _ => continue,
+            }
+        }
+
+        // 先清除标记，恢复期间再次被拦截的 upload 会开始新的探测
+        self.probing.store(false, Ordering::SeqCst);
+        self.release().await;
+    }
+
+    /// 把因为拦截进入 Blocked 的 upload 重新排队
+    async fn release(&self) {
+        let mut released = 0;
+        for mut upload in self.upload_state.list().await {
+            if !is_intercepted(&upload) {
+                continue;
+            }
+            if upload.transition_at(UploadStatus::Pending, self.clock.now_utc()).is_ok() {
+                match self.upload_state.replace(upload).await {
+                    Ok(_) => released += 1,
+                    Err(err) => log::warn!("Failed to resume upload after sign-in: {}", err),
+                }
+            }
+        }
+        self.events.emit(UploadEvent::CaptivePortalCleared { released });
+    }
+}
+
+/// 因为强制门户拦截而进入 Blocked 的 upload
+fn is_intercepted(upload: &Upload) -> bool {
+    upload.status == UploadStatus::Blocked && upload.blocked_cause == Some(BlockCause::CaptivePortal)
+}
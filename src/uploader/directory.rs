@@ -0,0 +1,300 @@
+//! 添加整个文件夹
+//!
+//! 按名称顺序遍历，每个文件一个 upload。include 和 exclude 使用简单的 glob：`*` 和 `?` 不匹配 `/`，
+//! `**` 匹配任意层文件夹；不包含 `/` 的模式只和名称比较，否则和相对于根文件夹的路径比较。
+use std::collections::HashSet;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::core::error::{UploadError, UploadResult};
+use crate::core::options::AddUploadOptions;
+
+/// 记录相对路径的元数据 key
+pub const RELATIVE_PATH_KEY: &str = "relative_path";
+
+/// 添加文件夹时的参数
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AddDirectoryOptions {
+    /// 只添加匹配其中一个模式的文件，为空时添加所有文件
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    /// 跳过匹配其中一个模式的文件和文件夹
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// 最多进入几层子文件夹，0 时只添加直接位于文件夹中的文件，为空时不限制
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+
+    /// 进入符号链接指向的文件夹和文件，否则跳过符号链接
+    #[serde(default)]
+    pub follow_symlinks: bool,
+
+    /// 在元数据的 relative_path 中记录以 `/` 分隔的相对路径，服务端可以据此还原目录结构
+    #[serde(default)]
+    pub record_relative_path: bool,
+
+    /// 跳过隐藏的文件和文件夹：名称以 `.` 开头的，Windows 上还有带隐藏或系统属性的
+    #[serde(default)]
+    pub skip_hidden: bool,
+
+    /// 每个文件使用的参数
+    #[serde(default)]
+    pub upload: AddUploadOptions,
+}
+
+impl AddDirectoryOptions {
+    pub fn validate(&self) -> UploadResult<()> {
+        if self.include.iter().chain(&self.exclude).any(|pattern| pattern.trim_matches('/').is_empty()) {
+            return Err(UploadError::InvalidOptions("Glob pattern cannot be empty".into()));
+        }
+        self.upload.validate()
+    }
+}
+
+/// 添加文件夹的结果
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DirectoryAdded {
+    /// 文件所在的分组
+    pub group: String,
+
+    /// 新添加的 upload，按遍历的顺序
+    pub ids: Vec<String>,
+
+    /// 跳过的文件和文件夹：被过滤的、隐藏的、符号链接、超过深度的、已经在列表中的和读取或添加失败的
+    pub skipped: usize,
+}
+
+/// 要添加的文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DirectoryFile {
+    pub path: PathBuf,
+
+    /// 相对于根文件夹，以 `/` 分隔
+    pub relative: String,
+}
+
+/// 遍历文件夹，返回要添加的文件和跳过的数量
+/// 根文件夹不存在或不是文件夹时返回错误，子文件夹读取失败时只记录日志
+pub(crate) async fn collect(root: &Path, options: &AddDirectoryOptions) -> UploadResult<(Vec<DirectoryFile>, usize)> {
+    if !tokio::fs::metadata(root).await?.is_dir() {
+        return Err(UploadError::InvalidOptions(format!("Not a directory: {}", root.display())));
+    }
+
+    let mut files = Vec::new();
+    let mut skipped = 0;
+    // 跟随符号链接时同一个文件夹只进入一次，避免循环
+    let mut visited = HashSet::from([tokio::fs::canonicalize(root).await?]);
+    let mut pending = vec![(root.to_path_buf(), String::new(), 0usize)];
+    while let Some((dir, prefix, depth)) = pending.pop() {
+        let entries = match read_sorted(&dir).await {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::warn!("Failed to read directory {}: {}", dir.display(), err);
+                skipped += 1;
+                continue;
+            }
+        };
+
+        let mut subdirs = Vec::new();
+        for (name, path) in entries {
+            let relative = if prefix.is_empty() { name.clone() } else { format!("{}/{}", prefix, name) };
+            let Some(metadata) = entry_metadata(&path, options.follow_symlinks).await else {
+                skipped += 1;
+                continue;
+            };
+            if (options.skip_hidden && is_hidden(&name, &metadata))
+                || options.exclude.iter().any(|pattern| glob_match(pattern, &relative))
+            {
+                skipped += 1;
+                continue;
+            }
+
+            if metadata.is_dir() {
+                if options.max_depth.is_some_and(|max| depth >= max) {
+                    skipped += 1;
+                    continue;
+                }
+                let entered = tokio::fs::canonicalize(&path).await.is_ok_and(|canonical| visited.insert(canonical));
+                if entered {
+                    subdirs.push((path, relative, depth + 1));
+                } else {
+                    skipped += 1;
+                }
+            } else if metadata.is_file()
+                && (options.include.is_empty() || options.include.iter().any(|pattern| glob_match(pattern, &relative)))
+            {
+                files.push(DirectoryFile { path, relative });
+            } else {
+                skipped += 1;
+            }
+        }
+        // 倒序放入栈中，子文件夹按名称顺序遍历
+        pending.extend(subdirs.into_iter().rev());
+    }
+
+    Ok((files, skipped))
+}
+
+/// 文件夹中的条目，按名称排序
+async fn read_sorted(dir: &Path) -> std::io::Result<Vec<(String, PathBuf)>> {
+    let mut reader = tokio::fs::read_dir(dir).await?;
+    let mut entries = Vec::new();
+    while let Some(entry) = reader.next_entry().await? {
+        entries.push((entry.file_name().to_string_lossy().into_owned(), entry.path()));
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+/// 条目的类型；符号链接在不跟随或已经断开时返回 None
+async fn entry_metadata(path: &Path, follow_symlinks: bool) -> Option<Metadata> {
+    let metadata = tokio::fs::symlink_metadata(path).await.ok()?;
+    if !metadata.file_type().is_symlink() {
+        return Some(metadata);
+    }
+    if !follow_symlinks {
+        return None;
+    }
+    tokio::fs::metadata(path).await.ok()
+}
+
+fn is_hidden(name: &str, metadata: &Metadata) -> bool {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        const FILE_ATTRIBUTE_SYSTEM: u32 = 0x4;
+        if metadata.file_attributes() & (FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM) != 0 {
+            return true;
+        }
+    }
+    #[cfg(not(windows))]
+    let _ = metadata;
+    name.starts_with('.')
+}
+
+/// 不包含 `/` 的模式只和名称比较，否则和完整的相对路径比较
+fn glob_match(pattern: &str, relative: &str) -> bool {
+    let pattern = pattern.trim_start_matches('/');
+    if !pattern.contains('/') {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        return name_match(pattern, name);
+    }
+    let pattern: Vec<&str> = pattern.split('/').filter(|segment| !segment.is_empty()).collect();
+    let path: Vec<&str> = relative.split('/').collect();
+    segments_match(&pattern, &path)
+}
+
+fn segments_match(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| segments_match(rest, &path[skip..])),
+        Some((first, rest)) => path.split_first()
+            .is_some_and(|(name, path)| name_match(first, name) && segments_match(rest, path)),
+    }
+}
+
+/// 单个名称的匹配，`*` 匹配任意个字符，`?` 匹配一个字符
+fn name_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // 最近一个 `*` 之后的位置和它开始匹配的位置，失败时让它多匹配一个字符
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((after, start)) => {
+                    p = after;
+                    n = start + 1;
+                    star = Some((after, start + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.jpg", "photos/2024/a.jpg"));
+        assert!(!glob_match("*.jpg", "photos/a.jpeg"));
+        assert!(glob_match("?.txt", "a.txt"));
+        assert!(!glob_match("?.txt", "ab.txt"));
+        assert!(glob_match("photos/*.jpg", "photos/a.jpg"));
+        assert!(!glob_match("photos/*.jpg", "photos/2024/a.jpg"));
+        assert!(glob_match("photos/**/*.jpg", "photos/a.jpg"));
+        assert!(glob_match("photos/**/*.jpg", "photos/2024/01/a.jpg"));
+        assert!(glob_match("/build/**", "build"));
+        assert!(glob_match("**/node_modules", "web/app/node_modules"));
+        assert!(glob_match("a*b*c", "aXXbYYbc"));
+        assert!(!glob_match("a*b*c", "aXXbYYb"));
+        assert!(glob_match("照片*", "照片 01.png"));
+    }
+
+    #[tokio::test]
+    async fn test_collect_nested_tree() {
+        let root = tempfile::tempdir().unwrap();
+        for file in ["a.txt", "b.log", ".hidden", "sub/c.txt", "sub/deep/d.txt", "sub/deep/e.log", "tmp/f.txt", ".git/config"] {
+            let path = root.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, file).unwrap();
+        }
+        let relative = |files: Vec<DirectoryFile>| files.into_iter().map(|file| file.relative).collect::<Vec<_>>();
+
+        let (files, skipped) = collect(root.path(), &AddDirectoryOptions::default()).await.unwrap();
+        assert_eq!(relative(files), [
+            ".hidden", "a.txt", "b.log", ".git/config", "sub/c.txt", "sub/deep/d.txt", "sub/deep/e.log", "tmp/f.txt",
+        ]);
+        assert_eq!(skipped, 0);
+
+        let options = AddDirectoryOptions {
+            exclude: vec!["*.log".into(), "tmp".into()],
+            skip_hidden: true,
+            ..Default::default()
+        };
+        let (files, skipped) = collect(root.path(), &options).await.unwrap();
+        assert_eq!(relative(files), ["a.txt", "sub/c.txt", "sub/deep/d.txt"]);
+        // .hidden、.git、b.log、tmp 和 e.log
+        assert_eq!(skipped, 5);
+
+        let options = AddDirectoryOptions { include: vec!["sub/**/*.txt".into()], max_depth: Some(1), ..Default::default() };
+        let (files, _) = collect(root.path(), &options).await.unwrap();
+        assert_eq!(relative(files), ["sub/c.txt"]);
+
+        #[cfg(unix)]
+        {
+            // 指向上层的链接不会导致循环
+            std::os::unix::fs::symlink(root.path(), root.path().join("sub/loop")).unwrap();
+            std::os::unix::fs::symlink(root.path().join("a.txt"), root.path().join("link.txt")).unwrap();
+            let options = AddDirectoryOptions { include: vec!["*.txt".into()], ..Default::default() };
+            let (files, _) = collect(root.path(), &options).await.unwrap();
+            assert!(!relative(files).contains(&"link.txt".to_string()));
+
+            let options = AddDirectoryOptions { follow_symlinks: true, ..options };
+            let (files, skipped) = collect(root.path(), &options).await.unwrap();
+            assert!(relative(files).contains(&"link.txt".to_string()));
+            assert!(skipped >= 1);
+        }
+
+        let file = root.path().join("a.txt");
+        assert!(matches!(collect(&file, &AddDirectoryOptions::default()).await, Err(UploadError::InvalidOptions(_))));
+        let invalid = AddDirectoryOptions { exclude: vec!["/".into()], ..Default::default() };
+        assert!(invalid.validate().is_err());
+    }
+}
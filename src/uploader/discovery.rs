@@ -0,0 +1,281 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::Client;
+use serde::Serialize;
+use crate::core::config::{FieldIssue, InitConfig, TusConfig};
+use crate::core::error::{ErrorDto, UploadError, UploadResult};
+use crate::core::headers;
+use crate::core::tls;
+use crate::uploader::worker::{detect_interception, is_tus_response, read_error_body, send_following};
+
+/// 校验配置时 OPTIONS 探测的超时时间
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 缓存的服务端功能在这段时间后，下一次需要时重新探测
+pub const CAPABILITY_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// OPTIONS 响应中服务端声明的功能
+/// 参考 Tus 协议文档：https://tus.io/protocols/resumable-upload#options
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerCapabilities {
+    /// 支持的协议版本，优先的在前
+    pub versions: Vec<String>,
+
+    /// 支持的扩展，例如 creation、termination
+    pub extensions: Vec<String>,
+
+    /// 单个 upload 的最大字节数
+    pub max_size: Option<u64>,
+
+    /// checksum 扩展支持的算法
+    pub checksum_algorithms: Vec<String>,
+}
+
+impl ServerCapabilities {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let list = |name: &str| -> Vec<String> {
+            headers.get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(str::to_string).collect())
+                .unwrap_or_default()
+        };
+        Self {
+            versions: list(headers::TUS_VERSION_HEADER),
+            extensions: list(headers::TUS_EXTENSION),
+            max_size: headers.get(headers::TUS_MAX_SIZE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok()),
+            checksum_algorithms: list(headers::TUS_CHECKSUM_ALGORITHM),
+        }
+    }
+
+    pub fn supports(&self, extension: &str) -> bool {
+        self.extensions.iter().any(|supported| supported == extension)
+    }
+}
+
+/// 向 endpoint 发送 OPTIONS，读取服务端的功能
+pub async fn discover(client: &Client, config: &TusConfig) -> UploadResult<ServerCapabilities> {
+    let mut request = client.request(reqwest::Method::OPTIONS, &config.endpoint)
+        .header(headers::TUS_RESUMABLE, headers::TUS_VERSION);
+    for (name, value) in &config.headers {
+        request = request.header(name, value);
+    }
+
+    let response = send_following(client, config, request.build()?).await?.response;
+    detect_interception(&response)?;
+    if !response.status().is_success() {
+        return Err(read_error_body(response).await);
+    }
+    if !is_tus_response(&response) {
+        return Err(UploadError::Config(format!("{} did not respond like a tus server", config.endpoint)));
+    }
+    Ok(ServerCapabilities::from_headers(response.headers()))
+}
+
+/// 每个 endpoint 的服务端功能，第一次需要时发送 OPTIONS，超过 CAPABILITY_TTL 后再次需要时重新探测
+/// 网络错误和超时不缓存，下次需要时重新探测；服务端返回错误时按不支持任何扩展缓存
+#[derive(Debug, Default)]
+pub struct CapabilityCache {
+    entries: Mutex<HashMap<String, (ServerCapabilities, Instant)>>,
+
+    /// 同一时间只发送一个 OPTIONS，同时需要的调用方使用它的结果
+    probing: tokio::sync::Mutex<()>,
+
+    /// 永久重定向到新地址的 endpoint，只保存在内存中，重启后第一次创建时重新得到
+    moved: Mutex<HashMap<String, String>>,
+
+    /// 需要用 X-HTTP-Method-Override 发送 PATCH 和 DELETE 的 endpoint，只保存在内存中
+    method_override: Mutex<HashSet<String>>,
+}
+
+impl CapabilityCache {
+    pub async fn get(&self, client: &Client, config: &TusConfig) -> Option<ServerCapabilities> {
+        if let Some(capabilities) = self.fresh(&config.endpoint) {
+            return Some(capabilities);
+        }
+        let _probing = self.probing.lock().await;
+        if let Some(capabilities) = self.fresh(&config.endpoint) {
+            return Some(capabilities);
+        }
+
+        let capabilities = match tokio::time::timeout(PROBE_TIMEOUT, discover(client, config)).await {
+            Ok(Ok(capabilities)) => capabilities,
+            // 探测失败时继续使用过期的结果
+            Ok(Err(UploadError::NetworkError(_) | UploadError::ProxyFailed { .. })) | Err(_) => {
+                return self.peek(&config.endpoint)
+            }
+            Ok(Err(_)) => ServerCapabilities::default(),
+        };
+        self.entries.lock().unwrap().insert(config.endpoint.clone(), (capabilities.clone(), Instant::now()));
+        Some(capabilities)
+    }
+
+    fn fresh(&self, endpoint: &str) -> Option<ServerCapabilities> {
+        self.entries.lock().unwrap().get(endpoint)
+            .filter(|(_, fetched)| fetched.elapsed() < CAPABILITY_TTL)
+            .map(|(capabilities, _)| capabilities.clone())
+    }
+
+    /// 已经缓存的结果（包括过期的），不发送请求
+    pub fn peek(&self, endpoint: &str) -> Option<ServerCapabilities> {
+        self.entries.lock().unwrap().get(endpoint).map(|(capabilities, _)| capabilities.clone())
+    }
+
+    /// endpoint 被永久重定向后的地址
+    pub fn moved_to(&self, endpoint: &str) -> Option<String> {
+        self.moved.lock().unwrap().get(endpoint).cloned()
+    }
+
+    pub fn record_move(&self, endpoint: &str, target: &str) {
+        self.moved.lock().unwrap().insert(endpoint.to_string(), target.to_string());
+    }
+
+    /// 这个 endpoint 之前自动切换到了 X-HTTP-Method-Override
+    pub fn needs_method_override(&self, endpoint: &str) -> bool {
+        self.method_override.lock().unwrap().contains(endpoint)
+    }
+
+    pub fn require_method_override(&self, endpoint: &str) {
+        self.method_override.lock().unwrap().insert(endpoint.to_string());
+    }
+
+    /// 服务端声明了扩展但实际不支持，之后不再使用
+    pub fn withdraw(&self, endpoint: &str, extension: &str) {
+        if let Some((capabilities, _)) = self.entries.lock().unwrap().get_mut(endpoint) {
+            capabilities.extensions.retain(|supported| supported != extension);
+        }
+    }
+}
+
+/// 配置的检查结果
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigReport {
+    /// 没有任何错误，探测失败也算错误
+    pub valid: bool,
+    pub errors: Vec<FieldIssue>,
+
+    /// 可以使用但可能有问题的设置
+    pub warnings: Vec<FieldIssue>,
+
+    /// 探测成功时服务端的功能
+    pub server: Option<ServerCapabilities>,
+
+    /// 探测失败的原因
+    pub probe_error: Option<ErrorDto>,
+}
+
+/// 检查设置界面的配置，probe 时向服务端发送 OPTIONS
+/// 只读取配置，不创建任何状态，也不影响正在运行的 manager
+pub async fn validate_config(init: &InitConfig, probe: bool) -> ConfigReport {
+    let config = init.to_config();
+    let mut report = ConfigReport { errors: config.field_errors(), ..Default::default() };
+
+    let mut header_map = HeaderMap::new();
+    for (name, value) in &config.headers {
+        match (name.parse::<HeaderName>(), value.parse::<HeaderValue>()) {
+            (Ok(name), Ok(value)) => {
+                header_map.insert(name, value);
+            }
+            (Err(_), _) => report.errors.push(issue(format!("headers.{}", name), "Invalid header name")),
+            (_, Err(_)) => report.errors.push(issue(format!("headers.{}", name), "Invalid header value")),
+        }
+    }
+    if header_map.contains_key(headers::TUS_RESUMABLE) {
+        report.warnings.push(issue(format!("headers.{}", headers::TUS_RESUMABLE), "Tus-Resumable is set by the uploader"));
+    }
+
+    // 地址或请求头无效时探测没有意义
+    if probe && report.errors.is_empty() {
+        let result = async {
+            let client = tls::client_for(&config)?;
+            match tokio::time::timeout(PROBE_TIMEOUT, discover(&client, &config)).await {
+                Ok(result) => result,
+                Err(_) => Err(UploadError::Config(format!("{} did not respond within {:?}", config.endpoint, PROBE_TIMEOUT))),
+            }
+        }.await;
+        match result {
+            Ok(server) => {
+                report.warnings.extend(server_warnings(&config, &server));
+                report.server = Some(server);
+            }
+            Err(err) => report.probe_error = Some(ErrorDto::from(err)),
+        }
+    }
+
+    report.valid = report.errors.is_empty() && report.probe_error.is_none();
+    report
+}
+
+/// 服务端的功能与配置不匹配的地方
+fn server_warnings(config: &TusConfig, server: &ServerCapabilities) -> Vec<FieldIssue> {
+    let mut warnings = Vec::new();
+    if !server.versions.is_empty() && !server.versions.iter().any(|version| version == headers::TUS_VERSION) {
+        warnings.push(issue("endpoint", format!("Server does not advertise tus {}", headers::TUS_VERSION)));
+    }
+    if !server.supports("creation") {
+        warnings.push(issue("endpoint", "Server does not advertise the creation extension"));
+    }
+    if let Some(max_size) = server.max_size {
+        if config.chunk_size as u64 > max_size {
+            warnings.push(issue("chunk_size", format!("Chunk size is larger than the server maximum of {} bytes", max_size)));
+        }
+    }
+    warnings
+}
+
+fn issue(field: impl Into<String>, message: impl Into<String>) -> FieldIssue {
+    FieldIssue { field: field.into(), message: message.into() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tus_server::TusServer;
+
+    #[tokio::test]
+    async fn test_validate_config() {
+        // 字段错误全部报告，不会探测
+        let init = InitConfig {
+            endpoint: "ftp://example.com".into(),
+            headers: [("Bad Header".to_string(), "x".to_string())].into(),
+            chunk_size: Some(0),
+            max_concurrent: Some(0),
+            ..Default::default()
+        };
+        let report = validate_config(&init, true).await;
+        let fields: Vec<&str> = report.errors.iter().map(|issue| issue.field.as_str()).collect();
+        assert_eq!(fields, ["endpoint", "max_concurrent", "chunk_size", "headers.Bad Header"]);
+        assert!(!report.valid && report.probe_error.is_none() && report.server.is_none());
+
+        // 无法连接
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = format!("http://{}/files", listener.local_addr().unwrap());
+        drop(listener);
+        let report = validate_config(&InitConfig { endpoint: closed.clone(), ..Default::default() }, true).await;
+        assert!(report.errors.is_empty());
+        assert_eq!(report.probe_error.as_ref().unwrap().code, "network");
+        assert!(!report.valid);
+        let offline = validate_config(&InitConfig { endpoint: closed, ..Default::default() }, false).await;
+        assert!(offline.valid && offline.probe_error.is_none());
+
+        // 探测成功，块大小超过服务端的最大值
+        let server = TusServer::start().await;
+        server.enable_termination();
+        server.set_max_size(Some(1024));
+        let init = InitConfig { endpoint: server.endpoint(), chunk_size: Some(4096), ..Default::default() };
+        let report = validate_config(&init, true).await;
+        assert!(report.valid, "{:?}", report);
+        assert_eq!(report.server, Some(ServerCapabilities {
+            versions: vec!["1.0.0".into()],
+            extensions: vec!["creation".into(), "creation-defer-length".into(), "termination".into()],
+            max_size: Some(1024),
+            checksum_algorithms: Vec::new(),
+        }));
+        assert_eq!(report.warnings.iter().map(|issue| issue.field.as_str()).collect::<Vec<_>>(), ["chunk_size"]);
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use crate::core::event::{EventBus, SequencedEvent};
+use crate::uploader::manager::UploadManager;
+use crate::uploader::status::UploadStatusInfo;
+
+/// 事件的接收方，例如 webview
+pub trait EventSink: Send + Sync + 'static {
+    fn emit(&self, event: &SequencedEvent);
+}
+
+/// 前端重新注册监听后的同步结果
+#[derive(Debug, Clone, Serialize)]
+pub struct EventSync {
+    /// 每个 upload 已经包含在快照中的事件序号，前端忽略不大于它的事件
+    pub watermarks: HashMap<String, u64>,
+
+    /// 所有 upload 的当前状态
+    pub uploads: Vec<UploadStatusInfo>,
+}
+
+/// 把事件转发给 EventSink，并处理前端重新加载
+///
+/// 前端重新注册监听后调用 sync，之后不再转发序号不大于返回的 watermark 的事件，
+/// 同一个事件也不会被转发两次
+pub struct EventForwarder {
+    /// 每个 upload 已经转发或者已经包含在快照中的最大序号
+    delivered: Arc<Mutex<HashMap<String, u64>>>,
+    bus: Arc<EventBus>,
+    handle: JoinHandle<()>,
+}
+
+impl EventForwarder {
+    pub fn spawn(bus: Arc<EventBus>, sink: impl EventSink) -> Self {
+        let (mut receiver, watermarks) = bus.subscribe_with_watermarks();
+        let delivered = Arc::new(Mutex::new(watermarks));
+
+        let task_delivered = delivered.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("Event forwarder skipped {} events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+
+                if forward(&task_delivered, &event) {
+                    sink.emit(&event);
+                }
+            }
+        });
+
+        Self { delivered, bus, handle }
+    }
+
+    /// 确认前端已经拥有到当前为止的状态，返回每个 upload 的 watermark
+    pub fn acknowledge(&self) -> HashMap<String, u64> {
+        let watermarks = self.bus.watermarks();
+        let mut delivered = self.delivered.lock().unwrap();
+        for (id, seq) in &watermarks {
+            let entry = delivered.entry(id.clone()).or_default();
+            *entry = (*entry).max(*seq);
+        }
+        watermarks
+    }
+
+    /// 前端注册监听后调用，返回带有 watermark 的状态快照
+    /// 先确认 watermark 再读取状态，快照至少包含 watermark 之前的所有事件
+    pub async fn sync(&self, manager: &UploadManager) -> EventSync {
+        let watermarks = self.acknowledge();
+        let uploads = manager.list_upload_statuses().await;
+        EventSync { watermarks, uploads }
+    }
+}
+
+impl Drop for EventForwarder {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// 序号大于已转发的序号时才转发，并记录下来
+fn forward(delivered: &Mutex<HashMap<String, u64>>, event: &SequencedEvent) -> bool {
+    let mut delivered = delivered.lock().unwrap();
+    let last = delivered.entry(event.event.upload_id().to_string()).or_default();
+    if event.seq <= *last {
+        return false;
+    }
+    *last = event.seq;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::core::event::UploadEvent;
+
+    #[derive(Default, Clone)]
+    struct RecordingSink {
+        events: Arc<Mutex<Vec<SequencedEvent>>>,
+    }
+
+    impl EventSink for RecordingSink {
+        fn emit(&self, event: &SequencedEvent) {
+            self.events.lock().unwrap().push(event.clone());
+        }
+    }
+
+    fn conflict(id: &str) -> UploadEvent {
+        UploadEvent::IdConflict { id: id.to_string() }
+    }
+
+    #[tokio::test]
+    async fn test_no_event_at_or_below_watermark_is_redelivered() {
+        let bus = Arc::new(EventBus::default());
+        let sink = RecordingSink::default();
+
+        // 转发器启动前的事件属于初始快照
+        bus.emit(conflict("a"));
+        let forwarder = EventForwarder::spawn(bus.clone(), sink.clone());
+
+        bus.emit(conflict("a"));
+        bus.emit(conflict("b"));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        // 模拟前端重新加载：还没转发的事件已经包含在确认后的快照中
+        bus.emit(conflict("a"));
+        bus.emit(conflict("b"));
+        let watermarks = forwarder.acknowledge();
+        assert_eq!(watermarks, HashMap::from([("a".to_string(), 3), ("b".to_string(), 2)]));
+        bus.emit(conflict("a"));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let delivered: Vec<(String, u64)> = sink.events.lock().unwrap().iter()
+            .map(|event| (event.event.upload_id().to_string(), event.seq))
+            .collect();
+        assert_eq!(delivered, [("a".to_string(), 2), ("b".to_string(), 1), ("a".to_string(), 4)]);
+    }
+
+    #[test]
+    fn test_stale_in_flight_events_are_dropped() {
+        let delivered = Mutex::new(HashMap::from([("a".to_string(), 5)]));
+        let event = |seq| SequencedEvent { seq, event: conflict("a") };
+
+        assert!(!forward(&delivered, &event(4)));
+        assert!(!forward(&delivered, &event(5)));
+        assert!(forward(&delivered, &event(6)));
+        assert!(!forward(&delivered, &event(6)));
+    }
+}
@@ -1,27 +1,95 @@
 use std::collections::HashMap;
 use std::future::Future;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::select;
-use tokio::sync::{RwLock, Semaphore};
+use tokio::sync::{broadcast, mpsc, OwnedSemaphorePermit, RwLock, Semaphore};
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use crate::core::auth::{self, AuthProvider};
+use crate::core::backup::{BackupInfo, StateBackups};
+use crate::core::bandwidth::BandwidthLimiter;
+use crate::core::clock::{self, Clock};
+use crate::core::cloud::{self, CloudDirCheck};
+use crate::core::capabilities::Capabilities;
 use crate::core::config::TusConfig;
-use crate::core::error::UploadResult;
-use crate::core::state::UploadStateManager;
-use crate::core::upload::{Upload, UploadStatus};
-use crate::uploader::worker::UploadWorker;
+use crate::core::error::{ErrorDto, UploadError, UploadResult};
+use crate::core::fingerprint::{self, SourceFingerprint};
+use crate::core::event::{ActivityState, EventBus, SequencedEvent, TransferStats, UploadEvent};
+use crate::core::headers;
+use crate::core::history::{HistoryEntry, UploadHistory};
+use crate::core::metadata::{self, MetadataTruncation};
+use crate::core::guard::{GuardDecision, TransitionGuard};
+use crate::core::options::{AddOutcome, AddUploadOptions, BatchAdded, GroupPriority};
+use crate::core::rng::Rng;
+use crate::core::skew::ClockSkew;
+use crate::core::snapshot;
+use crate::core::tls::{self, ClientCache};
+use crate::core::location;
+use crate::core::state::{ConflictSide, QueueEntry, StateLoaded, UploadStateManager};
+use crate::core::config::SplitNaming;
+use crate::core::upload::{BlockCause, Upload, UploadPart, UploadStatus, SPLIT_GROUP_KEY};
+use crate::core::event::CorrectionReason;
+use crate::uploader::activity::ActivityMonitor;
+use crate::uploader::audit::{AuditSummary, LocationAudit};
+use crate::uploader::changes::{ChangeLog, UploadChanges};
+use crate::uploader::connectivity::{self, ConnectivityWatcher};
+use crate::uploader::directory::{self, AddDirectoryOptions, DirectoryAdded, RELATIVE_PATH_KEY};
+use crate::uploader::stats::{self, SpeedWindow};
+use crate::uploader::discovery::{CapabilityCache, ServerCapabilities};
+use crate::uploader::retry;
+use crate::uploader::scheduler::SchedulerHandle;
+use crate::uploader::status::{GroupStatusInfo, LiveProgress, ProgressReporter, ProgressThrottle, ProgressUpdate, StatusCache, UploadStatusInfo};
+use crate::uploader::worker::{terminate, UploadWorker, WorkerOutcome};
+
+/// shutdown 等待内部任务结束的最长时间
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// worker 进度通道的容量，处理不及时时 worker 丢弃较早的进度
+const PROGRESS_CHANNEL_CAPACITY: usize = 256;
+
+/// 定期检查到期但不空闲时，再次确认是否空闲的间隔
+const AUDIT_IDLE_RECHECK: Duration = Duration::from_secs(60);
+
+
+/// worker 占用的并发名额，worker 结束、panic 或被中止时都会释放
+struct WorkerSlots {
+    _permit: OwnedSemaphorePermit,
+
+    /// 所属服务端的名额
+    profile_permit: Option<OwnedSemaphorePermit>,
+
+    upload_state: Arc<UploadStateManager>,
+}
+
+impl Drop for WorkerSlots {
+    fn drop(&mut self) {
+        if let Some(permit) = self.profile_permit.take() {
+            drop(permit);
+            // 唤醒等待这个服务端名额的 upload
+            self.upload_state.wake();
+        }
+    }
+}
 
 struct ActiveUpload {
     handle: JoinHandle<Upload>,
 
+    /// 所属的分组
+    group: Option<String>,
+
+    /// 上传方式支持的功能
+    capabilities: Capabilities,
+
     /// child token
     cancellation_token: CancellationToken
 }
 
 pub struct UploadManager {
     // 所有的 upload
-    upload_state: UploadStateManager,
+    upload_state: Arc<UploadStateManager>,
 
     // 上传配置
     config: TusConfig,
@@ -29,23 +97,241 @@ pub struct UploadManager {
     // 正在上传的 upload
     active_uploads: Arc<RwLock<HashMap<String, ActiveUpload>>>,
 
-    // 非 pending 状态的 upload 放这里
-    shelved_uploads: Arc<RwLock<Vec<Upload>>>,
-
     // 并发锁
     semaphore: Arc<Semaphore>,
 
     // token
-    cancellation_token: CancellationToken
+    cancellation_token: CancellationToken,
+
+    // 状态变化守卫
+    transition_guard: Option<Arc<dyn TransitionGuard>>,
+
+    // 请求的认证信息，所有 worker 共享
+    auth: Option<Arc<dyn AuthProvider>>,
+
+    // 所有内部任务，shutdown 时等待它们结束
+    tasks: TaskTracker,
+
+    // 服务端时钟偏差，所有 worker 共享
+    clock_skew: Arc<ClockSkew>,
+
+    // 全局带宽限制，没有配置时不限制，可以在运行时修改
+    bandwidth: Arc<BandwidthLimiter>,
+
+    // 事件通知
+    events: Arc<EventBus>,
+
+    // 轮询状态的缓存
+    status_cache: Arc<StatusCache>,
+
+    // 已经结束的 upload
+    history: Arc<UploadHistory>,
+
+    // 对外显示的进度
+    progress: Arc<ProgressReporter>,
+
+    // worker 发送进度的通道
+    progress_tx: mpsc::Sender<ProgressUpdate>,
+
+    // 设置了 max_concurrent_override 的服务端配置各自的并发锁
+    profile_slots: HashMap<String, Arc<Semaphore>>,
+
+    // 状态文件夹位于同步文件夹中时的检测结果
+    cloud_dir: Option<CloudDirCheck>,
+
+    // 已经发出 GroupCompleted 的分组，避免最后几个部分同时完成时重复发出
+    completed_groups: Arc<std::sync::Mutex<std::collections::HashSet<String>>>,
+
+    // 检查重复到添加完成之间持有，同时添加同一个文件时只添加一次
+    add_lock: tokio::sync::Mutex<()>,
+
+    // 汇总统计最近的速度采样
+    stats_window: std::sync::Mutex<SpeedWindow>,
+
+    // 创建快照到写入状态之间持有，避免启动清理误删刚创建的快照
+    snapshot_lock: Arc<tokio::sync::Mutex<()>>,
+
+    /// 请求被拦截后探测网络并恢复 upload
+    connectivity: Arc<ConnectivityWatcher>,
+
+    /// 运行循环的句柄，shutdown 等待它停止
+    scheduler: std::sync::Mutex<Option<SchedulerHandle>>,
+
+    /// 状态文件的备份
+    backups: Arc<StateBackups>,
+
+    /// 列表的版本和最近的变化
+    changes: ChangeLog,
+
+    /// 服务端资源的检查
+    audit: LocationAudit,
+
+    /// 每个服务端声明的功能
+    capabilities: Arc<CapabilityCache>,
+
+    /// 所有 worker 共享的客户端
+    clients: ClientCache,
+
+    /// 整个队列的活动状态
+    activity: Arc<ActivityMonitor>,
+
+    /// 时间来源，所有 worker 共享
+    clock: Arc<dyn Clock>,
+
+    /// 随机数来源，所有 worker 共享
+    rng: Arc<Rng>,
 }
 
 impl UploadManager {
     pub async fn new(config: TusConfig) -> UploadResult<Self> {
-        let upload_state = UploadStateManager::new(config.clone()).await?;
+        Self::new_with(config, clock::system(), Arc::new(Rng::from_entropy())).await
+    }
+
+    /// 使用指定的时钟和随机数，测试中传入 TestClock 和固定种子的 Rng
+    pub async fn new_with(config: TusConfig, clock: Arc<dyn Clock>, rng: Arc<Rng>) -> UploadResult<Self> {
+        // 之后所有的文件都写到处理后的状态文件夹
+        let (config, cloud_dir) = cloud::resolve_state_dir(config).await?;
+        let upload_state = Arc::new(UploadStateManager::new_with_clock(config.clone(), clock.clone()).await?);
         let active_uploads = Arc::new(RwLock::new(HashMap::new()));
         let semaphore = Arc::new(Semaphore::new(config.max_concurrent));
         let cancellation_token = CancellationToken::new();
-        let shelved_uploads = Arc::new(RwLock::new(Vec::new()));
+        let bandwidth = Arc::new(BandwidthLimiter::new(config.bandwidth_limit, config.fair_bandwidth));
+        let events = Arc::new(EventBus::default());
+        let clients = ClientCache::default();
+        clients.for_profile(&config, None)?;
+        // 这时还不能订阅，保留到第一个订阅方，也可以通过 cloud_dir 取得
+        if let Some(check) = &cloud_dir {
+            events.emit_retained(UploadEvent::StateDirSynced {
+                provider: check.provider.clone(),
+                path: check.path.clone(),
+                relocated_to: check.relocated_to.clone(),
+            });
+        }
+        let history = Arc::new(UploadHistory::new(&config.state_dir, config.log_rotation));
+        let progress = Arc::new(ProgressReporter::new(events.clone()));
+        let profile_slots = config.endpoints_by_name.iter()
+            .filter_map(|(name, profile)| {
+                let limit = profile.max_concurrent_override?;
+                Some((name.clone(), Arc::new(Semaphore::new(limit))))
+            })
+            .collect();
+
+        // 状态加载完成后通知还没有处理的 id 冲突，并清理不再使用的快照
+        // 冲突通知保留到第一个订阅方订阅，完整的列表也可以通过 conflicts 取得
+        let tasks = TaskTracker::new();
+        let status_cache = Arc::new(StatusCache::new(clock.clone()));
+        let loading_state = upload_state.clone();
+        let live_uploads = status_cache.clone();
+        let conflict_events = events.clone();
+        let snapshots = snapshot::snapshot_dir(&config.state_dir);
+        let snapshot_lock = Arc::new(tokio::sync::Mutex::new(()));
+        let sweep_lock = snapshot_lock.clone();
+        tasks.spawn(async move {
+            loading_state.wait_loaded().await;
+            for upload in loading_state.conflicts().await {
+                conflict_events.emit_retained(UploadEvent::IdConflict { id: upload.id });
+            }
+
+            // 已经出队的 upload 不在状态中，出队时在状态锁内开始跟踪，先读取状态再读取它们不会遗漏
+            let _guard = sweep_lock.lock().await;
+            let mut uploads = loading_state.list().await;
+            uploads.extend(live_uploads.live_uploads());
+            let keep = uploads
+                .into_iter()
+                .filter(|upload| !matches!(upload.status, UploadStatus::Completed | UploadStatus::Cancelled))
+                .filter_map(|upload| upload.snapshot_path)
+                .collect();
+            if let Err(err) = snapshot::sweep_orphans(&snapshots, &keep).await {
+                log::warn!("Failed to clean up snapshots: {}", err);
+            }
+        });
+
+        // 加载完成后备份一次，之后每个间隔最多备份一次
+        let backups = Arc::new(StateBackups::new(&config.state_dir, config.backup).with_clock(clock.clone()));
+        let periodic_backups = backups.clone();
+        let backup_state = upload_state.clone();
+        let backup_token = cancellation_token.clone();
+        let backup_interval = config.backup.interval;
+        let backup_live = status_cache.clone();
+        tasks.spawn(async move {
+            backup_state.wait_loaded().await;
+            let mut ticker = tokio::time::interval(backup_interval);
+            loop {
+                select! {
+                    _ = backup_token.cancelled() => return,
+                    _ = ticker.tick() => {}
+                }
+                let result = match backup_state.export(|| backup_live.live_uploads()).await {
+                    Ok(Some(content)) => periodic_backups.backup_if_due(&content).await.map(|_| ()),
+                    Ok(None) => Ok(()),
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = result {
+                    log::warn!("Failed to back up upload state: {}", err);
+                }
+            }
+        });
+
+        let connectivity = Arc::new(ConnectivityWatcher::new(
+            config.clone(),
+            upload_state.clone(),
+            events.clone(),
+            cancellation_token.clone(),
+            tasks.clone(),
+            clock.clone(),
+        ));
+        let resume_state = upload_state.clone();
+        let watcher = connectivity.clone();
+        tasks.spawn(async move {
+            resume_state.wait_loaded().await;
+            watcher.resume().await;
+        });
+
+        // 活动状态在每次状态变化后重新计算
+        let activity = Arc::new(ActivityMonitor::new(
+            &config.state_dir,
+            upload_state.clone(),
+            events.clone(),
+        ).await);
+        let monitor = activity.clone();
+        let activity_token = cancellation_token.clone();
+        tasks.spawn(async move { monitor.run(activity_token).await });
+
+        // worker 确认的进度按间隔转发为事件
+        let (progress_tx, mut progress_rx) = mpsc::channel::<ProgressUpdate>(PROGRESS_CHANNEL_CAPACITY);
+        let progress_events = events.clone();
+        let progress_token = cancellation_token.clone();
+        let progress_clock = clock.clone();
+        let mut throttle = ProgressThrottle::new(config.progress_event_interval);
+        tasks.spawn(async move {
+            loop {
+                let update = select! {
+                    _ = progress_token.cancelled() => return,
+                    update = progress_rx.recv() => match update {
+                        Some(update) => update,
+                        None => return,
+                    },
+                };
+                if !throttle.admit(&update, progress_clock.now_instant()) {
+                    continue;
+                }
+                progress_events.emit(UploadEvent::Progress {
+                    id: update.id,
+                    bytes_transferred: update.offset,
+                    total_bytes: update.total_bytes,
+                    speed: update.speed,
+                    at: update.at,
+                });
+            }
+        });
+
+        let audit = LocationAudit::new(
+            config.clone(),
+            upload_state.clone(),
+            events.clone(),
+            progress.clone(),
+            status_cache.clone(),
+        );
 
         Ok(Self {
             config,
@@ -53,136 +339,4635 @@ impl UploadManager {
             active_uploads,
             semaphore,
             cancellation_token,
-            shelved_uploads,
+            transition_guard: None,
+            auth: None,
+            tasks,
+            clock_skew: Arc::new(ClockSkew::new()),
+            bandwidth,
+            events,
+            status_cache,
+            snapshot_lock,
+            profile_slots,
+            history,
+            progress,
+            progress_tx,
+            cloud_dir,
+            completed_groups: Arc::new(std::sync::Mutex::new(std::collections::HashSet::new())),
+            add_lock: tokio::sync::Mutex::new(()),
+            stats_window: std::sync::Mutex::new(SpeedWindow::default()),
+            connectivity,
+            scheduler: std::sync::Mutex::new(None),
+            backups,
+            changes: ChangeLog::default(),
+            audit,
+            capabilities: Arc::new(CapabilityCache::default()),
+            clients,
+            activity,
+            clock,
+            rng,
         })
     }
 
-    /// 开是运行循环执行任务
-    pub async fn run(&self) {
+    pub fn config(&self) -> &TusConfig {
+        &self.config
+    }
+
+    /// 状态文件夹位于同步文件夹中时的检测结果
+    pub fn cloud_dir(&self) -> Option<&CloudDirCheck> {
+        self.cloud_dir.as_ref()
+    }
+
+    /// 等待持久化的状态在后台加载完成
+    pub async fn wait_state_loaded(&self) -> StateLoaded {
+        self.upload_state.wait_loaded().await
+    }
+
+    /// 服务端时间减去本地时间的估计值，还没有收到响应时返回 None
+    pub fn clock_skew(&self) -> Option<chrono::TimeDelta> {
+        self.clock_skew.estimate()
+    }
+
+    /// 查询 upload 的状态
+    /// 正在上传的 upload 从实时进度读取，其他 upload 在 STATUS_CACHE_TTL 内返回缓存
+    pub async fn get_upload_status(&self, id: &str) -> UploadResult<UploadStatusInfo> {
+        if let Some(info) = self.status_cache.get(id) {
+            return Ok(self.progress.present(info));
+        }
+
+        let upload = match self.upload_state.get_upload(id).await {
+            Ok(upload) => upload,
+            // 刚刚出队，出队时已经开始跟踪实时进度
+            Err(err) => return self.status_cache.get(id).map(|info| self.progress.present(info)).ok_or(err),
+        };
+        self.status_cache.store(&upload);
+        Ok(self.progress.present(UploadStatusInfo::at(&upload, self.clock.now_utc())))
+    }
+
+    /// 查询 upload，正在上传的带有实时进度
+    pub async fn get_upload(&self, id: &str) -> UploadResult<Upload> {
+        match self.upload_state.get_upload(id).await {
+            // 已经出队，出队时已经开始跟踪实时进度
+            Err(err) => self.status_cache.live_upload(id).ok_or(err),
+            upload => upload,
+        }
+    }
+
+    /// 所有 upload，包括队列中、正在上传、等待重试和已经结束的，按创建时间排序
+    /// 正在上传的带有实时进度。每个来源分别加锁读取，不会同时持有两个锁
+    pub async fn list_uploads(&self) -> Vec<Upload> {
+        // 出队时在状态锁内开始跟踪，前后各读一次实时进度，读取状态期间出队的 upload 不会遗漏；
+        // 后读到的记录覆盖先读到的，结束的 upload 以状态中的为准
+        let mut uploads: HashMap<String, Upload> = HashMap::new();
+        let live = self.status_cache.live_uploads();
+        let stored = self.upload_state.list().await;
+        let started = self.status_cache.live_uploads();
+        for upload in live.into_iter().chain(stored).chain(started) {
+            uploads.insert(upload.id.clone(), upload);
+        }
+
+        let mut uploads: Vec<Upload> = uploads.into_values().collect();
+        uploads.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+        uploads
+    }
+
+    /// 已完成的 upload，最早完成的在前，数量由 completed_retention 限制
+    pub async fn list_completed(&self) -> Vec<Upload> {
+        self.upload_state.completed().await
+    }
+
+    /// 等待开始的 upload 的顺序
+    pub async fn get_queue(&self) -> Vec<QueueEntry> {
+        self.upload_state.get_queue().await
+    }
+
+    /// 下一个开始这个 upload，已经开始的不做修改
+    pub async fn move_to_front(&self, id: &str) -> UploadResult<()> {
+        self.move_to_position(id, 0).await
+    }
+
+    /// 移动到队列中 index 的位置，已经开始的不做修改
+    pub async fn move_to_position(&self, id: &str, index: usize) -> UploadResult<()> {
+        if !self.upload_state.move_to_position(id, index).await? {
+            // 不在队列中，确认 upload 存在
+            self.get_upload(id).await?;
+        }
+        Ok(())
+    }
+
+    /// 按界面中的顺序排列队列，ids 是完整的顺序；已经开始或不存在的 id 被忽略
+    pub async fn reorder_queue(&self, ids: &[String]) -> UploadResult<()> {
+        self.upload_state.reorder(ids).await.map(|_| ())
+    }
+
+    /// 正在上传的 upload 数量
+    pub async fn get_active_count(&self) -> usize {
+        self.active_uploads.read().await.len()
+    }
+
+    /// 所有 upload 的状态，包括正在上传和等待重试的
+    pub async fn list_upload_statuses(&self) -> Vec<UploadStatusInfo> {
+        let mut ids: Vec<String> = self.upload_state.list().await.into_iter().map(|u| u.id).collect();
+        ids.extend(self.active_uploads.read().await.keys().cloned());
+
+        let mut seen = std::collections::HashSet::new();
+        let mut statuses = Vec::new();
+        for id in ids {
+            if seen.insert(id.clone()) {
+                if let Ok(status) = self.get_upload_status(&id).await {
+                    statuses.push(status);
+                }
+            }
+        }
+        statuses
+    }
+
+    /// 列表的当前版本，添加、删除、状态变化等会改变列表显示的操作之后递增
+    pub async fn state_version(&self) -> u64 {
+        let mut changes = self.changes.lock().await;
+        changes.observe(&self.list_upload_statuses().await)
+    }
+
+    /// 从 version 之后的变化，version 太旧时返回 TooOld，调用方需要重新获取完整列表
+    pub async fn get_changes_since(&self, version: u64) -> UploadChanges {
+        let mut changes = self.changes.lock().await;
+        let statuses = self.list_upload_statuses().await;
+        changes.observe(&statuses);
+        changes.since(version, &statuses)
+    }
+
+    /// 已经完成或失败的 upload，最旧的在前
+    pub async fn get_history(&self) -> UploadResult<Vec<HistoryEntry>> {
+        self.history.entries().await
+    }
+
+    /// 订阅 upload 事件，包括添加、开始、进度、暂停、完成、失败和取消等生命周期事件
+    pub fn subscribe(&self) -> broadcast::Receiver<SequencedEvent> {
+        self.events.subscribe()
+    }
+
+    /// 事件总线，用于 EventForwarder 等需要序号的订阅方
+    pub fn event_bus(&self) -> Arc<EventBus> {
+        self.events.clone()
+    }
+
+    /// 公平模式下每个正在上传的 upload 分到的速率，字节/秒
+    pub fn bandwidth_allocations(&self) -> HashMap<String, u64> {
+        self.bandwidth.allocations()
+    }
+
+    /// 当前的总带宽限制，字节/秒
+    pub fn bandwidth_limit(&self) -> Option<u64> {
+        self.bandwidth.limit()
+    }
+
+    /// 修改总带宽限制，为空时取消限制；正在上传的 upload 立即按新的限制发送，不需要重新开始
+    pub fn set_bandwidth_limit(&self, limit: Option<u64>) -> UploadResult<()> {
+        if limit == Some(0) {
+            return Err(UploadError::Config("Bandwidth limit must be greater than 0".into()));
+        }
+        self.bandwidth.set_limit(limit);
+        Ok(())
+    }
+
+    /// 注册状态变化守卫
+    pub fn with_transition_guard(mut self, guard: Arc<dyn TransitionGuard>) -> Self {
+        self.transition_guard = Some(guard);
+        self
+    }
+
+    /// 注册认证信息，worker 的每个请求都会带上它提供的请求头
+    pub fn with_auth_provider(mut self, provider: Arc<dyn AuthProvider>) -> Self {
+        self.auth = Some(provider);
+        self
+    }
+
+    /// 推送新的认证请求头，例如应用刷新了 token；之后的请求立即使用，不需要重启 upload
+    pub async fn update_auth(&self, headers: HashMap<String, String>) -> UploadResult<()> {
+        let provider = self.auth.as_ref()
+            .ok_or_else(|| UploadError::Config("No auth provider configured".into()))?;
+        provider.update(auth::header_map(&headers)?).await
+    }
+
+    /// 询问守卫是否允许状态变化
+    /// 守卫拿到的是副本，无法修改状态；守卫 panic 或超过 guard_timeout 没有结果时视为允许，并发出 GuardFailed
+    async fn check_guard(&self, upload: &Upload, to: UploadStatus) -> GuardDecision {
+        let Some(guard) = self.transition_guard.clone() else {
+            return GuardDecision::Allow;
+        };
+
+        let id = upload.id.clone();
+        let upload = upload.clone();
+        let from = upload.status;
+        let mut handle = tokio::spawn(async move { guard.allow(&upload, from, to).await });
+        let reason = select! {
+            result = &mut handle => match result {
+                Ok(decision) => return decision,
+                Err(err) => match err.try_into_panic() {
+                    Ok(payload) => format!("Transition guard panicked: {}", panic_message(payload.as_ref())),
+                    Err(err) => format!("Transition guard failed: {}", err),
+                },
+            },
+            _ = self.clock.sleep(self.config.guard_timeout) => {
+                handle.abort();
+                format!("Transition guard timed out after {:?}", self.config.guard_timeout)
+            }
+        };
+
+        log::error!("{} for upload {}, allowing transition", reason, id);
+        self.events.emit(UploadEvent::GuardFailed { id, reason });
+        GuardDecision::Allow
+    }
+
+    /// 等待一段时间后把 upload 放回队列
+    /// 等待期间 upload 带着 retry_at 保存在状态中，关闭或崩溃后下次启动时重新排队
+    async fn defer_upload(&self, mut upload: Upload, delay: Duration) {
+        let upload_id = upload.id.clone();
+        upload.retry_at = Some(self.clock.now_utc() + delay);
+        if let Err(err) = self.upload_state.shelve(upload).await {
+            log::error!("Failed to persist deferred upload: {}", err);
+        }
+        self.activity.finished(&upload_id);
+        schedule_requeue(&self.tasks, self.cancellation_token.child_token(), self.clock.clone(), self.upload_state.clone(), upload_id, delay);
+    }
+
+    /// 在后台开始运行循环执行任务，返回可以等待循环停止的句柄
+    /// 调用 shutdown 后退出；每个 manager 只能运行一次，再次调用返回 SchedulerAlreadyRunning
+    pub fn run(self: &Arc<Self>) -> UploadResult<SchedulerHandle> {
+        let mut scheduler = self.scheduler.lock().unwrap();
+        if scheduler.is_some() {
+            return Err(UploadError::SchedulerAlreadyRunning);
+        }
+
+        let loop_stopped = CancellationToken::new();
+        let handle = SchedulerHandle::new(loop_stopped.clone(), self.tasks.clone());
+        let manager = self.clone();
+        self.tasks.spawn(async move {
+            // 循环 panic 时也标记为已退出
+            let _stopped = loop_stopped.drop_guard();
+            manager.run_loop().await
+        });
+
+        if let Some(policy) = self.config.audit {
+            let manager = self.clone();
+            self.tasks.spawn(async move { manager.audit_loop(policy.interval).await });
+        }
+
+        if !self.config.stats_event_interval.is_zero() {
+            let manager = self.clone();
+            self.tasks.spawn(async move { manager.stats_loop(manager.config.stats_event_interval).await });
+        }
+
+        // 提前探测服务端的功能，添加 upload 时可以直接检查大小限制
+        let manager = self.clone();
+        self.tasks.spawn(async move {
+            let profiles = std::iter::once(None).chain(manager.config.endpoints_by_name.keys().map(Some));
+            for profile in profiles {
+                select! {
+                    _ = manager.cancellation_token.cancelled() => return,
+                    _ = manager.get_server_capabilities(profile.map(String::as_str)) => {}
+                }
+            }
+        });
+
+        *scheduler = Some(handle.clone());
+        Ok(handle)
+    }
+
+    /// 每个间隔检查一次服务端资源，到期时不空闲则等到空闲
+    async fn audit_loop(&self, interval: Duration) {
+        let token = self.cancellation_token.clone();
+        loop {
+            select! {
+                _ = token.cancelled() => return,
+                _ = self.clock.sleep(interval) => {}
+            }
+            while !self.is_idle().await {
+                select! {
+                    _ = token.cancelled() => return,
+                    _ = self.clock.sleep(AUDIT_IDLE_RECHECK) => {}
+                }
+            }
+            if let Err(err) = self.audit.run(|| self.is_idle()).await {
+                log::warn!("Failed to audit upload locations: {}", err);
+            }
+        }
+    }
+
+    /// 每个间隔计算一次汇总，和上一次发出的不同时发出 StatsUpdated
+    async fn stats_loop(&self, interval: Duration) {
+        let mut last = None;
+        loop {
+            select! {
+                _ = self.cancellation_token.cancelled() => return,
+                _ = self.clock.sleep(interval) => {}
+            }
+            let stats = self.get_stats().await;
+            if last.as_ref() != Some(&stats) {
+                self.events.emit(UploadEvent::StatsUpdated { stats: stats.clone() });
+                last = Some(stats);
+            }
+        }
+    }
+
+    /// 所有 upload 的数量、字节数、平滑后的速度和预计剩余时间，正在上传的使用实时进度
+    pub async fn get_stats(&self) -> TransferStats {
+        let statuses = self.list_upload_statuses().await;
+        let now = self.clock.now_instant();
+        stats::summarize(&statuses, &mut self.stats_window.lock().unwrap(), now)
+    }
+
+    /// 没有正在上传、等待重试或排队的 upload，并且网络没有被拦截
+    async fn is_idle(&self) -> bool {
+        if self.connectivity.is_probing() {
+            return false;
+        }
+        if self.active_uploads.read().await.values().any(|active| !active.handle.is_finished()) {
+            return false;
+        }
+        !self.upload_state.list().await.iter()
+            .any(|upload| matches!(upload.status, UploadStatus::Pending | UploadStatus::WaitingRetry))
+    }
+
+    /// 服务端（或指定的服务端配置）声明的功能，没有缓存或已经过期时发送 OPTIONS
+    /// 无法连接时返回 None
+    pub async fn get_server_capabilities(&self, profile: Option<&str>) -> UploadResult<Option<ServerCapabilities>> {
+        let config = self.config.for_profile(profile)?;
+        Ok(self.capabilities.get(&self.clients.for_profile(&self.config, profile)?, &config).await)
+    }
+
+    /// 队列当前的活动状态：空闲、处理中，或者有还没有确认的失败
+    pub async fn get_activity_state(&self) -> ActivityState {
+        self.activity.refresh().await
+    }
+
+    /// 确认当前所有失败的 upload，活动状态不再因为它们显示 Attention，返回新确认的数量
+    /// 确认会保存到状态文件夹；upload 重试或删除后再失败时重新提示
+    pub async fn acknowledge_failures(&self) -> UploadResult<usize> {
+        self.activity.acknowledge().await
+    }
+
+    /// 立即检查暂停和阻塞的 upload 在服务端的资源是否还存在，不要求空闲
+    /// 资源不存在的 upload 标记 remote_missing，配置了 reset_missing 时同时丢弃进度
+    pub async fn audit_now(&self) -> UploadResult<AuditSummary> {
+        let connectivity = &self.connectivity;
+        self.audit.run(|| std::future::ready(!connectivity.is_probing())).await
+    }
+
+    /// 不需要句柄时使用，句柄仍然保存在 manager 中，shutdown 会等待它
+    pub fn start(self: &Arc<Self>) -> UploadResult<()> {
+        self.run().map(|_| ())
+    }
+
+    /// 运行循环的句柄，还没有调用 run 时为空
+    pub fn scheduler(&self) -> Option<SchedulerHandle> {
+        self.scheduler.lock().unwrap().clone()
+    }
+
+    async fn run_loop(&self) {
         let semaphore = self.semaphore.clone();
+        let token = self.cancellation_token.clone();
         loop {
             // 获取信号量
-            let permit = semaphore.clone().acquire_owned().await.unwrap();
+            let permit = select! {
+                _ = token.cancelled() => return,
+                permit = semaphore.clone().acquire_owned() => permit.unwrap(),
+            };
+
+            // 跳过所属服务端已经达到并发上限的 upload
+            // 出队时持有状态锁，同时开始跟踪实时进度，轮询不会在状态和缓存中都找不到它
+            let profile_slots = &self.profile_slots;
+            let activity = &self.activity;
+            let status_cache = &self.status_cache;
+            let mut live = None;
+            let mut upload = select! {
+                _ = token.cancelled() => return,
+                upload = self.upload_state.pop_where(|upload| {
+                    let available = upload.endpoint.as_ref()
+                        .and_then(|name| profile_slots.get(name))
+                        .map_or(true, |slots| slots.available_permits() > 0);
+                    if available {
+                        activity.started(&upload.id);
+                        let tracked = Arc::new(LiveProgress::new(upload));
+                        status_cache.track(upload, tracked.clone());
+                        live = Some(tracked);
+                    }
+                    available
+                }) => upload,
+            };
+            let live = live.expect("tracked when dequeued");
+            let profile_permit = upload.endpoint.as_ref()
+                .and_then(|name| self.profile_slots.get(name))
+                .and_then(|slots| slots.clone().try_acquire_owned().ok());
+            let slots = WorkerSlots { _permit: permit, profile_permit, upload_state: self.upload_state.clone() };
+
+            // 之前的会话已经用完重试次数，等用户重新开始
+            if upload.retries_exhausted(self.config.max_retries as u32) {
+                drop(slots);
+                self.upload_state.wake();
+                let err = UploadError::RetriesExhausted { id: upload.id.clone(), attempts: upload.retry_count };
+                self.fail_dequeued(upload, err).await;
+                continue;
+            }
 
-            // 创建 worker
-            let upload = self.upload_state.pop().await;
+            // 出队后状态中不再有这个 upload，轮询从实时进度读取
             let upload_id = upload.id.clone();
-            let mut worker = UploadWorker::new(self.config.clone(), upload, self.cancellation_token.child_token());
+            live.sync(&upload);
 
-            // 执行 upload
+            // 启动前询问守卫，此时还没有修改 upload
+            match self.check_guard(&upload, UploadStatus::Active).await {
+                GuardDecision::Allow => {}
+                GuardDecision::Deny(reason) => {
+                    drop(slots);
+                    self.upload_state.wake();
+                    if upload.block_at(BlockCause::Guard, reason, self.clock.now_utc()).is_ok() {
+                        if let Err(err) = self.upload_state.shelve(upload).await {
+                            log::error!("Failed to persist blocked upload: {}", err);
+                        }
+                    }
+                    self.activity.finished(&upload_id);
+                    self.status_cache.invalidate(&upload_id);
+                    continue;
+                }
+                GuardDecision::Defer(delay) => {
+                    drop(slots);
+                    self.upload_state.wake();
+                    self.status_cache.invalidate(&upload_id);
+                    self.defer_upload(upload, delay).await;
+                    continue;
+                }
+            }
+
+            let at = self.clock.now_utc();
+            self.events.emit(match upload.location {
+                Some(_) => UploadEvent::Resumed { id: upload_id.clone(), at },
+                None => UploadEvent::Started { id: upload_id.clone(), at },
+            });
+
+            // worker 收到取消后自己退出，返回前等待它的辅助任务结束
             let child_token = self.cancellation_token.child_token();
-            let cancellation_token = child_token.clone();
-            let handle = tokio::spawn(async move {
-                let future = worker.start();
+            let mut worker = UploadWorker::new(self.config.clone(), upload, child_token.clone())
+                .with_clock_skew(self.clock_skew.clone())
+                .with_clock(self.clock.clone())
+                .with_rng(self.rng.clone())
+                .with_progress_reporter(self.progress.clone())
+                .with_progress_channel(self.progress_tx.clone())
+                .with_capability_cache(self.capabilities.clone())
+                .with_live_progress(live.clone());
+            worker = worker.with_bandwidth(self.bandwidth.register(upload_id.clone(), 1));
+            // 服务端配置无效时不传入，worker 开始时会报告这个错误
+            if let Ok(client) = self.clients.for_profile(&self.config, worker.upload.endpoint.as_deref()) {
+                worker = worker.with_client(client);
+            }
+            if let Some(auth) = &self.auth {
+                worker = worker.with_auth(auth.clone());
+            }
 
-                select! {
-                    _ = cancellation_token.cancelled() => {},
-                    result = future => {
-                        match result {
-                            Ok(res) => {
+            // 执行 upload
+            let upload_state = self.upload_state.clone();
+            let status_cache = self.status_cache.clone();
+            let history = self.history.clone();
+            let events = self.events.clone();
+            let completed_groups = self.completed_groups.clone();
+            let connectivity = self.connectivity.clone();
+            let group = worker.upload.group.clone();
+            let capabilities = Capabilities::for_upload(&worker.upload);
+            let retry_token = self.cancellation_token.child_token();
+            let shutdown_token = self.cancellation_token.clone();
+            let tasks = self.tasks.clone();
+            let activity = self.activity.clone();
+            let clock = self.clock.clone();
+            let lock_retry_delay = self.config.retry_delay;
+            let active_uploads = self.active_uploads.clone();
+            let auto_retry = self.config.auto_retry;
+            let rng = self.rng.clone();
+            // 登记之前持有锁，worker 结束时一定能找到自己的记录
+            let mut active_guard = self.active_uploads.write().await;
+            let handle = self.tasks.spawn(async move {
+                // worker 在单独的任务中运行，panic 时这里仍然能记录失败；名额在 worker 的任务结束时释放
+                let run = tasks.spawn(async move {
+                    let _slots = slots;
+                    let outcome = worker.start().await;
+                    (worker.upload, outcome)
+                });
+                let (mut upload, outcome) = match run.await {
+                    Ok(result) => result,
+                    Err(err) => {
+                        // 进度以 worker 最后一次同步的为准
+                        let upload = live.upload();
+                        log::error!("Worker of upload {} crashed: {}", upload.id, err);
+                        let reason = if err.is_panic() { "upload worker panicked" } else { "upload worker was aborted" };
+                        (upload, Err(UploadError::Internal(reason.to_string())))
+                    }
+                };
+
+                let outcome = match outcome {
+                    // 资源暂时被锁定不算失败，让出名额稍后重新调度
+                    Err(UploadError::UploadLocked { waited }) if upload.transition_at(UploadStatus::WaitingRetry, clock.now_utc()).is_ok() => {
+                        log::warn!("Upload {} is still locked after {:?}, rescheduling", upload.id, waited);
+                        Ok(WorkerOutcome::WaitingRetry(lock_retry_delay))
+                    }
+                    outcome => outcome,
+                };
 
+                match outcome {
+                    Ok(WorkerOutcome::Completed) => {
+                        release_snapshot(&mut upload).await;
+                        status_cache.store(&upload);
+                        if let Err(err) = upload_state.shelve(upload.clone()).await {
+                            log::error!("Failed to persist completed upload: {}", err);
+                        }
+                        if let Err(err) = history.record(&upload).await {
+                            log::warn!("Failed to record upload history: {}", err);
+                        }
+                        events.emit(UploadEvent::Completed {
+                            id: upload.id.clone(),
+                            location: upload.location.clone(),
+                            at: clock.now_utc(),
+                        });
+                        if let (Some(group), Some(part)) = (upload.split_group(), upload.part) {
+                            notify_group_completed(&upload_state, &events, &completed_groups, group, part.count).await;
+                        }
+                    }
+                    Err(UploadError::EndpointIntercepted { url, content_type }) => {
+                        log::info!("Upload {} was intercepted by {} ({})", upload.id, url, content_type);
+                        connectivity.intercepted(upload.clone()).await;
+                        status_cache.invalidate(&upload.id);
+                    }
+                    Err(err) => {
+                        log::info!("Upload {} failed: {}", upload.id, err);
+                        if let UploadError::TlsPinMismatch { host } = &err {
+                            events.emit(UploadEvent::TlsPinMismatch { id: upload.id.clone(), host: host.clone() });
+                        }
+                        // worker 已经记录了失败，开始前就被拒绝的 upload 在这里记录
+                        // 失败的 upload 可能重试，保留快照
+                        if upload.status == UploadStatus::Failed || upload.fail_at(&err, clock.now_utc()).is_ok() {
+                            status_cache.store(&upload);
+                            if let Err(err) = upload_state.shelve(upload.clone()).await {
+                                log::error!("Failed to persist failed upload: {}", err);
+                            }
+                            if let Err(err) = history.record(&upload).await {
+                                log::warn!("Failed to record upload history: {}", err);
                             }
-                            Err(err) => {
+                            events.emit(UploadEvent::Failed {
+                                id: upload.id.clone(),
+                                error: upload.last_error.clone().unwrap_or_else(|| ErrorDto::from(&err)),
+                                at: clock.now_utc(),
+                            });
 
+                            // 自动重试，到时间时已经被取消或手动处理的 upload 不再是 Failed，不会重新排队
+                            let attempts = upload.auto_retries;
+                            if let Some(policy) = auto_retry.filter(|policy| !err.is_fatal() && attempts < policy.max_attempts) {
+                                let delay = retry::backoff(policy.base_delay, Duration::MAX, attempts + 1, rng.next_f64());
+                                let upload_id = upload.id.clone();
+                                let upload_state = upload_state.clone();
+                                let status_cache = status_cache.clone();
+                                let clock = clock.clone();
+                                tasks.spawn(async move {
+                                    select! {
+                                        _ = retry_token.cancelled() => {},
+                                        _ = clock.sleep(delay) => {
+                                            if let Err(err) = requeue_failed(&upload_state, &status_cache, &upload_id, true, clock.now_utc()).await {
+                                                log::warn!("Failed to requeue upload {} for auto retry: {}", upload_id, err);
+                                            }
+                                        }
+                                    }
+                                });
                             }
                         }
                     }
+                    Ok(WorkerOutcome::WaitingRetry(delay)) => {
+                        // 让出名额期间带着重试时间保存在状态中，到时间后插入队列最前面；崩溃后加载时按中断处理
+                        upload.retry_at = Some(clock.now_utc() + delay);
+                        if let Err(err) = upload_state.shelve(upload.clone()).await {
+                            log::error!("Failed to persist upload waiting for retry: {}", err);
+                        }
+                        status_cache.invalidate(&upload.id);
+                        schedule_requeue(&tasks, retry_token, clock.clone(), upload_state.clone(), upload.id.clone(), delay);
+                    }
+                    Ok(WorkerOutcome::Cancelled) => {
+                        status_cache.invalidate(&upload.id);
+                    }
+                }
+                activity.finished(&upload.id);
+
+                // 结果已经保存，从 active 中移除；暂停、取消时已经被取走，同一个 upload 可能已经重新开始。
+                // 关闭时保留记录，由 shutdown 取得被中断的 upload
+                let mut active = active_uploads.write().await;
+                let own = active.get(&upload.id).is_some_and(|active| active.handle.id() == tokio::task::id());
+                if own && !shutdown_token.is_cancelled() {
+                    active.remove(&upload.id);
                 }
+                drop(active);
 
-                drop(permit);
-                worker.upload
+                upload
             });
 
             // 添加任务列表
-            {
-                let mut active_guard = self.active_uploads.write().await;
-                active_guard.insert(upload_id, ActiveUpload {
-                    handle,
-                    cancellation_token: child_token,
-                });
-            }
+            active_guard.insert(upload_id, ActiveUpload {
+                handle,
+                group,
+                capabilities,
+                cancellation_token: child_token,
+            });
+            drop(active_guard);
         }
     }
 
-    /// 创建一个新的 upload
-    /// 新的 upload 最初状态是 pending，添加到 upload_state 中
-    pub async fn add_upload(&self, file_path: PathBuf) -> UploadResult<String> {
-        let upload = Upload::new(file_path, self.config.chunk_size)?;
+    /// 出队后不能开始的 upload 记录失败并放回状态
+    async fn fail_dequeued(&self, mut upload: Upload, err: UploadError) {
         let upload_id = upload.id.clone();
-        self.upload_state.push(upload).await?;
+        if upload.fail_at(&err, self.clock.now_utc()).is_ok() {
+            if let Err(err) = self.upload_state.shelve(upload).await {
+                log::error!("Failed to persist failed upload {}: {}", upload_id, err);
+            }
+            self.events.emit(UploadEvent::Failed { id: upload_id.clone(), error: ErrorDto::from(&err), at: self.clock.now_utc() });
+        }
+        self.activity.finished(&upload_id);
+        self.status_cache.invalidate(&upload_id);
+    }
 
-        Ok(upload_id)
+    /// 停止所有内部任务并最后写入一次状态，最多等待 SHUTDOWN_TIMEOUT
+    /// 返回后不会再有任何任务访问状态文件夹
+    pub async fn shutdown(&self) -> UploadResult<()> {
+        self.shutdown_with_timeout(SHUTDOWN_TIMEOUT).await
     }
 
-    /// 暂停 upload
-    /// 从 active 中移除，添加到 shelved 中
-    pub async fn pause_upload(&self, id: String) -> UploadResult<()> {
-        let mut active_guard = self.active_uploads.write().await;
-        if let Some(active_upload) = active_guard.remove(&id) {
-            active_upload.cancellation_token.cancel();
-            match active_upload.handle.await {
-                Ok(mut upload) => {
-                    if let Ok(_) = upload.transition_to(UploadStatus::Paused) {
-                        let mut shelved_guard = self.shelved_uploads.write().await;
-                        shelved_guard.push(upload);
-                    }
-                }
-                Err(err) => {
-                    println!("{}", err);
+    /// 停止调度新的 upload，中断正在上传的 upload，最多等待 timeout
+    /// 被中断和等待重试的 upload 改为 Paused 写入状态，下次启动后由用户继续
+    pub async fn shutdown_with_timeout(&self, timeout: Duration) -> UploadResult<()> {
+        self.cancellation_token.cancel();
+        self.tasks.close();
+        let stopped = async {
+            match self.scheduler() {
+                Some(scheduler) => scheduler.stopped().await,
+                None => self.tasks.wait().await,
+            }
+        };
+        if tokio::time::timeout(timeout, stopped).await.is_err() {
+            log::warn!("Timed out waiting for {} upload tasks to stop", self.tasks.len());
+        }
+
+        // 超时后还没有结束的 worker 直接中止，它的 upload 已经出队，从实时进度取回后和其他 upload 一样暂停
+        let active: Vec<(String, ActiveUpload)> = self.active_uploads.write().await.drain().collect();
+        let mut interrupted = Vec::new();
+        for (id, active) in active {
+            if !active.handle.is_finished() {
+                active.handle.abort();
+                let _ = active.handle.await;
+                interrupted.extend(self.status_cache.live_upload(&id));
+                continue;
+            }
+            if let Ok(upload) = active.handle.await {
+                interrupted.push(upload);
+            }
+        }
+        for mut upload in interrupted {
+            if upload.is_finished() || upload.transition_at(UploadStatus::Paused, self.clock.now_utc()).is_err() {
+                continue;
+            }
+            let id = upload.id.clone();
+            match self.upload_state.shelve(upload).await {
+                Ok(()) => self.events.emit(UploadEvent::Paused { id: id.clone(), at: self.clock.now_utc() }),
+                Err(err) => log::error!("Failed to persist interrupted upload {}: {}", id, err),
+            }
+            self.status_cache.invalidate(&id);
+        }
+        match self.upload_state.pause_where(|upload| upload.status == UploadStatus::WaitingRetry).await {
+            Ok(ids) => {
+                let at = self.clock.now_utc();
+                for id in ids {
+                    self.events.emit(UploadEvent::Paused { id, at });
                 }
-            };
+            }
+            Err(err) => log::warn!("Failed to pause uploads waiting for retry: {}", err),
         }
 
-        Ok(())
+        self.upload_state.shutdown().await
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tokio::{join, select};
+    /// 创建一个新的 upload
+    /// 新的 upload 最初状态是 pending，添加到 upload_state 中
+    /// 同一个文件已经在列表中时返回已有的 upload
+    pub async fn add_upload(&self, file_path: PathBuf) -> UploadResult<AddOutcome> {
+        self.add_upload_with_options(file_path, AddUploadOptions::default()).await
+    }
+
+    /// 使用自定义参数创建 upload，没有 allow_duplicates 时同一个文件只添加一次
+    pub async fn add_upload_with_options(&self, file_path: PathBuf, options: AddUploadOptions) -> UploadResult<AddOutcome> {
+        self.add_batch(vec![(file_path, options)]).await.pop().expect("one result per file")
+    }
+
+    /// 一次添加多个文件，只写入一次状态文件，新添加的 upload 放在同一个新的分组中
+    /// 单个文件失败（不存在、名称不合法等）不影响其他文件；同一批中重复的文件只添加一次
+    pub async fn add_uploads(&self, paths: Vec<PathBuf>) -> BatchAdded {
+        let group = uuid::Uuid::new_v4().to_string();
+        let options = AddUploadOptions { group: Some(group.clone()), ..Default::default() };
+        let results = self.add_batch(paths.into_iter().map(|path| (path, options.clone())).collect()).await;
+        BatchAdded { group, results }
+    }
+
+    fn validate_add_options(&self, options: &AddUploadOptions) -> UploadResult<()> {
+        options.validate()?;
+        if let Some(name) = &options.endpoint {
+            if !self.config.endpoints_by_name.contains_key(name) {
+                return Err(UploadError::InvalidOptions(format!("Unknown endpoint profile: {}", name)));
+            }
+        }
+        Ok(())
+    }
+
+    /// 添加文件夹中的所有文件，返回新添加的 upload 和跳过的数量
+    /// 文件放在 options 指定的分组中，没有指定时使用新的分组；
+    /// 已经在列表中的文件也算作跳过；单个文件添加失败时记录日志并跳过，不影响其他文件
+    pub async fn add_directory(&self, path: PathBuf, mut options: AddDirectoryOptions) -> UploadResult<DirectoryAdded> {
+        options.validate()?;
+        self.validate_add_options(&options.upload)?;
+        let group = options.upload.group.get_or_insert_with(|| uuid::Uuid::new_v4().to_string()).clone();
+
+        let (files, mut skipped) = directory::collect(&path, &options).await?;
+        let items: Vec<(PathBuf, AddUploadOptions)> = files.into_iter()
+            .map(|file| {
+                let mut upload_options = options.upload.clone();
+                if options.record_relative_path {
+                    upload_options.metadata.insert(RELATIVE_PATH_KEY.to_string(), file.relative);
+                }
+                (file.path, upload_options)
+            })
+            .collect();
+        let paths: Vec<PathBuf> = items.iter().map(|(path, _)| path.clone()).collect();
+
+        let mut ids = Vec::with_capacity(items.len());
+        for (path, result) in paths.into_iter().zip(self.add_batch(items).await) {
+            match result {
+                Ok(AddOutcome::Added(id)) => ids.push(id),
+                Ok(AddOutcome::Duplicate(_)) => skipped += 1,
+                Err(err) => {
+                    log::warn!("Failed to add {}: {}", path.display(), err);
+                    skipped += 1;
+                }
+            }
+        }
+        Ok(DirectoryAdded { group, ids, skipped })
+    }
+
+    /// 检查参数和重复后构建所有 upload，一次写入状态
+    /// 检查重复到写入完成之间持有 add_lock，同时添加同一个文件时只添加一次
+    async fn add_batch(&self, items: Vec<(PathBuf, AddUploadOptions)>) -> Vec<UploadResult<AddOutcome>> {
+        let _adding = self.add_lock.lock().await;
+        let mut known: Option<KnownSources> = None;
+        let mut results: Vec<Option<UploadResult<AddOutcome>>> = Vec::with_capacity(items.len());
+        let mut pending = Vec::new();
+        for (file_path, options) in items {
+            match self.prepare_upload(&mut known, file_path, options).await {
+                Ok(Prepared::New(upload, truncations)) => {
+                    pending.push((results.len(), upload, truncations));
+                    results.push(None);
+                }
+                Ok(Prepared::Added(id)) => results.push(Some(Ok(AddOutcome::Added(id)))),
+                Ok(Prepared::Duplicate(id)) => results.push(Some(Ok(AddOutcome::Duplicate(id)))),
+                Err(err) => results.push(Some(Err(err))),
+            }
+        }
+
+        if !pending.is_empty() {
+            // 创建快照到写入状态之间持有锁，避免启动清理误删刚创建的快照
+            let _snapshots = match self.config.snapshot_sources {
+                true => Some(self.snapshot_lock.lock().await),
+                false => None,
+            };
+            let mut uploads = Vec::with_capacity(pending.len());
+            for (index, mut upload, truncations) in pending {
+                if self.config.snapshot_sources {
+                    let dir = snapshot::snapshot_dir(&self.config.state_dir);
+                    match snapshot::create_snapshot(&upload.file_path, &dir, &upload.id, self.config.snapshot_copy_threshold).await {
+                        Ok(Some((path, _))) => upload.snapshot_path = Some(path),
+                        Ok(None) => {}
+                        Err(err) => {
+                            results[index] = Some(Err(err));
+                            continue;
+                        }
+                    }
+                }
+                uploads.push((index, upload, truncations));
+            }
+
+            let inserted = self.upload_state.insert_many(uploads.iter().map(|(_, upload, _)| upload.clone()).collect()).await;
+            for ((index, mut upload, truncations), result) in uploads.into_iter().zip(inserted) {
+                results[index] = Some(match result {
+                    Ok(()) => {
+                        self.events.emit(UploadEvent::Added { id: upload.id.clone(), at: self.clock.now_utc() });
+                        self.emit_truncations(&upload.id, truncations);
+                        Ok(AddOutcome::Added(upload.id))
+                    }
+                    Err(err) => {
+                        release_snapshot(&mut upload).await;
+                        Err(err)
+                    }
+                });
+            }
+        }
+
+        results.into_iter().map(|result| result.expect("every file has a result")).collect()
+    }
+
+    /// 检查参数和重复，构建还没有写入状态的 upload；拆分上传和长度未知的 upload 直接添加
+    async fn prepare_upload(
+        &self,
+        known: &mut Option<KnownSources>,
+        file_path: PathBuf,
+        options: AddUploadOptions,
+    ) -> UploadResult<Prepared> {
+        self.validate_add_options(&options)?;
+        // 长度未知的来源还在写入，不检查重复
+        if options.defer_length {
+            return self.add_deferred_upload(file_path, options).await.map(Prepared::Added);
+        }
+        let source = match options.allow_duplicates {
+            true => None,
+            false => {
+                let source = SourceKey::of(&file_path).await?;
+                if known.is_none() {
+                    *known = Some(KnownSources::of(&self.list_uploads().await).await);
+                }
+                let known = known.as_mut().unwrap();
+                if let Some(existing) = known.get(&source) {
+                    return Ok(Prepared::Duplicate(existing.clone()));
+                }
+                Some((source, known))
+            }
+        };
+
+        // 服务端的大小限制已知时直接拒绝，拆分上传时限制的是每个部分
+        let config = self.config.for_profile(options.endpoint.as_deref())?;
+        if let Some(limit) = self.capabilities.peek(&config.endpoint).and_then(|server| server.max_size) {
+            let mut size = tokio::fs::metadata(&file_path).await?.len();
+            if let Some(policy) = &self.config.split_oversize {
+                size = size.min(policy.part_size);
+            }
+            if size > limit {
+                return Err(UploadError::FileTooLarge { size, limit });
+            }
+        }
+
+        let chunk_size = options.chunk_size.unwrap_or(self.config.chunk_size);
+        if let Some(policy) = &self.config.split_oversize {
+            if tokio::fs::metadata(&file_path).await?.len() > policy.part_size {
+                let group = self.add_split_upload(file_path, options, chunk_size).await?;
+                if let Some((source, known)) = source {
+                    known.insert(source, group.clone());
+                }
+                return Ok(Prepared::Added(group));
+            }
+        }
+
+        let mut upload = Upload::new(file_path, chunk_size)?;
+        upload.endpoint = options.endpoint;
+        upload.metadata.extend(options.metadata);
+        upload.client_ref = options.client_ref;
+        upload.bandwidth_limit = options.bandwidth_limit;
+        upload.headers = options.headers;
+        upload.secret_headers = options.secret_headers;
+        upload.max_retries_override = options.max_retries;
+        upload.group = options.group;
+        upload.fingerprint = Some(SourceFingerprint::of(&upload.file_path).await?);
+        let truncations = upload.enforce_metadata_limits(&self.config.metadata_limits)?;
+        if let Some((source, known)) = source {
+            known.insert(source, upload.id.clone());
+        }
+        Ok(Prepared::New(upload, truncations))
+    }
+
+    /// 添加长度未知的 upload，来源还在写入，所以不检查大小、不拆分也不创建快照
+    async fn add_deferred_upload(&self, file_path: PathBuf, options: AddUploadOptions) -> UploadResult<String> {
+        let chunk_size = options.chunk_size.unwrap_or(self.config.chunk_size);
+        let mut upload = Upload::new_deferred(file_path, chunk_size)?;
+        upload.endpoint = options.endpoint;
+        upload.metadata.extend(options.metadata);
+        upload.client_ref = options.client_ref;
+        upload.bandwidth_limit = options.bandwidth_limit;
+        upload.headers = options.headers;
+        upload.secret_headers = options.secret_headers;
+        upload.max_retries_override = options.max_retries;
+        upload.group = options.group;
+        let truncations = upload.enforce_metadata_limits(&self.config.metadata_limits)?;
+
+        let id = self.add_existing_upload(upload).await?;
+        self.emit_truncations(&id, truncations);
+        Ok(id)
+    }
+
+    fn emit_truncations(&self, id: &str, truncations: Vec<MetadataTruncation>) {
+        for truncation in truncations {
+            log::info!("Truncated metadata {} of upload {} from {} to {} bytes",
+                      truncation.key, id, truncation.original_size, truncation.truncated_size);
+            self.events.emit(UploadEvent::MetadataTruncated {
+                id: id.to_string(),
+                key: truncation.key,
+                original_size: truncation.original_size,
+                truncated_size: truncation.truncated_size,
+            });
+        }
+    }
+
+    /// 把超过 part_size 的文件拆分成多个 upload，返回拆分分组 id
+    /// 每个部分的元数据记录序号、数量以及源文件指纹，服务端据此重新组合；拆分的部分不创建快照；
+    /// 指定了分组时所有部分加入这个分组，拆分分组只记录在元数据中
+    async fn add_split_upload(&self, file_path: PathBuf, options: AddUploadOptions, chunk_size: usize) -> UploadResult<String> {
+        let policy = self.config.split_oversize.clone().unwrap();
+        let metadata = tokio::fs::metadata(&file_path).await?;
+        let size = metadata.len();
+        let modified = metadata.modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let fingerprint = format!("{}-{}", size, modified);
+        let source = SourceFingerprint::of(&file_path).await?;
+
+        let group = uuid::Uuid::new_v4().to_string();
+        let count = size.div_ceil(policy.part_size) as u32;
+        let mut parts = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            let start = index as u64 * policy.part_size;
+            let part = UploadPart { index, count, start, end: (start + policy.part_size).min(size) };
+            let mut upload = Upload::new_part(file_path.clone(), chunk_size, group.clone(), part)?;
+            if policy.naming == SplitNaming::Suffix {
+                upload.filename = format!("{}.part{:02}", upload.filename, index + 1);
+            }
+            upload.endpoint = options.endpoint.clone();
+            upload.client_ref = options.client_ref.clone();
+            upload.bandwidth_limit = options.bandwidth_limit;
+            upload.headers = options.headers.clone();
+            upload.secret_headers = options.secret_headers.clone();
+            upload.max_retries_override = options.max_retries;
+            if let Some(batch) = &options.group {
+                upload.group = Some(batch.clone());
+            }
+            upload.fingerprint = Some(source.clone());
+            upload.metadata.extend(options.metadata.clone());
+            upload.metadata.insert("part_index".to_string(), index.to_string());
+            upload.metadata.insert("part_count".to_string(), count.to_string());
+            upload.metadata.insert("parent_fingerprint".to_string(), fingerprint.clone());
+            upload.metadata.insert(SPLIT_GROUP_KEY.to_string(), group.clone());
+            let truncations = upload.enforce_metadata_limits(&self.config.metadata_limits)?;
+            parts.push((upload, truncations));
+        }
+
+        let mut added = Vec::with_capacity(parts.len());
+        for (upload, truncations) in parts {
+            match self.add_existing_upload(upload).await {
+                Ok(id) => {
+                    self.emit_truncations(&id, truncations);
+                    added.push(id);
+                }
+                Err(err) => {
+                    // 已经添加的部分一起撤销，所在的分组中可能还有其他 upload，只删除这些部分
+                    let at = self.clock.now_utc();
+                    for id in added {
+                        if let Ok(Some(mut upload)) = self.upload_state.remove(&id).await {
+                            release_snapshot(&mut upload).await;
+                            self.status_cache.invalidate(&id);
+                            self.events.emit(UploadEvent::Cancelled { id, at });
+                        }
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(group)
+    }
+
+    /// 分组的汇总进度、各状态的数量、速度和预计剩余时间
+    pub async fn get_group_status(&self, group: &str) -> UploadResult<GroupStatusInfo> {
+        let parts: Vec<UploadStatusInfo> = self.list_upload_statuses().await
+            .into_iter()
+            .filter(|status| status.group.as_deref() == Some(group))
+            .collect();
+        if parts.is_empty() {
+            return Err(UploadError::UploadNotFound(group.to_string()));
+        }
+        Ok(GroupStatusInfo::new(group.to_string(), parts))
+    }
+
+    /// 暂停分组中没有结束的 upload，返回暂停的数量
+    /// 排队的一次全部暂停，之后逐个停止等待重试和正在上传的；不支持继续的 upload 继续上传
+    pub async fn pause_group(&self, group: &str) -> UploadResult<usize> {
+        let in_group = |upload: &Upload| upload.group.as_deref() == Some(group);
+        if !self.list_uploads().await.iter().any(in_group) {
+            return Err(UploadError::UploadNotFound(group.to_string()));
+        }
+
+        // 先暂停排队和等待重试的，停止正在上传的之后空出的名额不会被同一分组使用
+        let mut paused = self.upload_state.pause_where(in_group).await?;
+        let at = self.clock.now_utc();
+        for id in &paused {
+            self.status_cache.invalidate(id);
+            self.events.emit(UploadEvent::Paused { id: id.clone(), at });
+        }
+
+        let active: Vec<String> = self.active_uploads.read().await.iter()
+            .filter(|(_, active)| active.group.as_deref() == Some(group))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in active {
+            match self.pause_upload(id.clone()).await {
+                Ok(()) => {
+                    if self.upload_state.get_upload(&id).await.is_ok_and(|upload| upload.status == UploadStatus::Paused) {
+                        paused.push(id);
+                    }
+                }
+                Err(UploadError::NotResumable(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.activity.touch();
+        Ok(paused.len())
+    }
+
+    /// 把分组中排队的 upload 移到队列最前面或最后面，分组内保持原来的顺序，返回移动的数量
+    /// 已经开始的不受影响
+    pub async fn set_group_priority(&self, group: &str, priority: GroupPriority) -> UploadResult<usize> {
+        let in_group = |upload: &Upload| upload.group.as_deref() == Some(group);
+        if !self.list_uploads().await.iter().any(in_group) {
+            return Err(UploadError::UploadNotFound(group.to_string()));
+        }
+        self.upload_state.move_where(in_group, priority == GroupPriority::High).await
+    }
+
+    /// 取消分组中的所有 upload，正在上传的先停止，返回删除的数量
+    pub async fn cancel_group(&self, group: &str) -> UploadResult<usize> {
+        self.backup_before("cancel group").await;
+        let in_group = |upload_group: &Option<String>| upload_group.as_deref() == Some(group);
+
+        // 先删除队列中的部分，避免停止正在上传的部分后空出的名额被同一分组使用；
+        // 刚出队还没有登记为 active 的部分在下一轮处理
+        let mut removed = Vec::new();
+        loop {
+            let before = removed.len();
+
+            for upload in self.upload_state.list().await {
+                if in_group(&upload.group) {
+                    if let Some(mut upload) = self.upload_state.remove(&upload.id).await? {
+                        release_snapshot(&mut upload).await;
+                        self.terminate_remote(&upload);
+                    }
+                    removed.push(upload.id);
+                }
+            }
+
+            let active: Vec<(String, ActiveUpload)> = {
+                let mut active_guard = self.active_uploads.write().await;
+                let ids: Vec<String> = active_guard.iter()
+                    .filter(|(_, active)| in_group(&active.group))
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                ids.into_iter().filter_map(|id| active_guard.remove(&id).map(|active| (id, active))).collect()
+            };
+            for (id, active) in active {
+                active.cancellation_token.cancel();
+                if let Ok(upload) = active.handle.await {
+                    self.terminate_remote(&upload);
+                }
+                removed.push(id);
+            }
+
+            if removed.len() == before {
+                break;
+            }
+        }
+
+        self.activity.touch();
+        if removed.is_empty() {
+            return Err(UploadError::UploadNotFound(group.to_string()));
+        }
+        removed.sort();
+        removed.dedup();
+        let at = self.clock.now_utc();
+        for id in &removed {
+            self.status_cache.invalidate(id);
+            self.progress.forget(id);
+            self.events.emit(UploadEvent::Cancelled { id: id.clone(), at });
+        }
+        Ok(removed.len())
+    }
+
+    /// 取消 upload，排队、正在上传、等待重试和队列外的都可以取消，正在上传的先停止
+    /// 状态改为 Cancelled 并保存，开启 terminate_abandoned 时删除服务端资源；已经完成的返回 InvalidState
+    pub async fn cancel_upload(&self, id: &str) -> UploadResult<()> {
+        self.backup_before("cancel upload").await;
+        let not_cancellable = |upload: &Upload| UploadError::InvalidState(
+            format!("Cannot cancel {:?} upload {}", upload.status, upload.id)
+        );
+
+        // 停止之前已经结束的，worker 已经保存了结果
+        let mut upload = self.take_upload(id, |upload| match upload.status.can_transition_to(UploadStatus::Cancelled) {
+            true => Ok(()),
+            false => Err(not_cancellable(upload)),
+        }).await?;
+
+        upload.transition_at(UploadStatus::Cancelled, self.clock.now_utc())?;
+        release_snapshot(&mut upload).await;
+        self.upload_state.shelve(upload.clone()).await?;
+        if let Err(err) = self.history.record(&upload).await {
+            log::warn!("Failed to record upload history: {}", err);
+        }
+        if self.config.terminate_abandoned {
+            self.terminate_remote(&upload);
+        }
+        self.status_cache.invalidate(id);
+        self.activity.touch();
+        self.events.emit(UploadEvent::Cancelled { id: id.to_string(), at: self.clock.now_utc() });
+        Ok(())
+    }
+
+    /// 从队列、shelved、等待重试或正在上传中取出 upload，正在上传的先停止并等待 worker 退出
+    /// check 不通过时返回它的错误，队列中的不会被取出
+    async fn take_upload(&self, id: &str, check: impl Fn(&Upload) -> UploadResult<()>) -> UploadResult<Upload> {
+        loop {
+            if let Ok(existing) = self.upload_state.get_upload(id).await {
+                check(&existing)?;
+                if let Some(upload) = self.upload_state.remove(id).await? {
+                    return Ok(upload);
+                }
+            }
+            let active = self.active_uploads.write().await.remove(id);
+            if let Some(active) = active {
+                active.cancellation_token.cancel();
+                return match active.handle.await {
+                    Ok(upload) => check(&upload).map(|_| upload),
+                    Err(err) => Err(UploadError::InvalidState(format!("Upload {} task failed: {}", id, err))),
+                };
+            }
+            // 刚出队还没有登记为 active，出队时已经开始跟踪实时进度
+            if !self.status_cache.is_live(id) {
+                return Err(UploadError::UploadNotFound(id.to_string()));
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    /// 删除 upload，正在上传的先停止；delete_remote 时同时删除服务端资源，资源已经不存在时忽略
+    /// 本地记录总是会删除，删除服务端资源失败时返回错误
+    pub async fn remove_upload(&self, id: &str, delete_remote: bool) -> UploadResult<()> {
+        self.backup_before("remove upload").await;
+        let mut upload = self.take_upload(id, |_| Ok(())).await?;
+        // 停止之前 worker 可能已经保存了结果
+        self.upload_state.remove(id).await?;
+        release_snapshot(&mut upload).await;
+        self.status_cache.invalidate(id);
+        self.progress.forget(id);
+        self.activity.touch();
+        self.events.emit(UploadEvent::Removed { id: id.to_string(), at: self.clock.now_utc() });
+
+        match (delete_remote, &upload.location) {
+            (true, Some(location)) => {
+                let config = self.config.for_upload(&upload)?;
+                terminate_if_supported(&self.capabilities, &config, location, self.clock.as_ref()).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// 删除已经结束的 upload 的记录，不会影响等待、上传中和暂停的 upload，返回删除的数量
+    /// filter 为空时删除已完成和已取消的，也可以指定 Completed、Cancelled 或 Failed 中的一种
+    pub async fn clear_finished(&self, filter: Option<UploadStatus>) -> UploadResult<usize> {
+        let statuses = match filter {
+            None => vec![UploadStatus::Completed, UploadStatus::Cancelled],
+            Some(status @ (UploadStatus::Completed | UploadStatus::Cancelled | UploadStatus::Failed)) => vec![status],
+            Some(status) => {
+                return Err(UploadError::InvalidState(format!("Cannot clear uploads in status {:?}", status)));
+            }
+        };
+
+        self.backup_before("clear finished uploads").await;
+        let removed = self.upload_state.clear_finished(&statuses).await?;
+        let count = removed.len();
+        for mut upload in removed {
+            release_snapshot(&mut upload).await;
+            self.status_cache.invalidate(&upload.id);
+            self.progress.forget(&upload.id);
+            self.events.emit(UploadEvent::Removed { id: upload.id, at: self.clock.now_utc() });
+        }
+        if count > 0 {
+            self.activity.touch();
+        }
+        Ok(count)
+    }
+
+    /// 在后台删除取消的 upload 在服务端的资源，失败时只记录日志
+    fn terminate_remote(&self, upload: &Upload) {
+        if let Some(location) = upload.location.clone() {
+            self.terminate_location(upload, location);
+        }
+    }
+
+    /// 在后台删除 upload 不再使用的服务端资源，失败时只记录日志
+    fn terminate_location(&self, upload: &Upload, location: String) {
+        let config = match self.config.for_upload(upload) {
+            Ok(config) => config,
+            Err(err) => {
+                log::warn!("Failed to terminate upload resource {}: {}", location, err);
+                return;
+            }
+        };
+        let token = self.cancellation_token.clone();
+        let capabilities = self.capabilities.clone();
+        let clock = self.clock.clone();
+        self.tasks.spawn(async move {
+            let result = terminate_if_supported(&capabilities, &config, &location, clock.as_ref());
+            select! {
+                _ = token.cancelled() => {}
+                result = result => {
+                    if let Err(err) = result {
+                        log::warn!("Failed to terminate upload resource {}: {}", location, err);
+                    }
+                }
+            }
+        });
+    }
+
+    /// 立即备份当前状态，正在上传的 upload 也包括在内，状态文件还不存在时返回 None
+    pub async fn create_backup(&self) -> UploadResult<Option<BackupInfo>> {
+        self.upload_state.save_state().await?;
+        match self.upload_state.export(|| self.status_cache.live_uploads()).await? {
+            Some(content) => self.backups.backup(&content).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// 所有备份，最新的排在前面
+    pub async fn list_backups(&self) -> UploadResult<Vec<BackupInfo>> {
+        self.backups.list().await
+    }
+
+    /// 用备份替换当前的所有 upload，替换前会先备份当前状态
+    /// 有 upload 正在上传或等待重试时返回 InvalidState，force 时先停止它们
+    pub async fn restore_backup(&self, name: &str, force: bool) -> UploadResult<StateLoaded> {
+        let path = self.backups.path(name)?;
+
+        let active_count = self.active_uploads.read().await.values()
+            .filter(|active| !active.handle.is_finished())
+            .count();
+        let waiting = self.upload_state.list().await.iter()
+            .filter(|upload| upload.status == UploadStatus::WaitingRetry)
+            .count();
+        let busy = active_count + waiting;
+        if busy > 0 && !force {
+            return Err(UploadError::InvalidState(format!(
+                "Cannot restore backup while {} uploads are active", busy
+            )));
+        }
+
+        // 停止的 upload 不再写回状态，之后会被备份中的版本替换
+        let active: Vec<(String, ActiveUpload)> = self.active_uploads.write().await.drain().collect();
+        for (_, active) in &active {
+            active.cancellation_token.cancel();
+        }
+        for (_, active) in active {
+            let _ = active.handle.await;
+        }
+        self.activity.touch();
+
+        self.backup_before("restore backup").await;
+        let previous = self.upload_state.list().await;
+        let loaded = self.upload_state.restore_from(&path).await?;
+        for upload in previous.into_iter().chain(self.upload_state.list().await) {
+            self.status_cache.invalidate(&upload.id);
+            self.progress.forget(&upload.id);
+        }
+        Ok(loaded)
+    }
+
+    /// 大量删除或替换前的备份，失败时只记录日志
+    async fn backup_before(&self, action: &str) {
+        if let Err(err) = self.create_backup().await {
+            log::warn!("Failed to back up upload state before {}: {}", action, err);
+        }
+    }
+
+    /// 添加已经构建好的 upload，例如从其他设备导入
+    /// id 已经存在时返回 DuplicateUploadId，带有的服务端地址按服务端返回的 Location 同样校验
+    pub async fn add_existing_upload(&self, mut upload: Upload) -> UploadResult<String> {
+        if let Some(raw) = upload.location.take() {
+            let config = self.config.for_profile(upload.endpoint.as_deref())?;
+            upload.location = Some(location::resolve(
+                &config.endpoint,
+                &raw,
+                config.max_location_len,
+                config.location_policy,
+            )?);
+        }
+
+        let upload_id = upload.id.clone();
+        if self.active_uploads.read().await.contains_key(&upload_id) {
+            return Err(UploadError::DuplicateUploadId(upload_id));
+        }
+
+        self.upload_state.insert(upload).await?;
+        self.events.emit(UploadEvent::Added { id: upload_id.clone(), at: self.clock.now_utc() });
+
+        Ok(upload_id)
+    }
+
+    /// 加载状态时发现的、还没有处理的 id 冲突
+    pub async fn conflicts(&self) -> Vec<Upload> {
+        self.upload_state.conflicts().await
+    }
+
+    /// 处理 id 冲突
+    pub async fn resolve_conflict(&self, id: &str, keep: ConflictSide) -> UploadResult<()> {
+        self.upload_state.resolve_conflict(id, keep).await?;
+        self.status_cache.invalidate(id);
+        Ok(())
+    }
+
+    /// 修改还未开始的 upload 的元数据，值为 None 时删除对应的 key
+    /// 只允许 Pending、Blocked 以及没有服务端地址的 Paused；
+    /// 服务端已经创建资源后返回 MetadataFrozen，除非开启了 recreate_on_metadata_update，
+    /// 此时放弃旧的资源，服务端支持 termination 时在后台删除
+    pub async fn update_metadata(&self, id: &str, patch: HashMap<String, Option<String>>) -> UploadResult<()> {
+        for key in patch.keys() {
+            crate::core::options::validate_metadata_key(key)?;
+        }
+
+        if self.is_started(id).await {
+            return Err(UploadError::MetadataFrozen(id.to_string()));
+        }
+
+        let recreate = self.config.recreate_on_metadata_update;
+        let limits = self.config.metadata_limits;
+        let (upload, (abandoned, discarded, truncations)) = self.upload_state.update(id, |upload| {
+            if !matches!(upload.status, UploadStatus::Pending | UploadStatus::Paused | UploadStatus::Blocked) {
+                return Err(UploadError::InvalidState(format!(
+                    "Cannot update metadata of {:?} upload", upload.status
+                )));
+            }
+
+            let mut discarded = 0;
+            let abandoned = upload.location.take();
+            if abandoned.is_some() {
+                if !recreate {
+                    return Err(UploadError::MetadataFrozen(upload.id.clone()));
+                }
+                // 放弃旧的资源，下次开始时重新创建
+                upload.verification = None;
+                discarded = std::mem::take(&mut upload.progress.bytes_transferred);
+            }
+
+            for (key, value) in patch {
+                match value {
+                    Some(value) => upload.metadata.insert(key, value),
+                    None => upload.metadata.remove(&key),
+                };
+            }
+            let truncations = upload.enforce_metadata_limits(&limits)?;
+            Ok((abandoned, discarded, truncations))
+        }).await?;
+
+        self.status_cache.invalidate(id);
+        if discarded > 0 {
+            self.progress.correct(id, discarded, 0, CorrectionReason::Recreated);
+        }
+        if let Some(location) = abandoned {
+            self.terminate_location(&upload, location);
+        }
+        self.emit_truncations(id, truncations);
+        self.events.emit(UploadEvent::MetadataUpdated {
+            id: upload.id,
+            metadata: upload.metadata,
+        });
+
+        Ok(())
+    }
+
+    /// 用新的源文件重新开始 upload，保留 id、元数据、分组等字段
+    /// 只允许 Pending、Paused、Failed 和 Blocked；正在上传的 upload 在 pause_active 时先暂停，否则返回 InvalidState
+    /// 进度和服务端地址被重置，Failed 的 upload 重新排队；开启 terminate_abandoned 时删除旧的服务端资源
+    pub async fn replace_source(&self, id: &str, new_path: PathBuf, pause_active: bool) -> UploadResult<()> {
+        if self.is_started(id).await {
+            if !pause_active {
+                return Err(UploadError::InvalidState(format!(
+                    "Cannot replace source of active upload {}, pause it first", id
+                )));
+            }
+            self.pause_upload(id.to_string()).await?;
+        }
+
+        let upload = self.upload_state.get_upload(id).await?;
+        check_replaceable(&upload)?;
+
+        // 重新读取文件信息，得到新的文件名、长度和指纹
+        let mut source = Upload::new(new_path.clone(), upload.chunk_size)?;
+        let fingerprint = SourceFingerprint::of(&source.file_path).await?;
+
+        // 旧的快照在替换成功后才删除，新的快照使用不同的文件名；创建到写入状态之间持有锁，避免启动清理误删
+        let snapshot_guard = match self.config.snapshot_sources {
+            true => Some(self.snapshot_lock.lock().await),
+            false => None,
+        };
+        if self.config.snapshot_sources {
+            let dir = snapshot::snapshot_dir(&self.config.state_dir);
+            let name = format!("{}-{}", upload.id, uuid::Uuid::new_v4().simple());
+            let threshold = self.config.snapshot_copy_threshold;
+            if let Some((path, _)) = snapshot::create_snapshot(&source.file_path, &dir, &name, threshold).await? {
+                source.snapshot_path = Some(path);
+            }
+        }
+
+        // 读取文件期间 upload 可能已经开始或被修改，在状态锁内重新检查后再替换
+        let limits = self.config.metadata_limits;
+        let new_snapshot = source.snapshot_path.clone();
+        let now = self.clock.now_utc();
+        let replaced = self.upload_state.replace_with(id, |upload| {
+            check_replaceable(upload)?;
+            let old_path = std::mem::replace(&mut upload.file_path, source.file_path);
+            let old_snapshot = std::mem::replace(&mut upload.snapshot_path, source.snapshot_path);
+            let old_location = upload.location.take();
+            let discarded = upload.progress.bytes_transferred;
+            upload.fingerprint = Some(fingerprint);
+            upload.filename = source.filename;
+            upload.total_bytes = source.total_bytes;
+            upload.progress = source.progress;
+            upload.verification = None;
+            upload.digest = None;
+            upload.retry_count = 0;
+            upload.active_time = Default::default();
+            if upload.status == UploadStatus::Failed {
+                upload.transition_at(UploadStatus::Pending, now)?;
+            }
+            upload.update_at = now;
+            // 新的文件名也计入元数据的大小限制
+            let truncations = upload.enforce_metadata_limits(&limits)?;
+            Ok((old_path, old_snapshot, old_location, discarded, truncations))
+        }).await;
+        drop(snapshot_guard);
+        let (upload, (old_path, old_snapshot, old_location, discarded, truncations)) = match replaced {
+            Ok(replaced) => replaced,
+            Err(err) => {
+                if let Some(path) = new_snapshot {
+                    snapshot::remove_snapshot(&path).await;
+                }
+                // 期间已经出队，不在状态中
+                if matches!(err, UploadError::UploadNotFound(_)) && self.is_started(id).await {
+                    return Err(UploadError::InvalidState(format!(
+                        "Cannot replace source of upload {}, it started while reading the new file", id
+                    )));
+                }
+                return Err(err);
+            }
+        };
+        if let Some(path) = old_snapshot {
+            snapshot::remove_snapshot(&path).await;
+        }
+
+        self.status_cache.invalidate(id);
+        if discarded > 0 {
+            self.progress.correct(id, discarded, 0, CorrectionReason::Recreated);
+        }
+        self.events.emit(UploadEvent::SourceReplaced {
+            id: id.to_string(),
+            old_path,
+            new_path: upload.file_path.clone(),
+            total_bytes: upload.total_bytes,
+        });
+        self.emit_truncations(id, truncations);
+
+        // 新的资源在下一次开始时创建，旧的资源删除失败不影响替换
+        if let (Some(location), true) = (old_location, self.config.terminate_abandoned) {
+            let config = self.config.for_upload(&upload)?;
+            if let Err(err) = terminate_if_supported(&self.capabilities, &config, &location, self.clock.as_ref()).await {
+                log::warn!("Failed to terminate abandoned upload {}: {}", location, err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 源文件被修改（FileChanged）后确实要上传新的内容时调用：放弃服务端已有的进度，按文件当前的内容重新创建
+    /// 正在上传的 upload 先暂停
+    pub async fn force_restart(&self, id: &str) -> UploadResult<()> {
+        if self.is_started(id).await {
+            self.pause_upload(id.to_string()).await?;
+        }
+        let file_path = self.upload_state.get_upload(id).await?.file_path;
+        self.replace_source(id, file_path, false).await
+    }
+
+    /// 正在上传或等待重试
+    async fn is_started(&self, id: &str) -> bool {
+        self.active_uploads.read().await.contains_key(id)
+            || self.upload_state.get_upload(id).await.is_ok_and(|upload| upload.status == UploadStatus::WaitingRetry)
+    }
+
+    /// 暂停 upload
+    /// 从 active 中移除，添加到 shelved 中；等待重试的直接改为 Paused
+    /// 不支持继续的 upload 返回 NotResumable
+    pub async fn pause_upload(&self, id: String) -> UploadResult<()> {
+        // 取出后立即释放锁，等待 worker 结束期间不阻塞调度和其他操作
+        let active_upload = {
+            let mut active_guard = self.active_uploads.write().await;
+            if let Some(active) = active_guard.get(&id) {
+                active.capabilities.ensure_resumable(&id)?;
+            }
+            active_guard.remove(&id)
+        };
+        if let Some(active_upload) = active_upload {
+            active_upload.cancellation_token.cancel();
+            match active_upload.handle.await {
+                Ok(mut upload) => {
+                    if let Ok(_) = upload.transition_at(UploadStatus::Paused, self.clock.now_utc()) {
+                        self.upload_state.shelve(upload).await?;
+                        self.events.emit(UploadEvent::Paused { id: id.clone(), at: self.clock.now_utc() });
+                    }
+                    self.status_cache.invalidate(&id);
+                }
+                Err(err) => {
+                    log::error!("Upload {} task failed: {}", id, err);
+                }
+            };
+        } else {
+            // 等待重试的在状态中，改为 Paused 后到时间也不会放回队列
+            let now = self.clock.now_utc();
+            let paused = self.upload_state.update(&id, |upload| match upload.status {
+                UploadStatus::WaitingRetry => upload.transition_at(UploadStatus::Paused, now).map(|_| true),
+                _ => Ok(false),
+            }).await;
+            if let Ok((_, true)) = paused {
+                self.status_cache.invalidate(&id);
+                self.activity.touch();
+                self.events.emit(UploadEvent::Paused { id: id.clone(), at: self.clock.now_utc() });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 继续暂停或失败的 upload
+    /// 从 shelved 中取出，改为 Pending 后放到队列最前面；重试次数清零，worker 通过 HEAD 从服务端的进度继续
+    pub async fn resume_upload(&self, id: &str) -> UploadResult<()> {
+        let mut upload = match self.upload_state.get_upload(id).await {
+            Ok(upload) => upload,
+            // 已经出队，正在上传
+            Err(UploadError::UploadNotFound(_)) if self.status_cache.is_live(id) => {
+                return Err(UploadError::InvalidState(format!("Upload {} is already running", id)));
+            }
+            Err(err) => return Err(err),
+        };
+        if !matches!(upload.status, UploadStatus::Paused | UploadStatus::Failed) {
+            return Err(UploadError::InvalidState(
+                format!("Cannot resume {:?} upload {}", upload.status, id)
+            ));
+        }
+        self.requeue_shelved(id).await?;
+        self.activity.touch();
+        Ok(())
+    }
+
+    /// 把 shelved 中的 upload 改为 Pending 放到队列最前面，重试次数清零
+    async fn requeue_shelved(&self, id: &str) -> UploadResult<()> {
+        let mut upload = self.upload_state.remove(id).await?
+            .ok_or_else(|| UploadError::UploadNotFound(id.to_string()))?;
+        if let Err(err) = upload.transition_at(UploadStatus::Pending, self.clock.now_utc()) {
+            // 检查之后状态已经改变，原样放回
+            self.upload_state.insert(upload).await?;
+            return Err(err);
+        }
+
+        upload.retry_count = 0;
+        // 入队后可能立即开始跟踪实时进度，在这之前丢弃缓存的状态
+        self.status_cache.invalidate(id);
+        self.upload_state.push_front(upload).await
+    }
+
+    /// 立即开始指定的 upload：从队列、shelved 或等待重试中取出，放到队列最前面并保存
+    /// 有空闲名额时下一次调度就开始它，返回 true；否则等第一个空出的名额。
+    /// 已经在上传的不做修改并返回 true，已经完成或取消的返回 InvalidState
+    pub async fn start_upload(&self, id: &str) -> UploadResult<bool> {
+        let upload = match self.upload_state.get_upload(id).await {
+            Ok(upload) => upload,
+            Err(UploadError::UploadNotFound(_)) if self.status_cache.is_live(id) => return Ok(true),
+            Err(err) => return Err(err),
+        };
+        match upload.status {
+            // 刚刚出队
+            UploadStatus::Pending if !self.upload_state.move_to_front(id).await? => {
+                // 被守卫推迟的不在队列中，提前放回
+                if !self.upload_state.requeue_scheduled(id).await? {
+                    return Ok(true);
+                }
+                self.upload_state.move_to_front(id).await?;
+            }
+            UploadStatus::Pending => {}
+            // 不再等待退避结束，放回队列最前面
+            UploadStatus::WaitingRetry => {
+                self.upload_state.requeue_scheduled(id).await?;
+            }
+            UploadStatus::Paused | UploadStatus::Failed | UploadStatus::Blocked => self.requeue_shelved(id).await?,
+            status => return Err(UploadError::InvalidState(
+                format!("Cannot start {:?} upload {}", status, id)
+            )),
+        }
+        let endpoint = upload.endpoint;
+        self.activity.touch();
+
+        let profile_free = endpoint.as_ref()
+            .and_then(|name| self.profile_slots.get(name))
+            .map_or(true, |slots| slots.available_permits() > 0);
+        let running = self.scheduler().is_some_and(|scheduler| scheduler.is_running());
+        Ok(running && profile_free && self.get_active_count().await < self.config.max_concurrent)
+    }
+
+    /// 重新开始一个失败的 upload，同时清零自动重试的次数
+    pub async fn retry_upload(&self, id: &str) -> UploadResult<()> {
+        if !requeue_failed(&self.upload_state, &self.status_cache, id, false, self.clock.now_utc()).await? {
+            return Err(UploadError::InvalidState(format!("Upload {} has not failed", id)));
+        }
+        self.activity.touch();
+        Ok(())
+    }
+
+    /// 重新开始所有失败的 upload，返回重新排队的数量
+    pub async fn retry_failed(&self) -> UploadResult<usize> {
+        let failed: Vec<String> = self.upload_state.list().await
+            .into_iter()
+            .filter(|upload| upload.status == UploadStatus::Failed)
+            .map(|upload| upload.id)
+            .collect();
+
+        // 逐个插入到队列最前面，倒序插入保持原来的顺序
+        let mut count = 0;
+        for id in failed.iter().rev() {
+            match requeue_failed(&self.upload_state, &self.status_cache, id, false, self.clock.now_utc()).await {
+                Ok(true) => count += 1,
+                Ok(false) | Err(UploadError::UploadNotFound(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if count > 0 {
+            self.activity.touch();
+        }
+        Ok(count)
+    }
+}
+
+/// 把失败的 upload 改为 Pending 放到队列最前面，清除错误和重试次数
+/// auto 为 true 时累计自动重试的次数，手动重试时清零；upload 已经不是 Failed（例如被取消）时返回 false
+async fn requeue_failed(upload_state: &UploadStateManager, status_cache: &StatusCache, id: &str, auto: bool, now: chrono::DateTime<chrono::Utc>) -> UploadResult<bool> {
+    if upload_state.get_upload(id).await?.status != UploadStatus::Failed {
+        return Ok(false);
+    }
+    let Some(mut upload) = upload_state.remove(id).await? else {
+        return Ok(false);
+    };
+    // 取出之前状态已经改变
+    if upload.status != UploadStatus::Failed {
+        upload_state.shelve(upload).await?;
+        return Ok(false);
+    }
+
+    upload.transition_at(UploadStatus::Pending, now)?;
+    upload.retry_count = 0;
+    upload.auto_retries = if auto { upload.auto_retries + 1 } else { 0 };
+    status_cache.invalidate(id);
+    upload_state.push_front(upload).await?;
+    Ok(true)
+}
+
+/// 到时间后把 shelve 时设置了 retry_at 的 upload 放回队列，关闭时不再处理
+fn schedule_requeue(
+    tasks: &TaskTracker,
+    token: CancellationToken,
+    clock: Arc<dyn Clock>,
+    upload_state: Arc<UploadStateManager>,
+    id: String,
+    delay: Duration,
+) {
+    tasks.spawn(async move {
+        select! {
+            _ = token.cancelled() => {},
+            _ = clock.sleep(delay) => {
+                if let Err(err) = upload_state.requeue_scheduled(&id).await {
+                    log::warn!("Failed to requeue upload {}: {}", id, err);
+                }
+            }
+        }
+    });
+}
+
+/// 服务端没有声明 termination 扩展时不发送 DELETE，无法确认时仍然尝试
+async fn terminate_if_supported(capabilities: &CapabilityCache, config: &TusConfig, location: &str, clock: &dyn Clock) -> UploadResult<()> {
+    let client = tls::client_for(config)?;
+    if capabilities.get(&client, config).await.is_some_and(|server| !server.supports(headers::TERMINATION)) {
+        return Ok(());
+    }
+    if capabilities.needs_method_override(&config.endpoint) {
+        let config = TusConfig { use_method_override: true, ..config.clone() };
+        return terminate(&client, &config, location, clock).await;
+    }
+    terminate(&client, config, location, clock).await
+}
+
+/// 拆分上传的最后一个部分完成后发出一次 GroupCompleted
+/// 按状态中记录的已完成部分计数，较早完成的部分可能已经超出保留范围，不在已完成列表中
+async fn notify_group_completed(
+    upload_state: &UploadStateManager,
+    events: &EventBus,
+    completed_groups: &std::sync::Mutex<std::collections::HashSet<String>>,
+    group: &str,
+    count: u32,
+) {
+    let parts = upload_state.completed_parts(group).await;
+    if parts.len() != count as usize {
+        return;
+    }
+    if !completed_groups.lock().unwrap().insert(group.to_string()) {
+        return;
+    }
+
+    events.emit(UploadEvent::GroupCompleted {
+        id: group.to_string(),
+        locations: parts.into_values().flatten().collect(),
+    });
+    if let Err(err) = upload_state.forget_completed_parts(group).await {
+        log::error!("Failed to persist completed group {}: {}", group, err);
+    }
+}
+
+/// prepare_upload 的结果
+enum Prepared {
+    /// 还没有写入状态的 upload 和被截断的元数据
+    New(Upload, Vec<MetadataTruncation>),
+
+    /// 已经添加的拆分上传或长度未知的 upload
+    Added(String),
+
+    /// 同一个文件已经在列表中
+    Duplicate(String),
+}
+
+/// 检查重复时比较的来源：规范化的路径、大小和修改时间
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct SourceKey {
+    path: PathBuf,
+    size: u64,
+    modified: Option<u64>,
+}
+
+impl SourceKey {
+    async fn of(file_path: &Path) -> UploadResult<Self> {
+        let metadata = tokio::fs::metadata(file_path).await?;
+        Ok(Self {
+            path: fingerprint::canonical_path(file_path).await,
+            size: metadata.len(),
+            modified: fingerprint::modified_nanos(&metadata),
+        })
+    }
+}
+
+/// 没有取消或失败的 upload 的来源，拆分上传对应分组 id；没有指纹的记录（长度未知、旧版本添加的）不参与比较
+#[derive(Debug, Default)]
+struct KnownSources(HashMap<SourceKey, String>);
+
+impl KnownSources {
+    async fn of(uploads: &[Upload]) -> Self {
+        let mut known = Self::default();
+        for upload in uploads {
+            if matches!(upload.status, UploadStatus::Cancelled | UploadStatus::Failed) {
+                continue;
+            }
+            let Some(source) = &upload.fingerprint else {
+                continue;
+            };
+            let id = upload.split_group().map_or_else(|| upload.id.clone(), str::to_string);
+            let path = fingerprint::canonical_path(&upload.file_path).await;
+            known.insert(SourceKey { path, size: source.size, modified: source.modified }, id);
+        }
+        known
+    }
+
+    fn get(&self, source: &SourceKey) -> Option<&String> {
+        self.0.get(source)
+    }
+
+    /// 同一个来源保留最先记录的 id
+    fn insert(&mut self, source: SourceKey, id: String) {
+        self.0.entry(source).or_insert(id);
+    }
+}
+
+/// 完成、取消或删除后删除快照，Failed 和 Paused 的 upload 可能继续上传，不能调用
+/// 没有加入状态就撤销的 upload 也在这里删除
+async fn release_snapshot(upload: &mut Upload) {
+    if let Some(path) = upload.snapshot_path.take() {
+        snapshot::remove_snapshot(&path).await;
+    }
+}
+
+/// panic 携带的消息，不是字符串时返回固定的说明
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload.downcast_ref::<&str>().copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("non-string panic payload")
+}
+
+/// replace_source 只允许还没有开始的、不是拆分部分的 upload
+fn check_replaceable(upload: &Upload) -> UploadResult<()> {
+    if !matches!(upload.status, UploadStatus::Pending | UploadStatus::Paused | UploadStatus::Failed | UploadStatus::Blocked) {
+        return Err(UploadError::InvalidState(format!(
+            "Cannot replace source of {:?} upload", upload.status
+        )));
+    }
+    if upload.part.is_some() {
+        return Err(UploadError::InvalidState(format!(
+            "Cannot replace source of upload {}, it is a part of a split file", upload.id
+        )));
+    }
+    Ok(())
+}
+
+impl Drop for UploadManager {
+    fn drop(&mut self) {
+        if !self.cancellation_token.is_cancelled() {
+            log::error!("UploadManager dropped without shutdown");
+            self.cancellation_token.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uploader::pipeline;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::{join, select};
     use tokio_util::sync::CancellationToken;
+    use crate::testing::TestClock;
+    use crate::tus_server::TusServer;
+
+    fn test_file(len: usize) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, &vec![7u8; len]).unwrap();
+        file
+    }
+
+    #[tokio::test]
+    async fn test_captive_portal_pauses_until_sign_in() {
+        let server = TusServer::start().await;
+        server.set_captive_portal(true);
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            captive_portal_probe_interval: Duration::from_millis(50),
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let mut events = manager.subscribe();
+        let run = manager.run().unwrap();
+
+        let files = [test_file(3000), test_file(5000)];
+        let mut ids = Vec::new();
+        for file in &files {
+            ids.push(manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id());
+        }
+        for id in &ids {
+            let upload = wait_for_status(&manager, id, UploadStatus::Blocked).await;
+            assert_eq!(upload.blocked_reason.as_deref(), Some(connectivity::CAPTIVE_PORTAL_REASON));
+        }
+        let event = next_event(&mut events).await;
+        assert_eq!(event, UploadEvent::CaptivePortalSuspected { endpoint: server.endpoint() });
+
+        // 登录后探测到真正的 tus 响应，全部恢复并完成
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        server.set_captive_portal(false);
+        for id in &ids {
+            wait_for_status(&manager, id, UploadStatus::Completed).await;
+        }
+        let mut lengths: Vec<usize> = server.uploads().iter().map(|upload| upload.data.len()).collect();
+        lengths.sort();
+        assert_eq!(lengths, [3000, 5000]);
+        let cleared = loop {
+            if let UploadEvent::CaptivePortalCleared { released } = events.recv().await.unwrap().event {
+                break released;
+            }
+        };
+        assert_eq!(cleared, 2);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_captive_portal_probe_resumes_after_restart() {
+        let server = TusServer::start().await;
+        server.set_captive_portal(true);
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            captive_portal_probe_interval: Duration::from_millis(50),
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config.clone()).await.unwrap());
+        let run = manager.run().unwrap();
+        let file = test_file(3000);
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+        let upload = wait_for_status(&manager, &id, UploadStatus::Blocked).await;
+        assert_eq!(upload.blocked_cause, Some(BlockCause::CaptivePortal));
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+        drop(manager);
+
+        // 重启后没有新的请求被拦截，也要继续探测并恢复上次被拦截的 upload
+        server.set_captive_portal(false);
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let run = manager.run().unwrap();
+        wait_for_status(&manager, &id, UploadStatus::Completed).await;
+        assert_eq!(server.uploads()[0].data.len(), 3000);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_pause_resume_never_overlaps_readers() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(2)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let run = manager.run().unwrap();
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let content: Vec<u8> = (0..256 * 1024).map(|i| (i % 251) as u8).collect();
+        std::io::Write::write_all(&mut file, &content).unwrap();
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+
+        for _ in 0..50 {
+            while !manager.active_uploads.read().await.contains_key(&id) {
+                tokio::task::yield_now().await;
+            }
+            manager.pause_upload(id.clone()).await.unwrap();
+            // 暂停返回时读取任务已经退出
+            assert_eq!(pipeline::active_readers(&id), 0);
+
+            if manager.upload_state.get_upload(&id).await.unwrap().status == UploadStatus::Completed {
+                break;
+            }
+            manager.resume_upload(&id).await.unwrap();
+        }
+
+        let upload = wait_for_status(&manager, &id, UploadStatus::Completed).await;
+        assert_eq!(server.upload(upload.location.as_ref().unwrap()).unwrap().data, content);
+        assert!(pipeline::max_readers_per_upload() <= 1);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+        assert_eq!(pipeline::active_readers(&id), 0);
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_pause_and_failure() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(20)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let run = manager.run().unwrap();
+
+        let file = test_file(16 * 1024);
+        let paused = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+        while server.patch_count() < 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        manager.pause_upload(paused.clone()).await.unwrap();
+        let offset = manager.upload_state.get_upload(&paused).await.unwrap().progress.bytes_transferred;
+        assert!(offset > 0 && offset < 16 * 1024);
+
+        // 失败并且用完了重试次数
+        let mut failed = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+        failed.fail(&UploadError::Config("bad".into())).unwrap();
+        failed.retry_count = 10;
+        let failed = manager.add_existing_upload(failed).await.unwrap();
+
+        assert!(matches!(manager.resume_upload("missing").await, Err(UploadError::UploadNotFound(_))));
+        manager.resume_upload(&failed).await.unwrap();
+        manager.resume_upload(&paused).await.unwrap();
+        assert!(matches!(manager.resume_upload(&paused).await, Err(UploadError::InvalidState(_))));
+
+        let failed = wait_for_status(&manager, &failed, UploadStatus::Completed).await;
+        assert_eq!(failed.retry_count, 0);
+        assert!(failed.last_error.is_none());
+        let paused = wait_for_status(&manager, &paused, UploadStatus::Completed).await;
+        // 从服务端的进度继续，没有重新创建
+        assert_eq!(server.uploads().len(), 2);
+        assert_eq!(server.upload(paused.location.as_ref().unwrap()).unwrap().data, vec![7u8; 16 * 1024]);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_list_uploads_merges_all_sources() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(20)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_concurrent: 1,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let file = test_file(64 * 1024);
+
+        let mut paused = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+        paused.status = UploadStatus::Paused;
+        let paused = manager.add_existing_upload(paused).await.unwrap();
+        let active = add_copy(&manager, file.path().to_path_buf()).await;
+        let queued = add_copy(&manager, file.path().to_path_buf()).await;
+
+        let run = manager.run().unwrap();
+        while server.patch_count() < 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(manager.get_active_count().await, 1);
+
+        let uploads = manager.list_uploads().await;
+        let ids: Vec<&str> = uploads.iter().map(|upload| upload.id.as_str()).collect();
+        assert_eq!(ids, [&paused, &active, &queued]);
+        let statuses: Vec<UploadStatus> = uploads.iter().map(|upload| upload.status).collect();
+        assert_eq!(statuses, [UploadStatus::Paused, UploadStatus::Active, UploadStatus::Pending]);
+        assert!(uploads[1].progress.bytes_transferred > 0);
+
+        // 正在上传的不在状态中，从实时进度读取
+        let live = manager.get_upload(&active).await.unwrap();
+        assert_eq!(live.status, UploadStatus::Active);
+        assert!(live.location.is_some());
+        assert!(matches!(manager.get_upload("missing").await, Err(UploadError::UploadNotFound(_))));
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+        assert_eq!(manager.get_active_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_reordered_queue_starts_in_new_order() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_concurrent: 1,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let file = test_file(1024);
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            ids.push(add_copy(&manager, file.path().to_path_buf()).await);
+        }
+
+        manager.reorder_queue(&[ids[2].clone(), ids[0].clone()]).await.unwrap();
+        manager.move_to_front(&ids[1]).await.unwrap();
+        let queue: Vec<String> = manager.get_queue().await.into_iter().map(|entry| entry.id).collect();
+        assert_eq!(queue, [ids[1].clone(), ids[2].clone(), ids[0].clone()]);
+        assert!(matches!(manager.move_to_front("missing").await, Err(UploadError::UploadNotFound(_))));
+
+        let mut events = manager.subscribe();
+        let run = manager.run().unwrap();
+        let mut started = Vec::new();
+        while started.len() < 3 {
+            if let UploadEvent::Started { id, .. } = events.recv().await.unwrap().event {
+                started.push(id);
+            }
+        }
+        assert_eq!(started, queue);
+
+        // 已经结束的 upload 不在队列中，不做修改
+        wait_for_status(&manager, &ids[0], UploadStatus::Completed).await;
+        manager.move_to_front(&ids[0]).await.unwrap();
+        assert!(manager.get_queue().await.is_empty());
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_start_upload_promotes_specific_upload() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(20)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_concurrent: 1,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let file = test_file(4 * 1024);
+        let first = add_copy(&manager, file.path().to_path_buf()).await;
+        let second = add_copy(&manager, file.path().to_path_buf()).await;
+        let last = add_copy(&manager, file.path().to_path_buf()).await;
+        let mut paused = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+        paused.status = UploadStatus::Paused;
+        let paused = manager.add_existing_upload(paused).await.unwrap();
+
+        // 还没有开始调度，没有空闲名额
+        assert!(!manager.start_upload(&last).await.unwrap());
+        let mut events = manager.subscribe();
+        let run = manager.run().unwrap();
+        while manager.get_active_count().await == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        // 唯一的名额正在上传 last
+        assert!(!manager.start_upload(&second).await.unwrap());
+        assert!(!manager.start_upload(&paused).await.unwrap());
+        assert!(manager.start_upload(&last).await.unwrap());
+
+        let queue: Vec<String> = manager.get_queue().await.into_iter().map(|entry| entry.id).collect();
+        assert_eq!(queue, [paused.clone(), second.clone(), first.clone()]);
+        let mut started = Vec::new();
+        while started.len() < 4 {
+            if let UploadEvent::Started { id, .. } = events.recv().await.unwrap().event {
+                started.push(id);
+            }
+        }
+        assert_eq!(started, [last, paused, second, first.clone()]);
+
+        // 有空闲名额时立即开始，完成的不能再开始
+        wait_for_status(&manager, &first, UploadStatus::Completed).await;
+        let mut failed = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+        failed.fail(&UploadError::Config("bad".into())).unwrap();
+        let failed = manager.add_existing_upload(failed).await.unwrap();
+        assert!(manager.start_upload(&failed).await.unwrap());
+        wait_for_status(&manager, &failed, UploadStatus::Completed).await;
+        assert!(matches!(manager.start_upload(&first).await, Err(UploadError::InvalidState(_))));
+        assert!(matches!(manager.start_upload("missing").await, Err(UploadError::UploadNotFound(_))));
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_finished_uploads_leave_active() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_retries: 0,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config.clone()).await.unwrap());
+        let mut events = manager.subscribe();
+        let run = manager.run().unwrap();
+        let file = test_file(2048);
+
+        let id = add_copy(&manager, file.path().to_path_buf()).await;
+        let location = loop {
+            if let UploadEvent::Completed { id: completed, location, .. } = next_lifecycle_event(&mut events).await {
+                assert_eq!(completed, id);
+                break location.unwrap();
+            }
+        };
+        server.fail_patch(3, 500);
+        let failed = add_copy(&manager, file.path().to_path_buf()).await;
+        wait_for_status(&manager, &failed, UploadStatus::Failed).await;
+        for _ in 0..100 {
+            if manager.active_uploads.read().await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(manager.active_uploads.read().await.is_empty());
+
+        let completed = manager.list_completed().await;
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].location.as_deref(), Some(location.as_str()));
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+
+        // 重启后仍然在已完成列表中
+        let manager = UploadManager::new(config).await.unwrap();
+        manager.wait_state_loaded().await;
+        assert_eq!(manager.list_completed().await[0].id, id);
+        assert_eq!(manager.get_upload(&failed).await.unwrap().status, UploadStatus::Failed);
+        manager.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_uploads_are_detected() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(temp_config(state_dir.path())).await.unwrap();
+        let file = test_file(2048);
+
+        let first = manager.add_upload(file.path().to_path_buf()).await.unwrap();
+        assert!(!first.is_duplicate());
+        let again = manager.add_upload(file.path().to_path_buf()).await.unwrap();
+        assert_eq!(again, AddOutcome::Duplicate(first.id().to_string()));
+        assert_eq!(
+            serde_json::to_value(&again).unwrap(),
+            serde_json::json!({ "outcome": "duplicate", "id": first.id() }),
+        );
+
+        // 符号链接和其他写法的路径指向同一个文件
+        #[cfg(unix)]
+        {
+            let link = state_dir.path().join("link");
+            std::os::unix::fs::symlink(file.path(), &link).unwrap();
+            assert!(manager.add_upload(link).await.unwrap().is_duplicate());
+        }
+        let dotted = file.path().parent().unwrap().join(".").join(file.path().file_name().unwrap());
+        assert!(manager.add_upload(dotted).await.unwrap().is_duplicate());
+
+        let copy = add_copy(&manager, file.path().to_path_buf()).await;
+        assert_eq!(manager.list_uploads().await.len(), 2);
+
+        // 取消后可以重新添加
+        manager.cancel_upload(first.id()).await.unwrap();
+        manager.cancel_upload(&copy).await.unwrap();
+        let readded = manager.add_upload(file.path().to_path_buf()).await.unwrap();
+        assert!(!readded.is_duplicate());
+
+        // 文件被修改后不再是同一个
+        std::fs::write(file.path(), [1u8; 4096]).unwrap();
+        assert!(!manager.add_upload(file.path().to_path_buf()).await.unwrap().is_duplicate());
+        manager.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_add_uploads_in_batch() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = temp_config(state_dir.path());
+        let manager = UploadManager::new(config.clone()).await.unwrap();
+        let mut events = manager.subscribe();
+        let files: Vec<_> = (0..3).map(|_| test_file(1024)).collect();
+        let existing = manager.add_upload(files[0].path().to_path_buf()).await.unwrap().into_id();
+
+        let missing = state_dir.path().join("missing.bin");
+        let paths = vec![
+            files[0].path().to_path_buf(),
+            files[1].path().to_path_buf(),
+            missing,
+            files[2].path().to_path_buf(),
+            files[1].path().to_path_buf(),
+        ];
+        let BatchAdded { group, results } = manager.add_uploads(paths).await;
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0].as_ref().unwrap(), &AddOutcome::Duplicate(existing.clone()));
+        assert!(matches!(results[2], Err(UploadError::IOError(_))));
+        let second = results[1].as_ref().unwrap().id().to_string();
+        let third = results[3].as_ref().unwrap().id().to_string();
+        assert_eq!(results[4].as_ref().unwrap(), &AddOutcome::Duplicate(second.clone()));
+        // 已经存在的不加入新的分组
+        assert_eq!(manager.get_upload(&existing).await.unwrap().group, None);
+        let status = manager.get_group_status(&group).await.unwrap();
+        assert_eq!(status.parts.iter().map(|part| part.id.clone()).collect::<Vec<_>>(), [second.clone(), third.clone()]);
+
+        let queue: Vec<String> = manager.get_queue().await.into_iter().map(|entry| entry.id).collect();
+        assert_eq!(queue, [existing.clone(), second.clone(), third.clone()]);
+        let mut added = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let UploadEvent::Added { id, .. } = event.event {
+                added.push(id);
+            }
+        }
+        assert_eq!(added, [existing, second, third]);
+
+        // 重启后仍然在队列中
+        manager.shutdown().await.unwrap();
+        let manager = UploadManager::new(config).await.unwrap();
+        assert_eq!(manager.wait_state_loaded().await.queued, 3);
+        manager.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_group_operations() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(temp_config(state_dir.path())).await.unwrap();
+        let files: Vec<_> = (0..4).map(|_| test_file(1000)).collect();
+        let other = manager.add_upload(files[0].path().to_path_buf()).await.unwrap().into_id();
+        let BatchAdded { group, results } = manager.add_uploads(files[1..].iter().map(|file| file.path().to_path_buf()).collect()).await;
+        let members: Vec<String> = results.into_iter().map(|result| result.unwrap().into_id()).collect();
+        async fn queue(manager: &UploadManager) -> Vec<String> {
+            manager.get_queue().await.into_iter().map(|entry| entry.id).collect()
+        }
+
+        let status = manager.get_group_status(&group).await.unwrap();
+        assert_eq!((status.bytes_transferred, status.total_bytes), (0, 3000));
+        assert_eq!(status.counts, HashMap::from([(UploadStatus::Pending, 3)]));
+        assert!(status.speed.is_zero());
+        assert_eq!(status.eta_secs, None);
+        assert!(!status.completed);
+        assert_eq!(manager.get_upload_status(&members[0]).await.unwrap().group.as_ref(), Some(&group));
+
+        // 分组整体移动，分组内保持原来的顺序
+        assert_eq!(manager.set_group_priority(&group, GroupPriority::High).await.unwrap(), 3);
+        assert_eq!(queue(&manager).await, [members[0].clone(), members[1].clone(), members[2].clone(), other.clone()]);
+        assert_eq!(manager.set_group_priority(&group, GroupPriority::Low).await.unwrap(), 3);
+        assert_eq!(queue(&manager).await, [other.clone(), members[0].clone(), members[1].clone(), members[2].clone()]);
+
+        let mut events = manager.subscribe();
+        assert_eq!(manager.pause_group(&group).await.unwrap(), 3);
+        assert_eq!(queue(&manager).await, [other.clone()]);
+        let status = manager.get_group_status(&group).await.unwrap();
+        assert_eq!(status.counts, HashMap::from([(UploadStatus::Paused, 3)]));
+        // 活动状态在后台计算，ActivityChanged 可能夹在中间
+        let mut paused = 0;
+        while let Ok(event) = events.try_recv() {
+            match event.event {
+                UploadEvent::Paused { .. } => paused += 1,
+                UploadEvent::ActivityChanged { .. } => {}
+                event => panic!("unexpected event {:?}", event),
+            }
+        }
+        assert_eq!(paused, 3);
+        // 暂停后可以单独继续
+        manager.resume_upload(&members[1]).await.unwrap();
+        assert_eq!(queue(&manager).await, [members[1].clone(), other.clone()]);
+
+        assert_eq!(manager.cancel_group(&group).await.unwrap(), 3);
+        assert_eq!(queue(&manager).await, [other]);
+        assert!(matches!(manager.get_group_status(&group).await, Err(UploadError::UploadNotFound(_))));
+        assert!(matches!(manager.pause_group(&group).await, Err(UploadError::UploadNotFound(_))));
+        assert!(matches!(
+            manager.set_group_priority(&group, GroupPriority::High).await,
+            Err(UploadError::UploadNotFound(_)),
+        ));
+        manager.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_add_directory() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(temp_config(state_dir.path())).await.unwrap();
+        let root = tempfile::tempdir().unwrap();
+        for file in ["a.txt", "cache/skip.txt", "docs/b.md", "docs/old/c.md", "docs/old/d.tmp"] {
+            let path = root.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, file).unwrap();
+        }
+
+        let options = AddDirectoryOptions {
+            exclude: vec!["*.tmp".into(), "cache".into()],
+            record_relative_path: true,
+            upload: AddUploadOptions::builder().metadata("batch", "1").build().unwrap(),
+            ..Default::default()
+        };
+        let added = manager.add_directory(root.path().to_path_buf(), options.clone()).await.unwrap();
+        assert_eq!(added.skipped, 2);
+        let mut relative = Vec::new();
+        for id in &added.ids {
+            let upload = manager.get_upload(id).await.unwrap();
+            assert_eq!(upload.metadata["batch"], "1");
+            assert_eq!(upload.group.as_ref(), Some(&added.group));
+            relative.push(upload.metadata[RELATIVE_PATH_KEY].clone());
+        }
+        assert_eq!(relative, ["a.txt", "docs/b.md", "docs/old/c.md"]);
+
+        // 再次添加时都已经在列表中
+        let again = manager.add_directory(root.path().to_path_buf(), options).await.unwrap();
+        assert_ne!(again.group, added.group);
+        assert_eq!(again, DirectoryAdded { group: again.group.clone(), ids: Vec::new(), skipped: 5 });
+
+        let unknown = AddDirectoryOptions {
+            upload: AddUploadOptions::builder().endpoint("missing").build().unwrap(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            manager.add_directory(root.path().to_path_buf(), unknown).await,
+            Err(UploadError::InvalidOptions(_)),
+        ));
+        manager.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clear_finished_uploads() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(temp_config(state_dir.path())).await.unwrap();
+        let mut events = manager.subscribe();
+        let file = test_file(2048);
+
+        let completed = add_copy(&manager, file.path().to_path_buf()).await;
+        let mut upload = manager.upload_state.remove(&completed).await.unwrap().unwrap();
+        upload.transition_to(UploadStatus::Active).unwrap();
+        upload.set_location("http://127.0.0.1:6440/api/file/tus/done");
+        upload.progress.bytes_transferred = upload.total_bytes;
+        upload.transition_to(UploadStatus::Completed).unwrap();
+        manager.upload_state.shelve(upload).await.unwrap();
+        let paused = add_copy(&manager, file.path().to_path_buf()).await;
+        manager.pause_upload(paused.clone()).await.unwrap();
+        let cancelled = add_copy(&manager, file.path().to_path_buf()).await;
+        manager.cancel_upload(&cancelled).await.unwrap();
+
+        assert!(matches!(manager.clear_finished(Some(UploadStatus::Paused)).await, Err(UploadError::InvalidState(_))));
+        assert_eq!(manager.clear_finished(Some(UploadStatus::Failed)).await.unwrap(), 0);
+        assert_eq!(manager.clear_finished(None).await.unwrap(), 2);
+        let ids: Vec<String> = manager.list_uploads().await.into_iter().map(|u| u.id).collect();
+        assert_eq!(ids, [paused]);
+        let mut removed = Vec::new();
+        while removed.len() < 2 {
+            if let UploadEvent::Removed { id, .. } = next_lifecycle_event(&mut events).await {
+                removed.push(id);
+            }
+        }
+        assert_eq!(removed, [completed, cancelled]);
+        manager.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_remove_upload() {
+        let server = TusServer::start().await;
+        server.enable_termination();
+        server.set_patch_delay(Some(Duration::from_millis(20)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_concurrent: 1,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let run = manager.run().unwrap();
+        let mut events = manager.subscribe();
+
+        let small = test_file(1024);
+        let completed = add_copy(&manager, small.path().to_path_buf()).await;
+        let completed = wait_for_status(&manager, &completed, UploadStatus::Completed).await;
+        manager.remove_upload(&completed.id, false).await.unwrap();
+        assert!(server.deleted().is_empty());
+
+        // 正在上传的先停止，名额和 active 中的记录都被释放
+        let large = test_file(64 * 1024);
+        let active = manager.add_upload(large.path().to_path_buf()).await.unwrap().into_id();
+        while server.patch_count() < 3 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        manager.remove_upload(&active, true).await.unwrap();
+        assert_eq!(server.deleted().len(), 1);
+        assert!(!manager.active_uploads.read().await.contains_key(&active));
+        assert!(manager.list_uploads().await.is_empty());
+        assert!(matches!(manager.get_upload(&active).await, Err(UploadError::UploadNotFound(_))));
+        assert!(matches!(manager.remove_upload(&active, true).await, Err(UploadError::UploadNotFound(_))));
+
+        let next = add_copy(&manager, small.path().to_path_buf()).await;
+        wait_for_status(&manager, &next, UploadStatus::Completed).await;
+
+        let mut removed = Vec::new();
+        while removed.len() < 2 {
+            if let UploadEvent::Removed { id, .. } = next_lifecycle_event(&mut events).await {
+                removed.push(id);
+            }
+        }
+        assert_eq!(removed, [completed.id.clone(), active]);
+
+        // 状态文件中也已经删除
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+        let state = UploadStateManager::new(temp_config(state_dir.path())).await.unwrap();
+        state.wait_loaded().await;
+        let ids: Vec<String> = state.list().await.into_iter().map(|upload| upload.id).collect();
+        assert_eq!(ids, [next]);
+    }
+
+    #[tokio::test]
+    async fn test_auto_retry_failed_uploads() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_concurrent: 1,
+            max_retries: 0,
+            auto_retry: Some(crate::core::config::AutoRetryPolicy { max_attempts: 1, base_delay: Duration::from_millis(200) }),
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let run = manager.run().unwrap();
+        let file = test_file(2048);
+
+        // 取消的 upload 不会被自动重试
+        server.fail_patch(1, 500);
+        let cancelled = add_copy(&manager, file.path().to_path_buf()).await;
+        wait_for_status(&manager, &cancelled, UploadStatus::Failed).await;
+        manager.cancel_upload(&cancelled).await.unwrap();
+
+        // 失败一次后自动重新排队
+        server.fail_patch(2, 500);
+        let recovered = add_copy(&manager, file.path().to_path_buf()).await;
+        let recovered = wait_for_status(&manager, &recovered, UploadStatus::Completed).await;
+        assert_eq!(recovered.auto_retries, 1);
+        assert_eq!(manager.upload_state.get_upload(&cancelled).await.unwrap().status, UploadStatus::Cancelled);
+
+        // 用完自动重试次数后保持失败，等待手动重试
+        server.fail_patch(5, 500);
+        server.fail_patch(6, 500);
+        let exhausted = add_copy(&manager, file.path().to_path_buf()).await;
+        for _ in 0..100 {
+            let upload = manager.upload_state.get_upload(&exhausted).await;
+            if upload.is_ok_and(|upload| upload.status == UploadStatus::Failed && upload.auto_retries == 1) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(manager.upload_state.get_upload(&exhausted).await.unwrap().status, UploadStatus::Failed);
+        assert_eq!(server.patch_count(), 6);
+
+        assert!(matches!(manager.retry_upload(&recovered.id).await, Err(UploadError::InvalidState(_))));
+        assert_eq!(manager.retry_failed().await.unwrap(), 1);
+        let exhausted = wait_for_status(&manager, &exhausted, UploadStatus::Completed).await;
+        assert_eq!(exhausted.auto_retries, 0);
+        assert_eq!(exhausted.retry_count, 0);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_pause_does_not_block_scheduling() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let run = manager.run().unwrap();
+
+        // worker 收到取消后要很久才结束，例如正在写入一个很大的块
+        let slow_file = test_file(1024);
+        let mut slow = Upload::new(slow_file.path().to_path_buf(), 1024).unwrap();
+        slow.transition_to(UploadStatus::Active).unwrap();
+        let slow_id = slow.id.clone();
+        let (finish, finished) = tokio::sync::oneshot::channel::<()>();
+        manager.active_uploads.write().await.insert(slow_id.clone(), ActiveUpload {
+            capabilities: Capabilities::for_upload(&slow),
+            handle: tokio::spawn(async move {
+                let _ = finished.await;
+                slow
+            }),
+            group: None,
+            cancellation_token: CancellationToken::new(),
+        });
+        let pausing = tokio::spawn({
+            let manager = manager.clone();
+            let id = slow_id.clone();
+            async move { manager.pause_upload(id).await }
+        });
+
+        // 暂停等待期间调度循环继续开始其他 upload
+        let file = test_file(2048);
+        let bound = Duration::from_secs(2);
+        for _ in 0..2 {
+            let id = tokio::time::timeout(bound, add_copy(&manager, file.path().to_path_buf())).await.unwrap();
+            tokio::time::timeout(bound, wait_for_status(&manager, &id, UploadStatus::Completed)).await.unwrap();
+        }
+        assert!(!pausing.is_finished());
+
+        finish.send(()).unwrap();
+        tokio::time::timeout(bound, pausing).await.unwrap().unwrap().unwrap();
+        assert_eq!(manager.upload_state.get_upload(&slow_id).await.unwrap().status, UploadStatus::Paused);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_cancel_upload_in_any_state() {
+        let server = TusServer::start().await;
+        server.enable_termination();
+        server.set_patch_delay(Some(Duration::from_millis(20)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_concurrent: 1,
+            terminate_abandoned: true,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let run = manager.run().unwrap();
+        let file = test_file(64 * 1024);
+
+        // 第一个正在上传，第二个在队列中等待
+        let active = add_copy(&manager, file.path().to_path_buf()).await;
+        let queued = add_copy(&manager, file.path().to_path_buf()).await;
+        while server.patch_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        manager.cancel_upload(&queued).await.unwrap();
+        manager.cancel_upload(&active).await.unwrap();
+
+        // 暂停的 upload
+        let paused_file = test_file(1024);
+        let mut paused = Upload::new(paused_file.path().to_path_buf(), 1024).unwrap();
+        paused.status = UploadStatus::Paused;
+        let paused = manager.add_existing_upload(paused).await.unwrap();
+        manager.cancel_upload(&paused).await.unwrap();
+
+        for id in [&active, &queued, &paused] {
+            let upload = manager.upload_state.get_upload(id).await.unwrap();
+            assert_eq!(upload.status, UploadStatus::Cancelled);
+            assert_eq!(manager.get_upload_status(id).await.unwrap().status, UploadStatus::Cancelled);
+        }
+        assert!(manager.active_uploads.read().await.is_empty());
+        assert!(manager.history.entries().await.unwrap().iter().any(|entry| entry.id == active));
+
+        // 已经开始的 upload 删除服务端资源
+        let location = manager.upload_state.get_upload(&active).await.unwrap().location.unwrap();
+        for _ in 0..100 {
+            if !server.deleted().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(location.ends_with(&server.deleted()[0]));
+
+        assert!(matches!(manager.cancel_upload("missing").await, Err(UploadError::UploadNotFound(_))));
+        assert!(matches!(manager.cancel_upload(&active).await, Err(UploadError::InvalidState(_))));
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_handle() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(50)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        assert!(manager.scheduler().is_none());
+
+        let run = manager.run().unwrap();
+        assert!(run.is_running());
+        assert!(matches!(manager.run(), Err(UploadError::SchedulerAlreadyRunning)));
+        assert!(matches!(manager.start(), Err(UploadError::SchedulerAlreadyRunning)));
+
+        let file = test_file(8 * 1024);
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+        while !manager.active_uploads.read().await.contains_key(&id) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(run.draining() >= 1);
+
+        // 句柄在 shutdown 之前不会返回
+        assert!(tokio::time::timeout(Duration::from_millis(100), run.stopped()).await.is_err());
+
+        let stopped = tokio::spawn({
+            let run = run.clone();
+            let manager = manager.clone();
+            async move {
+                run.stopped().await;
+                (manager.tasks.is_empty(), manager.scheduler().unwrap().is_running())
+            }
+        });
+        manager.shutdown().await.unwrap();
+        assert_eq!(stopped.await.unwrap(), (true, false));
+        assert_eq!(run.draining(), 0);
+
+        // start 保存的句柄同样由 shutdown 等待
+        let state_dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(UploadManager::new(temp_config(state_dir.path())).await.unwrap());
+        manager.start().unwrap();
+        assert!(manager.scheduler().unwrap().is_running());
+        manager.shutdown().await.unwrap();
+        assert!(!manager.scheduler().unwrap().is_running());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_uploads() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+
+        manager.run().unwrap();
+
+        let files = [test_file(1000), test_file(2500), test_file(4096)];
+        for file in &files {
+            manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+        }
+
+        for _ in 0..100 {
+            let mut lengths: Vec<usize> = server.uploads().iter()
+                .filter(|u| u.length == Some(u.data.len() as u64))
+                .map(|u| u.data.len())
+                .collect();
+            lengths.sort();
+            if lengths == [1000, 2500, 4096] {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("uploads did not complete: {:?}", server.uploads().len());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_backoff_yields_slot() {
+        let server = TusServer::start().await;
+        server.fail_patch(1, 503);
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_concurrent: 1,
+            retry_delay: Duration::from_secs(60 * 60),
+            yield_slot_during_backoff: true,
+            yield_backoff_threshold: Duration::from_millis(100),
+            connect_timeout: Duration::ZERO,
+            request_timeout: Duration::ZERO,
+            stall_timeout: Duration::ZERO,
+            ..TusConfig::new(server.endpoint())
+        };
+        let clock = Arc::new(TestClock::new());
+        let manager = UploadManager::new_with(config, clock.clone(), Arc::new(Rng::seeded(7))).await.unwrap();
+        let manager = Arc::new(manager);
+
+        manager.run().unwrap();
+
+        let first = test_file(3000);
+        let second = test_file(1000);
+        let first_id = manager.add_upload(first.path().to_path_buf()).await.unwrap().into_id();
+        let second_id = manager.add_upload(second.path().to_path_buf()).await.unwrap().into_id();
+
+        // 第一个任务退避期间，名额被第二个任务使用；等待重试的 upload 带着重试时间保存在状态中
+        wait_for_status(&manager, &second_id, UploadStatus::Completed).await;
+        let parked = manager.upload_state.get_upload(&first_id).await.unwrap();
+        assert_eq!(parked.status, UploadStatus::WaitingRetry);
+        assert_eq!(parked.retry_count, 1);
+        assert!(parked.retry_at.is_some_and(|at| at > clock.now_utc()));
+        assert!(clock.elapsed() < Duration::from_secs(60 * 60));
+
+        // 这时崩溃，重新加载后 upload 不会丢失
+        let persisted: serde_json::Value = serde_json::from_slice(&std::fs::read(manager.upload_state.state_file()).unwrap()).unwrap();
+        let shelved = persisted["shelved"].as_array().unwrap();
+        assert!(shelved.iter().any(|upload| upload["id"] == first_id.as_str() && upload["status"] == "WaitingRetry"));
+
+        // 退避结束后第一个任务继续完成
+        clock.advance(Duration::from_secs(60 * 60)).await;
+        wait_for_status(&manager, &first_id, UploadStatus::Completed).await;
+        assert_eq!(manager.upload_state.get_upload(&first_id).await.unwrap().retry_at, None);
+        assert!(server.uploads().iter().any(|u| u.data.len() == 3000));
+    }
+
+    #[tokio::test]
+    async fn test_locked_upload_is_rescheduled() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            retry_delay: Duration::from_millis(50),
+            lock_wait_budget: Duration::ZERO,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        manager.run().unwrap();
+
+        // 不等待锁时立即让出，稍后重新调度，不标记为失败
+        server.lock_upload(1);
+        let file = test_file(2048);
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+        let upload = wait_for_status(&manager, &id, UploadStatus::Completed).await;
+        assert_eq!(upload.retry_count, 0);
+        assert!(server.uploads().iter().any(|u| u.data.len() == 2048));
+    }
+
+    #[tokio::test]
+    async fn test_failed_upload_keeps_snapshot() {
+        let server = TusServer::start().await;
+        server.fail_patch(1, 403);
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            snapshot_sources: true,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config.clone()).await.unwrap());
+        let run = manager.run().unwrap();
+
+        let original = vec![1u8; 2048];
+        let file = test_file(0);
+        std::fs::write(file.path(), &original).unwrap();
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+        let upload = wait_for_status(&manager, &id, UploadStatus::Failed).await;
+        let snapshot_path = upload.snapshot_path.clone().unwrap();
+        assert!(snapshot_path.exists());
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+
+        // 重启后清理孤立快照时也保留，重试时上传的仍然是添加时的内容
+        std::fs::write(file.path(), vec![2u8; 2048]).unwrap();
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        manager.wait_state_loaded().await;
+        let run = manager.run().unwrap();
+        manager.retry_upload(&id).await.unwrap();
+        let upload = wait_for_status(&manager, &id, UploadStatus::Completed).await;
+        assert!(server.upload(upload.location.as_deref().unwrap()).unwrap().data == original);
+        assert!(!snapshot_path.exists());
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_rejected_upload_records_last_error() {
+        let server = TusServer::start().await;
+        server.fail_patch(1, 403);
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            retry_delay: Duration::from_secs(3600),
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config.clone()).await.unwrap());
+        let run = manager.run().unwrap();
+
+        let file = test_file(2048);
+        let id = add_copy(&manager, file.path().to_path_buf()).await;
+        let upload = wait_for_status(&manager, &id, UploadStatus::Failed).await;
+        let error = upload.last_error.unwrap();
+        assert_eq!(error.code, "server_error");
+        assert_eq!(error.status, Some(403));
+        assert!(error.message.contains("403"), "{}", error.message);
+
+        let status = manager.get_upload_status(&id).await.unwrap();
+        assert_eq!(status.last_error.unwrap().code, "server_error");
+        assert_eq!(server.patch_count(), 1);
+
+        // 协议版本不同时立即失败，状态中带有服务端支持的版本
+        server.set_versions(&["0.2.2"]);
+        let file = test_file(1024);
+        let id = add_copy(&manager, file.path().to_path_buf()).await;
+        wait_for_status(&manager, &id, UploadStatus::Failed).await;
+        let error = manager.get_upload_status(&id).await.unwrap().last_error.unwrap();
+        assert_eq!(error.code, "version_mismatch");
+        assert!(error.message.contains("0.2.2"), "{}", error.message);
+
+        // 失败的原因在重启后仍然可以看到
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+        let manager = UploadManager::new(config).await.unwrap();
+        let uploads = manager.list_upload_statuses().await;
+        let failed: Vec<_> = uploads.iter()
+            .filter(|upload| upload.status == UploadStatus::Failed)
+            .map(|upload| upload.last_error.as_ref().unwrap().code.as_str())
+            .collect();
+        assert_eq!(failed.len(), 2);
+        assert!(failed.contains(&"server_error") && failed.contains(&"version_mismatch"), "{:?}", failed);
+    }
+
+    #[tokio::test]
+    async fn test_retry_budget_spans_sessions() {
+        let server = TusServer::start().await;
+        server.fail_patch(1, 503);
+        server.fail_patch(2, 503);
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            max_retries: 1,
+            retry_delay: Duration::from_millis(10),
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config.clone()).await.unwrap());
+        let run = manager.run().unwrap();
+
+        let file = test_file(1024);
+        let failed = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+        wait_for_status(&manager, &failed, UploadStatus::Failed).await;
+        let status = manager.get_upload_status(&failed).await.unwrap();
+        assert_eq!((status.retry_count, status.max_retries), (2, None));
+
+        // 单独设置的重试次数
+        server.fail_patch(3, 503);
+        server.fail_patch(4, 503);
+        let options = AddUploadOptions::builder().max_retries(3).build().unwrap();
+        let id = manager.add_upload_with_options(file.path().to_path_buf(), options).await.unwrap().into_id();
+        let upload = wait_for_status(&manager, &id, UploadStatus::Completed).await;
+        assert_eq!((upload.retry_count, upload.max_retries_override), (0, Some(3)));
+
+        // 上一次运行中用完了重试次数的 upload 不再自动开始
+        let mut exhausted = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+        exhausted.retry_count = 2;
+        let id = manager.add_existing_upload(exhausted).await.unwrap();
+        let upload = wait_for_status(&manager, &id, UploadStatus::Failed).await;
+        assert_eq!(upload.last_error.unwrap().code, "retries_exhausted");
+        assert_eq!(server.patch_count(), 5);
+
+        // 次数保存在状态中
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+        let manager = UploadManager::new(config).await.unwrap();
+        assert_eq!(manager.get_upload_status(&failed).await.unwrap().retry_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_fair_bandwidth_allocations() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            bandwidth_limit: Some(30 * 1024),
+            fair_bandwidth: true,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+
+        let run = manager.run().unwrap();
+
+        let files = [test_file(64 * 1024), test_file(64 * 1024), test_file(64 * 1024)];
+        for file in &files {
+            manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+        }
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let allocations = manager.bandwidth_allocations();
+        assert_eq!(allocations.len(), 3);
+        for rate in allocations.values() {
+            assert_eq!(*rate, 10 * 1024);
+        }
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_bandwidth_limit_changes_at_runtime() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let run = manager.run().unwrap();
+        assert_eq!(manager.bandwidth_limit(), None);
+        assert!(matches!(manager.set_bandwidth_limit(Some(0)), Err(UploadError::Config(_))));
+
+        // 限制为 2 KiB/s 时 64 KiB 需要 30 多秒，取消限制后立即继续，不需要重新开始
+        manager.set_bandwidth_limit(Some(2 * 1024)).unwrap();
+        let file = test_file(64 * 1024);
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let patches = server.patch_count();
+        assert!((1..8).contains(&patches), "{}", patches);
+
+        manager.set_bandwidth_limit(None).unwrap();
+        assert_eq!(manager.bandwidth_limit(), None);
+        let upload = wait_for_status(&manager, &id, UploadStatus::Completed).await;
+        assert_eq!(upload.progress.bytes_transferred, 64 * 1024);
+        assert_eq!(server.count_requests(hyper::Method::POST), 1);
+
+        // 单个 upload 的上限同时生效
+        let options = AddUploadOptions::builder().bandwidth_limit(2 * 1024).build().unwrap();
+        let file = test_file(64 * 1024);
+        manager.add_upload_with_options(file.path().to_path_buf(), options).await.unwrap().into_id();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let patches = server.patch_count() - 64;
+        assert!((1..8).contains(&patches), "{}", patches);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_changed_source_fails_until_restarted() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let file = test_file(4096);
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+
+        // 添加之后、开始之前文件被重新导出，大小不变
+        let content: Vec<u8> = (0..4096).map(|i| (i % 7) as u8).collect();
+        std::fs::write(file.path(), &content).unwrap();
+        let run = manager.run().unwrap();
+        let upload = wait_for_status(&manager, &id, UploadStatus::Failed).await;
+        assert_eq!(upload.last_error.unwrap().code, "file_changed");
+        assert_eq!(server.patch_count(), 0);
+
+        // 确认要上传新的内容后重新开始
+        manager.force_restart(&id).await.unwrap();
+        let upload = wait_for_status(&manager, &id, UploadStatus::Completed).await;
+        assert!(upload.last_error.is_none());
+        assert_eq!(server.upload(&upload.location.unwrap()).unwrap().data, content);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_deleted_source_fails_and_frees_slot() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(50)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_concurrent: 1,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let run = manager.run().unwrap();
+        let file = test_file(16 * 1024);
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+        let other = test_file(2048);
+        let other_id = manager.add_upload(other.path().to_path_buf()).await.unwrap().into_id();
+
+        // 第一块发送后删除文件
+        while server.patch_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let path = file.path().display().to_string();
+        file.close().unwrap();
+        let upload = wait_for_status(&manager, &id, UploadStatus::Failed).await;
+        let error = upload.last_error.unwrap();
+        assert_eq!(error.code, "source_missing");
+        assert!(error.message.contains(&path), "{}", error.message);
+        assert!(upload.progress.bytes_transferred < 16 * 1024);
+
+        // 名额已经释放，排在后面的 upload 继续
+        wait_for_status(&manager, &other_id, UploadStatus::Completed).await;
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_metadata_limits_at_add_and_update() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let mut config = temp_config(state_dir.path());
+        config.metadata_limits = metadata::MetadataLimits { max_entry_size: 1024, max_total_size: 2048, truncate: false };
+        let manager = UploadManager::new(config.clone()).await.unwrap();
+        let file = test_file(10);
+
+        let options = AddUploadOptions::builder().metadata("description", "x".repeat(200 * 1024)).build().unwrap();
+        let err = manager.add_upload_with_options(file.path().to_path_buf(), options.clone()).await.unwrap_err();
+        assert!(matches!(err, UploadError::MetadataTooLarge { ref key, limit: 1024, .. } if key == "description"));
+        assert!(manager.upload_state.list().await.is_empty());
+        manager.shutdown().await.unwrap();
+
+        // 允许截断时添加成功，并通知截断
+        let state_dir = tempfile::tempdir().unwrap();
+        config.state_dir = state_dir.path().to_path_buf();
+        config.metadata_limits.truncate = true;
+        let manager = UploadManager::new(config).await.unwrap();
+        let mut events = manager.subscribe();
+        let id = manager.add_upload_with_options(file.path().to_path_buf(), options).await.unwrap().into_id();
+
+        let upload = manager.upload_state.get_upload(&id).await.unwrap();
+        assert!(metadata::encoded_size(&upload.metadata) <= 1024);
+        match next_event(&mut events).await {
+            UploadEvent::MetadataTruncated { id: event_id, key, original_size, truncated_size } => {
+                assert_eq!(event_id, id);
+                assert_eq!(key, "description");
+                assert_eq!(original_size, 200 * 1024);
+                assert_eq!(truncated_size, upload.metadata["description"].len());
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        // 修改时同样检查
+        let patch = HashMap::from([("notes".to_string(), Some("y".repeat(4096)))]);
+        manager.update_metadata(&id, patch).await.unwrap();
+        let upload = manager.upload_state.get_upload(&id).await.unwrap();
+        assert!(metadata::encoded_size(&upload.metadata) <= 2048);
+        assert!(matches!(next_event(&mut events).await, UploadEvent::MetadataTruncated { ref key, .. } if key == "notes"));
+
+        manager.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_update_metadata_before_creation() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(50)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let mut events = manager.subscribe();
+
+        let file = test_file(8000);
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+
+        // 还没有发送创建请求
+        let patch = HashMap::from([
+            ("title".to_string(), Some("holiday".to_string())),
+            ("draft".to_string(), None),
+        ]);
+        manager.update_metadata(&id, patch).await.unwrap();
+        let upload = manager.upload_state.get_upload(&id).await.unwrap();
+        assert_eq!(upload.metadata.get("title").map(String::as_str), Some("holiday"));
+        match next_event(&mut events).await {
+            UploadEvent::MetadataUpdated { id: event_id, metadata } => {
+                assert_eq!(event_id, id);
+                assert_eq!(metadata, upload.metadata);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        let invalid = HashMap::from([("bad key".to_string(), Some("x".to_string()))]);
+        assert!(matches!(manager.update_metadata(&id, invalid).await, Err(UploadError::InvalidOptions(_))));
+
+        // 创建请求发送之后不能再修改
+        let run = manager.run().unwrap();
+        for _ in 0..100 {
+            if !server.uploads().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let patch = HashMap::from([("title".to_string(), Some("late".to_string()))]);
+        assert!(matches!(manager.update_metadata(&id, patch.clone()).await, Err(UploadError::MetadataFrozen(_))));
+
+        // 暂停后已经有服务端地址，同样不能修改
+        manager.pause_upload(id.clone()).await.unwrap();
+        assert!(matches!(manager.update_metadata(&id, patch).await, Err(UploadError::MetadataFrozen(_))));
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_state_dir_synced_reaches_first_subscriber() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_dir = dir.path().join("Dropbox").join("uploader");
+        let manager = UploadManager::new(temp_config(&state_dir)).await.unwrap();
+        let check = manager.cloud_dir().cloned().unwrap();
+        assert_eq!(check.provider, "Dropbox");
+
+        let mut events = manager.subscribe();
+        let event = events.try_recv().unwrap().event;
+        assert_eq!(event, UploadEvent::StateDirSynced { provider: check.provider, path: check.path, relocated_to: None });
+        manager.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_id_conflicts_reach_late_subscribers() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = temp_config(state_dir.path());
+        let manager = UploadManager::new(config.clone()).await.unwrap();
+        let file = test_file(100);
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+        manager.shutdown().await.unwrap();
+
+        // 手动合并的状态文件中同一个 upload 出现两次
+        let state_file = state_dir.path().join("upload-state.json");
+        let mut state: serde_json::Value = serde_json::from_slice(&std::fs::read(&state_file).unwrap()).unwrap();
+        let mut copy = state["uploads"][0].clone();
+        copy["update_at"] = serde_json::json!("2000-01-01T00:00:00Z");
+        state["uploads"].as_array_mut().unwrap().push(copy);
+        std::fs::write(&state_file, serde_json::to_vec(&state).unwrap()).unwrap();
+
+        // 加载完成之后才订阅也能收到通知
+        let manager = UploadManager::new(config.clone()).await.unwrap();
+        manager.wait_state_loaded().await;
+        tokio::task::yield_now().await;
+        let mut events = manager.subscribe();
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap();
+        assert_eq!(event.event, UploadEvent::IdConflict { id: id.clone() });
+        assert_eq!(manager.conflicts().await.len(), 1);
+        manager.shutdown().await.unwrap();
+
+        // 没有处理的冲突在下次启动时再次通知
+        let manager = UploadManager::new(config).await.unwrap();
+        let mut events = manager.subscribe();
+        let conflict = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let UploadEvent::IdConflict { id } = events.recv().await.unwrap().event {
+                    return id;
+                }
+            }
+        }).await.unwrap();
+        assert_eq!(conflict, id);
+        manager.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_update_metadata_recreates_when_enabled() {
+        let server = TusServer::start().await;
+        server.enable_termination();
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            recreate_on_metadata_update: true,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = UploadManager::new(config).await.unwrap();
+
+        let response = reqwest::Client::new().post(server.endpoint())
+            .header(headers::TUS_RESUMABLE, headers::TUS_VERSION)
+            .header(headers::UPLOAD_LENGTH, 100)
+            .send().await.unwrap();
+        let location = response.headers()[reqwest::header::LOCATION].to_str().unwrap().to_string();
+
+        let file = test_file(100);
+        let mut upload = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+        upload.set_location(location.clone());
+        upload.progress.bytes_transferred = 50;
+        upload.transition_to(UploadStatus::Active).unwrap();
+        upload.transition_to(UploadStatus::Paused).unwrap();
+        manager.upload_state.shelve(upload.clone()).await.unwrap();
+
+        let patch = HashMap::from([("title".to_string(), Some("new".to_string()))]);
+        manager.update_metadata(&upload.id, patch).await.unwrap();
+
+        let updated = manager.upload_state.get_upload(&upload.id).await.unwrap();
+        assert_eq!(updated.location, None);
+        assert_eq!(updated.progress.bytes_transferred, 0);
+        assert_eq!(updated.metadata.get("title").map(String::as_str), Some("new"));
+
+        // 旧的资源在后台删除
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while server.deleted().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.unwrap();
+        assert!(location.ends_with(&server.deleted()[0]));
+        manager.shutdown().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_status_polling_during_uploads() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(5)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+
+        let files = [test_file(20 * 1024), test_file(20 * 1024), test_file(20 * 1024)];
+        let mut ids = Vec::new();
+        for file in &files {
+            ids.push(manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id());
+        }
+
+        let run = manager.run().unwrap();
+
+        // 8 个轮询方同时查询，持续查询不应该拖慢上传或出现锁竞争
+        let started = std::time::Instant::now();
+        let pollers: Vec<_> = (0..8).map(|_| {
+            let manager = manager.clone();
+            let ids = ids.clone();
+            tokio::spawn(async move {
+                for i in 0..2000 {
+                    manager.get_upload_status(&ids[i % ids.len()]).await.unwrap();
+                }
+            })
+        }).collect();
+        for poller in pollers {
+            poller.await.unwrap();
+        }
+        assert!(started.elapsed() < Duration::from_secs(2), "polling took {:?}", started.elapsed());
+
+        // 完成后立即查询必须返回完成状态
+        for id in &ids {
+            let mut completed = false;
+            for _ in 0..200 {
+                let status = manager.get_upload_status(id).await.unwrap();
+                if status.status == UploadStatus::Completed {
+                    assert_eq!(status.bytes_transferred, 20 * 1024);
+                    assert!(status.speed.is_zero());
+                    completed = true;
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert!(completed, "upload {} never reported completion", id);
+        }
+        let persisted = manager.upload_state.get_upload(&ids[0]).await.unwrap();
+        assert_eq!(persisted.status, UploadStatus::Completed);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_add_existing_upload_rejects_duplicate_id() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(temp_config(state_dir.path())).await.unwrap();
+
+        let file = test_file(100);
+        let upload = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+        let id = manager.add_existing_upload(upload.clone()).await.unwrap();
+        assert_eq!(id, upload.id);
+
+        let err = manager.add_existing_upload(upload).await.unwrap_err();
+        assert!(matches!(err, UploadError::DuplicateUploadId(duplicate) if duplicate == id));
+        assert_eq!(manager.upload_state.list().await.len(), 1);
+        manager.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_isolates_source_changes() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(20)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            snapshot_sources: true,
+            ..TusConfig::new(server.endpoint())
+        };
+
+        // 上一次运行留下的快照
+        let snapshots = snapshot::snapshot_dir(state_dir.path());
+        std::fs::create_dir_all(&snapshots).unwrap();
+        std::fs::write(snapshots.join("orphan.bin"), b"stale").unwrap();
+
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let original = vec![1u8; 10 * 1024];
+        let file = test_file(0);
+        std::fs::write(file.path(), &original).unwrap();
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+
+        let upload = manager.upload_state.get_upload(&id).await.unwrap();
+        assert_eq!(upload.file_path, file.path());
+        let snapshot_path = upload.snapshot_path.clone().unwrap();
+        assert!(snapshot_path.starts_with(&snapshots));
+
+        let run = manager.run().unwrap();
+
+        // 上传过程中修改源文件
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        std::fs::write(file.path(), vec![2u8; 10 * 1024]).unwrap();
+
+        let upload = wait_for_status(&manager, &id, UploadStatus::Completed).await;
+        let location = upload.location.clone().unwrap();
+        assert!(server.upload(&location).unwrap().data == original);
+        assert_eq!(upload.snapshot_path, None);
+        assert!(!snapshot_path.exists());
+        assert!(!snapshots.join("orphan.bin").exists());
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_group_completed_after_retention_trims_parts() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            max_concurrent: 1,
+            completed_retention: crate::core::config::CompletedRetention { max_count: 1, max_age: None },
+            split_oversize: Some(crate::core::config::SplitPolicy { part_size: 1000, naming: SplitNaming::Suffix }),
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let mut events = manager.subscribe();
+        let run = manager.run().unwrap();
+
+        let content: Vec<u8> = (0..2500).map(|i| (i % 251) as u8).collect();
+        let file = test_file(0);
+        std::fs::write(file.path(), &content).unwrap();
+        let group = add_copy(&manager, file.path().to_path_buf()).await;
 
-    fn test_file1() -> PathBuf {
-        let mut file_path = dirs::video_dir().unwrap();
-        file_path.push("1086599689-1-208.mp4");
-        file_path
+        // 最后一个部分完成时只保留了一条已完成记录，仍然按部分顺序通知所有地址
+        let (id, locations) = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let UploadEvent::GroupCompleted { id, locations } = events.recv().await.unwrap().event {
+                    return (id, locations);
+                }
+            }
+        }).await.unwrap();
+        assert_eq!(id, group);
+        assert_eq!(manager.list_completed().await.len(), 1);
+        let windows: Vec<Vec<u8>> = locations.iter().map(|location| server.upload(location).unwrap().data).collect();
+        assert_eq!(windows, [content[..1000].to_vec(), content[1000..2000].to_vec(), content[2000..].to_vec()]);
+        assert!(manager.upload_state.completed_parts(&group).await.is_empty());
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_split_oversize_uploads() {
+        let server = TusServer::start().await;
+        server.enable_termination();
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 256,
+            buffer_size: 256,
+            max_concurrent: 2,
+            split_oversize: Some(crate::core::config::SplitPolicy { part_size: 1000, naming: SplitNaming::Suffix }),
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let mut events = manager.subscribe();
+
+        let content: Vec<u8> = (0..2500).map(|i| (i % 251) as u8).collect();
+        let file = test_file(0);
+        std::fs::write(file.path(), &content).unwrap();
+        let group = add_copy(&manager, file.path().to_path_buf()).await;
+
+        let parts = manager.upload_state.list().await;
+        assert_eq!(parts.len(), 3);
+        for upload in &parts {
+            let part = upload.part.unwrap();
+            assert_eq!(upload.group.as_deref(), Some(group.as_str()));
+            assert_eq!(upload.total_bytes, part.end - part.start);
+            assert_eq!(upload.metadata["part_index"], part.index.to_string());
+            assert_eq!(upload.metadata["part_count"], "3");
+            assert_eq!(upload.metadata["group_id"], group);
+            assert!(upload.filename.ends_with(&format!(".part{:02}", part.index + 1)));
+        }
+        let fingerprints: std::collections::HashSet<&String> = parts.iter()
+            .map(|upload| &upload.metadata["parent_fingerprint"])
+            .collect();
+        assert_eq!(fingerprints.len(), 1);
+
+        let run = manager.run().unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let UploadEvent::GroupCompleted { id, locations } = events.recv().await.unwrap().event {
+                    return (id, locations);
+                }
+            }
+        }).await.unwrap();
+        assert_eq!(event.0, group);
+
+        // 每个部分上传源文件的对应范围，locations 按部分顺序排列
+        let windows: Vec<Vec<u8>> = event.1.iter().map(|location| server.upload(location).unwrap().data).collect();
+        assert_eq!(windows, [content[..1000].to_vec(), content[1000..2000].to_vec(), content[2000..].to_vec()]);
+
+        let status = manager.get_group_status(&group).await.unwrap();
+        assert!(status.completed);
+        assert_eq!(status.bytes_transferred, 2500);
+        assert_eq!(status.total_bytes, 2500);
+        assert_eq!(status.parts.iter().map(|part| part.part_index).collect::<Vec<_>>(), [Some(0), Some(1), Some(2)]);
+
+        // 取消分组时所有部分一起取消，并删除已经在服务端创建的资源
+        server.set_patch_delay(Some(Duration::from_millis(50)));
+        let second = add_copy(&manager, file.path().to_path_buf()).await;
+        while server.uploads().len() <= 3 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(manager.cancel_group(&second).await.unwrap(), 3);
+        assert!(matches!(manager.get_group_status(&second).await, Err(UploadError::UploadNotFound(_))));
+        assert_eq!(manager.upload_state.list().await.len(), 3);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while server.uploads().len() > 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.unwrap();
+        assert!(!server.deleted().is_empty());
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_replaying_changes_matches_full_list() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            split_oversize: Some(crate::core::config::SplitPolicy { part_size: 1000, naming: SplitNaming::Suffix }),
+            ..temp_config(state_dir.path())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let small = test_file(500);
+        let large = test_file(2500);
+
+        let mut replica: HashMap<String, UploadStatusInfo> = HashMap::new();
+        let mut version = 0;
+        let mut sync = async |replica: &mut HashMap<String, UploadStatusInfo>| {
+            let UploadChanges::Diff { added, removed, changed, version: next } = manager.get_changes_since(version).await else {
+                panic!("baseline {} should still be available", version);
+            };
+            for id in removed {
+                assert!(replica.remove(&id).is_some());
+            }
+            for info in added {
+                assert!(replica.insert(info.id.clone(), info).is_none());
+            }
+            for info in changed {
+                assert!(replica.insert(info.id.clone(), info).is_some());
+            }
+            version = next;
+            let mut expected: Vec<(String, UploadStatus)> = manager.list_upload_statuses().await
+                .into_iter()
+                .map(|info| (info.id, info.status))
+                .collect();
+            let mut actual: Vec<(String, UploadStatus)> = replica.values()
+                .map(|info| (info.id.clone(), info.status))
+                .collect();
+            expected.sort_by(|a, b| a.0.cmp(&b.0));
+            actual.sort_by(|a, b| a.0.cmp(&b.0));
+            assert_eq!(actual, expected);
+            next
+        };
+
+        let first = add_copy(&manager, small.path().to_path_buf()).await;
+        let v1 = sync(&mut replica).await;
+        assert_eq!(v1, 1);
+        assert_eq!(manager.state_version().await, v1);
+
+        let group = manager.add_upload(large.path().to_path_buf()).await.unwrap().into_id();
+        add_copy(&manager, small.path().to_path_buf()).await;
+        let v2 = sync(&mut replica).await;
+        assert_eq!(v2, 2);
+        assert_eq!(replica.len(), 5);
+
+        // 状态变化
+        let mut upload = manager.upload_state.get_upload(&first).await.unwrap();
+        upload.transition_to(UploadStatus::Blocked).unwrap();
+        manager.upload_state.replace(upload).await.unwrap();
+        manager.status_cache.invalidate(&first);
+        sync(&mut replica).await;
+        assert_eq!(replica[&first].status, UploadStatus::Blocked);
+
+        // 删除，以及同一版本内添加后又删除的 upload 不出现在变化中
+        let backup = manager.create_backup().await.unwrap().unwrap();
+        manager.cancel_group(&group).await.unwrap();
+        let removed_only = sync(&mut replica).await;
+        manager.restore_backup(&backup.name, false).await.unwrap();
+        let transient = add_copy(&manager, small.path().to_path_buf()).await;
+        manager.upload_state.remove(&transient).await.unwrap();
+        manager.status_cache.invalidate(&transient);
+        sync(&mut replica).await;
+        assert!(!replica.contains_key(&transient));
+        assert_eq!(replica.len(), 5);
+
+        // 两个客户端从不同的基准版本同步到同一个结果
+        let UploadChanges::Diff { added, removed, .. } = manager.get_changes_since(removed_only - 1).await else {
+            panic!("baseline should still be available");
+        };
+        assert!(added.is_empty() && removed.is_empty());
+        assert!(matches!(manager.get_changes_since(version + 1).await, UploadChanges::TooOld { .. }));
+
+        manager.shutdown().await.unwrap();
     }
 
-    fn test_file2() -> PathBuf {
-        let mut file_path = dirs::video_dir().unwrap();
-        file_path.push("1086599689-1-209.mp4");
-        file_path
+    #[tokio::test]
+    async fn test_audit_flags_missing_locations() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(20)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            audit: Some(crate::core::config::AuditPolicy { reset_missing: true, ..Default::default() }),
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let mut events = manager.subscribe();
+        let run = manager.run().unwrap();
+
+        // 一半的资源在服务端存在，另一半已经过期
+        let file = test_file(4000);
+        let mut paused = Vec::new();
+        for index in 0..4 {
+            let location = if index % 2 == 0 {
+                let response = reqwest::Client::new().post(server.endpoint())
+                    .header(headers::TUS_RESUMABLE, headers::TUS_VERSION)
+                    .header(headers::UPLOAD_LENGTH, 4000)
+                    .send().await.unwrap();
+                response.headers()[reqwest::header::LOCATION].to_str().unwrap().to_string()
+            } else {
+                format!("{}/expired-{}", server.endpoint(), index)
+            };
+            let mut upload = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+            upload.status = UploadStatus::Paused;
+            upload.location = Some(location);
+            upload.progress.bytes_transferred = 1000;
+            paused.push(manager.add_existing_upload(upload).await.unwrap());
+        }
+        let active_file = test_file(8 * 1024);
+        let active = manager.add_upload(active_file.path().to_path_buf()).await.unwrap().into_id();
+        while server.patch_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        let summary = manager.audit_now().await.unwrap();
+        assert_eq!(summary.checked, 4);
+        assert_eq!(summary.reset, 2);
+        let mut missing = summary.missing.clone();
+        missing.sort();
+        let mut expected = vec![paused[1].clone(), paused[3].clone()];
+        expected.sort();
+        assert_eq!(missing, expected);
+        assert!(!summary.interrupted);
+
+        for (index, id) in paused.iter().enumerate() {
+            let upload = manager.upload_state.get_upload(id).await.unwrap();
+            let gone = index % 2 == 1;
+            assert_eq!(upload.remote_missing, gone);
+            assert_eq!(upload.location.is_none(), gone);
+            assert_eq!(upload.progress.bytes_transferred, if gone { 0 } else { 1000 });
+            assert_eq!(upload.status, UploadStatus::Paused);
+        }
+        let mut corrected = 0;
+        loop {
+            match next_event(&mut events).await {
+                UploadEvent::ProgressCorrected { reason: CorrectionReason::RemoteMissing, .. } => corrected += 1,
+                UploadEvent::AuditCompleted { checked, missing, reset, interrupted } => {
+                    assert_eq!((checked, missing, reset, interrupted), (4, 2, 2, false));
+                    break;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(corrected, 2);
+
+        // 检查不影响正在上传的 upload
+        let upload = wait_for_status(&manager, &active, UploadStatus::Completed).await;
+        assert!(!upload.remote_missing);
+        assert_eq!(server.upload(upload.location.as_ref().unwrap()).unwrap().data.len(), 8 * 1024);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
     }
 
-    fn test_file3() -> PathBuf {
-        let mut file_path = dirs::video_dir().unwrap();
-        file_path.push("1086599689-1-210.mp4");
-        file_path
+    /// 第一次取得请求头时 panic，模拟 worker 内部的错误
+    struct PanicOnceAuth(AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl AuthProvider for PanicOnceAuth {
+        async fn headers(&self) -> UploadResult<reqwest::header::HeaderMap> {
+            if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("injected worker panic");
+            }
+            Ok(reqwest::header::HeaderMap::new())
+        }
     }
 
-    async fn create_manager() -> UploadManager {
-        let config = TusConfig::new("http://127.0.0.1:6440/api/file/tus".to_string());
-        UploadManager::new(config).await.unwrap()
+    #[tokio::test]
+    async fn test_worker_panic_marks_upload_failed() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_concurrent: 1,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = UploadManager::new(config).await.unwrap()
+            .with_auth_provider(Arc::new(PanicOnceAuth(AtomicUsize::new(0))));
+        let manager = Arc::new(manager);
+        let mut events = manager.subscribe();
+        let file = test_file(2048);
+        let crashed = add_copy(&manager, file.path().to_path_buf()).await;
+        let run = manager.run().unwrap();
+
+        let failed = wait_for_status(&manager, &crashed, UploadStatus::Failed).await;
+        assert_eq!(failed.last_error.unwrap().code, "internal");
+        let error = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let UploadEvent::Failed { id, error, .. } = next_lifecycle_event(&mut events).await {
+                    return (id, error);
+                }
+            }
+        }).await.unwrap();
+        assert_eq!(error.0, crashed);
+        assert_eq!(error.1.code, "internal");
+
+        // 唯一的名额已经释放，之后的 upload 可以开始；panic 的 upload 不会自动重试
+        let next = add_copy(&manager, file.path().to_path_buf()).await;
+        wait_for_status(&manager, &next, UploadStatus::Completed).await;
+        assert_eq!(manager.get_upload(&crashed).await.unwrap().status, UploadStatus::Failed);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
     }
 
     #[tokio::test]
-    async fn test_concurrent_uploads() {
-        let manager = Arc::new(create_manager().await);
+    async fn test_transfer_stats_are_pushed() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(20)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            stats_event_interval: Duration::from_millis(10),
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let mut events = manager.subscribe();
+        // 暂停的计入总大小，但不计入剩余时间
+        let small = test_file(1000);
+        let paused = manager.add_upload(small.path().to_path_buf()).await.unwrap().into_id();
+        manager.upload_state.pause_where(|upload| upload.id == paused).await.unwrap();
+        let file = test_file(4096);
+        add_copy(&manager, file.path().to_path_buf()).await;
+        let stats = manager.get_stats().await;
+        assert_eq!((stats.active, stats.pending, stats.completed), (0, 1, 0));
+        assert_eq!((stats.total_bytes, stats.transferred_bytes), (5096, 0));
+        assert_eq!(stats.eta_seconds, None);
+
+        let run = manager.run().unwrap();
+        let mut pushed = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let UploadEvent::StatsUpdated { stats } = events.recv().await.unwrap().event {
+                    let done = stats.completed == 1;
+                    pushed.push(stats);
+                    if done {
+                        break;
+                    }
+                }
+            }
+        }).await.unwrap();
+
+        // 上传中推送实时进度，完成后不再有速度
+        assert!(pushed.iter().any(|stats| stats.active == 1 && stats.transferred_bytes > 0 && stats.eta_seconds.is_some()), "{:?}", pushed);
+        let last = pushed.last().unwrap();
+        assert_eq!((last.active, last.pending, last.completed), (0, 0, 1));
+        assert_eq!(last.transferred_bytes, 4096);
+        assert!(last.aggregate_speed.is_zero());
+        assert_eq!(manager.get_stats().await, *last);
+
+        // 没有变化时不再推送
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        while let Ok(event) = events.try_recv() {
+            assert!(!matches!(event.event, UploadEvent::StatsUpdated { .. }), "{:?}", event);
+        }
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_activity_state_follows_queue() {
+        let server = TusServer::start().await;
+        // 上传太快时监视器可能在出队之前和完成之后各计算一次，看不到 Working
+        server.set_patch_delay(Some(Duration::from_millis(20)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_retries: 0,
+            ..TusConfig::new(server.endpoint())
+        };
+        let file = test_file(2048);
+
+        async fn next_activity(events: &mut broadcast::Receiver<SequencedEvent>) -> ActivityState {
+            loop {
+                let event = tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap();
+                if let UploadEvent::ActivityChanged { state } = event.event {
+                    return state;
+                }
+            }
+        }
+
+        let manager = Arc::new(UploadManager::new(config.clone()).await.unwrap());
+        let mut events = manager.subscribe();
+        let run = manager.run().unwrap();
+        assert_eq!(manager.get_activity_state().await, ActivityState::Idle);
+
+        let done = add_copy(&manager, file.path().to_path_buf()).await;
+        assert_eq!(next_activity(&mut events).await, ActivityState::Working);
+        assert_eq!(next_activity(&mut events).await, ActivityState::Idle);
+        wait_for_status(&manager, &done, UploadStatus::Completed).await;
+
+        server.fail_patch(server.patch_count() + 1, 500);
+        let failed = add_copy(&manager, file.path().to_path_buf()).await;
+        assert_eq!(next_activity(&mut events).await, ActivityState::Working);
+        assert_eq!(next_activity(&mut events).await, ActivityState::Attention);
+        assert_eq!(manager.acknowledge_failures().await.unwrap(), 1);
+        assert_eq!(next_activity(&mut events).await, ActivityState::Idle);
+        assert_eq!(manager.acknowledge_failures().await.unwrap(), 0);
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+
+        // 重启后已确认的失败不再提示
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let mut events = manager.subscribe();
+        let run = manager.run().unwrap();
+        assert_eq!(manager.get_activity_state().await, ActivityState::Idle);
+
+        // 重试后再次失败时重新提示，重试成功后恢复空闲；用户重试时清零失败次数
+        async fn retry(manager: &UploadManager, id: &str) {
+            let mut upload = manager.upload_state.get_upload(id).await.unwrap();
+            upload.transition_to(UploadStatus::Pending).unwrap();
+            upload.retry_count = 0;
+            manager.upload_state.replace(upload).await.unwrap();
+        }
+        server.fail_patch(server.patch_count() + 1, 500);
+        retry(&manager, &failed).await;
+        assert_eq!(next_activity(&mut events).await, ActivityState::Working);
+        assert_eq!(next_activity(&mut events).await, ActivityState::Attention);
+        retry(&manager, &failed).await;
+        assert_eq!(next_activity(&mut events).await, ActivityState::Working);
+        assert_eq!(next_activity(&mut events).await, ActivityState::Idle);
+        wait_for_status(&manager, &failed, UploadStatus::Completed).await;
+        while let Ok(event) = events.try_recv() {
+            assert!(!matches!(event.event, UploadEvent::ActivityChanged { .. }), "{:?}", event);
+        }
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_keeps_aborted_worker_upload() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = temp_config(state_dir.path());
+        let manager = UploadManager::new(config.clone()).await.unwrap();
+        let file = test_file(3000);
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+
+        // 出队后 worker 一直不结束，只能在超时后中止
+        let mut upload = manager.upload_state.pop().await;
+        upload.transition_to(UploadStatus::Active).unwrap();
+        upload.location = Some("http://127.0.0.1:6440/api/file/tus/aborted".to_string());
+        upload.progress.bytes_transferred = 1024;
+        manager.status_cache.track(&upload, Arc::new(LiveProgress::new(&upload)));
+        manager.active_uploads.write().await.insert(id.clone(), ActiveUpload {
+            handle: tokio::spawn(std::future::pending()),
+            group: None,
+            capabilities: Capabilities::tus(1024),
+            cancellation_token: CancellationToken::new(),
+        });
+
+        manager.shutdown_with_timeout(Duration::from_millis(50)).await.unwrap();
+        drop(manager);
+
+        let manager = UploadManager::new(config).await.unwrap();
+        let upload = manager.upload_state.get_upload(&id).await.unwrap();
+        assert_eq!(upload.status, UploadStatus::Paused);
+        assert_eq!(upload.progress.bytes_transferred, 1024);
+        assert!(upload.location.is_some());
+        manager.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_pauses_interrupted_uploads() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(20)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config.clone()).await.unwrap());
+        let run = manager.run().unwrap();
+        let file = test_file(64 * 1024);
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+        while server.patch_count() < 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        manager.shutdown_with_timeout(Duration::from_secs(5)).await.unwrap();
+        assert!(!run.is_running());
+        run.stopped().await;
+        drop(manager);
+
+        // 重启后从服务端确认的偏移继续，而不是丢失这个 upload
+        let manager = UploadManager::new(config).await.unwrap();
+        let upload = manager.upload_state.get_upload(&id).await.unwrap();
+        assert_eq!(upload.status, UploadStatus::Paused);
+        let location = upload.location.unwrap();
+        assert_eq!(upload.progress.bytes_transferred, server.upload(&location).unwrap().data.len() as u64);
+        assert!(upload.progress.bytes_transferred >= 1024);
+        manager.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_events() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_retries: 0,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let mut events = manager.subscribe();
+        let run = manager.run().unwrap();
+
+        // 最后一块的进度可能在 Completed 之后才发出
+        let file = test_file(3000);
+        let id = add_copy(&manager, file.path().to_path_buf()).await;
+        let mut kinds = Vec::new();
+        let mut location = None;
+        let mut final_progress = false;
+        while location.is_none() || !final_progress {
+            match next_lifecycle_event(&mut events).await {
+                UploadEvent::Added { id: added, .. } if added == id => kinds.push("added"),
+                UploadEvent::Started { id: started, .. } if started == id => kinds.push("started"),
+                UploadEvent::Progress { bytes_transferred, total_bytes, .. } => {
+                    assert_eq!(total_bytes, 3000);
+                    final_progress = bytes_transferred == 3000;
+                }
+                UploadEvent::Completed { id: completed, location: Some(url), .. } if completed == id => {
+                    kinds.push("completed");
+                    location = Some(url);
+                }
+                event => panic!("unexpected event {:?}", event),
+            }
+        }
+        assert_eq!(kinds, ["added", "started", "completed"]);
+        assert_eq!(server.upload(&location.unwrap()).unwrap().data.len(), 3000);
+
+        server.fail_patch(server.patch_count() + 1, 500);
+        let failed = add_copy(&manager, file.path().to_path_buf()).await;
+        loop {
+            if let UploadEvent::Failed { id, error, .. } = next_lifecycle_event(&mut events).await {
+                assert_eq!(id, failed);
+                assert_eq!(error.status, Some(500));
+                break;
+            }
+        }
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_server_capabilities_limit_uploads() {
+        let server = TusServer::start().await;
+        server.set_max_size(Some(1000));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 256,
+            buffer_size: 256,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let run = manager.run().unwrap();
+
+        // 启动时探测，之后从缓存读取
+        let capabilities = manager.get_server_capabilities(None).await.unwrap().unwrap();
+        assert_eq!(capabilities.max_size, Some(1000));
+        assert!(!capabilities.supports(headers::TERMINATION));
+        manager.get_server_capabilities(None).await.unwrap();
+        assert_eq!(server.count_requests(hyper::Method::OPTIONS), 1);
+
+        let large = test_file(1001);
+        let err = manager.add_upload(large.path().to_path_buf()).await.unwrap_err();
+        assert!(matches!(err, UploadError::FileTooLarge { size: 1001, limit: 1000 }));
+        assert!(manager.upload_state.list().await.is_empty());
+
+        // 服务端没有声明 termination 时不发送 DELETE
+        let response = reqwest::Client::new().post(server.endpoint())
+            .header(headers::TUS_RESUMABLE, headers::TUS_VERSION)
+            .header(headers::UPLOAD_LENGTH, 10)
+            .send().await.unwrap();
+        let location = response.headers()[reqwest::header::LOCATION].to_str().unwrap().to_string();
+        terminate_if_supported(&manager.capabilities, &manager.config, &location, manager.clock.as_ref()).await.unwrap();
+        assert_eq!(server.count_requests(hyper::Method::DELETE), 0);
+        assert!(server.upload(&location).is_some());
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_workers_share_connections() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 256,
+            buffer_size: 256,
+            max_concurrent: 1,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let run = manager.run().unwrap();
+
+        // 先后上传的 upload 和启动时的探测使用同一个连接
+        let files: Vec<_> = (0..3).map(|_| test_file(1000)).collect();
+        for file in &files {
+            let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+            wait_for_status(&manager, &id, UploadStatus::Completed).await;
+        }
+        assert_eq!(server.uploads().len(), 3);
+        assert_eq!(server.connection_count(), 1);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_hostile_location_is_never_persisted() {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            max_retries: 0,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let run = manager.run().unwrap();
+        let file = test_file(100);
+
+        let marker = "HOSTILE";
+        let cases: [Vec<u8>; 5] = [
+            format!("/files/{}{}", marker, "a".repeat(10 * 1024)).into_bytes(),
+            format!("/files/\t{}", marker).into_bytes(),
+            format!("https://evil.example.org/files/{}", marker).into_bytes(),
+            format!("javascript:{}", marker).into_bytes(),
+            [b"/files/".as_slice(), marker.as_bytes(), &[0xff, 0xfe]].concat(),
+        ];
+        for location in cases {
+            server.set_location_override(Some(location.clone()));
+            let id = add_copy(&manager, file.path().to_path_buf()).await;
+            let upload = wait_for_status(&manager, &id, UploadStatus::Failed).await;
+            assert_eq!(upload.location, None, "{}", String::from_utf8_lossy(&location));
+        }
+        manager.upload_state.save_state().await.unwrap();
+        let saved = std::fs::read_to_string(manager.upload_state.state_file()).unwrap();
+        assert!(!saved.contains(marker));
+
+        // 导入的地址同样校验，相对地址按 endpoint 解析
+        let mut imported = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+        imported.status = UploadStatus::Paused;
+        imported.location = Some("https://evil.example.org/files/1".into());
+        let err = manager.add_existing_upload(imported.clone()).await.unwrap_err();
+        assert_eq!(err.code(), "location_origin_mismatch");
+        imported.location = Some("/files/1".into());
+        let id = manager.add_existing_upload(imported).await.unwrap();
+        let upload = manager.upload_state.get_upload(&id).await.unwrap();
+        let origin = server.endpoint().trim_end_matches("/files").to_string();
+        assert_eq!(upload.location, Some(format!("{}/files/1", origin)));
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_backup_includes_active_uploads() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(20)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let run = manager.run().unwrap();
+        let file = test_file(5000);
+        let id = manager.add_upload(file.path().to_path_buf()).await.unwrap().into_id();
+        while manager.status_cache.live_upload(&id).is_none_or(|upload| upload.progress.bytes_transferred == 0) {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        // 正在上传的 upload 已经出队，备份中也要有它，恢复后继续使用原来的服务端资源
+        let backup = manager.create_backup().await.unwrap().unwrap();
+        let loaded = manager.restore_backup(&backup.name, true).await.unwrap();
+        assert_eq!(loaded.recovered, 1);
+        wait_for_status(&manager, &id, UploadStatus::Paused).await;
+        manager.resume_upload(&id).await.unwrap();
+        wait_for_status(&manager, &id, UploadStatus::Completed).await;
+        let uploads = server.uploads();
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0].data.len(), 5000);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
 
-        let manager_clone = manager.clone();
-        tokio::spawn(async move {
-            manager_clone.run().await;
+    #[tokio::test]
+    async fn test_restore_backup_after_cancel_group() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            split_oversize: Some(crate::core::config::SplitPolicy { part_size: 1000, naming: SplitNaming::Suffix }),
+            ..temp_config(state_dir.path())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let small = test_file(500);
+        let large = test_file(2500);
+        manager.add_upload(small.path().to_path_buf()).await.unwrap().into_id();
+        let group = manager.add_upload(large.path().to_path_buf()).await.unwrap().into_id();
+
+        let summary = |uploads: Vec<Upload>| {
+            let mut uploads: Vec<(String, UploadStatus, u64)> = uploads.into_iter()
+                .map(|upload| (upload.id, upload.status, upload.total_bytes))
+                .collect();
+            uploads.sort_by(|a, b| a.0.cmp(&b.0));
+            uploads
+        };
+        let expected = summary(manager.upload_state.list().await);
+        assert_eq!(expected.len(), 4);
+        let backup = manager.create_backup().await.unwrap().unwrap();
+
+        // 有 upload 正在上传时拒绝恢复
+        let token = CancellationToken::new();
+        let busy = manager.upload_state.list().await.remove(0);
+        let worker_token = token.clone();
+        manager.active_uploads.write().await.insert("busy".to_string(), ActiveUpload {
+            handle: tokio::spawn(async move {
+                worker_token.cancelled().await;
+                busy
+            }),
+            group: None,
+            capabilities: Capabilities::tus(1024),
+            cancellation_token: token.clone(),
         });
+        assert!(matches!(
+            manager.restore_backup(&backup.name, false).await,
+            Err(UploadError::InvalidState(_))
+        ));
+
+        // 取消分组前会自动备份
+        assert_eq!(manager.cancel_group(&group).await.unwrap(), 3);
+        assert_eq!(manager.upload_state.list().await.len(), 1);
+        let backups = manager.list_backups().await.unwrap();
+        assert!(backups.len() >= 2);
+        assert_ne!(backups[0].name, backup.name);
+        assert!(backups.iter().any(|info| info.name == backup.name));
+
+        let loaded = manager.restore_backup(&backup.name, true).await.unwrap();
+        assert_eq!(loaded.queued, 4);
+        assert!(token.is_cancelled());
+        assert!(manager.active_uploads.read().await.is_empty());
+        assert_eq!(summary(manager.upload_state.list().await), expected);
+        assert!(matches!(
+            manager.restore_backup("state-missing.json", true).await,
+            Err(UploadError::BackupNotFound(_))
+        ));
+
+        manager.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replace_source_restarts_with_new_file() {
+        let server = TusServer::start().await;
+        server.enable_termination();
+        server.set_patch_delay(Some(Duration::from_millis(30)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            terminate_abandoned: true,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let mut events = manager.subscribe();
+        let run = manager.run().unwrap();
+
+        let old_file = test_file(8 * 1024);
+        let options = AddUploadOptions::builder().metadata("project", "demo").client_ref("row-1").build().unwrap();
+        let id = manager.add_upload_with_options(old_file.path().to_path_buf(), options).await.unwrap().into_id();
+
+        // 等到上传了一部分
+        while manager.get_upload_status(&id).await.map_or(true, |status| status.bytes_transferred == 0) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let mut new_file = tempfile::NamedTempFile::new().unwrap();
+        let content: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+        std::io::Write::write_all(&mut new_file, &content).unwrap();
+
+        // 正在上传时需要先暂停
+        assert!(matches!(
+            manager.replace_source(&id, new_file.path().to_path_buf(), false).await,
+            Err(UploadError::InvalidState(_))
+        ));
+        manager.replace_source(&id, new_file.path().to_path_buf(), true).await.unwrap();
+
+        let upload = manager.upload_state.get_upload(&id).await.unwrap();
+        assert_eq!(upload.status, UploadStatus::Paused);
+        assert_eq!(upload.file_path, new_file.path());
+        assert_eq!(upload.total_bytes, 3000);
+        assert_eq!(upload.progress.bytes_transferred, 0);
+        assert!(upload.location.is_none());
+        assert_eq!(upload.metadata["project"], "demo");
+        assert_eq!(upload.client_ref.as_deref(), Some("row-1"));
+        assert_eq!(server.deleted().len(), 1);
+
+        let replaced = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let UploadEvent::SourceReplaced { old_path, total_bytes, .. } = events.recv().await.unwrap().event {
+                    return (old_path, total_bytes);
+                }
+            }
+        }).await.unwrap();
+        assert_eq!(replaced, (old_file.path().to_path_buf(), 3000));
+
+        // 重新排队后上传新文件的内容
+        server.set_patch_delay(None);
+        let mut upload = upload;
+        upload.transition_to(UploadStatus::Pending).unwrap();
+        manager.upload_state.replace(upload).await.unwrap();
+        let upload = wait_for_status(&manager, &id, UploadStatus::Completed).await;
+        assert_eq!(server.upload(upload.location.as_ref().unwrap()).unwrap().data, content);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_resume_below_persisted_progress_is_corrected_once() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(20)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 512,
+            buffer_size: 512,
+            ..TusConfig::new(server.endpoint())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+        let mut events = manager.subscribe();
+
+        // 服务端只有 1024 字节，本地记录的进度却是 3072
+        let file = test_file(4096);
+        let client = reqwest::Client::new();
+        let created = client.post(server.endpoint())
+            .header("Tus-Resumable", "1.0.0")
+            .header("Upload-Length", "4096")
+            .send().await.unwrap();
+        let location = created.headers()["location"].to_str().unwrap().to_string();
+        client.patch(&location)
+            .header("Tus-Resumable", "1.0.0")
+            .header("Upload-Offset", "0")
+            .header("Content-Type", "application/offset+octet-stream")
+            .body(vec![7u8; 1024])
+            .send().await.unwrap();
+
+        let mut upload = Upload::new(file.path().to_path_buf(), 512).unwrap();
+        upload.set_location(location);
+        upload.progress.bytes_transferred = 3072;
+        let id = manager.add_existing_upload(upload).await.unwrap();
+        assert_eq!(manager.get_upload_status(&id).await.unwrap().bytes_transferred, 3072);
+
+        let run = manager.run().unwrap();
+
+        let mut reported = Vec::new();
+        loop {
+            let status = manager.get_upload_status(&id).await.unwrap();
+            reported.push(status.bytes_transferred);
+            if status.status == UploadStatus::Completed {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        let mut corrections = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let UploadEvent::ProgressCorrected { old, new, reason, .. } = event.event {
+                corrections.push((old, new, reason));
+            }
+        }
+        assert_eq!(corrections, [(3072, 1024, CorrectionReason::ServerOffset)]);
+
+        // 只在修正时减少一次，之后单调增加
+        let drops = reported.windows(2).filter(|pair| pair[1] < pair[0]).count();
+        assert!(drops <= 1, "reported {:?}", reported);
+        assert_eq!(*reported.last().unwrap(), 4096);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_profiles_route_and_limit_concurrency() {
+        let tenant_a = TusServer::start().await;
+        let tenant_b = TusServer::start().await;
+        tenant_a.set_patch_delay(Some(Duration::from_millis(10)));
+        tenant_b.set_patch_delay(Some(Duration::from_millis(10)));
+        let state_dir = tempfile::tempdir().unwrap();
+
+        let profile = |server: &TusServer, tenant: &str, limit| crate::core::config::EndpointProfile {
+            url: server.endpoint(),
+            headers: HashMap::from([("X-Tenant".to_string(), tenant.to_string())]),
+            max_concurrent_override: limit,
+            ..Default::default()
+        };
+        let config = TusConfig {
+            state_dir: state_dir.path().to_path_buf(),
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_concurrent: 4,
+            headers: HashMap::from([("X-Tenant".to_string(), "default".to_string())]),
+            endpoints_by_name: HashMap::from([
+                ("a".to_string(), profile(&tenant_a, "a", Some(1))),
+                ("b".to_string(), profile(&tenant_b, "b", None)),
+            ]),
+            ..TusConfig::new("http://127.0.0.1:1/unused".to_string())
+        };
+        let manager = Arc::new(UploadManager::new(config).await.unwrap());
+
+        let files: Vec<_> = (0..6).map(|_| test_file(4 * 1024)).collect();
+        for (i, file) in files.iter().enumerate() {
+            let name = if i % 2 == 0 { "a" } else { "b" };
+            let options = AddUploadOptions::builder().endpoint(name).build().unwrap();
+            manager.add_upload_with_options(file.path().to_path_buf(), options).await.unwrap().into_id();
+        }
+        let unknown = AddUploadOptions::builder().endpoint("c").build().unwrap();
+        assert!(manager.add_upload_with_options(files[0].path().to_path_buf(), unknown).await.is_err());
+
+        let run = manager.run().unwrap();
+        for _ in 0..200 {
+            let done = |server: &TusServer| server.uploads().iter()
+                .filter(|u| u.data.len() == 4 * 1024)
+                .count();
+            if done(&tenant_a) == 3 && done(&tenant_b) == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        for (server, tenant) in [(&tenant_a, "a"), (&tenant_b, "b")] {
+            assert_eq!(server.uploads().len(), 3);
+            for request in server.requests() {
+                assert_eq!(request.headers.get("X-Tenant").unwrap(), tenant);
+            }
+        }
+        // a 限制为 1，b 只受全局并发限制
+        assert_eq!(tenant_a.max_concurrent_patches(), 1);
+        assert!(tenant_b.max_concurrent_patches() > 1);
+
+        let statuses = manager.list_upload_statuses().await;
+        assert_eq!(statuses.iter().filter(|s| s.endpoint.as_deref() == Some("a")).count(), 3);
+
+        manager.shutdown().await.unwrap();
+        run.stopped().await;
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_all_tasks() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(20)));
+        let state_dir = tempfile::tempdir().unwrap();
+        let file = test_file(8000);
+
+        for _ in 0..5 {
+            let config = TusConfig {
+                state_dir: state_dir.path().to_path_buf(),
+                chunk_size: 1024,
+                buffer_size: 1024,
+                ..TusConfig::new(server.endpoint())
+            };
+            let manager = Arc::new(UploadManager::new(config).await.unwrap());
+
+            let run = manager.run().unwrap();
+
+            add_copy(&manager, file.path().to_path_buf()).await;
+            add_copy(&manager, file.path().to_path_buf()).await;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            manager.shutdown().await.unwrap();
+            assert!(manager.tasks.is_empty());
+            tokio::time::timeout(Duration::from_millis(100), run.stopped()).await.unwrap();
+            assert!(!run.is_running());
+
+            // 关闭后不再写入状态文件
+            add_copy(&manager, file.path().to_path_buf()).await;
+            let mut entries = std::fs::read_dir(state_dir.path()).unwrap();
+            assert!(entries.all(|e| e.unwrap().path().extension().map_or(true, |ext| ext != "tmp")));
+        }
+    }
+
+    struct QuotaGuard {
+        quota: u64,
+        calls: AtomicUsize,
+        defer_first: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl TransitionGuard for QuotaGuard {
+        async fn allow(&self, upload: &Upload, from: UploadStatus, to: UploadStatus) -> GuardDecision {
+            let calls = self.calls.fetch_add(1, Ordering::SeqCst);
+            assert_eq!((from, to), (UploadStatus::Pending, UploadStatus::Active));
+
+            if self.defer_first && calls == 0 {
+                return GuardDecision::Defer(std::time::Duration::from_millis(50));
+            }
+            if upload.total_bytes > self.quota {
+                return GuardDecision::Deny("daily quota exceeded".to_string());
+            }
+            GuardDecision::Allow
+        }
+    }
+
+    /// 添加同一个文件的又一个 upload
+    async fn add_copy(manager: &UploadManager, path: PathBuf) -> String {
+        let options = AddUploadOptions::builder().allow_duplicates().build().unwrap();
+        manager.add_upload_with_options(path, options).await.unwrap().into_id()
+    }
+
+    fn temp_config(state_dir: &std::path::Path) -> TusConfig {
+        TusConfig {
+            state_dir: state_dir.to_path_buf(),
+            ..TusConfig::new("http://127.0.0.1:6440/api/file/tus".to_string())
+        }
+    }
+
+    /// 下一个事件，跳过随时可能出现的 ActivityChanged、StatsUpdated 和生命周期事件
+    async fn next_event(events: &mut broadcast::Receiver<SequencedEvent>) -> UploadEvent {
+        loop {
+            match next_lifecycle_event(events).await {
+                UploadEvent::Added { .. } | UploadEvent::Started { .. } | UploadEvent::Resumed { .. } => continue,
+                UploadEvent::Progress { .. } | UploadEvent::Paused { .. } | UploadEvent::Cancelled { .. } => continue,
+                UploadEvent::Completed { .. } | UploadEvent::Failed { .. } | UploadEvent::Removed { .. } => continue,
+                event => return event,
+            }
+        }
+    }
+
+    /// 下一个事件，只跳过 ActivityChanged 和 StatsUpdated
+    async fn next_lifecycle_event(events: &mut broadcast::Receiver<SequencedEvent>) -> UploadEvent {
+        loop {
+            match events.recv().await.unwrap().event {
+                UploadEvent::ActivityChanged { .. } | UploadEvent::StatsUpdated { .. } => continue,
+                event => return event,
+            }
+        }
+    }
+
+    async fn wait_for_status(manager: &UploadManager, id: &str, status: UploadStatus) -> Upload {
+        for _ in 0..100 {
+            if let Ok(upload) = manager.upload_state.get_upload(id).await {
+                if upload.status == status {
+                    return upload;
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("upload {} never reached {:?}", id, status);
+    }
+
+    async fn run_guarded(
+        state_dir: &std::path::Path,
+        guard: Arc<QuotaGuard>,
+    ) -> (Arc<UploadManager>, String, tempfile::NamedTempFile) {
+        let manager = UploadManager::new(temp_config(state_dir)).await.unwrap()
+            .with_transition_guard(guard);
+        let manager = Arc::new(manager);
+
+        manager.run().unwrap();
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, &[0u8; 128]).unwrap();
+        let upload_id = add_copy(&manager, file.path().to_path_buf()).await;
+
+        (manager, upload_id, file)
+    }
+
+    #[tokio::test]
+    async fn test_guard_deny_blocks_upload() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let guard = Arc::new(QuotaGuard { quota: 64, calls: AtomicUsize::new(0), defer_first: false });
+        let (manager, upload_id, _file) = run_guarded(state_dir.path(), guard.clone()).await;
+
+        let upload = wait_for_status(&manager, &upload_id, UploadStatus::Blocked).await;
+        assert_eq!(upload.blocked_reason.as_deref(), Some("daily quota exceeded"));
+        assert_eq!(guard.calls.load(Ordering::SeqCst), 1);
+
+        // 原因需要持久化
+        let reloaded = UploadStateManager::new(temp_config(state_dir.path())).await.unwrap();
+        let upload = reloaded.get_upload(&upload_id).await.unwrap();
+        assert_eq!(upload.status, UploadStatus::Blocked);
+        assert_eq!(upload.blocked_reason.as_deref(), Some("daily quota exceeded"));
+    }
+
+    #[tokio::test]
+    async fn test_guard_defer_reschedules_upload() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let guard = Arc::new(QuotaGuard { quota: 64, calls: AtomicUsize::new(0), defer_first: true });
+        let (manager, upload_id, _file) = run_guarded(state_dir.path(), guard.clone()).await;
+
+        wait_for_status(&manager, &upload_id, UploadStatus::Blocked).await;
+        assert_eq!(guard.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_guard_failures_allow_and_report() {
+        struct PanicGuard;
+
+        #[async_trait::async_trait]
+        impl TransitionGuard for PanicGuard {
+            async fn allow(&self, _: &Upload, _: UploadStatus, _: UploadStatus) -> GuardDecision {
+                panic!("quota service unavailable")
+            }
+        }
+
+        struct SlowGuard;
+
+        #[async_trait::async_trait]
+        impl TransitionGuard for SlowGuard {
+            async fn allow(&self, _: &Upload, _: UploadStatus, _: UploadStatus) -> GuardDecision {
+                std::future::pending().await
+            }
+        }
+
+        // 打印 panic 的调用栈可能很慢，panic 的守卫使用较长的超时
+        let guards: [(Arc<dyn TransitionGuard>, Duration, &str); 2] = [
+            (Arc::new(PanicGuard), Duration::from_secs(30), "quota service unavailable"),
+            (Arc::new(SlowGuard), Duration::from_millis(50), "timed out"),
+        ];
+        let file = test_file(128);
+        for (guard, guard_timeout, expected) in guards {
+            let state_dir = tempfile::tempdir().unwrap();
+            let config = TusConfig { guard_timeout, ..temp_config(state_dir.path()) };
+            let manager = UploadManager::new(config).await.unwrap().with_transition_guard(guard);
+            let mut events = manager.subscribe();
+            let upload = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+
+            assert_eq!(manager.check_guard(&upload, UploadStatus::Active).await, GuardDecision::Allow);
+            let UploadEvent::GuardFailed { id, reason } = next_event(&mut events).await else {
+                panic!("expected GuardFailed");
+            };
+            assert_eq!(id, upload.id);
+            assert!(reason.contains(expected), "{}", reason);
+            manager.shutdown().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deferred_upload_survives_shutdown() {
+        struct DeferGuard;
+
+        #[async_trait::async_trait]
+        impl TransitionGuard for DeferGuard {
+            async fn allow(&self, _: &Upload, _: UploadStatus, _: UploadStatus) -> GuardDecision {
+                GuardDecision::Defer(Duration::from_secs(3600))
+            }
+        }
+
+        let state_dir = tempfile::tempdir().unwrap();
+        let manager = UploadManager::new(temp_config(state_dir.path())).await.unwrap()
+            .with_transition_guard(Arc::new(DeferGuard));
+        let manager = Arc::new(manager);
+        manager.run().unwrap();
+        let file = test_file(128);
+        let upload_id = add_copy(&manager, file.path().to_path_buf()).await;
+
+        while manager.get_upload(&upload_id).await.map_or(true, |upload| upload.retry_at.is_none()) {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        manager.shutdown().await.unwrap();
 
-        manager.add_upload(test_file1()).await.unwrap();
-        manager.add_upload(test_file2()).await.unwrap();
-        manager.add_upload(test_file3()).await.unwrap();
+        let reloaded = UploadStateManager::new(temp_config(state_dir.path())).await.unwrap();
+        reloaded.wait_loaded().await;
+        let queue: Vec<String> = reloaded.get_queue().await.into_iter().map(|entry| entry.id).collect();
+        assert_eq!(queue, [upload_id.clone()]);
+        assert_eq!(reloaded.get_upload(&upload_id).await.unwrap().retry_at, None);
     }
 
     #[tokio::test]
     async fn test_create() {
-        let config = TusConfig::new("http://127.0.0.1:6440/api/file/tus".to_string());
-        let upload_manager = UploadManager::new(config).await.unwrap();
+        let state_dir = tempfile::tempdir().unwrap();
+        let upload_manager = UploadManager::new(temp_config(state_dir.path())).await.unwrap();
     }
 }
@@ -1,2 +1,15 @@
-mod manager;
-mod worker;
\ No newline at end of file
+pub mod manager;
+pub mod worker;
+pub mod status;
+pub mod pipeline;
+pub mod forwarder;
+pub mod app_events;
+pub mod scheduler;
+pub mod connectivity;
+pub mod changes;
+pub mod retry;
+pub mod discovery;
+pub mod audit;
+pub mod activity;
+pub mod directory;
+pub mod stats;
@@ -0,0 +1,619 @@
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex};
+use async_trait::async_trait;
+use bytes::Bytes;
+use memmap2::MmapOptions;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio::select;
+use tokio::sync::mpsc;
+use tokio::task::{AbortHandle, JoinSet};
+use crate::core::config::ReadStrategy;
+
+/// 按偏移读取数据的来源
+#[async_trait]
+pub trait ChunkSource: Send + 'static {
+    /// 从 offset 开始读取，返回读取的字节数，0 表示没有更多数据
+    async fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize>;
+
+    /// 直接取得从 offset 开始最多 len 字节的数据，不复制到 buffer；返回 None 时用 read_at 读取
+    async fn map_at(&mut self, _offset: u64, _len: usize) -> Option<Bytes> {
+        None
+    }
+}
+
+#[async_trait]
+impl ChunkSource for BufReader<File> {
+    async fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.seek(SeekFrom::Start(offset)).await?;
+        self.read(buf).await
+    }
+}
+
+/// 读取源文件，按配置把每块映射到内存
+///
+/// 每块单独映射，32 位系统上也可以发送超过 4 GB 的文件；映射失败时（例如网络驱动器）之后都改为普通读取
+pub struct FileSource {
+    reader: BufReader<File>,
+
+    /// 用于映射的文件句柄，不映射时为空
+    mapped: Option<std::fs::File>,
+}
+
+impl FileSource {
+    pub async fn open(path: &Path, buffer_size: usize, strategy: ReadStrategy) -> std::io::Result<Self> {
+        let file = File::open(path).await?;
+        let mapped = match strategy {
+            ReadStrategy::Buffered => None,
+            ReadStrategy::Mmap => Some(file.try_clone().await?.into_std().await),
+        };
+        Ok(Self { reader: BufReader::with_capacity(buffer_size, file), mapped })
+    }
+}
+
+#[async_trait]
+impl ChunkSource for FileSource {
+    async fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read_at(offset, buf).await
+    }
+
+    async fn map_at(&mut self, offset: u64, len: usize) -> Option<Bytes> {
+        let file = self.mapped.as_ref()?;
+        match map_window(file, offset, len) {
+            Ok(data) => Some(data),
+            Err(err) => {
+                log::warn!("Failed to map source file, falling back to buffered reads: {}", err);
+                self.mapped = None;
+                None
+            }
+        }
+    }
+}
+
+/// 映射文件中从 offset 开始最多 len 字节，超出文件末尾的部分不映射
+fn map_window(file: &std::fs::File, offset: u64, len: usize) -> std::io::Result<Bytes> {
+    let len = (len as u64).min(file.metadata()?.len().saturating_sub(offset)) as usize;
+    if len == 0 {
+        return Ok(Bytes::new());
+    }
+    // SAFETY: 只读映射。映射期间文件被其他进程截断时访问会收到 SIGBUS，与 Upload 的指纹检查一样，
+    // 上传期间不应修改源文件；每块映射前按当前大小截取，缩短了这个窗口
+    let map = unsafe { MmapOptions::new().offset(offset).len(len).map(file)? };
+    Ok(Bytes::from_owner(map))
+}
+
+/// 只读取源文件中的一段，偏移从这一段的开头算起
+pub struct WindowedSource<S> {
+    inner: S,
+    start: u64,
+    end: u64,
+}
+
+impl<S> WindowedSource<S> {
+    pub fn new(inner: S, start: u64, end: u64) -> Self {
+        Self { inner, start, end }
+    }
+}
+
+#[async_trait]
+impl<S: ChunkSource> ChunkSource for WindowedSource<S> {
+    async fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let position = self.start.saturating_add(offset);
+        if position >= self.end {
+            return Ok(0);
+        }
+        let len = buf.len().min((self.end - position) as usize);
+        self.inner.read_at(position, &mut buf[..len]).await
+    }
+
+    async fn map_at(&mut self, offset: u64, len: usize) -> Option<Bytes> {
+        let position = self.start.saturating_add(offset);
+        if position >= self.end {
+            return Some(Bytes::new());
+        }
+        self.inner.map_at(position, len.min((self.end - position) as usize)).await
+    }
+}
+
+/// 每个 upload 正在运行的读取任务数量，检查读取任务不会比 worker 活得更久
+static READERS: LazyLock<Mutex<HashMap<String, usize>>> = LazyLock::new(Default::default);
+
+/// 同一个 upload 同时运行的读取任务数量的最大值，正常情况下不超过 1
+static MAX_READERS_PER_UPLOAD: AtomicUsize = AtomicUsize::new(0);
+
+/// 这个 upload 正在运行的读取任务数量
+pub fn active_readers(id: &str) -> usize {
+    READERS.lock().unwrap().get(id).copied().unwrap_or_default()
+}
+
+/// 任意 upload 曾经同时运行的读取任务数量的最大值
+pub fn max_readers_per_upload() -> usize {
+    MAX_READERS_PER_UPLOAD.load(Ordering::SeqCst)
+}
+
+/// 读取任务持有的登记，读取任务退出时注销
+#[derive(Debug)]
+pub struct ReaderLease {
+    id: String,
+}
+
+impl ReaderLease {
+    pub fn acquire(id: &str) -> Self {
+        let mut readers = READERS.lock().unwrap();
+        let count = readers.entry(id.to_string()).or_default();
+        *count += 1;
+        MAX_READERS_PER_UPLOAD.fetch_max(*count, Ordering::SeqCst);
+        debug_assert!(*count == 1, "upload {} has {} concurrent readers", id, count);
+        Self { id: id.to_string() }
+    }
+}
+
+impl Drop for ReaderLease {
+    fn drop(&mut self) {
+        let mut readers = READERS.lock().unwrap();
+        if let Some(count) = readers.get_mut(&self.id) {
+            *count -= 1;
+            if *count == 0 {
+                readers.remove(&self.id);
+            }
+        }
+    }
+}
+
+/// 带有登记的数据来源，随读取任务一起释放
+pub struct LeasedSource<S> {
+    inner: S,
+    _lease: ReaderLease,
+}
+
+impl<S> LeasedSource<S> {
+    pub fn new(inner: S, lease: ReaderLease) -> Self {
+        Self { inner, _lease: lease }
+    }
+}
+
+#[async_trait]
+impl<S: ChunkSource> ChunkSource for LeasedSource<S> {
+    async fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read_at(offset, buf).await
+    }
+
+    async fn map_at(&mut self, offset: u64, len: usize) -> Option<Bytes> {
+        self.inner.map_at(offset, len).await
+    }
+}
+
+/// 发送方通知读取方的命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReaderCommand {
+    /// 从指定偏移重新读取，之前读出的数据作废
+    Seek { offset: u64, generation: u64 },
+}
+
+/// 读取方发给发送方的消息
+#[derive(Debug)]
+enum ReaderMessage {
+    Chunk(Chunk),
+
+    /// 在这个偏移没有更多数据
+    Eof { offset: u64, generation: u64 },
+
+    Error(std::io::Error),
+}
+
+/// 读取出的一块数据，buffer 来自缓冲池，用完后通过 recycle 归还
+#[derive(Debug)]
+pub struct Chunk {
+    pub offset: u64,
+    generation: u64,
+    buffer: Bytes,
+    len: usize,
+
+    /// 数据来自映射的文件，不是缓冲池中的 buffer
+    mapped: bool,
+}
+
+impl Chunk {
+    pub fn data(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+
+    /// 作为请求体发送的数据，与 buffer 共享内存，不复制
+    /// 归还前需要释放，否则 recycle 只能重新分配一个 buffer
+    pub fn body(&self) -> Bytes {
+        self.buffer.slice(..self.len)
+    }
+}
+
+/// 读取任务与发送方之间的管道
+///
+/// 读取任务最多提前读出 capacity 块，另外一块在发送方手中；缓冲池中的 buffer 用完后等待发送方归还，
+/// 磁盘偶尔卡顿时发送方仍然可以继续发送已经读出的数据。capacity 为 0 时只有一个 buffer，
+/// 发送方归还之后才读取下一块
+pub struct ChunkPipeline {
+    commands: mpsc::UnboundedSender<ReaderCommand>,
+    messages: mpsc::Receiver<ReaderMessage>,
+    pool: mpsc::UnboundedSender<Vec<u8>>,
+    chunk_size: usize,
+
+    /// 当前有效的读取序号，Seek 后旧序号的数据直接丢弃
+    generation: u64,
+    handle: AbortHandle,
+}
+
+impl ChunkPipeline {
+    /// 读取任务放在调用方的 JoinSet 中，调用方负责在结束前等待它退出
+    pub fn spawn(tasks: &mut JoinSet<()>, source: impl ChunkSource, chunk_size: usize, capacity: usize, offset: u64) -> Self {
+        let (commands, command_rx) = mpsc::unbounded_channel();
+        let (message_tx, messages) = mpsc::channel(capacity.max(1));
+        let (pool, pool_rx) = mpsc::unbounded_channel();
+        for _ in 0..=capacity {
+            let _ = pool.send(vec![0u8; chunk_size]);
+        }
+
+        let handle = tasks.spawn(read_loop(source, offset, chunk_size, command_rx, message_tx, pool_rx));
+        Self { commands, messages, pool, chunk_size, generation: 0, handle }
+    }
+
+    /// 取得从 offset 开始的一块数据，没有更多数据时返回 None
+    /// 读取方的位置与 offset 不一致时（重试、服务端偏移修正）会让它重新读取
+    pub async fn next(&mut self, offset: u64) -> std::io::Result<Option<Chunk>> {
+        let mut requested = false;
+        loop {
+            let message = self.messages.recv().await.ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::BrokenPipe, "chunk reader stopped")
+            })?;
+
+            match message {
+                ReaderMessage::Chunk(chunk) if chunk.generation == self.generation && chunk.offset == offset => {
+                    return Ok(Some(chunk));
+                }
+                ReaderMessage::Eof { offset: eof, generation } if generation == self.generation && eof == offset => {
+                    return Ok(None);
+                }
+                ReaderMessage::Error(err) => return Err(err),
+                ReaderMessage::Chunk(chunk) => {
+                    let stale = chunk.generation != self.generation;
+                    self.recycle(chunk);
+                    if stale || requested {
+                        continue;
+                    }
+                }
+                ReaderMessage::Eof { generation, .. } => {
+                    if generation != self.generation || requested {
+                        continue;
+                    }
+                }
+            }
+
+            self.generation += 1;
+            requested = true;
+            let _ = self.commands.send(ReaderCommand::Seek { offset, generation: self.generation });
+        }
+    }
+
+    /// 归还 buffer 给读取任务
+    pub fn recycle(&self, chunk: Chunk) {
+        // 映射的块占用的名额换成空的 buffer，需要普通读取时再分配
+        let buffer = match chunk.mapped {
+            true => Vec::new(),
+            false => reclaim(chunk.buffer, self.chunk_size),
+        };
+        let _ = self.pool.send(buffer);
+    }
+}
+
+impl Drop for ChunkPipeline {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn read_loop(
+    mut source: impl ChunkSource,
+    mut offset: u64,
+    chunk_size: usize,
+    mut commands: mpsc::UnboundedReceiver<ReaderCommand>,
+    messages: mpsc::Sender<ReaderMessage>,
+    mut pool: mpsc::UnboundedReceiver<Vec<u8>>,
+) {
+    let mut generation = 0;
+    let mut eof = false;
+    // 读到末尾或出错时没有发出去的 buffer
+    let mut spare: Option<Vec<u8>> = None;
+
+    loop {
+        // 读完或者没有空闲 buffer 时只等待命令
+        let mut buffer = select! {
+            biased;
+            command = commands.recv() => {
+                let Some(ReaderCommand::Seek { offset: seek, generation: seek_generation }) = command else {
+                    return;
+                };
+                offset = seek;
+                generation = seek_generation;
+                eof = false;
+                continue;
+            }
+            buffer = next_buffer(&mut spare, &mut pool), if !eof => match buffer {
+                Some(buffer) => buffer,
+                None => return,
+            },
+        };
+
+        // 映射的数据直接作为请求体，这时 buffer 只用来限制提前读出的块数
+        let read = match source.map_at(offset, chunk_size).await {
+            Some(data) => Ok((data.len(), Some(data))),
+            None => {
+                buffer.resize(chunk_size, 0);
+                fill(&mut source, offset, &mut buffer).await.map(|len| (len, None))
+            }
+        };
+        let message = match read {
+            Ok((0, _)) => {
+                eof = true;
+                spare = Some(buffer);
+                ReaderMessage::Eof { offset, generation }
+            }
+            Ok((len, data)) => {
+                let mapped = data.is_some();
+                let buffer = data.unwrap_or_else(|| Bytes::from(buffer));
+                let chunk = Chunk { offset, generation, buffer, len, mapped };
+                offset += len as u64;
+                ReaderMessage::Chunk(chunk)
+            }
+            Err(err) => {
+                eof = true;
+                spare = Some(buffer);
+                ReaderMessage::Error(err)
+            }
+        };
+
+        if messages.send(message).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// 一直读取到 buffer 装满或者没有更多数据，底层的一次读取可能远小于块大小（例如 BufReader 的容量）
+async fn fill(source: &mut impl ChunkSource, offset: u64, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match source.read_at(offset + filled as u64, &mut buffer[filled..]).await? {
+            0 => break,
+            len => filled += len,
+        }
+    }
+    Ok(filled)
+}
+
+/// 取回 buffer 的内存；请求体还没有释放时（例如被请求的克隆持有）重新分配一个，缓冲池的大小保持不变
+fn reclaim(buffer: Bytes, chunk_size: usize) -> Vec<u8> {
+    match buffer.try_into_mut() {
+        Ok(buffer) => buffer.into(),
+        Err(_) => vec![0u8; chunk_size],
+    }
+}
+
+async fn next_buffer(spare: &mut Option<Vec<u8>>, pool: &mut mpsc::UnboundedReceiver<Vec<u8>>) -> Option<Vec<u8>> {
+    match spare.take() {
+        Some(buffer) => Some(buffer),
+        None => pool.recv().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    const CHUNK: usize = 4;
+
+    /// 按脚本延迟的内存数据源，记录每次读取的偏移
+    struct ScriptedSource {
+        data: Vec<u8>,
+        delays: Vec<Duration>,
+        reads: Arc<Mutex<Vec<u64>>>,
+    }
+
+    #[async_trait]
+    impl ChunkSource for ScriptedSource {
+        async fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = {
+                let mut reads = self.reads.lock().unwrap();
+                reads.push(offset);
+                reads.len() - 1
+            };
+            if let Some(delay) = self.delays.get(n) {
+                tokio::time::sleep(*delay).await;
+            }
+
+            let start = (offset as usize).min(self.data.len());
+            let len = buf.len().min(self.data.len() - start);
+            buf[..len].copy_from_slice(&self.data[start..start + len]);
+            Ok(len)
+        }
+    }
+
+    fn source(len: usize, delays: Vec<Duration>) -> (ScriptedSource, Arc<Mutex<Vec<u64>>>) {
+        let reads = Arc::new(Mutex::new(Vec::new()));
+        let data = (0..len).map(|i| i as u8).collect();
+        (ScriptedSource { data, delays, reads: reads.clone() }, reads)
+    }
+
+    #[tokio::test]
+    async fn test_chunks_in_order_with_slow_reader() {
+        let delays = [0, 30, 0, 0, 50, 0].map(Duration::from_millis).to_vec();
+        let (source, _) = source(CHUNK * 5 + 2, delays);
+        let mut tasks = JoinSet::new();
+        let mut pipeline = ChunkPipeline::spawn(&mut tasks, source, CHUNK, 2, 0);
+
+        let mut received = Vec::new();
+        let mut offset = 0;
+        while let Some(chunk) = pipeline.next(offset).await.unwrap() {
+            assert_eq!(chunk.offset, offset);
+            offset += chunk.data().len() as u64;
+            received.extend_from_slice(chunk.data());
+            pipeline.recycle(chunk);
+        }
+
+        let expected: Vec<u8> = (0..CHUNK * 5 + 2).map(|i| i as u8).collect();
+        assert_eq!(received, expected);
+    }
+
+    #[tokio::test]
+    async fn test_retry_rereads_offset() {
+        let (source, reads) = source(CHUNK * 8, Vec::new());
+        let mut tasks = JoinSet::new();
+        let mut pipeline = ChunkPipeline::spawn(&mut tasks, source, CHUNK, 2, 0);
+
+        let first = pipeline.next(0).await.unwrap().unwrap();
+        pipeline.recycle(first);
+        let second = pipeline.next(4).await.unwrap().unwrap();
+        let expected = second.data().to_vec();
+        pipeline.recycle(second);
+
+        // 发送失败后重试同一个偏移
+        let retry = pipeline.next(4).await.unwrap().unwrap();
+        assert_eq!(retry.data(), expected.as_slice());
+        pipeline.recycle(retry);
+        assert_eq!(reads.lock().unwrap().iter().filter(|offset| **offset == 4).count(), 2);
+
+        // 服务端偏移修正，跳到更后面的位置
+        let corrected = pipeline.next(20).await.unwrap().unwrap();
+        assert_eq!(corrected.data(), &[20, 21, 22, 23]);
+        pipeline.recycle(corrected);
+
+        // 超出文件末尾
+        assert!(pipeline.next(CHUNK as u64 * 8).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_windowed_source() {
+        let (source, reads) = source(CHUNK * 10, Vec::new());
+        let window = WindowedSource::new(source, 6, 16);
+        let mut tasks = JoinSet::new();
+        let mut pipeline = ChunkPipeline::spawn(&mut tasks, window, CHUNK, 2, 0);
+
+        let mut received = Vec::new();
+        let mut offset = 0;
+        while let Some(chunk) = pipeline.next(offset).await.unwrap() {
+            offset += chunk.data().len() as u64;
+            received.extend_from_slice(chunk.data());
+            pipeline.recycle(chunk);
+        }
+
+        assert_eq!(received, (6..16).collect::<Vec<u8>>());
+        assert_eq!(reads.lock().unwrap()[..3], [6, 10, 14]);
+    }
+
+    #[tokio::test]
+    async fn test_read_ahead_is_bounded() {
+        let (source, reads) = source(CHUNK * 100, Vec::new());
+        let mut tasks = JoinSet::new();
+        let mut pipeline = ChunkPipeline::spawn(&mut tasks, source, CHUNK, 2, 0);
+
+        // 发送方很慢时，读取方最多读出 capacity 块加上手中的一块
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(reads.lock().unwrap().len(), 3);
+
+        let chunk = pipeline.next(0).await.unwrap().unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(reads.lock().unwrap().len(), 3);
+
+        pipeline.recycle(chunk);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(reads.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_without_read_ahead() {
+        let (source, reads) = source(CHUNK * 3 + 1, Vec::new());
+        let mut tasks = JoinSet::new();
+        let mut pipeline = ChunkPipeline::spawn(&mut tasks, source, CHUNK, 0, 0);
+
+        // 只有一个 buffer，发送方归还之前不读取下一块
+        let chunk = pipeline.next(0).await.unwrap().unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(reads.lock().unwrap().len(), 1);
+        pipeline.recycle(chunk);
+
+        // 重试同一个偏移时重新读取
+        let retry = pipeline.next(0).await.unwrap().unwrap();
+        assert_eq!(retry.data(), &[0, 1, 2, 3]);
+        pipeline.recycle(retry);
+
+        let mut offset = 4;
+        while let Some(chunk) = pipeline.next(offset).await.unwrap() {
+            offset += chunk.data().len() as u64;
+            pipeline.recycle(chunk);
+        }
+        assert_eq!(offset, CHUNK as u64 * 3 + 1);
+    }
+
+    #[tokio::test]
+    async fn test_mapped_chunks() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let content: Vec<u8> = (0..CHUNK * 5 + 2).map(|i| i as u8).collect();
+        std::io::Write::write_all(&mut file, &content).unwrap();
+
+        for strategy in [ReadStrategy::Buffered, ReadStrategy::Mmap] {
+            let source = FileSource::open(file.path(), CHUNK, strategy).await.unwrap();
+            let window = WindowedSource::new(source, 3, CHUNK as u64 * 4);
+            let mut tasks = JoinSet::new();
+            let mut pipeline = ChunkPipeline::spawn(&mut tasks, window, CHUNK, 1, 0);
+
+            // 重试时重新读取同一个偏移
+            let first = pipeline.next(0).await.unwrap().unwrap();
+            pipeline.recycle(first);
+            let mut received = Vec::new();
+            let mut offset = 0;
+            while let Some(chunk) = pipeline.next(offset).await.unwrap() {
+                assert_eq!(chunk.mapped, strategy == ReadStrategy::Mmap);
+                offset += chunk.data().len() as u64;
+                received.extend_from_slice(chunk.data());
+                pipeline.recycle(chunk);
+            }
+            assert_eq!(received, content[3..CHUNK * 4], "{:?}", strategy);
+        }
+
+        // 超出文件末尾的部分不映射
+        let source = std::fs::File::open(file.path()).unwrap();
+        assert_eq!(map_window(&source, CHUNK as u64 * 5, CHUNK).unwrap().as_ref(), &content[CHUNK * 5..]);
+        assert!(map_window(&source, CHUNK as u64 * 6, CHUNK).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_recycles_buffers_without_copy() {
+        let (source, _) = source(CHUNK * 10, Vec::new());
+        let mut tasks = JoinSet::new();
+        let mut pipeline = ChunkPipeline::spawn(&mut tasks, source, CHUNK, 1, 0);
+
+        // 请求体与 buffer 共享内存，释放后归还的还是原来的 buffer
+        let mut buffers = HashSet::new();
+        let mut offset = 0;
+        while let Some(chunk) = pipeline.next(offset).await.unwrap() {
+            let body = chunk.body();
+            assert_eq!(body.as_ptr(), chunk.data().as_ptr());
+            buffers.insert(body.as_ptr() as usize);
+            offset += body.len() as u64;
+            drop(body);
+            pipeline.recycle(chunk);
+        }
+        assert_eq!(offset, CHUNK as u64 * 10);
+        assert_eq!(buffers.len(), 2);
+
+        // 请求体仍被持有时重新分配，不影响持有的数据
+        let buffer = Bytes::from(vec![7u8; CHUNK]);
+        let body = buffer.slice(..2);
+        let reclaimed = reclaim(buffer, CHUNK);
+        assert_eq!(reclaimed.len(), CHUNK);
+        assert_ne!(reclaimed.as_ptr(), body.as_ptr());
+        assert_eq!(body.as_ref(), &[7, 7]);
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+use crate::core::error::UploadError;
+
+/// 重试延迟的抖动幅度，±20%
+const BACKOFF_JITTER: f64 = 0.2;
+
+/// 失败时服务端是否可能已经保存了数据
+///
+/// 连接建立失败时请求一定没有发出；超时或连接在响应前断开时，服务端可能已经保存了部分或全部数据，
+/// 直接从原来的偏移重发会得到 409，宽松的服务端甚至会重复追加
+pub fn is_ambiguous(err: &UploadError) -> bool {
+    match err {
+        UploadError::NetworkError(err) => !err.is_connect() && !err.is_builder(),
+        _ => false,
+    }
+}
+
+/// 请求是否可能被代理拦截：返回 405，或者连接被直接重置
+pub fn is_method_blocked(err: &UploadError) -> bool {
+    match err {
+        UploadError::Http { status: 405, .. } => true,
+        UploadError::NetworkError(err) => {
+            let mut source = std::error::Error::source(err);
+            while let Some(err) = source {
+                if err.downcast_ref::<std::io::Error>()
+                    .is_some_and(|err| err.kind() == std::io::ErrorKind::ConnectionReset) {
+                    return true;
+                }
+                source = err.source();
+            }
+            false
+        }
+        _ => false,
+    }
+}
+
+/// 第 attempt 次重试（从 1 开始）前的等待时间：从 base 开始每次加倍，不超过 max，再加上抖动
+/// jitter 是 [0, 1) 之间的随机数，同时失败的多个 worker 因此错开重试的时间
+pub fn backoff(base: Duration, max: Duration, attempt: u32, jitter: f64) -> Duration {
+    let delay = base.saturating_mul(1 << attempt.saturating_sub(1).min(16)).min(max);
+    delay.mul_f64(1.0 + BACKOFF_JITTER * (jitter * 2.0 - 1.0))
+}
+
+/// 从 offset 发送 len 字节的结果不确定时，根据服务端当前的偏移计算已经保存的字节数
+/// 服务端没有前进时返回 None，需要按普通失败重试
+pub fn committed(offset: u64, len: u64, server_offset: u64) -> Option<u64> {
+    (server_offset > offset).then(|| (server_offset - offset).min(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_schedule() {
+        let (base, max) = (Duration::from_secs(1), Duration::from_secs(10));
+        let schedule: Vec<_> = (1..=6).map(|attempt| backoff(base, max, attempt, 0.5)).collect();
+        assert_eq!(schedule, [1, 2, 4, 8, 10, 10].map(Duration::from_secs));
+
+        // 抖动不超过 ±20%
+        assert_eq!(backoff(base, max, 3, 0.0), Duration::from_millis(3200));
+        assert_eq!(backoff(base, max, 3, 0.75), Duration::from_millis(4400));
+        assert!(backoff(base, max, 40, 0.999_999) <= Duration::from_secs(12));
+    }
+
+    #[test]
+    fn test_committed() {
+        assert_eq!(committed(100, 50, 100), None);
+        assert_eq!(committed(100, 50, 90), None);
+        assert_eq!(committed(100, 50, 120), Some(20));
+        assert_eq!(committed(100, 50, 150), Some(50));
+        assert_eq!(committed(100, 50, 200), Some(50));
+        assert!(!is_ambiguous(&UploadError::Http { status: 500, body: String::new() }));
+        assert!(is_method_blocked(&UploadError::Http { status: 405, body: String::new() }));
+        assert!(!is_method_blocked(&UploadError::Http { status: 409, body: String::new() }));
+    }
+}
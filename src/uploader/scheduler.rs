@@ -0,0 +1,33 @@
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// 运行循环的句柄，可以等待循环和所有 worker 完全停止
+#[derive(Debug, Clone)]
+pub struct SchedulerHandle {
+    /// 循环退出时取消
+    loop_stopped: CancellationToken,
+    tasks: TaskTracker,
+}
+
+impl SchedulerHandle {
+    pub(crate) fn new(loop_stopped: CancellationToken, tasks: TaskTracker) -> Self {
+        Self { loop_stopped, tasks }
+    }
+
+    /// 循环退出并且所有 worker 都已结束后返回
+    /// 只有调用 UploadManager::shutdown 后才会返回
+    pub async fn stopped(&self) {
+        self.loop_stopped.cancelled().await;
+        self.tasks.wait().await;
+    }
+
+    /// 循环是否还在调度新的 upload
+    pub fn is_running(&self) -> bool {
+        !self.loop_stopped.is_cancelled()
+    }
+
+    /// 还没有结束的 worker 和内部任务的数量，不包括循环本身
+    pub fn draining(&self) -> usize {
+        self.tasks.len() - self.is_running() as usize
+    }
+}
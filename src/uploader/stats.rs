@@ -0,0 +1,144 @@
+//! 所有 upload 的汇总统计
+//!
+//! 数量和字节数来自 `list_upload_statuses`，正在上传的 upload 使用实时进度；
+//! 速度按 `SPEED_WINDOW` 内服务端新确认的字节数计算，避免底部状态栏的数字频繁跳动。
+//! 单个 upload 的速度只在一块用时超过一秒时更新，块较小时一直是 0，所以不使用它们的和。
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+use crate::core::event::TransferStats;
+use crate::core::speed::Speed;
+use crate::core::upload::UploadStatus;
+use crate::uploader::status::UploadStatusInfo;
+
+/// 平滑速度的时间范围
+pub const SPEED_WINDOW: Duration = Duration::from_secs(5);
+
+/// 最近的进度采样
+#[derive(Debug, Default)]
+pub(crate) struct SpeedWindow {
+    /// 上一次采样时正在上传的 upload 的进度
+    last: HashMap<String, u64>,
+
+    /// 采样的时间和到那时为止新确认的字节数之和
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl SpeedWindow {
+    /// 记录正在上传和刚结束的 upload 新确认的字节数，返回窗口内的平均速度
+    /// 进度减少（例如重新开始）不计入；没有在上传时清空窗口并返回 0
+    pub fn record(&mut self, now: Instant, statuses: &[UploadStatusInfo]) -> Speed {
+        let confirmed: u64 = statuses.iter()
+            .filter_map(|status| {
+                let last = self.last.get(&status.id)?;
+                Some(status.bytes_transferred.saturating_sub(*last))
+            })
+            .sum();
+        self.last = statuses.iter()
+            .filter(|status| status.status == UploadStatus::Active)
+            .map(|status| (status.id.clone(), status.bytes_transferred))
+            .collect();
+        if self.last.is_empty() {
+            self.samples.clear();
+            return Speed::ZERO;
+        }
+
+        let total = self.samples.back().map_or(0, |(_, total)| total + confirmed);
+        self.samples.push_back((now, total));
+        // 保留窗口之前的最后一个采样作为起点
+        while self.samples.get(1).is_some_and(|(at, _)| now.duration_since(*at) >= SPEED_WINDOW) {
+            self.samples.pop_front();
+        }
+        let (start, base) = self.samples[0];
+        Speed::from_transfer(total - base, now.duration_since(start))
+    }
+}
+
+/// 汇总 upload 的状态，进度记录到 window 中计算平滑后的速度
+pub(crate) fn summarize(statuses: &[UploadStatusInfo], window: &mut SpeedWindow, now: Instant) -> TransferStats {
+    let mut stats = TransferStats::default();
+    let mut remaining = 0;
+    let mut unknown_length = false;
+    for status in statuses {
+        match status.status {
+            UploadStatus::Active => stats.active += 1,
+            UploadStatus::Pending | UploadStatus::WaitingRetry => stats.pending += 1,
+            UploadStatus::Failed => stats.failed += 1,
+            UploadStatus::Completed => stats.completed += 1,
+            UploadStatus::Cancelled => continue,
+            UploadStatus::Paused | UploadStatus::Blocked => {}
+        }
+        stats.total_bytes += status.total_bytes;
+        stats.transferred_bytes += status.bytes_transferred;
+
+        // 只有不需要操作就会继续的 upload 计入剩余时间
+        if matches!(status.status, UploadStatus::Active | UploadStatus::Pending | UploadStatus::WaitingRetry) {
+            remaining += status.total_bytes.saturating_sub(status.bytes_transferred);
+            unknown_length |= status.length_deferred;
+        }
+    }
+
+    stats.aggregate_speed = window.record(now, statuses);
+    if !unknown_length && !stats.aggregate_speed.is_zero() {
+        stats.eta_seconds = Some((remaining as f64 / stats.aggregate_speed.bytes_per_sec()).ceil() as u64);
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::upload::Upload;
+
+    fn status(file: &tempfile::NamedTempFile, status: UploadStatus, transferred: u64) -> UploadStatusInfo {
+        let mut upload = Upload::new(file.path().to_path_buf(), 100).unwrap();
+        upload.status = status;
+        upload.progress.bytes_transferred = transferred;
+        UploadStatusInfo::from(&upload)
+    }
+
+    #[test]
+    fn test_summarize_and_smooth_speed() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), vec![0u8; 1000]).unwrap();
+        let mut statuses = vec![
+            status(&file, UploadStatus::Active, 0),
+            status(&file, UploadStatus::Active, 0),
+            status(&file, UploadStatus::Pending, 0),
+            status(&file, UploadStatus::Paused, 500),
+            status(&file, UploadStatus::Completed, 1000),
+            status(&file, UploadStatus::Cancelled, 200),
+        ];
+
+        let mut window = SpeedWindow::default();
+        let start = Instant::now();
+        let stats = summarize(&statuses, &mut window, start);
+        assert_eq!((stats.active, stats.pending, stats.failed, stats.completed), (2, 1, 0, 1));
+        assert_eq!((stats.total_bytes, stats.transferred_bytes), (5000, 1500));
+        assert!(stats.aggregate_speed.is_zero());
+        assert_eq!(stats.eta_seconds, None);
+
+        // 两秒内确认了 800 字节
+        statuses[0].bytes_transferred = 300;
+        statuses[1].bytes_transferred = 500;
+        let stats = summarize(&statuses, &mut window, start + Duration::from_secs(2));
+        assert_eq!(stats.aggregate_speed, Speed::from(400));
+        // 暂停的不计入剩余：700 + 500 + 1000
+        assert_eq!(stats.eta_seconds, Some(6));
+
+        // 刚完成的 upload 最后确认的字节也计入，超出窗口的采样被丢弃
+        statuses[0].bytes_transferred = 1000;
+        statuses[0].status = UploadStatus::Completed;
+        let stats = summarize(&statuses, &mut window, start + Duration::from_secs(4));
+        assert_eq!(stats.aggregate_speed, Speed::from(375));
+        statuses[1].bytes_transferred = 1000;
+        let stats = summarize(&statuses, &mut window, start + Duration::from_secs(8));
+        assert_eq!(stats.aggregate_speed, Speed::from(200));
+        assert_eq!(stats.eta_seconds, Some(5));
+
+        statuses[1].status = UploadStatus::Paused;
+        let idle = summarize(&statuses, &mut window, start + Duration::from_secs(9));
+        assert!(idle.aggregate_speed.is_zero());
+        assert_eq!(idle.eta_seconds, None);
+    }
+}
@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use crate::core::capabilities::Capabilities;
+use crate::core::clock::Clock;
+use crate::core::error::ErrorDto;
+use crate::core::event::{CorrectionReason, EventBus, UploadEvent};
+use crate::core::speed::Speed;
+use crate::core::upload::{ActiveTime, BlockCause, Upload, UploadStatus};
+
+/// 非活动 upload 的缓存时间，与进度通知的节流间隔一致
+pub const STATUS_CACHE_TTL: Duration = Duration::from_millis(100);
+
+/// worker 实时更新的进度，读取时不需要获取状态锁
+#[derive(Debug)]
+pub struct LiveProgress {
+    bytes_transferred: AtomicU64,
+    /// Speed 的 f64 位表示
+    speed: AtomicU64,
+    retry_count: AtomicU32,
+    status: Mutex<(UploadStatus, ActiveTime)>,
+
+    /// 最近一次同步的完整 upload，进度和状态以上面的字段为准
+    upload: Mutex<Upload>,
+}
+
+impl LiveProgress {
+    pub fn new(upload: &Upload) -> Self {
+        Self {
+            bytes_transferred: AtomicU64::new(upload.progress.bytes_transferred),
+            speed: AtomicU64::new(upload.progress.speed.bytes_per_sec().to_bits()),
+            retry_count: AtomicU32::new(upload.retry_count),
+            status: Mutex::new((upload.status, upload.active_time)),
+            upload: Mutex::new(upload.clone()),
+        }
+    }
+
+    /// 从 upload 同步最新的进度和状态
+    pub fn sync(&self, upload: &Upload) {
+        self.bytes_transferred.store(upload.progress.bytes_transferred, Ordering::Relaxed);
+        self.speed.store(upload.progress.speed.bytes_per_sec().to_bits(), Ordering::Relaxed);
+        self.retry_count.store(upload.retry_count, Ordering::Relaxed);
+        *self.status.lock().unwrap() = (upload.status, upload.active_time);
+        self.upload.lock().unwrap().clone_from(upload);
+    }
+
+    /// 带有最新进度和状态的 upload
+    pub fn upload(&self) -> Upload {
+        let mut upload = self.upload.lock().unwrap().clone();
+        upload.progress.bytes_transferred = self.bytes_transferred.load(Ordering::Relaxed);
+        upload.progress.speed = Speed::new(f64::from_bits(self.speed.load(Ordering::Relaxed)));
+        upload.retry_count = self.retry_count.load(Ordering::Relaxed);
+        (upload.status, upload.active_time) = *self.status.lock().unwrap();
+        upload
+    }
+}
+
+/// worker 每次服务端确认数据后发给 manager 的进度
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProgressUpdate {
+    pub id: String,
+
+    /// 这次确认的字节数
+    pub delta: u64,
+
+    /// 服务端确认的偏移
+    pub offset: u64,
+
+    /// 长度未确定时为 0
+    pub total_bytes: u64,
+    pub speed: Speed,
+    pub at: DateTime<Utc>,
+}
+
+/// 按 upload 限制 Progress 事件的频率
+pub(crate) struct ProgressThrottle {
+    interval: Duration,
+
+    /// 每个 upload 上一次发出事件的时间
+    last: HashMap<String, tokio::time::Instant>,
+}
+
+impl ProgressThrottle {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, last: HashMap::new() }
+    }
+
+    /// 距离上一次发出已经超过间隔，或者是最后一块的进度时返回 true
+    pub fn admit(&mut self, update: &ProgressUpdate, now: tokio::time::Instant) -> bool {
+        if update.total_bytes > 0 && update.offset >= update.total_bytes {
+            self.last.remove(&update.id);
+            return true;
+        }
+        match self.last.get(&update.id) {
+            Some(last) if now.duration_since(*last) < self.interval => false,
+            _ => {
+                self.last.insert(update.id.clone(), now);
+                true
+            }
+        }
+    }
+}
+
+/// 未经处理的进度，用于调试
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RawProgress {
+    pub bytes_transferred: u64,
+}
+
+/// 对外返回的 upload 状态
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UploadStatusInfo {
+    pub id: String,
+    pub status: UploadStatus,
+
+    /// 显示的进度，只在 ProgressCorrected 事件时减少
+    pub bytes_transferred: u64,
+    pub raw: RawProgress,
+
+    /// 长度未确定时为 0
+    pub total_bytes: u64,
+
+    /// 0 到 100 的进度；空文件完成时为 100，长度未确定时为 0
+    pub percent: u8,
+
+    /// 长度还没有确定，前端显示不确定的进度条
+    pub length_deferred: bool,
+    pub speed: Speed,
+    pub blocked_reason: Option<String>,
+    pub blocked_cause: Option<BlockCause>,
+
+    /// 失败的原因
+    pub last_error: Option<ErrorDto>,
+
+    /// 累计失败的次数，与 max_retries 一起显示为“第几次尝试”
+    pub retry_count: u32,
+
+    /// 这个 upload 的重试次数，为空时使用 TusConfig::max_retries
+    pub max_retries: Option<u32>,
+
+    /// 服务端配置名称，可以按它分组
+    pub endpoint: Option<String>,
+
+    /// 所属的分组
+    pub group: Option<String>,
+
+    /// 拆分上传时部分的序号
+    pub part_index: Option<u32>,
+
+    /// 实际发送数据的时间，不包括排队、暂停和重试等待
+    pub active_duration_ms: u64,
+
+    /// 从创建到结束（未结束时到现在）的时间
+    pub wall_duration_ms: u64,
+
+    /// 上传方式支持的功能，不能继续的 upload 不显示暂停
+    pub capabilities: Capabilities,
+
+    /// 完成后的摘要
+    pub digest: Option<String>,
+
+    /// 服务端的资源已经不存在
+    pub remote_missing: bool,
+}
+
+impl From<&Upload> for UploadStatusInfo {
+    fn from(upload: &Upload) -> Self {
+        Self::at(upload, Utc::now())
+    }
+}
+
+impl UploadStatusInfo {
+    /// 在给定时间生成状态，用时统计到 now 为止
+    pub fn at(upload: &Upload, now: DateTime<Utc>) -> Self {
+        let mut info = Self {
+            id: upload.id.clone(),
+            status: upload.status,
+            bytes_transferred: upload.progress.bytes_transferred,
+            raw: RawProgress { bytes_transferred: upload.progress.bytes_transferred },
+            total_bytes: upload.total_bytes,
+            percent: 0,
+            length_deferred: upload.length_deferred,
+            speed: upload.progress.speed,
+            blocked_reason: upload.blocked_reason.clone(),
+            blocked_cause: upload.blocked_cause,
+            last_error: upload.last_error.clone(),
+            retry_count: upload.retry_count,
+            max_retries: upload.max_retries_override,
+            endpoint: upload.endpoint.clone(),
+            group: upload.group.clone(),
+            part_index: upload.part.map(|part| part.index),
+            active_duration_ms: upload.active_duration_at(now).as_millis() as u64,
+            wall_duration_ms: upload.wall_duration_at(now).as_millis() as u64,
+            capabilities: Capabilities::for_upload(upload),
+            digest: upload.digest.as_ref().and_then(|digest| digest.digest.clone()),
+            remote_missing: upload.remote_missing,
+        };
+        info.update_percent();
+        info
+    }
+
+    /// 按状态和字节数重新计算 percent，总字节数为 0 时不做除法
+    fn update_percent(&mut self) {
+        self.percent = if self.status == UploadStatus::Completed {
+            100
+        } else if self.length_deferred || self.total_bytes == 0 {
+            0
+        } else {
+            (self.bytes_transferred.min(self.total_bytes) as u128 * 100 / self.total_bytes as u128) as u8
+        };
+    }
+}
+
+/// 分组的汇总状态
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GroupStatusInfo {
+    pub id: String,
+
+    /// 按部分的顺序排列
+    pub parts: Vec<UploadStatusInfo>,
+    pub bytes_transferred: u64,
+    pub total_bytes: u64,
+
+    /// 所有部分都已完成
+    pub completed: bool,
+
+    /// 每种状态的数量
+    pub counts: HashMap<UploadStatus, usize>,
+
+    /// 正在上传的部分的速度之和
+    pub speed: Speed,
+
+    /// 按当前速度传完剩余部分需要的秒数，没有在上传或有长度未确定的部分时为空
+    pub eta_secs: Option<u64>,
+}
+
+impl GroupStatusInfo {
+    pub fn new(id: String, mut parts: Vec<UploadStatusInfo>) -> Self {
+        parts.sort_by_key(|part| part.part_index);
+        let mut counts = HashMap::new();
+        for part in &parts {
+            *counts.entry(part.status).or_insert(0) += 1;
+        }
+        let speed = Speed::new(parts.iter()
+            .filter(|part| part.status == UploadStatus::Active)
+            .map(|part| part.speed.bytes_per_sec())
+            .sum());
+        // 取消和完成的部分不再传输
+        let remaining: u64 = parts.iter()
+            .filter(|part| !matches!(part.status, UploadStatus::Completed | UploadStatus::Cancelled))
+            .map(|part| part.total_bytes.saturating_sub(part.bytes_transferred))
+            .sum();
+        let eta_secs = match parts.iter().any(|part| part.length_deferred) || speed.is_zero() {
+            true => None,
+            false => Some((remaining as f64 / speed.bytes_per_sec()).ceil() as u64),
+        };
+        Self {
+            id,
+            bytes_transferred: parts.iter().map(|part| part.bytes_transferred).sum(),
+            total_bytes: parts.iter().map(|part| part.total_bytes).sum(),
+            completed: parts.iter().all(|part| part.status == UploadStatus::Completed),
+            counts,
+            speed,
+            eta_secs,
+            parts,
+        }
+    }
+}
+
+struct CachedStatus {
+    info: UploadStatusInfo,
+    cached_at: tokio::time::Instant,
+    created_at: DateTime<Utc>,
+
+    /// 正在上传时从实时进度刷新，不会过期
+    live: Option<Arc<LiveProgress>>,
+}
+
+/// 按 id 缓存的状态，避免频繁轮询时反复获取状态锁并复制整个 upload
+/// 状态变化时由 manager 同步失效或覆盖
+pub(crate) struct StatusCache {
+    entries: Mutex<HashMap<String, CachedStatus>>,
+
+    /// 计算缓存过期和正在上传的时长
+    clock: Arc<dyn Clock>,
+}
+
+impl StatusCache {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self { entries: Mutex::new(HashMap::new()), clock }
+    }
+
+    pub fn get(&self, id: &str) -> Option<UploadStatusInfo> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(id)?;
+
+        match &entry.live {
+            Some(live) => {
+                entry.info.bytes_transferred = live.bytes_transferred.load(Ordering::Relaxed);
+                entry.info.raw.bytes_transferred = entry.info.bytes_transferred;
+                entry.info.speed = Speed::new(f64::from_bits(live.speed.load(Ordering::Relaxed)));
+                entry.info.retry_count = live.retry_count.load(Ordering::Relaxed);
+                let (status, active_time) = *live.status.lock().unwrap();
+                let now = self.clock.now_utc();
+                entry.info.status = status;
+                entry.info.active_duration_ms = active_time.total(now).as_millis() as u64;
+                entry.info.wall_duration_ms = (now - entry.created_at).num_milliseconds().max(0) as u64;
+                entry.info.update_percent();
+            }
+            None if self.clock.now_instant() - entry.cached_at >= STATUS_CACHE_TTL => {
+                entries.remove(id);
+                return None;
+            }
+            None => {}
+        }
+
+        Some(entry.info.clone())
+    }
+
+    /// 开始上传，之后从实时进度读取
+    pub fn track(&self, upload: &Upload, live: Arc<LiveProgress>) {
+        self.entries.lock().unwrap().insert(upload.id.clone(), CachedStatus {
+            info: UploadStatusInfo::at(upload, self.clock.now_utc()),
+            cached_at: self.clock.now_instant(),
+            created_at: upload.created_at,
+            live: Some(live),
+        });
+    }
+
+    /// 缓存从状态中读取的 upload
+    pub fn store(&self, upload: &Upload) {
+        self.entries.lock().unwrap().insert(upload.id.clone(), CachedStatus {
+            info: UploadStatusInfo::at(upload, self.clock.now_utc()),
+            cached_at: self.clock.now_instant(),
+            created_at: upload.created_at,
+            live: None,
+        });
+    }
+
+    /// 正在上传的 upload，带有实时进度
+    pub fn live_upload(&self, id: &str) -> Option<Upload> {
+        self.entries.lock().unwrap().get(id)?.live.as_ref().map(|live| live.upload())
+    }
+
+    /// 所有正在上传的 upload
+    pub fn live_uploads(&self) -> Vec<Upload> {
+        self.entries.lock().unwrap().values().filter_map(|entry| entry.live.as_ref()).map(|live| live.upload()).collect()
+    }
+
+    /// 正在从实时进度读取，即已经出队还没有结束
+    pub fn is_live(&self, id: &str) -> bool {
+        self.entries.lock().unwrap().get(id).is_some_and(|entry| entry.live.is_some())
+    }
+
+    pub fn invalidate(&self, id: &str) {
+        self.entries.lock().unwrap().remove(id);
+    }
+}
+
+/// 保证对外显示的进度不会减少
+///
+/// 进度确实需要减少时由造成减少的一方调用 correct，发出 ProgressCorrected 并同时重置显示值；
+/// 其他情况下读到较小的值（例如过期的缓存）时继续显示之前的最大值
+pub struct ProgressReporter {
+    /// 每个 upload 已经显示过的最大进度
+    displayed: Mutex<HashMap<String, u64>>,
+    events: Arc<EventBus>,
+}
+
+impl ProgressReporter {
+    pub fn new(events: Arc<EventBus>) -> Self {
+        Self { displayed: Mutex::new(HashMap::new()), events }
+    }
+
+    /// 进度从 old 减少到 new
+    pub fn correct(&self, id: &str, old: u64, new: u64, reason: CorrectionReason) {
+        // 显示值的重置和事件在同一个锁内，读取方不会看到没有事件的减少
+        let mut displayed = self.displayed.lock().unwrap();
+        displayed.insert(id.to_string(), new);
+        self.events.emit(UploadEvent::ProgressCorrected { id: id.to_string(), old, new, reason });
+    }
+
+    /// 连接停滞，正在重试
+    pub fn stalled(&self, id: &str, offset: u64) {
+        self.events.emit(UploadEvent::Stalled { id: id.to_string(), offset });
+    }
+
+    /// 把状态中的进度换成显示值
+    pub fn present(&self, mut info: UploadStatusInfo) -> UploadStatusInfo {
+        let mut displayed = self.displayed.lock().unwrap();
+        let shown = displayed.entry(info.id.clone()).or_default();
+        *shown = (*shown).max(info.raw.bytes_transferred);
+        info.bytes_transferred = *shown;
+        info.update_percent();
+        info
+    }
+
+    /// upload 被删除后不再记录
+    pub fn forget(&self, id: &str) {
+        self.displayed.lock().unwrap().remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reported_progress_is_monotonic() {
+        let events = Arc::new(EventBus::default());
+        let mut receiver = events.subscribe();
+        let reporter = ProgressReporter::new(events);
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), vec![0u8; 4096]).unwrap();
+        let mut upload = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+        let id = upload.id.clone();
+        let mut report = |bytes: u64| {
+            upload.progress.bytes_transferred = bytes;
+            let info = reporter.present(UploadStatusInfo::from(&upload));
+            (info.bytes_transferred, info.raw.bytes_transferred)
+        };
+
+        assert_eq!(report(1024), (1024, 1024));
+        assert_eq!(report(3072), (3072, 3072));
+        // 没有修正事件的减少不显示
+        assert_eq!(report(2048), (3072, 2048));
+
+        // 发送失败撤回进度
+        reporter.correct(&id, 3072, 2048, CorrectionReason::ChunkRollback);
+        assert_eq!(report(2048), (2048, 2048));
+        assert_eq!(report(4096), (4096, 4096));
+
+        let event = receiver.try_recv().unwrap().event;
+        assert!(matches!(event, UploadEvent::ProgressCorrected { old: 3072, new: 2048, reason: CorrectionReason::ChunkRollback, .. }));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_progress_throttle() {
+        let mut throttle = ProgressThrottle::new(Duration::from_millis(250));
+        let start = tokio::time::Instant::now();
+        let update = |id: &str, offset: u64| ProgressUpdate {
+            id: id.to_string(),
+            delta: 100,
+            offset,
+            total_bytes: 1000,
+            speed: Speed::ZERO,
+            at: DateTime::UNIX_EPOCH,
+        };
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        assert!(throttle.admit(&update("a", 100), at(0)));
+        assert!(!throttle.admit(&update("a", 200), at(100)));
+        // 每个 upload 分别计算
+        assert!(throttle.admit(&update("b", 100), at(100)));
+        assert!(throttle.admit(&update("a", 300), at(250)));
+        assert!(!throttle.admit(&update("a", 400), at(300)));
+        // 最后一块总是发出
+        assert!(throttle.admit(&update("a", 1000), at(310)));
+    }
+}
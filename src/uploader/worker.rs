@@ -1,21 +1,255 @@
-use std::io::SeekFrom;
+use std::collections::HashSet;
+use std::future::Future;
 use std::str::FromStr;
-use reqwest::{Client, Request, Url};
+use std::sync::Arc;
+use std::time::Duration;
+use std::io::SeekFrom;
+use bytes::Bytes;
+use reqwest::{Client, Request, RequestBuilder, Response, Url};
 use reqwest::header::{HeaderName, HeaderValue};
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
 use tokio::select;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
-use crate::core::config::TusConfig;
+use crate::core::auth::AuthProvider;
+use crate::core::bandwidth::{BandwidthLease, BandwidthLimiter};
+use crate::core::checksum::{self, ChecksumAlgorithm};
+use crate::core::clock::{self, Clock};
+use crate::core::config::{CreationWithUpload, TusConfig};
+use crate::core::digest::{self, UploadDigest};
 use crate::core::error::{UploadError, UploadResult};
+use crate::core::fingerprint::SourceFingerprint;
 use crate::core::headers;
-use crate::core::upload::{Upload, UploadStatus};
+use crate::core::location;
+use crate::core::metadata;
+use crate::core::rng::Rng;
+use crate::core::skew::{self, ClockSkew};
+use crate::core::timeline::TimelineEvent;
+use crate::core::tls;
+use crate::core::upload::{CompletionVerification, Upload, UploadStatus};
+use crate::uploader::pipeline::{ChunkPipeline, FileSource, LeasedSource, ReaderLease, WindowedSource};
+use crate::core::event::CorrectionReason;
+use crate::uploader::discovery::CapabilityCache;
+use crate::uploader::retry;
+use crate::uploader::status::{LiveProgress, ProgressReporter, ProgressUpdate};
+
+/// 错误响应体最多读取的字节数
+const ERROR_BODY_LIMIT: usize = 16 * 1024;
+
+/// 读取错误响应体的超时时间
+const ERROR_BODY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 连续偏移冲突的上限，服务端每次都返回 409 时不会无限重试
+const MAX_OFFSET_CONFLICTS: u32 = 5;
+
+/// 读取失败响应的响应体
+/// 读完响应体后连接才能回到连接池被复用，同时把内容保留下来用于诊断
+pub(crate) async fn read_error_body(mut response: Response) -> UploadError {
+    let status = response.status().as_u16();
+    let mut body = Vec::new();
+
+    let _ = tokio::time::timeout(ERROR_BODY_TIMEOUT, async {
+        while let Ok(Some(chunk)) = response.chunk().await {
+            let remaining = ERROR_BODY_LIMIT - body.len();
+            body.extend_from_slice(&chunk[..chunk.len().min(remaining)]);
+            if body.len() >= ERROR_BODY_LIMIT {
+                break;
+            }
+        }
+    }).await;
+
+    UploadError::Http {
+        status,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    }
+}
+
+/// 响应是否来自 tus 服务，tus 服务的响应都带有 Tus-Resumable 或 Tus-Version
+pub(crate) fn is_tus_response(response: &Response) -> bool {
+    let headers = response.headers();
+    headers.contains_key(headers::TUS_RESUMABLE) || headers.contains_key(headers::TUS_VERSION_HEADER)
+}
+
+/// 强制门户（酒店 Wi-Fi 登录页等）会拦截请求并返回 HTML
+pub(crate) fn detect_interception(response: &Response) -> UploadResult<()> {
+    let content_type = response.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if !is_tus_response(response) && content_type.starts_with("text/html") {
+        return Err(UploadError::EndpointIntercepted {
+            url: response.url().to_string(),
+            content_type: content_type.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// 服务端使用的 tus 版本与客户端不同
+///
+/// 不支持请求中的 Tus-Resumable 时服务端返回 412，并在 Tus-Version 中列出支持的版本；
+/// 成功的响应中 Tus-Resumable 与请求的版本不同也说明双方使用了不同的协议
+pub(crate) fn detect_version_mismatch(response: &Response) -> UploadResult<()> {
+    let headers = response.headers();
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let server = if response.status() == reqwest::StatusCode::PRECONDITION_FAILED {
+        header(headers::TUS_VERSION_HEADER)
+    } else {
+        header(headers::TUS_RESUMABLE).filter(|version| *version != headers::TUS_VERSION)
+    };
+    match server {
+        Some(versions) => Err(UploadError::VersionMismatch {
+            client: headers::TUS_VERSION.to_string(),
+            server: versions.split(',').map(|version| version.trim().to_string()).collect(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// 跟随重定向后的最终响应
+pub(crate) struct Redirected {
+    pub response: Response,
+
+    /// 最终响应对应的请求地址
+    pub url: String,
+
+    /// 至少跟随了一次重定向，并且每一次都是永久重定向（301/308）
+    pub moved: bool,
+}
+
+/// 发送请求，遇到重定向时按 location_policy 校验目标，带着完整的请求头和请求体重新发送
+/// 客户端本身不跟随重定向：自动跟随会在跨域时丢掉认证头，POST 也可能被改成 GET
+pub(crate) async fn send_following(client: &Client, config: &TusConfig, mut request: Request) -> UploadResult<Redirected> {
+    let mut visited = HashSet::new();
+    let mut moved = None;
+    loop {
+        let url = request.url().to_string();
+        visited.insert(url.clone());
+        let attempt = request.try_clone()
+            .ok_or_else(|| UploadError::InvalidState("Request body cannot be replayed".into()))?;
+        let response = client.execute(attempt).await.map_err(|err| proxy_error(config, request.url(), err))?;
+        let status = response.status().as_u16();
+        if !matches!(status, 301 | 302 | 307 | 308) {
+            return Ok(Redirected { response, url, moved: moved.unwrap_or(false) });
+        }
+
+        if visited.len() > location::MAX_REDIRECTS {
+            return Err(UploadError::TooManyRedirects { limit: location::MAX_REDIRECTS });
+        }
+        let target = response.headers()
+            .get(reqwest::header::LOCATION)
+            .ok_or_else(|| UploadError::InvalidLocation(format!("redirect {} has no location", status)))?
+            .to_str()
+            .map_err(|_| UploadError::InvalidLocation("is not visible ASCII".into()))?;
+        let target = location::redirect(&url, target, config.max_location_len, config.location_policy)?;
+        if visited.contains(&target) {
+            return Err(UploadError::RedirectLoop { url: target });
+        }
+
+        moved = Some(moved.unwrap_or(true) && matches!(status, 301 | 308));
+        *request.url_mut() = Url::parse(&target).map_err(|err| UploadError::InvalidLocation(err.to_string()))?;
+    }
+}
+
+/// 经过代理的请求连接失败时返回 ProxyFailed，不会被当作服务端不可用
+fn proxy_error(config: &TusConfig, url: &Url, err: reqwest::Error) -> UploadError {
+    match (&config.proxy, UploadError::from(err)) {
+        (Some(proxy), UploadError::NetworkError(err)) if err.is_connect() && proxy.applies_to(url) => {
+            UploadError::ProxyFailed { proxy: proxy.display_url(), source: err }
+        }
+        (_, err) => err,
+    }
+}
+
+/// 创建 PATCH、DELETE 请求，开启 use_method_override 时改用 POST 并在 X-HTTP-Method-Override 中指定方法
+/// 参考 Tus 协议文档：https://tus.io/protocols/resumable-upload#x-http-method-override
+pub(crate) fn overridable(client: &Client, config: &TusConfig, method: reqwest::Method, url: &str) -> RequestBuilder {
+    if config.use_method_override {
+        client.post(url).header(headers::X_HTTP_METHOD_OVERRIDE, method.as_str())
+    } else {
+        client.request(method, url)
+    }
+}
+
+/// 删除服务端的资源，资源已经不存在时也视为成功；服务端错误按 max_retries 重试，重试前通过 clock 等待
+/// 参考 Tus 协议文档：https://tus.io/protocols/resumable-upload#termination
+pub async fn terminate(client: &Client, config: &TusConfig, location: &str, clock: &dyn Clock) -> UploadResult<()> {
+    let mut retries = 0;
+    loop {
+        let mut builder = overridable(client, config, reqwest::Method::DELETE, location)
+            .header(headers::TUS_RESUMABLE, headers::TUS_VERSION);
+        for (name, value) in &config.headers {
+            builder = builder.header(name, value);
+        }
+
+        let response = send_following(client, config, builder.build()?).await?.response;
+        let status = response.status();
+        if status.is_success() || matches!(status, reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE) {
+            return Ok(());
+        }
+        let err = read_error_body(response).await;
+        if !status.is_server_error() || retries >= config.max_retries {
+            return Err(err);
+        }
+        retries += 1;
+        clock.sleep(config.retry_delay).await;
+    }
+}
+
+/// worker 结束时的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerOutcome {
+    /// 上传完成
+    Completed,
+
+    /// 被取消，正在发送的请求会立即中断，upload 的进度是服务端最后确认的偏移
+    Cancelled,
+
+    /// 重试等待时间较长，让出并发名额，等待指定时间后重新调度
+    WaitingRetry(Duration),
+}
+
+/// HEAD 请求返回的服务端状态
+#[derive(Debug, Clone)]
+struct ServerOffset {
+    offset: u64,
+    length: Option<u64>,
+    checksum: Option<String>,
+}
 
 pub struct UploadWorker {
     pub upload: Upload,
-    client: Client,
+
+    /// manager 传入的共享客户端，没有传入时 start 按配置创建
+    client: Option<Client>,
     config: TusConfig,
     cancellation_token: CancellationToken,
+    clock_skew: Arc<ClockSkew>,
+    bandwidth: Option<BandwidthLease>,
+    live: Option<Arc<LiveProgress>>,
+    reporter: Option<Arc<ProgressReporter>>,
+    progress_tx: Option<mpsc::Sender<ProgressUpdate>>,
+    capabilities: Option<Arc<CapabilityCache>>,
+    auth: Option<Arc<dyn AuthProvider>>,
+
+    /// 服务端支持的校验算法，开始发送数据时确定
+    checksum: Option<ChecksumAlgorithm>,
+
+    /// 已经发送过 PATCH，之后失败时不再尝试 X-HTTP-Method-Override
+    patch_attempted: bool,
+
+    /// 连续遇到 423 的次数和已经等待的时间
+    lock_waits: u32,
+    lock_waited: Duration,
+
+    clock: Arc<dyn Clock>,
+    rng: Arc<Rng>,
+
+    /// 读取任务等辅助任务，start 返回前全部结束
+    helpers: JoinSet<()>,
 }
 
 impl UploadWorker {
@@ -23,90 +257,670 @@ impl UploadWorker {
         Self {
             config,
             upload,
-            client: Client::new(),
+            client: None,
             cancellation_token: token,
+            clock_skew: Arc::new(ClockSkew::new()),
+            bandwidth: None,
+            live: None,
+            reporter: None,
+            progress_tx: None,
+            capabilities: None,
+            auth: None,
+            checksum: None,
+            patch_attempted: false,
+            lock_waits: 0,
+            lock_waited: Duration::ZERO,
+            clock: clock::system(),
+            rng: Arc::new(Rng::from_entropy()),
+            helpers: JoinSet::new(),
         }
     }
 
+    /// 使用共享的客户端，复用连接池和 TLS 会话
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// 使用共享的时钟偏差估计
+    pub fn with_clock_skew(mut self, clock_skew: Arc<ClockSkew>) -> Self {
+        self.clock_skew = clock_skew;
+        self
+    }
+
+    /// 重试等待和计时使用的时钟
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// 使用共享的随机数来源
+    pub fn with_rng(mut self, rng: Arc<Rng>) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// 发送数据前向带宽限制申请额度
+    pub fn with_bandwidth(mut self, lease: BandwidthLease) -> Self {
+        self.bandwidth = Some(lease);
+        self
+    }
+
+    /// 把进度和状态同步到共享的实时进度
+    pub fn with_live_progress(mut self, live: Arc<LiveProgress>) -> Self {
+        self.live = Some(live);
+        self
+    }
+
+    /// 进度减少时通过它发出修正事件
+    pub fn with_progress_reporter(mut self, reporter: Arc<ProgressReporter>) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+
+    /// 每次服务端确认数据后发送进度，通道已满时丢弃，之后的进度带有完整的偏移
+    pub fn with_progress_channel(mut self, sender: mpsc::Sender<ProgressUpdate>) -> Self {
+        self.progress_tx = Some(sender);
+        self
+    }
+
+    /// 使用共享的服务端功能缓存，creation_with_upload 为 Auto 时据此决定
+    pub fn with_capability_cache(mut self, capabilities: Arc<CapabilityCache>) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+
+    /// 每个请求之前从它取得认证请求头，遇到 401 时刷新一次再重试
+    pub fn with_auth(mut self, auth: Arc<dyn AuthProvider>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// 以服务端的偏移为准更新进度
+    fn sync_progress(&mut self, offset: u64) {
+        let previous = self.upload.progress.bytes_transferred;
+        let offset = self.upload.known_length().map_or(offset, |length| offset.min(length));
+        if offset == previous {
+            return;
+        }
+
+        self.upload.progress.bytes_transferred = offset;
+        self.sync_live();
+        if offset < previous {
+            if let Some(reporter) = &self.reporter {
+                reporter.correct(&self.upload.id, previous, offset, CorrectionReason::ServerOffset);
+            }
+        } else {
+            // 例如创建时一起发送的数据
+            self.send_progress(offset - previous);
+        }
+    }
+
+    /// 开始后一定存在
+    fn client(&self) -> &Client {
+        self.client.as_ref().expect("client is created when the worker starts")
+    }
+
+    fn sync_live(&self) {
+        if let Some(live) = &self.live {
+            live.sync(&self.upload);
+        }
+    }
+
+    /// 通知 manager 服务端确认了 delta 字节
+    fn send_progress(&self, delta: u64) {
+        if let Some(sender) = &self.progress_tx {
+            let _ = sender.try_send(ProgressUpdate {
+                id: self.upload.id.clone(),
+                delta,
+                offset: self.upload.progress.bytes_transferred,
+                total_bytes: self.upload.known_length().unwrap_or(0),
+                speed: self.upload.progress.speed,
+                at: self.clock.now_utc(),
+            });
+        }
+    }
+
+    /// 记录响应中的 Date 头，并检查响应是否被拦截、协议版本是否一致
+    fn observe_response(&self, response: &Response) -> UploadResult<()> {
+        detect_interception(response)?;
+        detect_version_mismatch(response)?;
+        let date = response.headers().get(reqwest::header::DATE).and_then(|v| v.to_str().ok());
+        if let Some(server_time) = date.and_then(skew::parse_http_date) {
+            self.clock_skew.observe_at(server_time, self.clock.now_utc());
+        }
+        Ok(())
+    }
+
     /// 开始以及检查配置
-    pub async fn start(&mut self) -> UploadResult<()> {
+    ///
+    /// 返回错误时 upload 已经转为 Failed 并记录了错误，锁定和被拦截的错误除外
+    pub async fn start(&mut self) -> UploadResult<WorkerOutcome> {
         if !self.upload.can_start() {
             return Err(UploadError::InvalidState("Upload cannot be started in current state".into()));
         }
 
-        self.upload.transition_to(UploadStatus::Active)?;
+        let result = match self.prepare() {
+            Ok(()) => self.execute().await,
+            Err(err) => Err(err),
+        };
+
+        // 无法继续的错误记录在 upload 上，manager 只需要保存；锁定和被拦截由 manager 重新调度
+        if let Err(err) = &result {
+            if !matches!(err, UploadError::UploadLocked { .. } | UploadError::EndpointIntercepted { .. })
+                && self.upload.fail_at(err, self.clock.now_utc()).is_ok() {
+                self.sync_live();
+            }
+        }
+
+        // 任何情况下都等辅助任务退出后再返回，释放 buffer 和文件句柄，之后恢复同一个 upload 不会与它们竞争
+        self.drain_helpers().await;
+        result
+    }
+
+    fn prepare(&mut self) -> UploadResult<()> {
+        // 每次开始时按名称重新解析，恢复上传时使用最新的地址和认证信息
+        self.config = self.config.for_upload(&self.upload)?;
+        if let Some(capabilities) = &self.capabilities {
+            if let Some(endpoint) = capabilities.moved_to(&self.config.endpoint) {
+                self.config.endpoint = endpoint;
+            }
+            if capabilities.needs_method_override(&self.config.endpoint) {
+                self.config.use_method_override = true;
+            }
+        }
+        if self.client.is_none() {
+            self.client = Some(tls::client_for(&self.config)?);
+        }
+        // 没有参与全局分配的 worker 只按自己的上限发送
+        if let (None, Some(_)) = (&self.bandwidth, self.upload.bandwidth_limit) {
+            self.bandwidth = Some(Arc::new(BandwidthLimiter::new(None, false)).register(self.upload.id.clone(), 1));
+        }
+        if let Some(bandwidth) = &self.bandwidth {
+            bandwidth.set_limit(self.upload.bandwidth_limit);
+        }
 
-        if self.upload.location.is_none() {
-            self.create_upload_in_server().await?;
+        // tus 的偏移以字节计，已经发送的数据与块大小无关，剩余的数据可以换成新的块大小
+        if self.upload.chunk_size == 0 || (self.config.rechunk_on_resume && self.upload.location.is_some()) {
+            self.upload.chunk_size = self.config.chunk_size;
         }
+        Ok(())
+    }
 
+    async fn execute(&mut self) -> UploadResult<WorkerOutcome> {
+        self.upload.transition_at(UploadStatus::Active, self.clock.now_utc())?;
+        self.sync_live();
+
+        // 取消时直接丢弃 run，正在发送的请求体随之中断，不等这一块发送完
         let token = self.cancellation_token.clone();
-        select! {
-            _ = token.cancelled() => {},
-            _ = self.start_upload_chunks() => {}
+        let result = select! {
+            _ = token.cancelled() => None,
+            result = self.run() => Some(result),
+        };
+        result.unwrap_or_else(|| {
+            self.interrupted();
+            Ok(WorkerOutcome::Cancelled)
+        })
+    }
+
+    async fn run(&mut self) -> UploadResult<WorkerOutcome> {
+        if let (Some(algorithm), None) = (self.config.hash_algorithm, &self.upload.digest) {
+            self.upload.digest = Some(UploadDigest::new(algorithm));
+        }
+
+        self.verify_source().await?;
+
+        // 已经过期的资源不再确认偏移，直接重新创建
+        let now = self.clock.now_utc();
+        if self.upload.location.is_some() && self.upload.expires_at.is_some_and(|at| self.clock_skew.is_expired_at(at, now)) {
+            self.discard_remote();
+        }
+        // 之前创建的资源可能已经被服务端清理，只重新创建一次
+        let mut can_recreate = self.upload.location.is_some();
+
+        loop {
+            if self.upload.location.is_none() {
+                if let Some(offset) = self.create_upload_in_server().await? {
+                    self.sync_progress(offset);
+                    // 创建请求已经包含全部数据，不需要再确认偏移
+                    if self.upload.known_length().is_some_and(|length| offset >= length) {
+                        let server = ServerOffset { offset, length: Some(self.upload.total_bytes), checksum: None };
+                        return self.complete(&server).await;
+                    }
+                }
+            }
+
+            match self.start_upload_chunks().await {
+                Err(UploadError::Http { status: 404 | 410, .. } | UploadError::ServerError { status: 404 | 410, .. }) if can_recreate => {
+                    // 新的读取任务开始前旧的必须已经退出
+                    self.drain_helpers().await;
+                    self.discard_remote();
+                    can_recreate = false;
+                }
+                result => return result,
+            }
         }
+    }
 
+    /// 源文件与添加时不同时不再发送，新的内容接在旧的偏移后面会得到损坏的文件
+    /// 从快照读取时源文件的修改不影响发送的数据，不需要检查
+    async fn verify_source(&self) -> UploadResult<()> {
+        let (Some(expected), None) = (&self.upload.fingerprint, &self.upload.snapshot_path) else {
+            return Ok(());
+        };
+        let fingerprint = SourceFingerprint::of(&self.upload.file_path).await.map_err(|err| self.source_error(err))?;
+        if fingerprint != *expected {
+            return Err(UploadError::FileChanged { path: self.upload.file_path.display().to_string() });
+        }
         Ok(())
     }
 
+    /// 打开的文件被删除或移走后仍然可以读取，每块之前检查路径，不继续上传已经不存在的文件
+    async fn check_source(&self) -> UploadResult<()> {
+        match tokio::fs::metadata(self.upload.read_path()).await {
+            Ok(_) => Ok(()),
+            Err(err) => Err(self.source_error(err)),
+        }
+    }
+
+    /// 来源不存在或没有权限时返回 SourceMissing，其他读取错误保持原样
+    fn source_error(&self, err: std::io::Error) -> UploadError {
+        match err.kind() {
+            std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied => {
+                UploadError::SourceMissing { path: self.upload.read_path().display().to_string() }
+            }
+            _ => err.into(),
+        }
+    }
+
+    /// 进度只在服务端确认后更新，中断时它就是服务端最后确认的偏移，记录下来便于排查
+    fn interrupted(&mut self) {
+        let offset = self.upload.progress.bytes_transferred;
+        self.upload.timeline.record_at(TimelineEvent::Interrupted { offset }, self.clock.now_utc());
+        self.sync_live();
+    }
+
+    /// 服务端的资源已经过期，放弃地址和进度，之后从 0 开始重新创建
+    fn discard_remote(&mut self) {
+        let discarded = std::mem::take(&mut self.upload.progress.bytes_transferred);
+        self.upload.location = None;
+        self.upload.expires_at = None;
+        self.upload.verification = None;
+        if let Some(digest) = &mut self.upload.digest {
+            *digest = UploadDigest::new(digest.algorithm);
+        }
+        self.upload.timeline.record_at(TimelineEvent::Recreated { discarded }, self.clock.now_utc());
+        log::info!("Upload {} expired on the server, restarting from zero", self.upload.id);
+
+        self.sync_live();
+        if let Some(reporter) = &self.reporter {
+            reporter.correct(&self.upload.id, discarded, 0, CorrectionReason::Expired);
+        }
+    }
+
+    /// 记录响应中的 Upload-Expires
+    fn observe_expires(&mut self, response: &Response) {
+        let expires = response.headers()
+            .get(headers::UPLOAD_EXPIRES)
+            .and_then(|v| v.to_str().ok())
+            .and_then(skew::parse_http_date);
+        if expires.is_some() {
+            self.upload.expires_at = expires;
+        }
+    }
+
+    /// 服务端已经收到全部数据，校验后标记完成
+    async fn complete(&mut self, server: &ServerOffset) -> UploadResult<WorkerOutcome> {
+        self.catch_up_digest(server.offset.min(self.upload.total_bytes)).await?;
+        self.verify_completion(server)?;
+        if let Some(digest) = &mut self.upload.digest {
+            digest.finalize();
+        }
+        self.upload.transition_at(UploadStatus::Completed, self.clock.now_utc())?;
+        self.sync_live();
+        Ok(WorkerOutcome::Completed)
+    }
+
+    async fn drain_helpers(&mut self) {
+        self.helpers.abort_all();
+        while let Some(result) = self.helpers.join_next().await {
+            if let Err(err) = result {
+                if err.is_panic() {
+                    log::error!("Helper task of upload {} panicked: {}", self.upload.id, err);
+                }
+            }
+        }
+    }
+
     /// 执行上传
     /// 参考 Tus 文档：https://tus.io/protocols/resumable-upload#patch
-    async fn start_upload_chunks(&mut self) -> UploadResult<()> {
-        let file = File::open(&self.upload.file_path).await?;
-        let mut reader = BufReader::with_capacity(self.config.buffer_size, file);
-        let mut buffer = vec![0u8; self.config.chunk_size];
+    async fn start_upload_chunks(&mut self) -> UploadResult<WorkerOutcome> {
+        let reader = FileSource::open(self.upload.read_path(), self.config.buffer_size, self.config.read_strategy).await
+            .map_err(|err| self.source_error(err))?;
+        let lease = ReaderLease::acquire(&self.upload.id);
+        let start = self.upload.progress.bytes_transferred;
+        let (chunk_size, read_ahead) = (self.upload.chunk_size, self.config.read_ahead);
+        let helpers = &mut self.helpers;
+        let mut pipeline = match self.upload.part {
+            Some(part) => {
+                let source = LeasedSource::new(WindowedSource::new(reader, part.start, part.end), lease);
+                ChunkPipeline::spawn(helpers, source, chunk_size, read_ahead, start)
+            }
+            None => ChunkPipeline::spawn(helpers, LeasedSource::new(reader, lease), chunk_size, read_ahead, start),
+        };
 
-        let max_retries = self.config.max_retries;
-        let mut retry_count = 0;
+        let max_retries = self.upload.retry_budget(self.config.max_retries as u32);
+        self.checksum = self.negotiate_checksum().await;
+        // 校验失败说明数据在途中被修改，偏移冲突说明之前的请求其实已经成功，都单独计数，不占用网络错误的重试次数
+        let mut checksum_failures = 0;
+        let mut conflicts = 0;
+        // 上一块成功后 PATCH 响应中的偏移
+        let mut confirmed: Option<u64> = None;
+        // 服务端最后一次确认新数据的时间，出错后重新计时，错误由各自的次数限制
+        let mut last_advance = self.clock.now_instant();
+        let clock = self.clock.clone();
 
         loop {
-            let offset = self.get_upload_offset().await?;
-            if offset >= self.upload.total_bytes {
-                self.upload.transition_to(UploadStatus::Completed)?;
-                return Ok(());
+            let server = match confirmed.take() {
+                // 完成前仍然用 HEAD 做最终校验
+                Some(offset) if !self.config.head_before_each_chunk
+                    && !self.upload.known_length().is_some_and(|length| offset >= length) => {
+                    ServerOffset { offset, length: None, checksum: None }
+                }
+                _ => match self.head_upload().await {
+                    Err(UploadError::UploadLocked { .. }) => {
+                        self.wait_for_lock().await?;
+                        continue;
+                    }
+                    result => result?,
+                },
+            };
+            let offset = server.offset;
+            // 之前告诉过服务端长度，但没有收到响应
+            if let (true, Some(length)) = (self.upload.length_deferred, server.length) {
+                self.upload.resolve_length(length);
             }
-
-            reader.seek(SeekFrom::Start(offset)).await?;
-            let read_length = reader.read(&mut buffer).await?;
-            if read_length == 0 {
-                // 如果读不到了，也认为完成
-                self.upload.transition_to(UploadStatus::Completed)?;
-                return Ok(());
+            self.sync_progress(offset);
+            self.catch_up_digest(self.upload.known_length().map_or(offset, |length| offset.min(length))).await?;
+            if self.upload.known_length().is_some_and(|length| offset >= length) {
+                return self.complete(&server).await;
             }
 
-            match self.upload_chunk(&buffer[..read_length], offset).await {
-                Ok(_) => {
-                    self.upload.progress.update(read_length as u64);
+            self.check_source().await?;
+            let next = pipeline.next(offset).await.map_err(|err| self.source_error(err))?;
+            let stall_timeout = self.config.stall_timeout;
+            let result = match next {
+                Some(chunk) => {
+                    let result = until_stalled(&*clock, last_advance, stall_timeout, self.send_chunk(chunk.body(), offset)).await;
+                    if let (Ok(committed), Some(digest)) = (&result, &mut self.upload.digest) {
+                        digest.update(offset, &chunk.data()[..*committed as usize]);
+                    }
+                    pipeline.recycle(chunk);
+                    result
+                }
+                // 来源已经读完，最后一个 PATCH 不带数据，只告诉服务端最终的长度
+                None if self.upload.length_deferred => until_stalled(&*clock, last_advance, stall_timeout, self.send_length(offset)).await,
+                // 文件无法提供剩余的数据
+                None => return Err(UploadError::IncompleteUpload {
+                    expected: self.upload.total_bytes,
+                    actual: offset,
+                }),
+            };
+            if result.as_ref().is_ok_and(|committed| *committed > 0) || result.is_err() {
+                last_advance = self.clock.now_instant();
+            }
+            match result {
+                Ok(committed) => {
+                    confirmed = Some(offset + committed);
+                    let previous = self.upload.progress.bytes_transferred;
+                    self.upload.progress.advance_at(offset + committed, self.clock.now_utc());
+                    self.upload.retry_count = 0;
+                    checksum_failures = 0;
+                    conflicts = 0;
+                    self.lock_waits = 0;
+                    self.lock_waited = Duration::ZERO;
+                    self.sync_live();
+                    if self.upload.progress.bytes_transferred > previous {
+                        self.send_progress(self.upload.progress.bytes_transferred - previous);
+                    }
+                }
+                Err(UploadError::ChecksumMismatch { offset }) => {
+                    checksum_failures += 1;
+                    self.upload.timeline.record_at(TimelineEvent::ChecksumRejected { offset }, self.clock.now_utc());
+                    if checksum_failures > max_retries {
+                        return Err(UploadError::ChecksumMismatch { offset });
+                    }
+                }
+                // 之前的请求还没有结束，等它释放锁，不占用重试次数
+                Err(UploadError::UploadLocked { .. }) => self.wait_for_lock().await?,
+                Err(UploadError::OffsetConflict { offset }) => {
+                    // 下一轮用 HEAD 得到服务端的偏移，读取任务从那里重新读取
+                    conflicts += 1;
+                    self.upload.timeline.record_at(TimelineEvent::OffsetConflict { offset }, self.clock.now_utc());
+                    if conflicts > MAX_OFFSET_CONFLICTS {
+                        return Err(UploadError::OffsetConflict { offset });
+                    }
                 }
                 Err(err) => {
-                    retry_count += 1;
+                    self.upload.retry_count += 1;
+                    if let UploadError::Stalled { .. } = err {
+                        self.stalled(offset);
+                    }
 
-                    if retry_count > max_retries {
+                    // 服务端明确拒绝的请求直接失败，不等待重试
+                    if !err.is_retryable() {
+                        return Err(err.into_permanent());
+                    }
+                    if self.upload.retry_count > max_retries {
                         return Err(err);
                     }
+
+                    let (base, max) = (self.config.retry_delay, self.config.max_retry_delay);
+                    let delay = retry::backoff(base, max, self.upload.retry_count, self.rng.next_f64());
+                    if self.config.yield_slot_during_backoff && delay >= self.config.yield_backoff_threshold {
+                        self.upload.transition_at(UploadStatus::WaitingRetry, self.clock.now_utc())?;
+                        self.sync_live();
+                        return Ok(WorkerOutcome::WaitingRetry(delay));
+                    }
+                    self.idle(delay).await;
                 }
             }
         }
     }
 
-    async fn upload_chunk(&mut self, chunk: &[u8], offset: u64) -> UploadResult<()> {
+    /// 连接停滞，下一轮用 HEAD 确认偏移后重试
+    fn stalled(&mut self, offset: u64) {
+        self.upload.timeline.record_at(TimelineEvent::Stalled { offset }, self.clock.now_utc());
+        if let Some(reporter) = &self.reporter {
+            reporter.stalled(&self.upload.id, offset);
+        }
+    }
+
+    /// 原地等待，不计入发送时间；取消时 start 直接丢弃整个等待
+    async fn idle(&mut self, delay: Duration) {
+        self.upload.active_time.stop(self.clock.now_utc());
+        self.sync_live();
+        self.clock.sleep(delay).await;
+        self.upload.active_time.start(self.clock.now_utc());
+        self.sync_live();
+    }
+
+    /// 资源被锁定时按退避等待，用完 lock_wait_budget 后返回 UploadLocked
+    async fn wait_for_lock(&mut self) -> UploadResult<()> {
+        let budget = self.config.lock_wait_budget;
+        if self.lock_waited >= budget {
+            return Err(UploadError::UploadLocked { waited: self.lock_waited });
+        }
+        let delay = self.config.retry_delay
+            .saturating_mul(1 << self.lock_waits.min(16))
+            .min(budget - self.lock_waited);
+        self.lock_waits += 1;
+        self.lock_waited += delay;
+        self.idle(delay).await;
+        Ok(())
+    }
+
+    /// 中间状态与服务端的偏移不一致时（重启时服务端已经收到更多数据，或偏移回退）从文件补齐
+    async fn catch_up_digest(&mut self, offset: u64) -> UploadResult<()> {
+        let Some(mut hasher) = self.upload.digest.as_ref().and_then(|digest| digest.hasher.clone()) else {
+            return Ok(());
+        };
+        if hasher.processed() == offset {
+            return Ok(());
+        }
+
+        let path = self.upload.read_path().clone();
+        let start = self.upload.part.map_or(0, |part| part.start);
+        let hasher = tokio::task::spawn_blocking(move || {
+            digest::catch_up(&path, start, offset, &mut hasher).map(|_| hasher)
+        })
+            .await
+            .map_err(|err| UploadError::Config(format!("Failed to hash upload: {}", err)))??;
+        if let Some(digest) = &mut self.upload.digest {
+            digest.hasher = Some(hasher);
+        }
+        Ok(())
+    }
+
+    /// 发送一块，返回服务端保存的字节数
+    /// 失败原因不确定时先用 HEAD 确认服务端的偏移，服务端已经保存的部分不再重新发送
+    async fn send_chunk(&mut self, chunk: Bytes, offset: u64) -> UploadResult<u64> {
+        let sent = chunk.len() as u64;
+        let err = match self.upload_chunk(chunk, offset, None).await {
+            Ok(committed) => {
+                // 服务端只保存了一部分，剩下的从服务端的偏移重新发送
+                if committed < sent {
+                    self.upload.timeline.record_at(TimelineEvent::PartiallyCommitted { offset, sent, committed }, self.clock.now_utc());
+                }
+                return Ok(committed);
+            }
+            Err(err) if retry::is_ambiguous(&err) => err,
+            Err(err) => return Err(err),
+        };
+
+        // HEAD 也失败时按原来的错误重试，下一轮开始时会再确认偏移
+        let Ok(server) = self.head_upload().await else {
+            return Err(err);
+        };
+        match retry::committed(offset, sent, server.offset) {
+            Some(committed) => {
+                self.upload.timeline.record_at(TimelineEvent::DuplicateSendAvoided { offset, committed }, self.clock.now_utc());
+                Ok(committed)
+            }
+            None => Err(err),
+        }
+    }
+
+    /// 读到结尾时确定长度，返回 0 表示没有发送数据
+    async fn send_length(&mut self, length: u64) -> UploadResult<u64> {
+        self.upload_chunk(Bytes::new(), length, Some(length)).await?;
+        self.upload.resolve_length(length);
+        Ok(0)
+    }
+
+    /// 返回服务端保存的字节数；length 为延迟的长度确定后发送的 Upload-Length
+    /// chunk 与读取的 buffer 共享内存，请求体和重定向时的克隆都不复制数据
+    /// 第一次 PATCH 看起来被代理拦截时改用 X-HTTP-Method-Override 再试一次，通过后记住这个选择
+    async fn upload_chunk(&mut self, chunk: Bytes, offset: u64, length: Option<u64>) -> UploadResult<u64> {
+        let result = self.send_patch(chunk.clone(), offset, length).await;
+        if self.config.use_method_override || std::mem::replace(&mut self.patch_attempted, true) {
+            return result;
+        }
+        let err = match result {
+            Err(err) if retry::is_method_blocked(&err) => err,
+            result => return result,
+        };
+
+        self.config.use_method_override = true;
+        let result = self.send_patch(chunk, offset, length).await;
+        if matches!(&result, Err(err) if retry::is_method_blocked(err)) {
+            self.config.use_method_override = false;
+            return Err(err);
+        }
+        log::info!("PATCH to {} appears to be blocked, using X-HTTP-Method-Override", self.config.endpoint);
+        if let Some(capabilities) = &self.capabilities {
+            capabilities.require_method_override(&self.config.endpoint);
+        }
+        result
+    }
+
+    async fn send_patch(&mut self, chunk: Bytes, offset: u64, length: Option<u64>) -> UploadResult<u64> {
         let url = self.upload.location.as_ref()
             .ok_or_else(|| UploadError::Config("No upload URL available".into()))?;
 
-        let response = self.client
-            .patch(url)
+        if let Some(bandwidth) = &self.bandwidth {
+            bandwidth.acquire(chunk.len() as u64).await;
+        }
+
+        let mut builder = self.with_config_headers(overridable(self.client(), &self.config, reqwest::Method::PATCH, url))
             .header(headers::TUS_RESUMABLE, headers::TUS_VERSION)
             .header(headers::UPLOAD_OFFSET, offset.to_string())
-            .header(reqwest::header::CONTENT_TYPE, headers::CONTENT_TYPE)
-            .body(chunk.to_vec())
-            .send()
-            .await?;
+            .header(reqwest::header::CONTENT_TYPE, headers::CONTENT_TYPE);
+        if let Some(timeout) = self.config.transfer_timeout(chunk.len()) {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(algorithm) = self.checksum {
+            builder = builder.header(headers::UPLOAD_CHECKSUM, algorithm.header_value(&chunk));
+        }
+        if let Some(length) = length {
+            builder = builder.header(headers::UPLOAD_LENGTH, length.to_string());
+        }
+        let expected = offset + chunk.len() as u64;
+        let request = builder.body(chunk).build()?;
+        let response = self.send(request).await?.response;
+        self.observe_response(&response)?;
 
+        if response.status().as_u16() == checksum::CHECKSUM_MISMATCH_STATUS {
+            return Err(UploadError::ChecksumMismatch { offset });
+        }
+        if response.status() == reqwest::StatusCode::CONFLICT {
+            return Err(UploadError::OffsetConflict { offset });
+        }
+        if response.status() == reqwest::StatusCode::LOCKED {
+            return Err(UploadError::UploadLocked { waited: Duration::ZERO });
+        }
         if !response.status().is_success() {
-            return Err(UploadError::Config(format!("Failed to upload chunk: {}", response.status())));
+            return Err(read_error_body(response).await);
         }
+        self.observe_expires(&response);
 
-        Ok(())
+        // 以响应中的偏移为准：代理截断请求体时服务端保存的数据比发送的少
+        let server_offset = response.headers()
+            .get(headers::UPLOAD_OFFSET)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| UploadError::Config("Invalid offset in response".to_string()))?;
+        if server_offset > expected {
+            return Err(UploadError::OffsetDiverged { expected, actual: server_offset });
+        }
+        Ok(server_offset.saturating_sub(offset))
+    }
+
+    /// 添加配置中的额外请求头
+    fn with_config_headers(&self, mut builder: RequestBuilder) -> RequestBuilder {
+        for (name, value) in &self.config.headers {
+            builder = builder.header(name, value);
+        }
+        builder
+    }
+
+    /// 附加认证请求头后发送，覆盖配置中的同名请求头
+    /// 服务端返回 401 时让认证信息失效，取得新的请求头重新发送一次，仍然失败时返回这次的响应
+    async fn send(&self, mut request: Request) -> UploadResult<Redirected> {
+        let Some(auth) = &self.auth else {
+            return send_following(self.client(), &self.config, request).await;
+        };
+
+        request.headers_mut().extend(auth.headers().await?);
+        let retry = request.try_clone();
+        let redirected = send_following(self.client(), &self.config, request).await?;
+        let Some(mut retry) = retry.filter(|_| redirected.response.status() == reqwest::StatusCode::UNAUTHORIZED) else {
+            return Ok(redirected);
+        };
+
+        auth.invalidate().await;
+        retry.headers_mut().extend(auth.headers().await?);
+        send_following(self.client(), &self.config, retry).await
     }
 
     async fn build_request(&self) -> UploadResult<Request> {
@@ -124,54 +938,199 @@ impl UploadWorker {
             HeaderName::from_str(headers::TUS_RESUMABLE)?,
             HeaderValue::from_str(headers::TUS_VERSION)?
         );
-        headers.insert(
-            HeaderName::from_str(headers::UPLOAD_LENGTH)?,
-            HeaderValue::from(self.upload.total_bytes)
-        );
+        match self.upload.known_length() {
+            Some(length) => headers.insert(
+                HeaderName::from_str(headers::UPLOAD_LENGTH)?,
+                HeaderValue::from(length)
+            ),
+            None => headers.insert(
+                HeaderName::from_str(headers::UPLOAD_DEFER_LENGTH)?,
+                HeaderValue::from_static("1")
+            ),
+        };
+
+        // 之前保存的 upload 可能没有按补充的 filename 检查过大小
+        let mut sent = self.upload.server_metadata();
+        metadata::enforce_limits(&mut sent, &self.config.metadata_limits)?;
+        let upload_metadata = metadata::encode(&sent)?;
+        if !upload_metadata.is_empty() {
+            headers.insert(
+                HeaderName::from_str(headers::UPLOAD_METADATA)?,
+                HeaderValue::from_str(&upload_metadata)?
+            );
+        }
 
         Ok(request)
     }
 
     /// 再 Tus 服务上创建一个新的上传任务
+    /// 使用 creation-with-upload 时同时发送第一块，返回服务端保存的偏移
     /// 参考 Tus 协议文档：https://tus.io/protocols/resumable-upload#creation
-    async fn create_upload_in_server(&mut self) -> UploadResult<()> {
-        let request = self.build_request().await?;
-        let response = self.client.execute(request).await?;
+    async fn create_upload_in_server(&mut self) -> UploadResult<Option<u64>> {
+        if self.use_creation_with_upload().await {
+            let chunk = self.read_first_chunk().await?;
+            match self.send_creation(Some(chunk)).await {
+                // 服务端不接受带有数据的创建请求时改用普通的创建请求
+                Err(UploadError::Http { status: 413 | 415, .. }) => {
+                    if let Some(capabilities) = &self.capabilities {
+                        capabilities.withdraw(&self.config.endpoint, headers::CREATION_WITH_UPLOAD);
+                    }
+                }
+                result => return result,
+            }
+        }
+        self.send_creation(None).await
+    }
+
+    async fn use_creation_with_upload(&self) -> bool {
+        // 长度未知时第一块可能就是全部数据，统一在 PATCH 中确定长度
+        if self.upload.length_deferred {
+            return false;
+        }
+        match self.config.creation_with_upload {
+            CreationWithUpload::Always => true,
+            CreationWithUpload::Never => false,
+            CreationWithUpload::Auto => match &self.capabilities {
+                Some(capabilities) => capabilities.get(self.client(), &self.config).await
+                    .is_some_and(|server| server.supports(headers::CREATION_WITH_UPLOAD)),
+                None => false,
+            },
+        }
+    }
+
+    /// 配置的校验算法，服务端没有声明支持时返回 None
+    async fn negotiate_checksum(&self) -> Option<ChecksumAlgorithm> {
+        let algorithm = self.config.checksum_algorithm?;
+        let server = match &self.capabilities {
+            Some(capabilities) => capabilities.get(self.client(), &self.config).await,
+            None => CapabilityCache::default().get(self.client(), &self.config).await,
+        };
+        let supported = server.is_some_and(|server| {
+            server.supports(headers::CHECKSUM) && algorithm.is_supported_by(&server.checksum_algorithms)
+        });
+        if !supported {
+            log::info!("Server does not support {} checksums, sending chunks without them", algorithm.name());
+            return None;
+        }
+        Some(algorithm)
+    }
+
+    /// 读取创建时一起发送的第一块
+    async fn read_first_chunk(&self) -> UploadResult<Vec<u8>> {
+        let _lease = ReaderLease::acquire(&self.upload.id);
+        let mut file = File::open(self.upload.read_path()).await.map_err(|err| self.source_error(err))?;
+        file.seek(SeekFrom::Start(self.upload.part.map_or(0, |part| part.start))).await?;
+        let mut chunk = vec![0u8; (self.upload.chunk_size as u64).min(self.upload.total_bytes) as usize];
+        file.read_exact(&mut chunk).await?;
+        Ok(chunk)
+    }
+
+    async fn send_creation(&mut self, chunk: Option<Vec<u8>>) -> UploadResult<Option<u64>> {
+        let mut request = self.build_request().await?;
+        let sent = chunk.as_ref().map(|chunk| chunk.len() as u64);
+        if let Some(chunk) = chunk {
+            if let Some(bandwidth) = &self.bandwidth {
+                bandwidth.acquire(chunk.len() as u64).await;
+            }
+            request.headers_mut().insert(reqwest::header::CONTENT_TYPE, HeaderValue::from_static(headers::CONTENT_TYPE));
+            if let Some(timeout) = self.config.transfer_timeout(chunk.len()) {
+                *request.timeout_mut() = Some(timeout);
+            }
+            *request.body_mut() = Some(chunk.into());
+        }
+
+        let Redirected { response, url, moved } = self.send(request).await?;
+        self.observe_response(&response)?;
 
         if !response.status().is_success() {
-            return Err(UploadError::Config(format!(
-                "Task creation failed, please check the configuration; Code: {}",
-                response.status()
-            )));
+            return Err(read_error_body(response).await);
         }
 
+        // endpoint 永久移动，之后的 upload 直接使用新地址
+        if moved {
+            if let Some(capabilities) = &self.capabilities {
+                capabilities.record_move(&self.config.endpoint, &url);
+            }
+            self.config.endpoint = url.clone();
+        }
+
+        // 没有返回偏移时服务端可能忽略了数据，之后用 HEAD 确认
+        let offset = match sent {
+            Some(sent) => {
+                let offset = response.headers()
+                    .get(headers::UPLOAD_OFFSET)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse::<u64>().ok());
+                if offset.is_some_and(|offset| offset > sent) {
+                    return Err(UploadError::Config("Invalid offset in response".to_string()));
+                }
+                offset
+            }
+            None => None,
+        };
+
         // 得到资源
         let location = response
             .headers()
             .get(reqwest::header::LOCATION)
-            .and_then(|l| l.to_str().ok())
-            .ok_or_else(|| UploadError::Config("No location header in response".to_string()))?;
+            .ok_or_else(|| UploadError::Config("No location header in response".to_string()))?
+            .to_str()
+            .map_err(|_| UploadError::InvalidLocation("is not visible ASCII".into()))?;
 
+        // 校验通过后才保存，异常的地址不会写入状态文件；相对地址按重定向后的地址解析
+        let location = location::resolve(
+            &url,
+            location,
+            self.config.max_location_len,
+            self.config.location_policy,
+        )?;
         self.upload.set_location(location);
+        self.observe_expires(&response);
+
+        Ok(offset)
+    }
+
+    /// 标记完成前的最终校验：服务端偏移必须等于文件长度
+    fn verify_completion(&mut self, server: &ServerOffset) -> UploadResult<()> {
+        let expected = self.upload.total_bytes;
+        if let Some(length) = server.length {
+            if length != expected {
+                return Err(UploadError::IncompleteUpload { expected, actual: length });
+            }
+        }
+        if server.offset != expected {
+            return Err(UploadError::IncompleteUpload { expected, actual: server.offset });
+        }
+
+        self.upload.verification = Some(CompletionVerification {
+            offset: server.offset,
+            length: server.length,
+            checksum: server.checksum.clone(),
+            verified_at: self.clock.now_utc(),
+        });
 
         Ok(())
     }
 
-    /// 获取文件再服务端的偏移
+    /// 获取文件再服务端的偏移、长度等信息
     /// 参考 Tus 协议文档：https://tus.io/protocols/resumable-upload#example
-    async fn get_upload_offset(&mut self) ->UploadResult<u64> {
+    async fn head_upload(&mut self) -> UploadResult<ServerOffset> {
         let url = self.upload.location.as_ref()
             .ok_or_else(|| UploadError::Config("No upload URL available".into()))?;
 
-        let response = self.client
-            .head(url)
+        let request = self.with_config_headers(self.client().head(url))
             .header(headers::TUS_RESUMABLE, headers::TUS_VERSION)
-            .send()
-            .await?;
+            .build()?;
+        let response = self.send(request).await?.response;
+        self.observe_response(&response)?;
 
+        if response.status() == reqwest::StatusCode::LOCKED {
+            return Err(UploadError::UploadLocked { waited: Duration::ZERO });
+        }
         if !response.status().is_success() {
-            return Err(UploadError::Config(format!("Failed to get offset: {}", response.status())));
+            return Err(read_error_body(response).await);
         }
+        self.observe_expires(&response);
 
         let offset = response
             .headers()
@@ -180,40 +1139,1205 @@ impl UploadWorker {
             .and_then(|v| v.parse::<u64>().ok())
             .ok_or_else(|| UploadError::Config("Invalid offset in response".to_string()))?;
 
-        Ok(offset)
+        let length = response
+            .headers()
+            .get(headers::UPLOAD_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+
+        let checksum = response
+            .headers()
+            .get(headers::UPLOAD_CHECKSUM)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+
+        Ok(ServerOffset { offset, length, checksum })
     }
 }
 
+/// 从 since 开始超过 timeout 服务端仍然没有确认新的数据时丢弃正在发送的请求，返回 Stalled；timeout 为 0 时不限制
+async fn until_stalled<T>(
+    clock: &dyn Clock,
+    since: Instant,
+    timeout: Duration,
+    request: impl Future<Output = UploadResult<T>>,
+) -> UploadResult<T> {
+    if timeout.is_zero() {
+        return request.await;
+    }
+    let remaining = (since + timeout).saturating_duration_since(clock.now_instant());
+    select! {
+        result = request => result,
+        _ = clock.sleep(remaining) => Err(UploadError::Stalled { timeout }),
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
+    use crate::core::auth::{self, CallbackAuth, StaticAuth};
+    use crate::core::config::ReadStrategy;
+    use crate::core::location::CrossOriginPolicy;
+    use crate::core::proxy::ProxyConfig;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use crate::testing::TestClock;
+    use crate::tus_server::{RecordedRequest, TusServer};
+    use crate::uploader::status::{StatusCache, UploadStatusInfo};
 
-    fn create_upload() -> Upload {
-        let mut file_path = dirs::video_dir().unwrap();
-        file_path.push("1148739452-1-30120.mp4");
-        Upload::new(file_path, 1024 * 1024 * 5).unwrap()
+    fn create_upload(len: usize) -> (Upload, tempfile::NamedTempFile) {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let content: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        std::io::Write::write_all(&mut file, &content).unwrap();
+        let upload = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+        (upload, file)
     }
 
-    fn create_worker() -> UploadWorker {
-        let config = TusConfig::new("http://127.0.0.1:6440/api/file/tus".to_string());
-        let token = CancellationToken::new();
-        UploadWorker::new(config, create_upload(), token)
+    fn create_worker(server: &TusServer, upload: Upload) -> UploadWorker {
+        let config = TusConfig {
+            chunk_size: 1024,
+            buffer_size: 1024,
+            ..TusConfig::new(server.endpoint())
+        };
+        let client = tls::client_for(&config).unwrap();
+        UploadWorker::new(config, upload, CancellationToken::new()).with_client(client)
     }
 
     #[tokio::test]
     async fn test_create_upload_in_server() {
-        let mut worker = create_worker();
+        let server = TusServer::start().await;
+        let (upload, _file) = create_upload(4096);
+        let mut worker = create_worker(&server, upload);
+        worker.create_upload_in_server().await.unwrap();
+
+        let location = worker.upload.location.clone().unwrap();
+        assert_eq!(server.upload(&location).unwrap().length, Some(4096));
+    }
+
+    #[tokio::test]
+    async fn test_create_sends_metadata() {
+        let server = TusServer::start().await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("毕业典礼 录像.mp4");
+        std::fs::write(&path, [1u8; 16]).unwrap();
+        let mut upload = Upload::new(path, 1024).unwrap();
+        upload.metadata.insert("album".into(), "二〇二五".into());
+        upload.metadata.insert("private".into(), String::new());
+        let mut worker = create_worker(&server, upload);
+        worker.create_upload_in_server().await.unwrap();
+
+        let location = worker.upload.location.clone().unwrap();
+        let header = server.upload(&location).unwrap().metadata.unwrap();
+        assert_eq!(metadata::decode(&header).unwrap(), HashMap::from([
+            ("filename".to_string(), "毕业典礼 录像.mp4".to_string()),
+            ("album".to_string(), "二〇二五".to_string()),
+            ("private".to_string(), String::new()),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_terminate_retries_server_errors() {
+        let server = TusServer::start().await;
+        server.enable_termination();
+        let config = TusConfig {
+            max_retries: 2,
+            retry_delay: Duration::from_millis(10),
+            ..TusConfig::new(server.endpoint())
+        };
+        let client = Client::new();
+        let (upload, _file) = create_upload(16);
+        let mut worker = create_worker(&server, upload);
+        worker.create_upload_in_server().await.unwrap();
+        let location = worker.upload.location.clone().unwrap();
+
+        server.fail_delete(2, 503);
+        terminate(&client, &config, &location, clock::system().as_ref()).await.unwrap();
+        assert!(server.upload(&location).is_none());
+        assert_eq!(server.count_requests(reqwest::Method::DELETE), 3);
+
+        // 已经不存在的资源视为成功
+        terminate(&client, &config, &location, clock::system().as_ref()).await.unwrap();
+
+        // 超过重试次数或者客户端错误时返回错误
+        server.fail_delete(3, 503);
+        let err = terminate(&client, &config, &location, clock::system().as_ref()).await.unwrap_err();
+        assert!(matches!(err, UploadError::Http { status: 503, .. }));
+        server.fail_delete(1, 403);
+        let err = terminate(&client, &config, &location, clock::system().as_ref()).await.unwrap_err();
+        assert!(matches!(err, UploadError::Http { status: 403, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_chunk_checksums() {
+        let server = TusServer::start().await;
+        server.enable_checksum(&["md5", "sha1"]);
+        server.corrupt_patch(2);
+        server.corrupt_patch(3);
+        let (upload, file) = create_upload(4096);
+        let config = TusConfig {
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_retries: 2,
+            retry_delay: Duration::from_millis(10),
+            checksum_algorithm: Some(ChecksumAlgorithm::Sha1),
+            ..TusConfig::new(server.endpoint())
+        };
+        let mut worker = UploadWorker::new(config.clone(), upload, CancellationToken::new());
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+
+        // 被修改的块重新发送，不计入网络错误的重试
+        let location = worker.upload.location.clone().unwrap();
+        assert_eq!(server.upload(&location).unwrap().data, std::fs::read(file.path()).unwrap());
+        assert_eq!(server.patch_count(), 6);
+        let rejected: Vec<_> = worker.upload.timeline.entries()
+            .filter(|entry| matches!(entry.event, TimelineEvent::ChecksumRejected { offset: 1024 }))
+            .collect();
+        assert_eq!(rejected.len(), 2);
+        assert!(server.requests().iter()
+            .filter(|request| request.method == reqwest::Method::PATCH)
+            .all(|request| request.headers[headers::UPLOAD_CHECKSUM].to_str().unwrap().starts_with("sha1 ")));
+
+        // 超过 max_retries 次时失败
+        for nth in 7..=9 {
+            server.corrupt_patch(nth);
+        }
+        let (upload, _file) = create_upload(2048);
+        let mut worker = UploadWorker::new(config.clone(), upload, CancellationToken::new());
+        let err = worker.start().await.unwrap_err();
+        assert!(matches!(err, UploadError::ChecksumMismatch { offset: 0 }));
+
+        // 服务端没有声明的算法不发送
+        let (upload, _file) = create_upload(1024);
+        let config = TusConfig { checksum_algorithm: Some(ChecksumAlgorithm::Crc32), ..config };
+        let mut worker = UploadWorker::new(config, upload, CancellationToken::new());
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        let last = server.requests().into_iter().rev().find(|request| request.method == reqwest::Method::PATCH).unwrap();
+        assert!(!last.headers.contains_key(headers::UPLOAD_CHECKSUM));
+    }
+
+    #[tokio::test]
+    async fn test_follows_redirects() {
+        let server = TusServer::start().await;
+        server.redirect(reqwest::Method::POST, "/files", 308, "/v2/files");
+        server.redirect(reqwest::Method::PATCH, "/files/0", 307, "/v2/files/0");
+        let capabilities = Arc::new(CapabilityCache::default());
+        let config = TusConfig {
+            chunk_size: 1024,
+            buffer_size: 1024,
+            headers: HashMap::from([("Authorization".to_string(), "Bearer secret".to_string())]),
+            ..TusConfig::new(server.endpoint())
+        };
+
+        let (upload, file) = create_upload(2048);
+        let mut worker = UploadWorker::new(config.clone(), upload, CancellationToken::new())
+            .with_capability_cache(capabilities.clone());
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        let location = worker.upload.location.clone().unwrap();
+        assert_eq!(server.upload(&location).unwrap().data, std::fs::read(file.path()).unwrap());
+
+        // 每一跳都带着完整的请求头和数据重新发送
+        let requests = server.requests();
+        let paths: Vec<_> = requests.iter()
+            .filter(|request| matches!(request.method, reqwest::Method::POST | reqwest::Method::PATCH))
+            .map(|request| (request.method.as_str(), request.path.as_str(), request.body_len))
+            .collect();
+        assert_eq!(paths, [
+            ("POST", "/files", 0),
+            ("POST", "/v2/files", 0),
+            ("PATCH", "/files/0", 0),
+            ("PATCH", "/v2/files/0", 1024),
+            ("PATCH", "/files/0", 0),
+            ("PATCH", "/v2/files/0", 1024),
+        ]);
+        assert!(requests.iter().all(|request| request.headers["Authorization"] == "Bearer secret"));
+
+        // 永久重定向之后直接使用新的 endpoint，临时重定向不记录
+        let endpoint = format!("{}/v2/files", server.endpoint().trim_end_matches("/files"));
+        assert_eq!(capabilities.moved_to(&config.endpoint), Some(endpoint));
+        let (upload, _file) = create_upload(16);
+        let mut worker = UploadWorker::new(config, upload, CancellationToken::new())
+            .with_capability_cache(capabilities);
+        worker.start().await.unwrap();
+        let posts: Vec<_> = server.requests().into_iter()
+            .filter(|request| request.method == reqwest::Method::POST)
+            .map(|request| request.path)
+            .collect();
+        assert_eq!(posts, ["/files", "/v2/files", "/v2/files"]);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_unsafe_redirects() {
+        let server = TusServer::start().await;
+        let port = server.endpoint().rsplit(':').next().unwrap().trim_end_matches("/files").to_string();
+        let other_origin = format!("http://localhost:{}/v2/files", port);
+        server.redirect(reqwest::Method::POST, "/files", 307, &other_origin);
+
+        // 默认只跟随同源的重定向
+        let (upload, _file) = create_upload(16);
+        let mut worker = create_worker(&server, upload);
+        let err = worker.start().await.unwrap_err();
+        assert!(matches!(err, UploadError::LocationOriginMismatch { .. }), "{:?}", err);
+        assert!(err.is_fatal());
+
+        let (upload, _file) = create_upload(16);
+        let config = TusConfig {
+            location_policy: location::CrossOriginPolicy::AllowAny,
+            ..TusConfig::new(server.endpoint())
+        };
+        let mut worker = UploadWorker::new(config, upload, CancellationToken::new());
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+
+        // 循环的重定向
+        server.redirect(reqwest::Method::POST, "/files", 302, "/a");
+        server.redirect(reqwest::Method::POST, "/a", 301, "/files");
+        let (upload, _file) = create_upload(16);
+        let mut worker = create_worker(&server, upload);
+        let err = worker.start().await.unwrap_err();
+        assert!(matches!(err, UploadError::RedirectLoop { ref url } if url.ends_with("/files")), "{:?}", err);
+        assert!(err.is_fatal());
+
+        // 超过最多跟随的次数
+        for hop in 0..=location::MAX_REDIRECTS {
+            let target = format!("/hop/{}", hop + 1);
+            let path = if hop == 0 { "/files".to_string() } else { format!("/hop/{}", hop) };
+            server.redirect(reqwest::Method::POST, &path, 307, &target);
+        }
+        let (upload, _file) = create_upload(16);
+        let mut worker = create_worker(&server, upload);
+        let err = worker.start().await.unwrap_err();
+        assert!(matches!(err, UploadError::TooManyRedirects { limit: location::MAX_REDIRECTS }), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_recreates_expired_uploads() {
+        let server = TusServer::start().await;
+        let expires = chrono::Utc::now() + chrono::TimeDelta::hours(24);
+        server.set_upload_expires(Some(expires));
+        let (upload, file) = create_upload(2048);
+        let content = std::fs::read(file.path()).unwrap();
+        let mut worker = create_worker(&server, upload);
+        worker.create_upload_in_server().await.unwrap();
+        assert_eq!(worker.upload.expires_at.unwrap().timestamp(), expires.timestamp());
+
+        // 服务端已经清理了资源，HEAD 返回 404 后重新创建
+        let expired = worker.upload.location.clone().unwrap();
+        server.expire_upload(&expired);
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        let location = worker.upload.location.clone().unwrap();
+        assert_ne!(location, expired);
+        assert_eq!(server.upload(&location).unwrap().data, content);
+        assert_eq!(server.count_requests(reqwest::Method::POST), 2);
+        assert!(worker.upload.timeline.entries().any(|entry| entry.event == TimelineEvent::Recreated { discarded: 0 }));
+
+        // 已知过期的资源不再发送 HEAD，已经上传的进度作废
+        server.set_upload_expires(Some(chrono::Utc::now() - chrono::TimeDelta::minutes(1)));
+        let (upload, _file) = create_upload(2048);
+        let mut worker = create_worker(&server, upload);
         worker.create_upload_in_server().await.unwrap();
-        assert!(worker.upload.location.is_some());
+        let expired = worker.upload.location.clone().unwrap();
+        worker.upload.progress.bytes_transferred = 1024;
+        server.set_upload_expires(None);
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        assert_ne!(worker.upload.location.as_deref(), Some(expired.as_str()));
+        assert!(worker.upload.expires_at.is_none());
+        assert!(worker.upload.timeline.entries().any(|entry| entry.event == TimelineEvent::Recreated { discarded: 1024 }));
+        let expired_path = expired.trim_start_matches(&server.endpoint().replace("/files", ""));
+        assert!(server.requests().iter().all(|request| request.path != expired_path));
+
+        // 刚创建的资源不存在时不再重新创建
+        let (upload, _file) = create_upload(2048);
+        let mut worker = create_worker(&server, upload);
+        server.set_location_override(Some(format!("{}/files/missing", server.endpoint().replace("/files", "")).into_bytes()));
+        let err = worker.start().await.unwrap_err();
+        assert!(matches!(err, UploadError::Http { status: 404, .. }), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_method_override() {
+        let server = TusServer::start().await;
+        server.enable_termination();
+        let override_of = |request: &RecordedRequest| request.headers.get(headers::X_HTTP_METHOD_OVERRIDE)
+            .map(|value| value.to_str().unwrap().to_string());
+
+        // 开启时 PATCH 和 DELETE 都改用 POST
+        let (upload, file) = create_upload(2048);
+        let mut worker = create_worker(&server, upload);
+        worker.config.use_method_override = true;
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        let location = worker.upload.location.clone().unwrap();
+        assert_eq!(server.upload(&location).unwrap().data, std::fs::read(file.path()).unwrap());
+        terminate(&Client::new(), &worker.config, &location, worker.clock.as_ref()).await.unwrap();
+        assert!(server.upload(&location).is_none());
+
+        let requests = server.requests();
+        let sent: Vec<_> = requests.iter()
+            .filter(|request| request.method != reqwest::Method::HEAD)
+            .map(|request| (request.method.as_str(), override_of(request)))
+            .collect();
+        assert_eq!(sent, [
+            ("POST", None),
+            ("POST", Some("PATCH".to_string())),
+            ("POST", Some("PATCH".to_string())),
+            ("POST", Some("DELETE".to_string())),
+        ]);
+
+        // 代理拦截 PATCH 时自动切换，之后的 worker 直接使用
+        let server = TusServer::start().await;
+        server.block_method(reqwest::Method::PATCH);
+        let capabilities = Arc::new(CapabilityCache::default());
+        let (upload, file) = create_upload(2048);
+        let mut worker = create_worker(&server, upload).with_capability_cache(capabilities.clone());
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        let location = worker.upload.location.clone().unwrap();
+        assert_eq!(server.upload(&location).unwrap().data, std::fs::read(file.path()).unwrap());
+        assert!(capabilities.needs_method_override(&server.endpoint()));
+        assert_eq!(server.count_requests(reqwest::Method::PATCH), 1);
+
+        let (upload, _file) = create_upload(1024);
+        let mut worker = create_worker(&server, upload).with_capability_cache(capabilities);
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        assert_eq!(server.count_requests(reqwest::Method::PATCH), 1);
+        let last = server.requests().into_iter().rev().find(|request| request.method == reqwest::Method::POST).unwrap();
+        assert_eq!(override_of(&last).as_deref(), Some("PATCH"));
+
+        // 没有缓存时只在这个 worker 中切换；改用 POST 仍然失败时保留原来的错误
+        let server = TusServer::start().await;
+        server.block_method(reqwest::Method::PATCH);
+        server.block_method(reqwest::Method::POST);
+        let (upload, _file) = create_upload(1024);
+        let mut worker = create_worker(&server, upload);
+        worker.upload.set_location(format!("{}/0", server.endpoint()));
+        let err = worker.upload_chunk(Bytes::from_static(b"data"), 0, None).await.unwrap_err();
+        assert!(matches!(err, UploadError::Http { status: 405, .. }));
+        assert!(!worker.config.use_method_override);
+    }
+
+    #[tokio::test]
+    async fn test_recovers_from_offset_conflicts() {
+        let server = TusServer::start().await;
+        let (upload, file) = create_upload(2048);
+        let mut worker = create_worker(&server, upload);
+        worker.create_upload_in_server().await.unwrap();
+        let location = worker.upload.location.clone().unwrap();
+
+        // 重发已经保存的块时服务端返回 409
+        worker.upload_chunk(Bytes::from(std::fs::read(file.path()).unwrap()).slice(..1024), 0, None).await.unwrap();
+        let err = worker.upload_chunk(Bytes::from_static(b"stale"), 0, None).await.unwrap_err();
+        assert!(matches!(err, UploadError::OffsetConflict { offset: 0 }));
+        assert_eq!(err.code(), "offset_conflict");
+
+        // 冲突后重新确认偏移继续上传，不占用重试次数
+        worker.config.max_retries = 0;
+        server.conflict_once();
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        assert_eq!(server.upload(&location).unwrap().data, std::fs::read(file.path()).unwrap());
+        assert_eq!(worker.upload.retry_count, 0);
+        assert!(worker.upload.timeline.entries().any(|entry| entry.event == TimelineEvent::OffsetConflict { offset: 1024 }));
+    }
+
+    #[tokio::test]
+    async fn test_validates_offset_after_patch() {
+        let server = TusServer::start().await;
+        server.truncate_patch(2, 300);
+        let (upload, file) = create_upload(3072);
+        let mut worker = create_worker(&server, upload);
+
+        // 第二块只保存了 300 字节，剩下的从服务端的偏移继续
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        let location = worker.upload.location.clone().unwrap();
+        assert_eq!(server.upload(&location).unwrap().data, std::fs::read(file.path()).unwrap());
+        assert_eq!(worker.upload.progress.bytes_transferred, 3072);
+        assert_eq!(worker.upload.retry_count, 0);
+        assert!(worker.upload.timeline.entries().any(|entry| {
+            entry.event == TimelineEvent::PartiallyCommitted { offset: 1024, sent: 1024, committed: 300 }
+        }));
+        let offsets: Vec<_> = server.requests().iter()
+            .filter(|request| request.method == reqwest::Method::PATCH)
+            .map(|request| request.headers[headers::UPLOAD_OFFSET].to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(offsets, ["0", "1024", "1324", "2348"]);
+
+        // 服务端的偏移超过发送的数据时不再继续
+        server.overstate_offset(5, 10);
+        let (upload, _file) = create_upload(2048);
+        let mut worker = create_worker(&server, upload);
+        let err = worker.start().await.unwrap_err();
+        assert!(matches!(err, UploadError::OffsetDiverged { expected: 1024, actual: 1034 }), "{:?}", err);
+        assert_eq!(err.code(), "offset_diverged");
+        assert_eq!(server.patch_count(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_version_mismatch() {
+        let server = TusServer::start().await;
+        server.set_versions(&["0.2.2", "0.2.1"]);
+        let (upload, _file) = create_upload(2048);
+        let mut worker = create_worker(&server, upload);
+        let err = worker.start().await.unwrap_err();
+        match &err {
+            UploadError::VersionMismatch { client, server } => {
+                assert_eq!(client, "1.0.0");
+                assert_eq!(server, &["0.2.2", "0.2.1"]);
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+        assert!(err.is_fatal());
+        assert_eq!(err.to_string(), "Server does not support tus 1.0.0, it supports 0.2.2, 0.2.1");
+        assert_eq!(server.count_requests(reqwest::Method::POST), 1);
+
+        // 成功的响应使用了其他版本
+        let response = |status: u16, name: &str, value: &str| Response::from(
+            hyper::Response::builder().status(status).header(name, value).body("").unwrap()
+        );
+        let err = detect_version_mismatch(&response(204, "Tus-Resumable", "0.2.2")).unwrap_err();
+        assert!(matches!(err, UploadError::VersionMismatch { server, .. } if server == ["0.2.2"]));
+        assert!(detect_version_mismatch(&response(204, "Tus-Resumable", "1.0.0")).is_ok());
+        assert!(detect_version_mismatch(&response(412, "Cache-Control", "no-store")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_sends_full_chunks() {
+        // 文件的一次读取最多返回 2MB，块大小超过它时需要多次读取才能装满
+        const MIB: usize = 1024 * 1024;
+        let server = TusServer::start().await;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let content: Vec<u8> = (0..MIB * 15 / 2).map(|i| (i % 251) as u8).collect();
+        std::io::Write::write_all(&mut file, &content).unwrap();
+        let upload = Upload::new(file.path().to_path_buf(), 3 * MIB).unwrap();
+        let config = TusConfig {
+            chunk_size: 3 * MIB,
+            buffer_size: MIB,
+            ..TusConfig::new(server.endpoint())
+        };
+        let mut worker = UploadWorker::new(config, upload, CancellationToken::new());
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+
+        let sizes: Vec<_> = server.requests().iter()
+            .filter(|request| request.method == reqwest::Method::PATCH)
+            .map(|request| request.body_len)
+            .collect();
+        assert_eq!(sizes, [3 * MIB, 3 * MIB, 3 * MIB / 2]);
+        let location = worker.upload.location.clone().unwrap();
+        assert_eq!(server.upload(&location).unwrap().data, content);
+    }
+
+    #[tokio::test]
+    async fn test_upload_headers_override_config() {
+        let server = TusServer::start().await;
+        server.enable_termination();
+        let (mut upload, _file) = create_upload(2048);
+        upload.headers.insert("x-tenant".to_string(), "upload".to_string());
+        upload.secret_headers.insert("Authorization".to_string(), "Bearer signed".to_string());
+        let mut worker = create_worker(&server, upload);
+        worker.config.headers.insert("X-Tenant".to_string(), "config".to_string());
+        worker.config.headers.insert("X-Client".to_string(), "app".to_string());
+        worker.config.head_before_each_chunk = true;
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+
+        // 创建、HEAD、PATCH 和 DELETE 都带上 upload 的请求头，同名时不区分大小写地覆盖配置
+        let config = worker.config.for_upload(&worker.upload).unwrap();
+        terminate(worker.client(), &config, worker.upload.location.as_ref().unwrap(), worker.clock.as_ref()).await.unwrap();
+        let requests = server.requests();
+        for method in [reqwest::Method::POST, reqwest::Method::HEAD, reqwest::Method::PATCH, reqwest::Method::DELETE] {
+            let request = requests.iter().find(|request| request.method == method).unwrap();
+            let tenant: Vec<_> = request.headers.get_all("x-tenant").iter().collect();
+            assert_eq!(tenant, ["upload"], "{}", method);
+            assert_eq!(request.headers["authorization"], "Bearer signed", "{}", method);
+            assert_eq!(request.headers["x-client"], "app", "{}", method);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_empty_file() {
+        for creation_with_upload in [CreationWithUpload::Never, CreationWithUpload::Always] {
+            let server = TusServer::start().await;
+            let (upload, _file) = create_upload(0);
+            let mut worker = create_worker(&server, upload);
+            worker.config.creation_with_upload = creation_with_upload;
+            assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+
+            // 只创建，不发送 PATCH
+            let requests = server.requests();
+            let creation = requests.iter().find(|request| request.method == reqwest::Method::POST).unwrap();
+            assert_eq!(creation.headers[headers::UPLOAD_LENGTH], "0", "{:?}", creation_with_upload);
+            assert_eq!(server.patch_count(), 0, "{:?}", creation_with_upload);
+            assert_eq!(server.upload(worker.upload.location.as_ref().unwrap()).unwrap().data, Vec::<u8>::new());
+            assert_eq!(UploadStatusInfo::from(&worker.upload).percent, 100);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_progress_channel_reports_confirmed_chunks() {
+        for creation_with_upload in [CreationWithUpload::Never, CreationWithUpload::Always] {
+            let server = TusServer::start().await;
+            let (upload, _file) = create_upload(3000);
+            let id = upload.id.clone();
+            let (sender, mut receiver) = mpsc::channel(16);
+            let mut worker = create_worker(&server, upload).with_progress_channel(sender);
+            worker.config.creation_with_upload = creation_with_upload;
+            assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+            drop(worker);
+
+            let mut updates = Vec::new();
+            while let Some(update) = receiver.recv().await {
+                assert_eq!((update.id.as_str(), update.total_bytes), (id.as_str(), 3000));
+                updates.push((update.offset, update.delta));
+            }
+            assert_eq!(updates, [(1024, 1024), (2048, 1024), (3000, 952)], "{:?}", creation_with_upload);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_mid_chunk_offset() {
+        let server = TusServer::start().await;
+        let (mut upload, file) = create_upload(4000);
+        let content = std::fs::read(file.path()).unwrap();
+
+        // 之前的会话在块中间断开：服务端保存了 1500 字节，本地记录的却是两个完整的块
+        let client = Client::new();
+        let response = client.post(server.endpoint())
+            .header(headers::TUS_RESUMABLE, headers::TUS_VERSION)
+            .header(headers::UPLOAD_LENGTH, 4000)
+            .send().await.unwrap();
+        let location = response.headers()[reqwest::header::LOCATION].to_str().unwrap().to_string();
+        client.patch(&location)
+            .header(headers::TUS_RESUMABLE, headers::TUS_VERSION)
+            .header(headers::UPLOAD_OFFSET, 0)
+            .header(reqwest::header::CONTENT_TYPE, headers::CONTENT_TYPE)
+            .body(content[..1500].to_vec())
+            .send().await.unwrap();
+        upload.set_location(location.clone());
+        upload.progress.bytes_transferred = 2048;
+        // 这次的第一个请求也只保存了一部分
+        server.drop_connection_after_commit(2, Some(300));
+
+        let live = Arc::new(LiveProgress::new(&upload));
+        let cache = StatusCache::new(clock::system());
+        cache.track(&upload, live.clone());
+        let id = upload.id.clone();
+        let mut worker = create_worker(&server, upload).with_live_progress(live);
+        worker.config.retry_delay = Duration::from_millis(10);
+        let handle = tokio::spawn(async move {
+            worker.start().await.unwrap();
+            worker
+        });
+
+        while !handle.is_finished() {
+            let info = cache.get(&id).unwrap();
+            assert!(info.bytes_transferred <= info.total_bytes, "{} > {}", info.bytes_transferred, info.total_bytes);
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let worker = handle.await.unwrap();
+        assert_eq!(worker.upload.progress.bytes_transferred, 4000);
+        assert_eq!(server.upload(&location).unwrap().data, content);
+
+        // 每次都从服务端的偏移继续，不按块对齐
+        let offsets: Vec<_> = server.requests().into_iter()
+            .filter(|request| request.method == reqwest::Method::PATCH)
+            .map(|request| request.headers[headers::UPLOAD_OFFSET].to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(offsets, ["0", "1500", "1800", "2824", "3848"]);
+    }
+
+    #[tokio::test]
+    async fn test_requests_use_configured_proxy() {
+        let server = TusServer::start().await;
+        let origin = server.endpoint().trim_end_matches("/files").to_string();
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let run = |endpoint: String, proxy: ProxyConfig| {
+            let (upload, file) = create_upload(2048);
+            let config = TusConfig {
+                chunk_size: 1024,
+                buffer_size: 1024,
+                max_retries: 0,
+                location_policy: CrossOriginPolicy::AllowAny,
+                proxy: Some(proxy),
+                ..TusConfig::new(endpoint)
+            };
+            let client = tls::client_for(&config).unwrap();
+            (UploadWorker::new(config, upload, CancellationToken::new()).with_client(client), file)
+        };
+
+        // 服务端本身作为 HTTP 代理，无法解析的主机名也能到达
+        let (mut worker, _file) = run("http://tus.invalid/files".into(), ProxyConfig::manual(&origin));
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        let creation = server.requests().into_iter().find(|request| request.method == reqwest::Method::POST).unwrap();
+        assert_eq!(creation.headers["host"], "tus.invalid");
+
+        // 代理不可用时的错误与服务端不可用区分
+        let proxy = ProxyConfig::manual(format!("http://{}", closed)).with_credentials("user", "secret");
+        let (mut worker, _file) = run(server.endpoint(), proxy.clone());
+        let err = worker.start().await.unwrap_err();
+        assert!(matches!(&err, UploadError::ProxyFailed { proxy, .. } if *proxy == format!("http://{}/", closed)), "{:?}", err);
+        assert_eq!(err.code(), "proxy");
+        assert!(!err.to_string().contains("secret"));
+
+        // no_proxy 中的主机直连
+        let bypass = ProxyConfig { no_proxy: vec!["127.0.0.1".into()], ..proxy };
+        let (mut worker, _file) = run(server.endpoint(), bypass);
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        let (mut worker, _file) = run(server.endpoint(), ProxyConfig::disabled());
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+    }
+
+    #[tokio::test]
+    async fn test_auth_refreshed_once_on_unauthorized() {
+        let server = TusServer::start().await;
+        server.fail_patch(1, 401);
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let counter = refreshes.clone();
+        let auth = CallbackAuth::new(move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { auth::header_map(&HashMap::from([("authorization".to_string(), format!("Bearer {}", n))])) }
+        });
+        let (mut upload, _file) = create_upload(2048);
+        upload.secret_headers.insert("Authorization".to_string(), "Bearer stale".to_string());
+        let mut worker = create_worker(&server, upload).with_auth(Arc::new(auth));
+        worker.config.retry_delay = Duration::from_secs(3600);
+
+        // 401 之后刷新一次直接重发，不计入重试
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        assert_eq!(worker.upload.retry_count, 0);
+        assert_eq!(refreshes.load(Ordering::SeqCst), 2);
+        let patches: Vec<_> = server.requests().into_iter()
+            .filter(|request| request.method == reqwest::Method::PATCH)
+            .map(|request| request.headers["authorization"].clone())
+            .collect();
+        assert_eq!(patches, ["Bearer 1", "Bearer 2", "Bearer 2"]);
+
+        // 刷新后仍然是 401 时按失败处理
+        server.fail_patch(4, 401);
+        server.fail_patch(5, 401);
+        let (upload, _file) = create_upload(2048);
+        let mut worker = create_worker(&server, upload).with_auth(Arc::new(StaticAuth::bearer("fixed").unwrap()));
+        let err = worker.start().await.unwrap_err();
+        assert!(matches!(err, UploadError::ServerError { status: 401, .. }), "{:?}", err);
+        assert_eq!(server.patch_count(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_read_strategies_send_identical_bytes() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let content: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        std::io::Write::write_all(&mut file, &content).unwrap();
+
+        let mut sent = Vec::new();
+        for read_strategy in [ReadStrategy::Buffered, ReadStrategy::Mmap] {
+            let server = TusServer::start().await;
+            server.enable_checksum(&["sha1"]);
+            let upload = Upload::new(file.path().to_path_buf(), 4096).unwrap();
+            let config = TusConfig {
+                chunk_size: 4096,
+                buffer_size: 1024,
+                checksum_algorithm: Some(ChecksumAlgorithm::Sha1),
+                read_strategy,
+                ..TusConfig::new(server.endpoint())
+            };
+            let mut worker = UploadWorker::new(config, upload, CancellationToken::new());
+            assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+            let location = worker.upload.location.clone().unwrap();
+            assert_eq!(server.upload(&location).unwrap().data, content);
+
+            // 每个 PATCH 的偏移、长度和校验和都相同
+            let patches: Vec<_> = server.requests().into_iter()
+                .filter(|request| request.method == reqwest::Method::PATCH)
+                .map(|request| (
+                    request.headers.get(headers::UPLOAD_OFFSET).cloned(),
+                    request.body_len,
+                    request.headers.get(headers::UPLOAD_CHECKSUM).cloned(),
+                ))
+                .collect();
+            sent.push(patches);
+        }
+        assert_eq!(sent[0].len(), 3);
+        assert_eq!(sent[0], sent[1]);
+    }
+
+    #[tokio::test]
+    async fn test_deferred_length() {
+        let server = TusServer::start().await;
+        let (_, file) = create_upload(2500);
+        let content = std::fs::read(file.path()).unwrap();
+        let upload = Upload::new_deferred(file.path().to_path_buf(), 1024).unwrap();
+        assert_eq!(upload.known_length(), None);
+        let status = UploadStatusInfo::from(&upload);
+        assert!(status.length_deferred);
+        assert_eq!(status.total_bytes, 0);
+
+        let mut worker = create_worker(&server, upload);
+        worker.config.creation_with_upload = CreationWithUpload::Always;
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        assert!(!worker.upload.length_deferred);
+        assert_eq!(worker.upload.total_bytes, 2500);
+        assert_eq!(worker.upload.progress.bytes_transferred, 2500);
+
+        let location = worker.upload.location.clone().unwrap();
+        let stored = server.upload(&location).unwrap();
+        assert_eq!(stored.length, Some(2500));
+        assert_eq!(stored.data, content);
+
+        // 创建时不带长度和数据，只有最后一个不带数据的 PATCH 带有长度
+        let requests = server.requests();
+        let post = requests.iter().find(|request| request.method == reqwest::Method::POST).unwrap();
+        assert_eq!(post.headers.get("Upload-Defer-Length").unwrap(), "1");
+        assert!(post.headers.get("Upload-Length").is_none());
+        assert_eq!(post.body_len, 0);
+        let patches: Vec<_> = requests.iter().filter(|request| request.method == reqwest::Method::PATCH).collect();
+        assert_eq!(patches.len(), 4);
+        assert!(patches[..3].iter().all(|patch| patch.headers.get("Upload-Length").is_none()));
+        assert_eq!(patches[3].headers.get("Upload-Length").unwrap(), "2500");
+        assert_eq!(patches[3].body_len, 0);
     }
 
     #[tokio::test]
     async fn test_upload() {
-        let mut worker = create_worker();
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(100)));
+        let (upload, _file) = create_upload(4096);
+        let mut worker = create_worker(&server, upload);
         let token = worker.cancellation_token.clone();
-        tokio::spawn(async move {
-            let _ = worker.start().await.unwrap();
+        let handle = tokio::spawn(async move {
+            worker.start().await.unwrap();
+            worker
         });
         tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
         token.cancel();
+
+        let worker = handle.await.unwrap();
+        assert!(!worker.upload.is_finished());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_backoff_uses_clock() {
+        let server = TusServer::start().await;
+        server.fail_patch(1, 503);
+        server.fail_patch(2, 503);
+        let (upload, file) = create_upload(2048);
+        let config = TusConfig {
+            chunk_size: 1024,
+            buffer_size: 1024,
+            max_retries: 3,
+            retry_delay: Duration::from_secs(30),
+            connect_timeout: Duration::ZERO,
+            request_timeout: Duration::ZERO,
+            stall_timeout: Duration::ZERO,
+            ..TusConfig::new(server.endpoint())
+        };
+        let clock = Arc::new(TestClock::new());
+        let mut worker = UploadWorker::new(config, upload, CancellationToken::new()).with_clock(clock.clone());
+
+        // 两次退避都由时钟推进，不需要真的等待
+        let (started, started_utc) = (std::time::Instant::now(), clock.now_utc());
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(clock.elapsed() >= Duration::from_secs(60));
+        let location = worker.upload.location.clone().unwrap();
+        assert_eq!(server.upload(&location).unwrap().data, std::fs::read(file.path()).unwrap());
+        assert!(worker.upload.verification.unwrap().verified_at >= started_utc + chrono::TimeDelta::seconds(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_schedule_with_jitter() {
+        let server = TusServer::start().await;
+        for nth in 1..=3 {
+            server.fail_patch(nth, 503);
+        }
+        let (upload, _file) = create_upload(1024);
+        let config = TusConfig {
+            max_retries: 5,
+            retry_delay: Duration::from_secs(10),
+            max_retry_delay: Duration::from_secs(25),
+            yield_slot_during_backoff: true,
+            yield_backoff_threshold: Duration::ZERO,
+            connect_timeout: Duration::ZERO,
+            request_timeout: Duration::ZERO,
+            stall_timeout: Duration::ZERO,
+            ..TusConfig::new(server.endpoint())
+        };
+        let mut worker = UploadWorker::new(config, upload, CancellationToken::new())
+            .with_clock(Arc::new(TestClock::new()))
+            .with_rng(Arc::new(Rng::seeded(11)));
+
+        // 每次让出时返回这一次的等待时间：加倍到上限，抖动来自同样种子的随机数
+        let mut delays = Vec::new();
+        while let WorkerOutcome::WaitingRetry(delay) = worker.start().await.unwrap() {
+            delays.push(delay);
+        }
+        assert_eq!(worker.upload.status, UploadStatus::Completed);
+        assert_eq!(worker.upload.retry_count, 0);
+
+        let jitter = Rng::seeded(11);
+        let expected: Vec<_> = [10, 20, 25]
+            .map(|secs| Duration::from_secs(secs).mul_f64(1.0 + 0.2 * (jitter.next_f64() * 2.0 - 1.0)))
+            .into();
+        assert_eq!(delays, expected);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_during_backoff() {
+        let server = TusServer::start().await;
+        server.fail_patch(1, 503);
+        let (upload, _file) = create_upload(2048);
+        let mut worker = create_worker(&server, upload);
+        worker.config.retry_delay = Duration::from_secs(3600);
+        let token = worker.cancellation_token.clone();
+        let handle = tokio::spawn(async move { worker.start().await });
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        token.cancel();
+        let outcome = tokio::time::timeout(Duration::from_secs(5), handle).await.unwrap().unwrap();
+        assert!(matches!(outcome, Ok(WorkerOutcome::Cancelled)));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_aborts_request_in_flight() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(50)));
+        let (upload, file) = create_upload(8192);
+        let mut worker = create_worker(&server, upload);
+        let token = worker.cancellation_token.clone();
+        let handle = tokio::spawn(async move {
+            let outcome = worker.start().await;
+            (worker, outcome)
+        });
+
+        // 两块确认之后的请求一直没有响应
+        while server.requests().iter().filter(|request| request.method == reqwest::Method::PATCH).count() < 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        server.set_patch_delay(Some(Duration::from_secs(3600)));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        token.cancel();
+        let (worker, outcome) = tokio::time::timeout(Duration::from_secs(1), handle).await.unwrap().unwrap();
+        assert!(matches!(outcome, Ok(WorkerOutcome::Cancelled)));
+
+        // 进度停在服务端确认的偏移
+        let location = worker.upload.location.clone().unwrap();
+        let confirmed = server.upload(&location).unwrap().data.len() as u64;
+        assert!(confirmed >= 2048 && confirmed < 8192);
+        assert_eq!(worker.upload.progress.bytes_transferred, confirmed);
+        let last = worker.upload.timeline.entries().last().unwrap();
+        assert_eq!(last.event, TimelineEvent::Interrupted { offset: confirmed });
+
+        // 继续时从确认的偏移发送，不重新发送已经确认的数据
+        let mut upload = worker.upload;
+        upload.transition_to(UploadStatus::Paused).unwrap();
+        server.set_patch_delay(None);
+        let resumed_from = server.requests().len();
+        let mut worker = create_worker(&server, upload);
+        worker.start().await.unwrap();
+        let patches: Vec<_> = server.requests()[resumed_from..].iter()
+            .filter(|request| request.method == reqwest::Method::PATCH)
+            .cloned()
+            .collect();
+        assert_eq!(patches[0].headers.get("Upload-Offset").unwrap(), confirmed.to_string().as_str());
+        assert_eq!(patches.iter().map(|request| request.body_len as u64).sum::<u64>(), 8192 - confirmed);
+        assert_eq!(server.upload(&location).unwrap().data, std::fs::read(file.path()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_stalled_patch_times_out_and_retries() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_secs(3600)));
+        let (upload, file) = create_upload(2048);
+        let mut worker = create_worker(&server, upload);
+        worker.config.request_timeout = Duration::from_millis(300);
+        worker.config.min_expected_throughput = 1024 * 1024;
+        worker.config.retry_delay = Duration::from_millis(10);
+        assert_eq!(worker.config.transfer_timeout(1024 * 1024), Some(Duration::from_millis(1300)));
+
+        // 第一个 PATCH 一直没有响应，超时后按网络错误重试
+        let handle = tokio::spawn(async move {
+            let outcome = worker.start().await;
+            (worker, outcome)
+        });
+        while server.patch_count() < 1 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        server.set_patch_delay(None);
+        let (worker, outcome) = tokio::time::timeout(Duration::from_secs(5), handle).await.unwrap().unwrap();
+        assert_eq!(outcome.unwrap(), WorkerOutcome::Completed);
+        assert_eq!(server.patch_count(), 3);
+
+        let location = worker.upload.location.unwrap();
+        assert_eq!(server.upload(&location).unwrap().data, std::fs::read(file.path()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_stall_aborts_request_and_retries() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_secs(3600)));
+        let events = Arc::new(crate::core::event::EventBus::default());
+        let mut received = events.subscribe();
+        let (upload, file) = create_upload(2048);
+        let mut worker = create_worker(&server, upload)
+            .with_progress_reporter(Arc::new(ProgressReporter::new(events.clone())));
+        worker.config.request_timeout = Duration::ZERO;
+        worker.config.stall_timeout = Duration::from_millis(300);
+        worker.config.retry_delay = Duration::from_millis(10);
+
+        // 第一个 PATCH 没有响应，停滞后中断并重新确认偏移
+        let handle = tokio::spawn(async move {
+            let outcome = worker.start().await;
+            (worker, outcome)
+        });
+        let event = tokio::time::timeout(Duration::from_secs(5), received.recv()).await.unwrap().unwrap();
+        assert!(matches!(event.event, crate::core::event::UploadEvent::Stalled { offset: 0, .. }), "{:?}", event);
+        server.set_patch_delay(None);
+        let (worker, outcome) = tokio::time::timeout(Duration::from_secs(5), handle).await.unwrap().unwrap();
+        assert_eq!(outcome.unwrap(), WorkerOutcome::Completed);
+        assert!(worker.upload.timeline.entries().any(|entry| entry.event == TimelineEvent::Stalled { offset: 0 }));
+        let location = worker.upload.location.unwrap();
+        assert_eq!(server.upload(&location).unwrap().data, std::fs::read(file.path()).unwrap());
+
+        // 一直停滞时用完重试次数后失败
+        server.set_patch_delay(Some(Duration::from_secs(3600)));
+        let (upload, _file) = create_upload(1024);
+        let mut worker = create_worker(&server, upload);
+        worker.config.request_timeout = Duration::ZERO;
+        worker.config.stall_timeout = Duration::from_millis(100);
+        worker.config.retry_delay = Duration::from_millis(10);
+        worker.config.max_retries = 1;
+        let err = worker.start().await.unwrap_err();
+        assert!(matches!(err, UploadError::Stalled { .. }), "{:?}", err);
+        assert_eq!(worker.upload.last_error.unwrap().code, "stalled");
+    }
+
+    #[tokio::test]
+    async fn test_header_too_large_is_not_retried() {
+        let server = TusServer::start().await;
+        server.fail_patch(1, 431);
+        let (upload, _file) = create_upload(2048);
+        let mut worker = create_worker(&server, upload);
+
+        let err = worker.start().await.unwrap_err();
+        assert!(matches!(err, UploadError::ServerError { status: 431, .. }));
+        assert!(err.is_fatal());
+        assert_eq!(server.patch_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried() {
+        let server = TusServer::start().await;
+        server.fail_patch(1, 413);
+        let (upload, _file) = create_upload(2048);
+        let mut worker = create_worker(&server, upload);
+        worker.config.retry_delay = Duration::from_secs(3600);
+
+        let err = worker.start().await.unwrap_err();
+        assert!(matches!(&err, UploadError::ServerError { status: 413, body } if body == "injected failure"), "{:?}", err);
+        assert!(!err.is_retryable());
+        assert_eq!(err.code(), "server_error");
+        assert_eq!(server.patch_count(), 1);
+        // 返回前已经记录在 upload 上
+        assert_eq!(worker.upload.status, UploadStatus::Failed);
+        let error = worker.upload.last_error.clone().unwrap();
+        assert_eq!((error.code.as_str(), error.status), ("server_error", Some(413)));
+
+        // 429 和 5xx 按重试处理
+        server.fail_patch(2, 429);
+        server.fail_patch(3, 503);
+        let (upload, file) = create_upload(1024);
+        let mut worker = create_worker(&server, upload);
+        worker.config.retry_delay = Duration::from_millis(10);
+        assert!(matches!(worker.start().await.unwrap(), WorkerOutcome::Completed));
+        let location = worker.upload.location.clone().unwrap();
+        assert_eq!(server.upload(&location).unwrap().data, std::fs::read(file.path()).unwrap());
+        assert_eq!(server.patch_count(), 4);
+
+        for (status, retryable) in [(400, false), (403, false), (404, false), (409, true), (423, true), (429, true), (500, true), (502, true)] {
+            let err = UploadError::Http { status, body: String::new() };
+            assert_eq!(err.is_retryable(), retryable, "{}", status);
+            assert_eq!(matches!(err.into_permanent(), UploadError::ServerError { .. }), !retryable, "{}", status);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fails_before_start_when_profile_missing() {
+        let server = TusServer::start().await;
+        let (mut upload, _file) = create_upload(1024);
+        upload.endpoint = Some("removed".to_string());
+        let mut worker = create_worker(&server, upload);
+
+        let err = worker.start().await.unwrap_err();
+        assert!(matches!(err, UploadError::Config(_)), "{:?}", err);
+        assert_eq!(worker.upload.status, UploadStatus::Failed);
+        let error = worker.upload.last_error.clone().unwrap();
+        assert_eq!((error.code.as_str(), error.status), ("config", None));
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_digest_continues_after_restart() {
+        let server = TusServer::start().await;
+        server.set_patch_delay(Some(Duration::from_millis(30)));
+        let (upload, file) = create_upload(10_000);
+        let content = std::fs::read(file.path()).unwrap();
+        let mut worker = create_worker(&server, upload);
+        worker.config.hash_algorithm = Some(digest::HashAlgorithm::Sha256);
+        let token = worker.cancellation_token.clone();
+        let handle = tokio::spawn(async move {
+            worker.start().await.unwrap();
+            worker
+        });
+        while server.patch_count() < 3 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        token.cancel();
+        let mut upload = handle.await.unwrap().upload;
+        upload.transition_to(UploadStatus::Paused).unwrap();
+        let hashed = upload.digest.as_ref().unwrap().offset().unwrap();
+        assert!(hashed > 0 && hashed < 10_000);
+
+        // 重启前服务端又收到了一块，中间状态需要从文件补齐
+        let location = upload.location.clone().unwrap();
+        let offset = server.upload(&location).unwrap().data.len();
+        reqwest::Client::new().patch(&location)
+            .header(headers::TUS_RESUMABLE, headers::TUS_VERSION)
+            .header(headers::UPLOAD_OFFSET, offset.to_string())
+            .header(reqwest::header::CONTENT_TYPE, headers::CONTENT_TYPE)
+            .body(content[offset..offset + 1024].to_vec())
+            .send().await.unwrap();
+
+        let upload: Upload = serde_json::from_str(&serde_json::to_string(&upload).unwrap()).unwrap();
+        server.set_patch_delay(None);
+        let mut worker = create_worker(&server, upload);
+        worker.config.hash_algorithm = Some(digest::HashAlgorithm::Sha256);
+        worker.start().await.unwrap();
+
+        let mut reference = digest::Sha256::default();
+        reference.update(&content);
+        let digest = worker.upload.digest.unwrap();
+        assert!(digest.hasher.is_none());
+        assert_eq!(digest.digest.unwrap(), reference.finalize());
+    }
+
+    #[tokio::test]
+    async fn test_completion_verified() {
+        let server = TusServer::start().await;
+        let (upload, _file) = create_upload(2500);
+        let mut worker = create_worker(&server, upload);
+        worker.start().await.unwrap();
+
+        assert_eq!(worker.upload.status, UploadStatus::Completed);
+        let verification = worker.upload.verification.clone().unwrap();
+        assert_eq!(verification.offset, 2500);
+        assert_eq!(verification.length, Some(2500));
+    }
+
+    #[tokio::test]
+    async fn test_short_source_is_incomplete() {
+        let server = TusServer::start().await;
+        let (upload, file) = create_upload(2500);
+        // 添加后文件被截断，无法提供剩余数据
+        file.as_file().set_len(1500).unwrap();
+
+        let mut worker = create_worker(&server, upload);
+        let err = worker.start().await.unwrap_err();
+        match err {
+            UploadError::IncompleteUpload { expected, actual } => {
+                assert_eq!(expected, 2500);
+                assert_eq!(actual, 1500);
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+        assert_ne!(worker.upload.status, UploadStatus::Completed);
+        assert!(worker.upload.verification.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_observes_server_clock_skew() {
+        let server = TusServer::start().await;
+        server.set_date_offset(Some(chrono::TimeDelta::hours(1)));
+        let (upload, _file) = create_upload(2048);
+        let clock_skew = Arc::new(ClockSkew::new());
+        let mut worker = create_worker(&server, upload).with_clock_skew(clock_skew.clone());
+        worker.start().await.unwrap();
+
+        let skew = clock_skew.estimate().unwrap();
+        assert!((skew - chrono::TimeDelta::hours(1)).abs() < chrono::TimeDelta::seconds(5));
+
+        // 本地看来还有半小时才过期，按服务端时间已经过期
+        assert!(clock_skew.is_expired(chrono::Utc::now() + chrono::TimeDelta::minutes(30)));
+    }
+
+    /// 读取一个 HTTP 请求，返回请求行，连接关闭时返回 None
+    async fn read_request(reader: &mut tokio::io::BufReader<tokio::net::TcpStream>) -> Option<String> {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).await.ok()? == 0 {
+            return None;
+        }
+
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.ok()?;
+            if line == "\r\n" {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+
+        let mut body = vec![0u8; content_length];
+        reader.read_exact(&mut body).await.ok()?;
+
+        Some(request_line)
+    }
+
+    #[tokio::test]
+    async fn test_connection_reused_after_failed_patch() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let connections_clone = connections.clone();
+        tokio::spawn(async move {
+            let mut request_count = 0;
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                connections_clone.fetch_add(1, Ordering::SeqCst);
+
+                let mut reader = tokio::io::BufReader::new(stream);
+                while read_request(&mut reader).await.is_some() {
+                    request_count += 1;
+                    let stream = reader.get_mut();
+                    if request_count == 1 {
+                        // 响应头和响应体分开发送，客户端不读响应体就无法复用连接
+                        stream.write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 4\r\n\r\n").await.unwrap();
+                        stream.flush().await.unwrap();
+                        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+                        stream.write_all(b"boom").await.unwrap();
+                    } else {
+                        stream.write_all(b"HTTP/1.1 204 No Content\r\nUpload-Offset: 4\r\n\r\n").await.unwrap();
+                    }
+                }
+            }
+        });
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let upload = Upload::new(file.path().to_path_buf(), 1024).unwrap();
+        let config = TusConfig::new(format!("http://{}/files", addr));
+        let client = tls::client_for(&config).unwrap();
+        let mut worker = UploadWorker::new(config, upload, CancellationToken::new()).with_client(client);
+        worker.upload.set_location(format!("http://{}/files/1", addr));
+
+        let err = worker.upload_chunk(Bytes::from_static(b"data"), 0, None).await.unwrap_err();
+        match err {
+            UploadError::Http { status, body } => {
+                assert_eq!(status, 500);
+                assert_eq!(body, "boom");
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+
+        worker.upload_chunk(Bytes::from_static(b"data"), 0, None).await.unwrap();
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
     }
 }
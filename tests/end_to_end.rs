@@ -0,0 +1,395 @@
+mod support;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use hyper::Method;
+use tokio_util::sync::CancellationToken;
+use uploader_rs::core::config::{CreationWithUpload, TusConfig};
+use uploader_rs::core::error::UploadError;
+use uploader_rs::core::state::UploadStateManager;
+use uploader_rs::core::timeline::TimelineEvent;
+use uploader_rs::core::upload::{Upload, UploadStatus};
+use uploader_rs::uploader::discovery::CapabilityCache;
+use uploader_rs::uploader::worker::UploadWorker;
+use support::tus_server::TusServer;
+
+const CHUNK_SIZE: usize = 1024;
+
+fn source_file(len: usize) -> (tempfile::NamedTempFile, Vec<u8>) {
+    let content: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    std::io::Write::write_all(&mut file, &content).unwrap();
+    (file, content)
+}
+
+fn config(server: &TusServer, state_dir: &Path) -> TusConfig {
+    TusConfig {
+        chunk_size: CHUNK_SIZE,
+        buffer_size: CHUNK_SIZE,
+        retry_delay: Duration::from_millis(10),
+        state_dir: state_dir.to_path_buf(),
+        ..TusConfig::new(server.endpoint())
+    }
+}
+
+async fn run_worker(config: TusConfig, upload: Upload) -> Upload {
+    let mut worker = UploadWorker::new(config, upload, CancellationToken::new());
+    worker.start().await.unwrap();
+    worker.upload
+}
+
+/// 启动 worker，等待一段时间后取消，返回暂停后的 upload
+async fn run_and_pause(config: TusConfig, upload: Upload, after: Duration) -> Upload {
+    let token = CancellationToken::new();
+    let mut worker = UploadWorker::new(config, upload, token.clone());
+    let handle = tokio::spawn(async move {
+        worker.start().await.unwrap();
+        worker
+    });
+
+    tokio::time::sleep(after).await;
+    token.cancel();
+
+    let mut upload = handle.await.unwrap().upload;
+    upload.transition_to(UploadStatus::Paused).unwrap();
+    upload
+}
+
+fn assert_uploaded(server: &TusServer, upload: &Upload, content: &[u8]) {
+    assert_eq!(upload.status, UploadStatus::Completed);
+    let location = upload.location.as_ref().expect("upload has no location");
+    let server_upload = server.upload(location).expect("server has no such upload");
+    assert_eq!(server_upload.length, Some(content.len() as u64));
+    assert!(server_upload.data == content, "server data differs from the source file");
+}
+
+#[tokio::test]
+async fn full_upload() {
+    let server = TusServer::start().await;
+    let state_dir = tempfile::tempdir().unwrap();
+    let (file, content) = source_file(CHUNK_SIZE * 3 + 100);
+
+    let upload = Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap();
+    let upload = run_worker(config(&server, state_dir.path()), upload).await;
+
+    assert_uploaded(&server, &upload, &content);
+    assert_eq!(server.count_requests(Method::POST), 1);
+    assert_eq!(server.patch_count(), 4);
+}
+
+#[tokio::test]
+async fn pause_and_resume() {
+    let server = TusServer::start().await;
+    server.set_patch_delay(Some(Duration::from_millis(50)));
+    let state_dir = tempfile::tempdir().unwrap();
+    let (file, content) = source_file(CHUNK_SIZE * 8);
+    let config = config(&server, state_dir.path());
+
+    let upload = Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap();
+    let upload = run_and_pause(config.clone(), upload, Duration::from_millis(150)).await;
+
+    let location = upload.location.clone().unwrap();
+    let uploaded = server.upload(&location).unwrap().data.len();
+    assert!(uploaded > 0 && uploaded < content.len());
+
+    server.set_patch_delay(None);
+    let upload = run_worker(config, upload).await;
+
+    assert_uploaded(&server, &upload, &content);
+    assert_eq!(upload.location.as_deref(), Some(location.as_str()));
+    assert_eq!(server.count_requests(Method::POST), 1);
+}
+
+#[tokio::test]
+async fn resume_after_restart() {
+    let server = TusServer::start().await;
+    server.set_patch_delay(Some(Duration::from_millis(50)));
+    let state_dir = tempfile::tempdir().unwrap();
+    let (file, content) = source_file(CHUNK_SIZE * 8);
+    let config = config(&server, state_dir.path());
+
+    let upload = Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap();
+    let upload_id = upload.id.clone();
+    let upload = run_and_pause(config.clone(), upload, Duration::from_millis(150)).await;
+    {
+        let state = UploadStateManager::new(config.clone()).await.unwrap();
+        state.shelve(upload).await.unwrap();
+    }
+
+    // 模拟重启：重新从状态文件夹加载
+    server.set_patch_delay(None);
+    let state = UploadStateManager::new(config.clone()).await.unwrap();
+    let upload = state.get_upload(&upload_id).await.unwrap();
+    assert_eq!(upload.status, UploadStatus::Paused);
+
+    let upload = run_worker(config, upload).await;
+    assert_uploaded(&server, &upload, &content);
+    assert_eq!(server.count_requests(Method::POST), 1);
+}
+
+#[tokio::test]
+async fn one_patch_per_chunk_without_extra_heads() {
+    let server = TusServer::start().await;
+    let state_dir = tempfile::tempdir().unwrap();
+    let (file, content) = source_file(CHUNK_SIZE * 10);
+
+    // 只在开始和完成前发送 HEAD，其余偏移来自 PATCH 响应
+    let upload = Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap();
+    let upload = run_worker(config(&server, state_dir.path()), upload).await;
+    assert_uploaded(&server, &upload, &content);
+    assert_eq!(server.count_requests(Method::PATCH), 10);
+    assert_eq!(server.count_requests(Method::HEAD), 2);
+
+    // 失败之后重新确认偏移
+    let server = TusServer::start().await;
+    server.fail_patch(4, 503);
+    let upload = Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap();
+    let upload = run_worker(config(&server, state_dir.path()), upload).await;
+    assert_uploaded(&server, &upload, &content);
+    assert_eq!(server.count_requests(Method::PATCH), 11);
+    assert_eq!(server.count_requests(Method::HEAD), 3);
+
+    // 旧的行为：每块之前都确认
+    let server = TusServer::start().await;
+    let upload = Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap();
+    let config = TusConfig { head_before_each_chunk: true, ..config(&server, state_dir.path()) };
+    let upload = run_worker(config, upload).await;
+    assert_uploaded(&server, &upload, &content);
+    assert_eq!(server.count_requests(Method::PATCH), 10);
+    assert_eq!(server.count_requests(Method::HEAD), 11);
+}
+
+#[tokio::test]
+async fn retry_after_server_error() {
+    let server = TusServer::start().await;
+    server.fail_patch(2, 503);
+    let state_dir = tempfile::tempdir().unwrap();
+    let (file, content) = source_file(CHUNK_SIZE * 3);
+
+    let upload = Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap();
+    let upload = run_worker(config(&server, state_dir.path()), upload).await;
+
+    assert_uploaded(&server, &upload, &content);
+    assert_eq!(server.patch_count(), 4);
+}
+
+#[tokio::test]
+async fn recover_from_offset_conflict() {
+    let server = TusServer::start().await;
+    server.conflict_once();
+    let state_dir = tempfile::tempdir().unwrap();
+    let (file, content) = source_file(CHUNK_SIZE * 2 + 1);
+
+    let upload = Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap();
+    let upload = run_worker(config(&server, state_dir.path()), upload).await;
+
+    assert_uploaded(&server, &upload, &content);
+}
+
+#[tokio::test]
+async fn wait_while_upload_is_locked() {
+    let server = TusServer::start().await;
+    let state_dir = tempfile::tempdir().unwrap();
+    let (file, content) = source_file(CHUNK_SIZE * 2);
+    let config = TusConfig { max_retries: 0, ..config(&server, state_dir.path()) };
+
+    // 之前的请求还持有锁，前两个请求返回 423，等待后继续，不占用重试次数
+    let upload = Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap();
+    let mut worker = UploadWorker::new(config.clone(), upload, CancellationToken::new());
+    server.lock_upload(2);
+    worker.start().await.unwrap();
+    assert_uploaded(&server, &worker.upload, &content);
+    assert_eq!(worker.upload.retry_count, 0);
+
+    // 超过等待时间后返回 UploadLocked
+    server.lock_upload(usize::MAX);
+    let upload = Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap();
+    let config = TusConfig { lock_wait_budget: Duration::from_millis(50), ..config };
+    let mut worker = UploadWorker::new(config, upload, CancellationToken::new());
+    let err = worker.start().await.unwrap_err();
+    assert!(matches!(err, UploadError::UploadLocked { waited } if waited == Duration::from_millis(50)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn recover_from_dropped_connection() {
+    let server = TusServer::start().await;
+    server.drop_connection_on_patch(2);
+    let state_dir = tempfile::tempdir().unwrap();
+    let (file, content) = source_file(CHUNK_SIZE * 4);
+
+    let upload = Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap();
+    let upload = run_worker(config(&server, state_dir.path()), upload).await;
+
+    // 服务端只保存了中断请求的一半数据，后续需要从服务端偏移继续
+    assert_uploaded(&server, &upload, &content);
+    assert!(server.connection_count() >= 2);
+}
+
+#[tokio::test]
+async fn ambiguous_failure_does_not_resend_committed_bytes() {
+    // (保存的字节数，预期的 PATCH 数，预期跳过的字节数)
+    let cases = [(None, 4, Some(CHUNK_SIZE as u64)), (Some(300), 5, Some(300)), (Some(0), 5, None)];
+    for (committed, patches, avoided) in cases {
+        let server = TusServer::start().await;
+        server.drop_connection_after_commit(2, committed);
+        let state_dir = tempfile::tempdir().unwrap();
+        let (file, content) = source_file(CHUNK_SIZE * 4);
+
+        let upload = Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap();
+        let upload = run_worker(config(&server, state_dir.path()), upload).await;
+
+        // 数据不重复也没有空洞，服务端没有因为重复的偏移返回 409
+        assert_uploaded(&server, &upload, &content);
+        assert_eq!(server.patch_count(), patches, "committed {:?}", committed);
+        let recorded: Vec<&TimelineEvent> = upload.timeline.entries().map(|entry| &entry.event).collect();
+        match avoided {
+            Some(bytes) => assert_eq!(
+                recorded,
+                [&TimelineEvent::DuplicateSendAvoided { offset: CHUNK_SIZE as u64, committed: bytes }]
+            ),
+            None => assert!(recorded.is_empty()),
+        }
+    }
+}
+
+/// 在服务端创建 upload 并直接发送前 sent 字节，模拟之前的会话
+async fn create_partial(server: &TusServer, content: &[u8], sent: usize) -> String {
+    let client = reqwest::Client::new();
+    let response = client.post(server.endpoint())
+        .header("Tus-Resumable", "1.0.0")
+        .header("Upload-Length", content.len())
+        .send().await.unwrap();
+    let location = response.headers()["Location"].to_str().unwrap().to_string();
+    client.patch(&location)
+        .header("Tus-Resumable", "1.0.0")
+        .header("Upload-Offset", 0)
+        .header("Content-Type", "application/offset+octet-stream")
+        .body(content[..sent].to_vec())
+        .send().await.unwrap();
+    location
+}
+
+#[tokio::test]
+async fn resume_with_changed_chunk_size() {
+    // (新的块大小，旧格式的状态文件，rechunk_on_resume，预期的块大小)
+    let cases = [
+        (512, true, false, 512),
+        (CHUNK_SIZE + 512, false, true, CHUNK_SIZE + 512),
+        (512, false, true, 512),
+        (CHUNK_SIZE + 512, false, false, CHUNK_SIZE),
+    ];
+    for (chunk_size, legacy, rechunk, expected) in cases {
+        let server = TusServer::start().await;
+        let state_dir = tempfile::tempdir().unwrap();
+        let (file, content) = source_file(CHUNK_SIZE * 4);
+        let location = create_partial(&server, &content, CHUNK_SIZE).await;
+
+        let mut upload = Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap();
+        upload.location = Some(location);
+        upload.progress.bytes_transferred = CHUNK_SIZE as u64;
+        upload.status = UploadStatus::Paused;
+        let mut entry = serde_json::to_value(&upload).unwrap();
+        if legacy {
+            entry.as_object_mut().unwrap().remove("chunk_size");
+        }
+        let fixture = serde_json::json!({
+            "version": if legacy { 1 } else { 2 },
+            "uploads": [],
+            "shelved": [entry],
+            "config": TusConfig::new(server.endpoint()),
+        });
+        std::fs::write(state_dir.path().join("upload-state.json"), fixture.to_string()).unwrap();
+
+        let config = TusConfig {
+            chunk_size,
+            rechunk_on_resume: rechunk,
+            ..config(&server, state_dir.path())
+        };
+        let state = UploadStateManager::new(config.clone()).await.unwrap();
+        state.wait_loaded().await;
+        let upload = state.get_upload(&upload.id).await.unwrap();
+        assert_eq!(upload.chunk_size, if legacy { chunk_size } else { CHUNK_SIZE });
+
+        let upload = run_worker(config, upload).await;
+        assert_uploaded(&server, &upload, &content);
+        let sizes: Vec<usize> = server.requests().iter()
+            .filter(|request| request.method == Method::PATCH)
+            .map(|request| request.body_len)
+            .skip(1)
+            .collect();
+        let remaining = content.len() - CHUNK_SIZE;
+        let expected_sizes: Vec<usize> = (0..remaining).step_by(expected)
+            .map(|start| expected.min(remaining - start))
+            .collect();
+        assert_eq!(sizes, expected_sizes, "chunk size {} legacy {} rechunk {}", chunk_size, legacy, rechunk);
+        assert_eq!(upload.chunk_size, expected);
+        assert_eq!(upload.progress.bytes_transferred, content.len() as u64);
+    }
+}
+
+#[tokio::test]
+async fn small_files_complete_in_creation_request() {
+    let server = TusServer::start().await;
+    server.enable_creation_with_upload();
+    let state_dir = tempfile::tempdir().unwrap();
+    let config = TusConfig { creation_with_upload: CreationWithUpload::Always, ..config(&server, state_dir.path()) };
+
+    // 小于一块的文件只有一个 POST
+    let (file, content) = source_file(CHUNK_SIZE / 2);
+    let upload = run_worker(config.clone(), Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap()).await;
+    assert_uploaded(&server, &upload, &content);
+    assert_eq!(upload.progress.bytes_transferred, content.len() as u64);
+    let methods: Vec<(Method, usize)> = server.requests().into_iter().map(|r| (r.method, r.body_len)).collect();
+    assert_eq!(methods, [(Method::POST, CHUNK_SIZE / 2)]);
+
+    // 更大的文件从创建请求返回的偏移继续
+    let (file, content) = source_file(CHUNK_SIZE * 2 + 100);
+    let upload = run_worker(config, Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap()).await;
+    assert_uploaded(&server, &upload, &content);
+    let patches: Vec<usize> = server.requests().into_iter()
+        .filter(|r| r.method == Method::PATCH)
+        .map(|r| r.body_len)
+        .collect();
+    assert_eq!(patches, [CHUNK_SIZE, 100]);
+}
+
+#[tokio::test]
+async fn creation_with_upload_follows_server_capabilities() {
+    let state_dir = tempfile::tempdir().unwrap();
+    let (file, content) = source_file(CHUNK_SIZE / 2);
+    let run = async |server: &TusServer, cache: &Arc<CapabilityCache>| {
+        let upload = Upload::new(file.path().to_path_buf(), CHUNK_SIZE).unwrap();
+        let mut worker = UploadWorker::new(config(server, state_dir.path()), upload, CancellationToken::new())
+            .with_capability_cache(cache.clone());
+        worker.start().await.unwrap();
+        assert_uploaded(server, &worker.upload, &content);
+    };
+
+    // 声明支持时使用，OPTIONS 只发送一次
+    let server = TusServer::start().await;
+    server.enable_creation_with_upload();
+    let cache = Arc::new(CapabilityCache::default());
+    run(&server, &cache).await;
+    run(&server, &cache).await;
+    assert_eq!(server.count_requests(Method::OPTIONS), 1);
+    assert_eq!(server.count_requests(Method::PATCH), 0);
+
+    // 没有声明时使用普通的创建请求
+    let server = TusServer::start().await;
+    let cache = Arc::new(CapabilityCache::default());
+    run(&server, &cache).await;
+    let bodies: Vec<usize> = server.requests().into_iter().filter(|r| r.method == Method::POST).map(|r| r.body_len).collect();
+    assert_eq!(bodies, [0]);
+    assert_eq!(server.count_requests(Method::PATCH), 1);
+
+    // 声明了但拒绝带有数据的请求时改用普通的创建请求，之后不再尝试
+    let server = TusServer::start().await;
+    server.enable_creation_with_upload();
+    server.reject_creation_body(Some(415));
+    let cache = Arc::new(CapabilityCache::default());
+    run(&server, &cache).await;
+    run(&server, &cache).await;
+    let bodies: Vec<usize> = server.requests().into_iter().filter(|r| r.method == Method::POST).map(|r| r.body_len).collect();
+    assert_eq!(bodies, [CHUNK_SIZE / 2, 0, 0]);
+}
@@ -0,0 +1,141 @@
+#![cfg(all(feature = "ipc", unix))]
+
+mod support;
+
+use std::os::unix::fs::PermissionsExt;
+use std::sync::Arc;
+use std::time::Duration;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio_util::sync::CancellationToken;
+use uploader_rs::core::config::TusConfig;
+use uploader_rs::core::event::UploadEvent;
+use uploader_rs::ipc::client::IpcClient;
+use uploader_rs::ipc::protocol::token_path;
+use uploader_rs::ipc::server::IpcServer;
+use uploader_rs::uploader::manager::UploadManager;
+use support::tus_server::TusServer;
+
+struct Daemon {
+    manager: Arc<UploadManager>,
+    shutdown: CancellationToken,
+    socket: std::path::PathBuf,
+    _dir: tempfile::TempDir,
+}
+
+async fn start_daemon(server: &TusServer) -> Daemon {
+    let dir = tempfile::tempdir().unwrap();
+    let config = TusConfig {
+        state_dir: dir.path().join("state"),
+        chunk_size: 1024,
+        buffer_size: 1024,
+        ..TusConfig::new(server.endpoint())
+    };
+    let manager = Arc::new(UploadManager::new(config).await.unwrap());
+    manager.run().unwrap();
+
+    let socket = dir.path().join("uploader.sock");
+    let shutdown = CancellationToken::new();
+    let ipc = Arc::new(IpcServer::new(manager.clone()).await.unwrap());
+    tokio::spawn({
+        let socket = socket.clone();
+        let shutdown = shutdown.clone();
+        async move { ipc.serve_unix(&socket, shutdown).await.unwrap() }
+    });
+    while !socket.exists() {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    Daemon { manager, shutdown, socket, _dir: dir }
+}
+
+#[tokio::test]
+async fn test_upload_lifecycle_over_socket() {
+    let server = TusServer::start().await;
+    let daemon = start_daemon(&server).await;
+    let state_dir = daemon.manager.config().state_dir.clone();
+
+    let mode = std::fs::metadata(token_path(&state_dir)).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let mut client = IpcClient::connect(&daemon.socket, &state_dir).await.unwrap();
+    client.call("subscribe", json!(null)).await.unwrap().unwrap();
+
+    let content: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    std::io::Write::write_all(&mut file, &content).unwrap();
+
+    let options = json!({ "metadata": { "filetype": "bin" } });
+    let added = client.call("add", json!({ "path": file.path(), "options": options })).await.unwrap().unwrap();
+    assert_eq!(added["outcome"], json!("added"));
+    let id = added["id"].as_str().unwrap().to_string();
+
+    let mut completed = false;
+    for _ in 0..100 {
+        let status = client.call("status", json!({ "id": id })).await.unwrap().unwrap();
+        if status["status"] == json!("Completed") {
+            assert_eq!(status["bytes_transferred"], json!(3000));
+            completed = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(completed, "upload did not complete");
+    assert_eq!(server.uploads()[0].data, content);
+
+    let list = client.call("list", json!(null)).await.unwrap().unwrap();
+    assert_eq!(list.as_array().unwrap().len(), 1);
+    let history = client.call("history", json!(null)).await.unwrap().unwrap();
+    assert_eq!(history[0]["id"], json!(id));
+    let activity = client.call("activity_state", json!(null)).await.unwrap().unwrap();
+    assert_eq!(activity, json!("idle"));
+
+    // 错误使用与前端相同的 ErrorDto
+    let error = client.call("status", json!({ "id": "missing" })).await.unwrap().unwrap_err();
+    assert_eq!(error.code, "upload_not_found");
+
+    // 订阅后推送与 Tauri 相同的事件
+    daemon.manager.event_bus().emit(UploadEvent::IdConflict { id: id.clone() });
+    let mut progress = Vec::new();
+    let event = loop {
+        let event = tokio::time::timeout(Duration::from_secs(1), client.next_event()).await.unwrap().unwrap();
+        // 跳过上传期间的生命周期、进度和活动状态变化
+        match event["event"]["type"].as_str() {
+            Some("progress") => progress.push(event["event"]["bytes_transferred"].clone()),
+            Some("idConflict") => break event,
+            _ => {}
+        }
+    };
+    assert_eq!(progress.last(), Some(&json!(3000)));
+    assert_eq!(event["event"]["type"], json!("idConflict"));
+    assert_eq!(event["event"]["id"], json!(id));
+
+    daemon.shutdown.cancel();
+    daemon.manager.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_requests_require_token() {
+    let server = TusServer::start().await;
+    let daemon = start_daemon(&server).await;
+
+    let stream = tokio::net::UnixStream::connect(&daemon.socket).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    writer.write_all(b"{\"id\":1,\"method\":\"list\"}\n").await.unwrap();
+    let response: serde_json::Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response["error"]["code"], json!("unauthorized"));
+
+    writer.write_all(b"{\"id\":2,\"method\":\"auth\",\"params\":{\"token\":\"guess\"}}\n").await.unwrap();
+    let response: serde_json::Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response["id"], json!(2));
+    assert_eq!(response["error"]["code"], json!("unauthorized"));
+
+    writer.write_all(b"not json\n").await.unwrap();
+    let response: serde_json::Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response["error"]["code"], json!("bad_request"));
+
+    daemon.shutdown.cancel();
+    daemon.manager.shutdown().await.unwrap();
+}
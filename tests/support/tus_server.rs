@@ -0,0 +1,669 @@
+//! 测试用的进程内 tus 服务
+//! 支持 creation、HEAD、带偏移校验的 PATCH、可选的 termination 和 checksum，以及故障注入
+#![allow(dead_code)]
+
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use base64::Engine;
+use bytes::Bytes;
+use sha1::Digest;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::header::HeaderMap;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+type HandlerError = Box<dyn std::error::Error + Send + Sync>;
+
+/// 服务端记录的上传资源
+#[derive(Debug, Clone, Default)]
+pub struct ServerUpload {
+    /// Upload-Length，延迟长度时为 None
+    pub length: Option<u64>,
+
+    /// 已接收的数据
+    pub data: Vec<u8>,
+
+    /// 原始 Upload-Metadata 头
+    pub metadata: Option<String>,
+}
+
+/// 记录收到的请求
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: Method,
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body_len: usize,
+}
+
+/// 断开连接前保存的数据量
+#[derive(Debug, Clone, Copy)]
+enum DroppedCommit {
+    Half,
+    Bytes(usize),
+    All,
+}
+
+#[derive(Debug, Default)]
+struct Faults {
+    /// 第 N 个 PATCH 返回指定状态码（从 1 开始计数）
+    fail_patch: HashMap<usize, u16>,
+
+    /// 第 N 个 PATCH 保存部分数据后不返回响应，直接断开连接
+    drop_patch: HashMap<usize, DroppedCommit>,
+
+    /// 下一个 PATCH 返回 409
+    conflict_once: bool,
+
+    /// 每个 PATCH 处理前的延迟
+    patch_delay: Option<Duration>,
+
+    /// 是否支持 termination 扩展
+    termination: bool,
+
+    /// 之后的 N 个 DELETE 返回这个状态
+    fail_delete: Option<(usize, u16)>,
+
+    /// 响应 Date 头相对本地时间的偏移
+    date_offset: Option<chrono::TimeDelta>,
+
+    /// 模拟强制门户，所有请求都返回 HTML 登录页
+    captive_portal: bool,
+
+    /// OPTIONS 返回的 Tus-Max-Size
+    max_size: Option<u64>,
+
+    /// POST 返回这个 Location 而不是真实的地址
+    location_override: Option<Vec<u8>>,
+
+    /// 支持 creation-with-upload 扩展
+    creation_with_upload: bool,
+
+    /// 带有数据的 POST 返回这个状态，不创建资源
+    reject_creation_body: Option<StatusCode>,
+
+    /// 支持 checksum 扩展时声明的算法
+    checksum_algorithms: Option<Vec<String>>,
+
+    /// 第 N 个 PATCH 的数据在途中被修改（从 1 开始计数）
+    corrupt_patch: HashSet<usize>,
+
+    /// 第 N 个 PATCH 只保存前面的这些字节，并返回实际的偏移
+    truncate_patch: HashMap<usize, usize>,
+
+    /// 第 N 个 PATCH 返回的偏移比实际多出这些字节
+    overstate_offset: HashMap<usize, usize>,
+
+    /// POST 和 PATCH 响应中的 Upload-Expires
+    upload_expires: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// 按方法和路径返回的重定向：状态码和 Location
+    redirects: HashMap<(Method, String), (StatusCode, String)>,
+
+    /// 模拟代理拦截这些方法，直接返回 405
+    blocked_methods: HashSet<Method>,
+
+    /// 之后的 N 个 HEAD 或 PATCH 返回 423，模拟之前的请求还持有锁
+    locked: usize,
+
+    /// 支持的 tus 版本，为空时只支持 1.0.0
+    versions: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    uploads: Mutex<HashMap<String, ServerUpload>>,
+    deleted: Mutex<Vec<String>>,
+    requests: Mutex<Vec<RecordedRequest>>,
+    faults: Mutex<Faults>,
+    next_id: AtomicUsize,
+    patch_count: AtomicUsize,
+    connections: AtomicUsize,
+    patches_in_flight: AtomicUsize,
+    max_patches_in_flight: AtomicUsize,
+}
+
+pub struct TusServer {
+    addr: SocketAddr,
+
+    /// `http://127.0.0.1:<port>` 或 `https://localhost:<port>`
+    origin: Arc<str>,
+    state: Arc<ServerState>,
+    handle: JoinHandle<()>,
+}
+
+impl TusServer {
+    /// 在随机端口上启动服务
+    pub async fn start() -> Self {
+        Self::serve(None).await
+    }
+
+    /// 使用 TLS 在随机端口上启动服务，证书需要包含 localhost
+    pub async fn start_tls(acceptor: tokio_rustls::TlsAcceptor) -> Self {
+        Self::serve(Some(acceptor)).await
+    }
+
+    async fn serve(acceptor: Option<tokio_rustls::TlsAcceptor>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(ServerState::default());
+        let origin: Arc<str> = match acceptor {
+            Some(_) => format!("https://localhost:{}", addr.port()).into(),
+            None => format!("http://{}", addr).into(),
+        };
+
+        let server_state = state.clone();
+        let server_origin = origin.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    break;
+                };
+                server_state.connections.fetch_add(1, Ordering::SeqCst);
+
+                let state = server_state.clone();
+                let origin = server_origin.clone();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |request| handle(origin.clone(), state.clone(), request));
+                    let connection = http1::Builder::new();
+                    match acceptor {
+                        Some(acceptor) => {
+                            // 客户端拒绝证书时握手失败
+                            if let Ok(stream) = acceptor.accept(stream).await {
+                                let _ = connection.serve_connection(TokioIo::new(stream), service).await;
+                            }
+                        }
+                        None => {
+                            let _ = connection.serve_connection(TokioIo::new(stream), service).await;
+                        }
+                    }
+                });
+            }
+        });
+
+        Self { addr, origin, state, handle }
+    }
+
+    /// creation 地址
+    pub fn endpoint(&self) -> String {
+        format!("{}/files", self.origin)
+    }
+
+    pub fn fail_patch(&self, nth: usize, status: u16) {
+        self.state.faults.lock().unwrap().fail_patch.insert(nth, status);
+    }
+
+    /// 第 nth 个 PATCH 只保存一半数据后断开连接
+    pub fn drop_connection_on_patch(&self, nth: usize) {
+        self.state.faults.lock().unwrap().drop_patch.insert(nth, DroppedCommit::Half);
+    }
+
+    /// 第 nth 个 PATCH 保存 committed 字节（为空时保存全部）后断开连接，客户端收不到响应
+    pub fn drop_connection_after_commit(&self, nth: usize, committed: Option<usize>) {
+        let commit = committed.map_or(DroppedCommit::All, DroppedCommit::Bytes);
+        self.state.faults.lock().unwrap().drop_patch.insert(nth, commit);
+    }
+
+    pub fn conflict_once(&self) {
+        self.state.faults.lock().unwrap().conflict_once = true;
+    }
+
+    pub fn set_patch_delay(&self, delay: Option<Duration>) {
+        self.state.faults.lock().unwrap().patch_delay = delay;
+    }
+
+    pub fn set_date_offset(&self, offset: Option<chrono::TimeDelta>) {
+        self.state.faults.lock().unwrap().date_offset = offset;
+    }
+
+    pub fn set_captive_portal(&self, enabled: bool) {
+        self.state.faults.lock().unwrap().captive_portal = enabled;
+    }
+
+    pub fn set_max_size(&self, max_size: Option<u64>) {
+        self.state.faults.lock().unwrap().max_size = max_size;
+    }
+
+    pub fn set_location_override(&self, location: Option<Vec<u8>>) {
+        self.state.faults.lock().unwrap().location_override = location;
+    }
+
+    pub fn enable_creation_with_upload(&self) {
+        self.state.faults.lock().unwrap().creation_with_upload = true;
+    }
+
+    /// 声明 checksum 扩展和支持的算法，之后带有 Upload-Checksum 的 PATCH 都会校验
+    pub fn enable_checksum(&self, algorithms: &[&str]) {
+        self.state.faults.lock().unwrap().checksum_algorithms =
+            Some(algorithms.iter().map(|algorithm| algorithm.to_string()).collect());
+    }
+
+    /// 第 nth 个 PATCH 的第一个字节在到达服务端前被修改
+    pub fn corrupt_patch(&self, nth: usize) {
+        self.state.faults.lock().unwrap().corrupt_patch.insert(nth);
+    }
+
+    /// 第 nth 个 PATCH 只保存前 bytes 字节，模拟代理截断了请求体
+    pub fn truncate_patch(&self, nth: usize, bytes: usize) {
+        self.state.faults.lock().unwrap().truncate_patch.insert(nth, bytes);
+    }
+
+    /// 第 nth 个 PATCH 的响应中偏移多出 extra 字节
+    pub fn overstate_offset(&self, nth: usize, extra: usize) {
+        self.state.faults.lock().unwrap().overstate_offset.insert(nth, extra);
+    }
+
+    pub fn reject_creation_body(&self, status: Option<u16>) {
+        self.state.faults.lock().unwrap().reject_creation_body = status.map(|status| StatusCode::from_u16(status).unwrap());
+    }
+
+    /// 之后的 times 个 DELETE 返回 status，不删除资源
+    pub fn fail_delete(&self, times: usize, status: u16) {
+        self.state.faults.lock().unwrap().fail_delete = Some((times, status));
+    }
+
+    /// method 请求 path 时返回 status，Location 为 target；/v2/files/<id> 与 /files/<id> 是同一个资源
+    pub fn redirect(&self, method: Method, path: &str, status: u16, target: &str) {
+        self.state.faults.lock().unwrap().redirects.insert(
+            (method, path.to_string()),
+            (StatusCode::from_u16(status).unwrap(), target.to_string()),
+        );
+    }
+
+    /// 只支持这些 tus 版本，其他版本的请求返回 412
+    pub fn set_versions(&self, versions: &[&str]) {
+        self.state.faults.lock().unwrap().versions = Some(versions.iter().map(|v| v.to_string()).collect());
+    }
+
+    /// 之后的 times 个 HEAD 或 PATCH 返回 423 Locked
+    pub fn lock_upload(&self, times: usize) {
+        self.state.faults.lock().unwrap().locked = times;
+    }
+
+    /// 像代理一样拦截这个方法，X-HTTP-Method-Override 中的方法不受影响
+    pub fn block_method(&self, method: Method) {
+        self.state.faults.lock().unwrap().blocked_methods.insert(method);
+    }
+
+    pub fn set_upload_expires(&self, expires: Option<chrono::DateTime<chrono::Utc>>) {
+        self.state.faults.lock().unwrap().upload_expires = expires;
+    }
+
+    /// 模拟资源过期：从服务端移除，之后的 HEAD 和 PATCH 返回 404
+    pub fn expire_upload(&self, location: &str) {
+        let id = location.rsplit('/').next().unwrap();
+        self.state.uploads.lock().unwrap().remove(id);
+    }
+
+    pub fn enable_termination(&self) {
+        self.state.faults.lock().unwrap().termination = true;
+    }
+
+    /// 根据 Location 取得服务端的上传资源
+    pub fn upload(&self, location: &str) -> Option<ServerUpload> {
+        let id = location.rsplit('/').next()?;
+        self.state.uploads.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn uploads(&self) -> Vec<ServerUpload> {
+        self.state.uploads.lock().unwrap().values().cloned().collect()
+    }
+
+    /// 已通过 DELETE 删除的资源 id
+    pub fn deleted(&self) -> Vec<String> {
+        self.state.deleted.lock().unwrap().clone()
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    pub fn count_requests(&self, method: Method) -> usize {
+        self.state.requests.lock().unwrap().iter().filter(|r| r.method == method).count()
+    }
+
+    pub fn patch_count(&self) -> usize {
+        self.state.patch_count.load(Ordering::SeqCst)
+    }
+
+    /// 同时处理中的 PATCH 的最大数量
+    pub fn max_concurrent_patches(&self) -> usize {
+        self.state.max_patches_in_flight.load(Ordering::SeqCst)
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.state.connections.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for TusServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+fn response(status: StatusCode) -> hyper::http::response::Builder {
+    Response::builder()
+        .status(status)
+        .header("Tus-Resumable", "1.0.0")
+}
+
+fn empty(builder: hyper::http::response::Builder) -> Result<Response<Full<Bytes>>, HandlerError> {
+    Ok(builder.body(Full::new(Bytes::new()))?)
+}
+
+/// 配置了过期时间时加上 Upload-Expires
+fn with_expires(state: &ServerState, builder: hyper::http::response::Builder) -> hyper::http::response::Builder {
+    match state.faults.lock().unwrap().upload_expires {
+        Some(expires) => builder.header("Upload-Expires", expires.format("%a, %d %b %Y %H:%M:%S GMT").to_string()),
+        None => builder,
+    }
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+async fn handle(
+    origin: Arc<str>,
+    state: Arc<ServerState>,
+    request: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, HandlerError> {
+    let (date_offset, captive_portal) = {
+        let faults = state.faults.lock().unwrap();
+        (faults.date_offset, faults.captive_portal)
+    };
+    if captive_portal {
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(Full::new(Bytes::from_static(b"<html><body>Please sign in</body></html>")))?);
+    }
+
+    let mut response = route(origin, state, request).await?;
+    if let Some(offset) = date_offset {
+        let date = (chrono::Utc::now() + offset).format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        response.headers_mut().insert("Date", date.parse()?);
+    }
+    Ok(response)
+}
+
+async fn route(
+    origin: Arc<str>,
+    state: Arc<ServerState>,
+    request: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, HandlerError> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let headers = request.headers().clone();
+    let id = path.split_once("/files/").map(|(_, id)| id.to_string());
+
+    let versions = state.faults.lock().unwrap().versions.clone().unwrap_or_else(|| vec!["1.0.0".to_string()]);
+    let requested = headers.get("Tus-Resumable").and_then(|v| v.to_str().ok());
+    if method != Method::OPTIONS && !requested.is_some_and(|version| versions.iter().any(|v| v == version)) {
+        state.requests.lock().unwrap().push(RecordedRequest { method, path, headers, body_len: 0 });
+        return empty(Response::builder().status(StatusCode::PRECONDITION_FAILED).header("Tus-Version", versions.join(",")));
+    }
+
+    let redirect = state.faults.lock().unwrap().redirects.get(&(method.clone(), path.clone())).cloned();
+    if let Some((status, target)) = redirect {
+        state.requests.lock().unwrap().push(RecordedRequest { method, path, headers, body_len: 0 });
+        return empty(response(status).header("Location", target));
+    }
+
+    if state.faults.lock().unwrap().blocked_methods.contains(&method) {
+        state.requests.lock().unwrap().push(RecordedRequest { method, path, headers, body_len: 0 });
+        return empty(Response::builder().status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    // POST 可以通过 X-HTTP-Method-Override 代替 PATCH 和 DELETE，记录的仍然是实际的方法
+    let overridden = match headers.get("X-HTTP-Method-Override") {
+        Some(value) if method == Method::POST => Some(Method::from_bytes(value.as_bytes())?),
+        _ => None,
+    };
+
+    let effective = overridden.unwrap_or_else(|| method.clone());
+    if matches!(effective, Method::HEAD | Method::PATCH) && id.is_some() {
+        let mut faults = state.faults.lock().unwrap();
+        if faults.locked > 0 {
+            faults.locked -= 1;
+            drop(faults);
+            state.requests.lock().unwrap().push(RecordedRequest { method, path, headers, body_len: 0 });
+            return empty(response(StatusCode::LOCKED));
+        }
+    }
+
+    let mut body_len = 0;
+    let result = match (effective, id) {
+        (Method::OPTIONS, _) => {
+            let (termination, creation_with_upload, max_size, checksum_algorithms) = {
+                let faults = state.faults.lock().unwrap();
+                (faults.termination, faults.creation_with_upload, faults.max_size, faults.checksum_algorithms.clone())
+            };
+            let mut extensions = vec!["creation", "creation-defer-length"];
+            if creation_with_upload {
+                extensions.push("creation-with-upload");
+            }
+            if termination {
+                extensions.push("termination");
+            }
+            if checksum_algorithms.is_some() {
+                extensions.push("checksum");
+            }
+            let extensions = extensions.join(",");
+            let mut builder = response(StatusCode::NO_CONTENT)
+                .header("Tus-Version", versions.join(","))
+                .header("Tus-Extension", extensions);
+            if let Some(algorithms) = checksum_algorithms {
+                builder = builder.header("Tus-Checksum-Algorithm", algorithms.join(","));
+            }
+            if let Some(max_size) = max_size {
+                builder = builder.header("Tus-Max-Size", max_size);
+            }
+            empty(builder)
+        }
+        (Method::POST, None) => {
+            let length = header_u64(&headers, "Upload-Length");
+            if length.is_none() && headers.get("Upload-Defer-Length").is_none() {
+                return empty(response(StatusCode::BAD_REQUEST));
+            }
+
+            let body = request.into_body().collect().await?.to_bytes();
+            body_len = body.len();
+            let (creation_with_upload, reject_body) = {
+                let faults = state.faults.lock().unwrap();
+                (faults.creation_with_upload, faults.reject_creation_body)
+            };
+            if let (false, Some(status)) = (body.is_empty(), reject_body) {
+                state.requests.lock().unwrap().push(RecordedRequest { method, path, headers, body_len });
+                return empty(response(status));
+            }
+
+            // 不支持 creation-with-upload 时忽略数据
+            let data = if creation_with_upload { body.to_vec() } else { Vec::new() };
+            let offset = data.len();
+            let id = state.next_id.fetch_add(1, Ordering::SeqCst).to_string();
+            let metadata = headers.get("Upload-Metadata")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string);
+            state.uploads.lock().unwrap().insert(id.clone(), ServerUpload {
+                length,
+                data,
+                metadata,
+            });
+
+            let location = state.faults.lock().unwrap().location_override.clone()
+                .unwrap_or_else(|| format!("{}/files/{}", origin, id).into_bytes());
+            let mut builder = with_expires(&state, response(StatusCode::CREATED).header("Location", location));
+            if creation_with_upload && !body.is_empty() {
+                builder = builder.header("Upload-Offset", offset);
+            }
+            empty(builder)
+        }
+        (Method::HEAD, Some(id)) => {
+            let uploads = state.uploads.lock().unwrap();
+            match uploads.get(&id) {
+                Some(upload) => {
+                    let mut builder = response(StatusCode::OK)
+                        .header("Upload-Offset", upload.data.len())
+                        .header("Cache-Control", "no-store");
+                    if let Some(length) = upload.length {
+                        builder = builder.header("Upload-Length", length);
+                    }
+                    empty(builder)
+                }
+                None => empty(response(StatusCode::NOT_FOUND)),
+            }
+        }
+        (Method::PATCH, Some(id)) => {
+            let in_flight = state.patches_in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            state.max_patches_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            let result = handle_patch(state.clone(), method, id, request).await;
+            state.patches_in_flight.fetch_sub(1, Ordering::SeqCst);
+            return result;
+        }
+        (Method::DELETE, Some(id)) => {
+            let failure = {
+                let mut faults = state.faults.lock().unwrap();
+                if !faults.termination {
+                    return empty(response(StatusCode::METHOD_NOT_ALLOWED));
+                }
+                match &mut faults.fail_delete {
+                    Some((times, status)) if *times > 0 => {
+                        *times -= 1;
+                        Some(StatusCode::from_u16(*status)?)
+                    }
+                    _ => None,
+                }
+            };
+            if let Some(status) = failure {
+                state.requests.lock().unwrap().push(RecordedRequest { method, path, headers, body_len });
+                return empty(response(status));
+            }
+            match state.uploads.lock().unwrap().remove(&id) {
+                Some(_) => {
+                    state.deleted.lock().unwrap().push(id);
+                    empty(response(StatusCode::NO_CONTENT))
+                }
+                None => empty(response(StatusCode::NOT_FOUND)),
+            }
+        }
+        _ => empty(response(StatusCode::NOT_FOUND)),
+    };
+
+    state.requests.lock().unwrap().push(RecordedRequest { method, path, headers, body_len });
+    result
+}
+
+async fn handle_patch(
+    state: Arc<ServerState>,
+    method: Method,
+    id: String,
+    request: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, HandlerError> {
+    let nth = state.patch_count.fetch_add(1, Ordering::SeqCst) + 1;
+    let headers = request.headers().clone();
+    let path = request.uri().path().to_string();
+
+    let (fail_status, drop_connection, conflict, delay, corrupt, checksum_algorithms) = {
+        let mut faults = state.faults.lock().unwrap();
+        let conflict = std::mem::take(&mut faults.conflict_once);
+        let corrupt = faults.corrupt_patch.remove(&nth);
+        let drop_connection = faults.drop_patch.remove(&nth);
+        (faults.fail_patch.remove(&nth), drop_connection, conflict, faults.patch_delay, corrupt, faults.checksum_algorithms.clone())
+    };
+    let (truncate, overstate) = {
+        let mut faults = state.faults.lock().unwrap();
+        (faults.truncate_patch.remove(&nth), faults.overstate_offset.remove(&nth).unwrap_or_default())
+    };
+
+    if let Some(delay) = delay {
+        tokio::time::sleep(delay).await;
+    }
+
+    let mut body = request.into_body().collect().await?.to_bytes();
+    if corrupt && !body.is_empty() {
+        let mut corrupted = body.to_vec();
+        corrupted[0] ^= 0xff;
+        body = Bytes::from(corrupted);
+    }
+    state.requests.lock().unwrap().push(RecordedRequest {
+        method,
+        path,
+        headers: headers.clone(),
+        body_len: body.len(),
+    });
+
+    if let Some(status) = fail_status {
+        return Ok(response(StatusCode::from_u16(status)?)
+            .body(Full::new(Bytes::from_static(b"injected failure")))?);
+    }
+
+    let content_type = headers.get("Content-Type").and_then(|v| v.to_str().ok());
+    if content_type != Some("application/offset+octet-stream") {
+        return empty(response(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+    }
+
+    if let (Some(algorithms), Some(checksum)) = (checksum_algorithms, headers.get("Upload-Checksum")) {
+        let Some((algorithm, expected)) = checksum.to_str()?.split_once(' ') else {
+            return empty(response(StatusCode::BAD_REQUEST));
+        };
+        let actual = match algorithm {
+            "sha1" => sha1::Sha1::digest(&body).to_vec(),
+            "md5" => md5::Md5::digest(&body).to_vec(),
+            "crc32" => crc32fast::hash(&body).to_be_bytes().to_vec(),
+            _ => return empty(response(StatusCode::BAD_REQUEST)),
+        };
+        if !algorithms.iter().any(|supported| supported == algorithm) {
+            return empty(response(StatusCode::BAD_REQUEST));
+        }
+        if base64::engine::general_purpose::STANDARD.encode(actual) != expected {
+            return empty(response(StatusCode::from_u16(460)?));
+        }
+    }
+
+    let mut uploads = state.uploads.lock().unwrap();
+    let Some(upload) = uploads.get_mut(&id) else {
+        return empty(response(StatusCode::NOT_FOUND));
+    };
+
+    let offset = header_u64(&headers, "Upload-Offset");
+    if conflict || offset != Some(upload.data.len() as u64) {
+        return empty(response(StatusCode::CONFLICT));
+    }
+
+    // 延迟的长度只能确定一次
+    if let Some(length) = header_u64(&headers, "Upload-Length") {
+        match upload.length {
+            None => upload.length = Some(length),
+            Some(existing) if existing != length => return empty(response(StatusCode::BAD_REQUEST)),
+            Some(_) => {}
+        }
+    }
+
+    if let Some(commit) = drop_connection {
+        // 保存部分数据，然后中断连接
+        let len = match commit {
+            DroppedCommit::Half => body.len() / 2,
+            DroppedCommit::Bytes(bytes) => bytes.min(body.len()),
+            DroppedCommit::All => body.len(),
+        };
+        upload.data.extend_from_slice(&body[..len]);
+        return Err("connection dropped by fault injection".into());
+    }
+
+    let keep = truncate.unwrap_or(body.len()).min(body.len());
+    upload.data.extend_from_slice(&body[..keep]);
+    let offset = upload.data.len() + overstate;
+    drop(uploads);
+    empty(with_expires(&state, response(StatusCode::NO_CONTENT).header("Upload-Offset", offset)))
+}